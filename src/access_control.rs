@@ -0,0 +1,174 @@
+//! Roles that let accounts other than the owner call some privileged methods.
+//!
+//! The owner grants and revokes roles, and can do anything a role allows, so
+//! a role only ever adds callers. While governance is on, an executed
+//! proposal stands in for the owner as usual. Roles derive from ownership:
+//! once it has been renounced, they stop working too.
+//!
+//! | Role   | Allows                                        |
+//! |--------|-----------------------------------------------|
+//! | Minter | `mint`, `mint_idempotent` and `mint_batch`    |
+//! | Pauser | `pause` and `unpause`                         |
+//! | Burner | nothing yet; the role byte is reserved        |
+
+use fvm_ipld_encoding::tuple::{Deserialize_tuple, Serialize_tuple};
+use fvm_ipld_encoding::Cbor;
+use fvm_ipld_hamt::{BytesKey, Hamt};
+use fvm_shared::address::Address;
+use fvm_shared::ActorID;
+
+use crate::error::ActorError;
+use crate::runtime::Runtime;
+use crate::{load_hamt, runtime_context, Context, State};
+
+/// A set of privileged methods that can be delegated to an account.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Role {
+    Minter = 1,
+    Pauser = 2,
+    Burner = 3,
+}
+
+impl Role {
+    /// The role a params `role` byte names, if any.
+    pub fn from_u8(role: u8) -> Option<Role> {
+        match role {
+            1 => Some(Role::Minter),
+            2 => Some(Role::Pauser),
+            3 => Some(Role::Burner),
+            _ => None,
+        }
+    }
+}
+
+fn parse_role(role: u8) -> Result<Role, ActorError> {
+    Role::from_u8(role).ok_or_else(|| actor_error!(USR_ILLEGAL_ARGUMENT, "unknown role {}", role))
+}
+
+/// The roles granted so far, keyed by `role_key`.
+pub type Roles<BS> = Hamt<BS, (), BytesKey>;
+
+/// The `Roles` key for `id` holding `role`: the role byte, then the ID
+/// big-endian.
+fn role_key(role: Role, id: ActorID) -> BytesKey {
+    let mut key = vec![role as u8];
+    key.extend_from_slice(&id.to_be_bytes());
+    BytesKey(key)
+}
+
+/// Returns true if `id` has been granted `role`. The owner isn't recorded as
+/// holding any role; see `require_role`.
+pub fn holds_role<BS: fvm_ipld_blockstore::Blockstore>(
+    store: &BS,
+    state: &State,
+    role: Role,
+    id: ActorID,
+) -> Result<bool, ActorError> {
+    let root = match &state.roles {
+        Some(root) => root,
+        None => return Ok(false),
+    };
+    let roles: Roles<&BS> = load_hamt(store, root, state.hamt_bit_width, "roles")?;
+    let entry = roles
+        .get(&role_key(role, id))
+        .map_err(|err| actor_error!(USR_ILLEGAL_STATE, "failed to get role entry: {:?}", err))?;
+    Ok(entry.is_some())
+}
+
+fn set_role<BS: fvm_ipld_blockstore::Blockstore>(
+    store: &BS,
+    state: &mut State,
+    role: Role,
+    id: ActorID,
+    granted: bool,
+) -> Result<(), ActorError> {
+    let mut roles: Roles<&BS> = match &state.roles {
+        Some(root) => load_hamt(store, root, state.hamt_bit_width, "roles")?,
+        None => Hamt::new_with_bit_width(store, state.hamt_bit_width),
+    };
+    let key = role_key(role, id);
+    if granted {
+        roles.set(key, ()).map_err(|err| {
+            actor_error!(USR_ILLEGAL_STATE, "failed to set role entry: {:?}", err)
+        })?;
+    } else {
+        roles.delete(&key).map_err(|err| {
+            actor_error!(USR_ILLEGAL_STATE, "failed to delete role entry: {:?}", err)
+        })?;
+    }
+    let root = roles
+        .flush()
+        .map_err(|err| actor_error!(USR_ILLEGAL_STATE, "failed to flush roles: {:?}", err))?;
+    state.roles = Some(root);
+    Ok(())
+}
+
+/// Fail unless the caller may call `method`, which `role` gates: it must
+/// pass `Context::require_owner` or hold `role`. Fails with the owner check's
+/// error otherwise.
+pub fn require_role<BS: fvm_ipld_blockstore::Blockstore>(
+    ctx: &Context<BS>,
+    state: &State,
+    role: Role,
+    method: &str,
+) -> Result<(), ActorError> {
+    let err = match ctx.require_owner(state, method) {
+        Ok(()) => return Ok(()),
+        Err(err) => err,
+    };
+    if !state.ownership_renounced && holds_role(ctx.store, state, role, ctx.caller)? {
+        return Ok(());
+    }
+    Err(err)
+}
+
+/// The input parameters for granting, revoking or checking a role. `role` is
+/// a `Role` discriminant.
+#[derive(Serialize_tuple, Deserialize_tuple, Clone, Debug)]
+pub struct RoleParams {
+    pub role: u8,
+    pub account: Address,
+}
+
+impl Cbor for RoleParams {}
+
+fn update_role<R: Runtime>(
+    rt: &R,
+    params: RoleParams,
+    granted: bool,
+    method: &str,
+) -> Result<(), ActorError> {
+    let mut state = State::load_from(rt)?;
+    state.check_unlocked()?;
+
+    let resolver = |addr: &Address| rt.resolve_address(addr);
+    let ctx = runtime_context(rt, &resolver);
+    ctx.require_owner(&state, method)?;
+
+    let role = parse_role(params.role)?;
+    let account = ctx.resolve(&params.account)?;
+    set_role(rt.store(), &mut state, role, account, granted)?;
+    state.save_to(rt)?;
+    Ok(())
+}
+
+/// Grant `account` a role. Only the owner may call this.
+pub fn grant_role<R: Runtime>(rt: &R, params: RoleParams) -> Result<(), ActorError> {
+    update_role(rt, params, true, "grant_role")
+}
+
+/// Take a role away from `account`. Only the owner may call this.
+pub fn revoke_role<R: Runtime>(rt: &R, params: RoleParams) -> Result<(), ActorError> {
+    update_role(rt, params, false, "revoke_role")
+}
+
+/// Returns true if `account` has been granted the role. Addresses that don't
+/// resolve hold none.
+pub fn has_role<R: Runtime>(rt: &R, params: RoleParams) -> Result<bool, ActorError> {
+    let state = State::load_from(rt)?;
+    let role = parse_role(params.role)?;
+    match rt.resolve_address(&params.account) {
+        Some(id) => holds_role(rt.store(), &state, role, id),
+        None => Ok(false),
+    }
+}
@@ -0,0 +1,642 @@
+//! Allowances and operators: moving tokens on another account's behalf.
+//!
+//! An owner can let a spender move up to a set amount of its tokens with
+//! `approve` and its variants, or let an operator move any amount with
+//! `set_operator`. Both are drawn on by `transfer_from` and `burn_from`,
+//! through `spend_allowance`, which checks for an operator first.
+
+use fvm_ipld_encoding::tuple::{Deserialize_tuple, Serialize_tuple};
+use fvm_ipld_encoding::{Cbor, RawBytes};
+use fvm_ipld_hamt::{BytesKey, Hamt};
+use fvm_shared::address::Address;
+use fvm_shared::bigint::bigint_ser;
+use fvm_shared::bigint::bigint_ser::BigIntDe;
+use fvm_shared::econ::TokenAmount;
+use fvm_shared::{ActorID, MethodNum};
+
+use crate::error::ActorError;
+use crate::runtime::Runtime;
+use crate::{
+    check_amount_cap, load_hamt, runtime_context, transfer_in, Frc46TransferParams, State,
+    TokenAmounts, TransferReturn, RECEIVER_HOOK_METHOD_NUM,
+};
+
+/// The operators each account has approved, as a HAMT set keyed by
+/// `pair_key`.
+pub type Operators<BS> = Hamt<BS, (), BytesKey>;
+
+/// The allowances each account has granted, keyed by `pair_key`. Only
+/// non-zero allowances are stored.
+pub type Allowances<BS> = Hamt<BS, BigIntDe, BytesKey>;
+
+/// The `Operators` and `Allowances` key for what `owner` has granted
+/// `grantee`: both IDs, big-endian, owner first.
+pub(crate) fn pair_key(owner: ActorID, grantee: ActorID) -> BytesKey {
+    let mut key = owner.to_be_bytes().to_vec();
+    key.extend_from_slice(&grantee.to_be_bytes());
+    BytesKey(key)
+}
+
+/// Returns true if `owner` has approved `operator` to move its tokens.
+pub fn operator_approved<BS: fvm_ipld_blockstore::Blockstore>(
+    store: &BS,
+    state: &State,
+    owner: ActorID,
+    operator: ActorID,
+) -> Result<bool, ActorError> {
+    let root = match &state.operators {
+        Some(root) => root,
+        None => return Ok(false),
+    };
+    let operators: Operators<&BS> = load_hamt(store, root, state.hamt_bit_width, "operators")?;
+    let entry = operators.get(&pair_key(owner, operator)).map_err(|err| {
+        actor_error!(USR_ILLEGAL_STATE, "failed to get operator entry: {:?}", err)
+    })?;
+    Ok(entry.is_some())
+}
+
+/// Returns what `spender` may still move out of `owner`'s balance.
+pub fn get_allowance<BS: fvm_ipld_blockstore::Blockstore>(
+    store: &BS,
+    state: &State,
+    owner: ActorID,
+    spender: ActorID,
+) -> Result<TokenAmount, ActorError> {
+    let root = match &state.allowances {
+        Some(root) => root,
+        None => return Ok(TokenAmount::from(0)),
+    };
+    let allowances: Allowances<&BS> = load_hamt(store, root, state.hamt_bit_width, "allowances")?;
+    let entry = allowances
+        .get(&pair_key(owner, spender))
+        .map_err(|err| actor_error!(USR_ILLEGAL_STATE, "failed to get allowance: {:?}", err))?;
+    Ok(entry.map(|amount| amount.0.clone()).unwrap_or_else(|| TokenAmount::from(0)))
+}
+
+/// Overwrite what `spender` may move out of `owner`'s balance. Zero removes
+/// the entry.
+pub(crate) fn set_allowance<BS: fvm_ipld_blockstore::Blockstore>(
+    store: &BS,
+    state: &mut State,
+    owner: ActorID,
+    spender: ActorID,
+    amount: TokenAmount,
+) -> Result<(), ActorError> {
+    let mut allowances: Allowances<&BS> = match &state.allowances {
+        Some(root) => load_hamt(store, root, state.hamt_bit_width, "allowances")?,
+        None => Hamt::new_with_bit_width(store, state.hamt_bit_width),
+    };
+    let key = pair_key(owner, spender);
+    if amount == TokenAmount::from(0) {
+        allowances.delete(&key).map_err(|err| {
+            actor_error!(USR_ILLEGAL_STATE, "failed to delete allowance: {:?}", err)
+        })?;
+    } else {
+        allowances
+            .set(key, BigIntDe(amount))
+            .map_err(|err| actor_error!(USR_ILLEGAL_STATE, "failed to set allowance: {:?}", err))?;
+    }
+    let root = allowances
+        .flush()
+        .map_err(|err| actor_error!(USR_ILLEGAL_STATE, "failed to flush allowances: {:?}", err))?;
+    state.allowances = Some(root);
+    Ok(())
+}
+
+/// The allowance that never runs out: `2^256 - 1`, the largest EVM `uint256`,
+/// as in ERC20. Approving a spender for exactly this amount lets it spend any
+/// amount, and spending never draws it down. Any other allowance is finite.
+pub fn infinite_allowance() -> TokenAmount {
+    (TokenAmount::from(1) << 256usize) - 1
+}
+
+/// Charge `spender` for moving `amount` out of `owner`'s balance.
+///
+/// Operators come first: an approved operator may move any amount, and its
+/// transfers never draw down an allowance it may also hold. Anyone else
+/// spends its allowance, and fails with `InsufficientAllowance` if that
+/// doesn't cover `amount`. An `infinite_allowance()` is left as it is.
+pub(crate) fn spend_allowance<BS: fvm_ipld_blockstore::Blockstore>(
+    store: &BS,
+    state: &mut State,
+    owner: ActorID,
+    spender: ActorID,
+    amount: &TokenAmount,
+) -> Result<(), ActorError> {
+    if operator_approved(store, state, owner, spender)? {
+        return Ok(());
+    }
+    let allowance = get_allowance(store, state, owner, spender)?;
+    if allowance == infinite_allowance() {
+        return Ok(());
+    }
+    if allowance < *amount {
+        return Err(actor_error!(
+            kind: InsufficientAllowance,
+            USR_INSUFFICIENT_FUNDS,
+            "{} may spend {} of {}'s tokens, less than {}",
+            spender,
+            allowance,
+            owner,
+            amount
+        ));
+    }
+    set_allowance(store, state, owner, spender, allowance - amount)
+}
+
+/// The input parameters for approving a spender.
+#[derive(Serialize_tuple, Deserialize_tuple, Clone, Debug)]
+pub struct ApproveParams {
+    pub spender: Address,
+    #[serde(with = "bigint_ser")]
+    pub amount: TokenAmount,
+}
+
+impl Cbor for ApproveParams {}
+
+impl TokenAmounts for ApproveParams {
+    fn token_amounts(&self) -> Vec<&TokenAmount> {
+        vec![&self.amount]
+    }
+}
+
+/// Let `spender` move up to `amount` of the caller's tokens through
+/// `transfer_from`, replacing any earlier allowance. Zero revokes it, and
+/// `infinite_allowance()` lets it move any amount.
+///
+/// As with any overwriting approval, a spender that sees a change coming can
+/// spend the old allowance first. Lowering an allowance through zero avoids
+/// that.
+pub fn approve<R: Runtime>(rt: &R, params: ApproveParams) -> Result<(), ActorError> {
+    let mut state = State::load_from(rt)?;
+    state.check_unlocked()?;
+    approve_in(rt, &mut state, params)?;
+    state.save_to(rt)?;
+    Ok(())
+}
+
+/// Approve against `state` without saving it.
+pub(crate) fn approve_in<R: Runtime>(
+    rt: &R,
+    state: &mut State,
+    params: ApproveParams,
+) -> Result<(), ActorError> {
+    let resolver = |addr: &Address| rt.resolve_address(addr);
+    let ctx = runtime_context(rt, &resolver);
+    let spender = ctx.resolve(&params.spender)?;
+    if spender == ctx.caller {
+        return Err(actor_error!(USR_ILLEGAL_ARGUMENT, "an account cannot approve itself"));
+    }
+    check_amount_cap(&params.amount, "allowance")?;
+
+    set_allowance(rt.store(), state, ctx.caller, spender, params.amount)
+}
+
+/// The input parameters for approving a spender and calling it in one message.
+#[derive(Serialize_tuple, Deserialize_tuple, Clone, Debug)]
+pub struct ApproveAndCallParams {
+    pub spender: Address,
+    #[serde(with = "bigint_ser")]
+    pub amount: TokenAmount,
+    /// The method to call on the spender once it is approved. It must be
+    /// the FRC-0046 receiver hook, `RECEIVER_HOOK_METHOD_NUM`.
+    pub call_method: MethodNum,
+    pub call_params: RawBytes,
+}
+
+impl Cbor for ApproveAndCallParams {}
+
+impl TokenAmounts for ApproveAndCallParams {
+    fn token_amounts(&self) -> Vec<&TokenAmount> {
+        vec![&self.amount]
+    }
+}
+
+/// Approve `spender` as `approve` would, then call its receiver hook, so it
+/// can spend the allowance straight away. Returns what the spender returned.
+/// If the call fails, so does the approval.
+///
+/// The call is made under the reentrancy lock. The only thing the spender
+/// may do with this token meanwhile is `transfer_from` the caller, drawing on
+/// the allowance it was just given.
+pub fn approve_and_call<R: Runtime>(
+    rt: &R,
+    params: ApproveAndCallParams,
+) -> Result<RawBytes, ActorError> {
+    let mut state = State::load_from(rt)?;
+    state.check_unlocked()?;
+    let resolver = |addr: &Address| rt.resolve_address(addr);
+    let ctx = runtime_context(rt, &resolver);
+    let spender = ctx.resolve(&params.spender)?;
+    if spender == rt.receiver() {
+        return Err(actor_error!(USR_ILLEGAL_ARGUMENT, "the token cannot be the spender"));
+    }
+    if params.call_method != RECEIVER_HOOK_METHOD_NUM {
+        return Err(actor_error!(
+            USR_ILLEGAL_ARGUMENT,
+            "the spender can only be called on the receiver hook method {}",
+            RECEIVER_HOOK_METHOD_NUM
+        ));
+    }
+    let approval = ApproveParams { spender: params.spender, amount: params.amount };
+    approve_in(rt, &mut state, approval)?;
+
+    // Persist the allowance with the lock before calling, so the spender can
+    // draw on it through `transfer_from` and on nothing else. That call saves
+    // its own changes, so the lock is cleared on the state as it left it.
+    state.reentrancy_locked = true;
+    state.approved_call = Some((ctx.caller, spender));
+    state.save_to(rt)?;
+    let spender = Address::new_id(spender);
+    let res = rt.send(&spender, params.call_method, params.call_params, TokenAmount::from(0));
+    let mut state = State::load_from(rt)?;
+    state.reentrancy_locked = false;
+    state.approved_call = None;
+    state.save_to(rt)?;
+
+    let receipt = res?;
+    if !receipt.exit_code.is_success() {
+        return Err(actor_error!(
+            USR_ILLEGAL_STATE,
+            "spender {} failed with exit code {:?}",
+            spender,
+            receipt.exit_code
+        ));
+    }
+    Ok(receipt.return_data)
+}
+
+/// The input parameters for transferring and approving in one message.
+#[derive(Serialize_tuple, Deserialize_tuple, Clone, Debug)]
+pub struct TransferAndApproveParams {
+    pub recipient: Address,
+    #[serde(with = "bigint_ser")]
+    pub amount: TokenAmount,
+    pub grant_spender: Address,
+    #[serde(with = "bigint_ser")]
+    pub grant_amount: TokenAmount,
+}
+
+impl Cbor for TransferAndApproveParams {}
+
+impl TokenAmounts for TransferAndApproveParams {
+    fn token_amounts(&self) -> Vec<&TokenAmount> {
+        vec![&self.amount, &self.grant_amount]
+    }
+}
+
+/// Transfer `amount` of the caller's tokens to `recipient`, as `transfer`
+/// would with no operator data, and set the caller's allowance for
+/// `grant_spender` to `grant_amount`, as `approve` would. Both land or
+/// neither does. The allowance is the caller's to grant, so an escrow that
+/// should be able to pull funds back names itself as `grant_spender`.
+pub fn transfer_and_approve<R: Runtime>(
+    rt: &R,
+    params: TransferAndApproveParams,
+) -> Result<TransferReturn, ActorError> {
+    let mut state = State::load_from(rt)?;
+    state.check_unlocked()?;
+    let transfer = Frc46TransferParams {
+        to: params.recipient,
+        amount: params.amount,
+        operator_data: RawBytes::default(),
+    };
+    let ret = transfer_in(rt, &mut state, transfer)?;
+    let approval = ApproveParams { spender: params.grant_spender, amount: params.grant_amount };
+    approve_in(rt, &mut state, approval)?;
+    state.save_to(rt)?;
+    Ok(ret)
+}
+
+/// The input parameters for raising an allowance.
+#[derive(Serialize_tuple, Deserialize_tuple, Clone, Debug)]
+pub struct IncreaseAllowanceParams {
+    pub spender: Address,
+    #[serde(with = "bigint_ser")]
+    pub increase: TokenAmount,
+}
+
+impl Cbor for IncreaseAllowanceParams {}
+
+impl TokenAmounts for IncreaseAllowanceParams {
+    fn token_amounts(&self) -> Vec<&TokenAmount> {
+        vec![&self.increase]
+    }
+}
+
+/// The input parameters for lowering an allowance.
+#[derive(Serialize_tuple, Deserialize_tuple, Clone, Debug)]
+pub struct DecreaseAllowanceParams {
+    pub spender: Address,
+    #[serde(with = "bigint_ser")]
+    pub decrease: TokenAmount,
+}
+
+impl Cbor for DecreaseAllowanceParams {}
+
+impl TokenAmounts for DecreaseAllowanceParams {
+    fn token_amounts(&self) -> Vec<&TokenAmount> {
+        vec![&self.decrease]
+    }
+}
+
+/// The input parameters for revoking an allowance.
+#[derive(Serialize_tuple, Deserialize_tuple, Clone, Debug)]
+pub struct RevokeAllowanceParams {
+    pub spender: Address,
+}
+
+impl Cbor for RevokeAllowanceParams {}
+
+/// Replace the allowance the caller has granted `spender` in `state` with
+/// `change` applied to it, returning the new allowance.
+fn change_allowance<R: Runtime>(
+    rt: &R,
+    state: &mut State,
+    spender: &Address,
+    change: impl FnOnce(TokenAmount) -> TokenAmount,
+) -> Result<TokenAmount, ActorError> {
+    let resolver = |addr: &Address| rt.resolve_address(addr);
+    let ctx = runtime_context(rt, &resolver);
+    let spender = ctx.resolve(spender)?;
+    if spender == ctx.caller {
+        return Err(actor_error!(USR_ILLEGAL_ARGUMENT, "an account cannot approve itself"));
+    }
+    let allowance = change(get_allowance(rt.store(), state, ctx.caller, spender)?);
+    check_amount_cap(&allowance, "allowance")?;
+
+    set_allowance(rt.store(), state, ctx.caller, spender, allowance.clone())?;
+    Ok(allowance)
+}
+
+/// Raise the allowance the caller has granted `spender` by `increase`, and
+/// return the new allowance. Unlike `approve`, this can't be front-run.
+pub fn increase_allowance<R: Runtime>(
+    rt: &R,
+    params: IncreaseAllowanceParams,
+) -> Result<TokenAmount, ActorError> {
+    let mut state = State::load_from(rt)?;
+    state.check_unlocked()?;
+    let allowance = increase_allowance_in(rt, &mut state, params)?;
+    state.save_to(rt)?;
+    Ok(allowance)
+}
+
+/// Raise an allowance against `state` without saving it.
+pub(crate) fn increase_allowance_in<R: Runtime>(
+    rt: &R,
+    state: &mut State,
+    params: IncreaseAllowanceParams,
+) -> Result<TokenAmount, ActorError> {
+    change_allowance(rt, state, &params.spender, |allowance| allowance + &params.increase)
+}
+
+/// Lower the allowance the caller has granted `spender` by `decrease`,
+/// stopping at zero, and return the new allowance.
+pub fn decrease_allowance<R: Runtime>(
+    rt: &R,
+    params: DecreaseAllowanceParams,
+) -> Result<TokenAmount, ActorError> {
+    let mut state = State::load_from(rt)?;
+    state.check_unlocked()?;
+    let allowance = decrease_allowance_in(rt, &mut state, params)?;
+    state.save_to(rt)?;
+    Ok(allowance)
+}
+
+/// Lower an allowance against `state` without saving it.
+pub(crate) fn decrease_allowance_in<R: Runtime>(
+    rt: &R,
+    state: &mut State,
+    params: DecreaseAllowanceParams,
+) -> Result<TokenAmount, ActorError> {
+    change_allowance(rt, state, &params.spender, |allowance| {
+        if allowance > params.decrease {
+            allowance - &params.decrease
+        } else {
+            TokenAmount::from(0)
+        }
+    })
+}
+
+/// Drop the allowance the caller has granted `spender`.
+pub fn revoke_allowance<R: Runtime>(
+    rt: &R,
+    params: RevokeAllowanceParams,
+) -> Result<(), ActorError> {
+    let mut state = State::load_from(rt)?;
+    state.check_unlocked()?;
+    revoke_allowance_in(rt, &mut state, params)?;
+    state.save_to(rt)?;
+    Ok(())
+}
+
+/// Drop an allowance against `state` without saving it.
+pub(crate) fn revoke_allowance_in<R: Runtime>(
+    rt: &R,
+    state: &mut State,
+    params: RevokeAllowanceParams,
+) -> Result<(), ActorError> {
+    change_allowance(rt, state, &params.spender, |_| TokenAmount::from(0))?;
+    Ok(())
+}
+
+/// The input parameters for setting an allowance only if it still holds the
+/// value the caller last saw.
+#[derive(Serialize_tuple, Deserialize_tuple, Clone, Debug)]
+pub struct CompareAndSetAllowanceParams {
+    pub spender: Address,
+    #[serde(with = "bigint_ser")]
+    pub expected: TokenAmount,
+    #[serde(with = "bigint_ser")]
+    pub new: TokenAmount,
+}
+
+impl Cbor for CompareAndSetAllowanceParams {}
+
+impl TokenAmounts for CompareAndSetAllowanceParams {
+    fn token_amounts(&self) -> Vec<&TokenAmount> {
+        vec![&self.expected, &self.new]
+    }
+}
+
+/// Replace the allowance the caller has granted `spender` with `new`, but
+/// only if it is still `expected`, where no allowance counts as zero.
+/// Otherwise fails as `ErrorKind::CompareFailed`, so an approval can't land
+/// on top of a spend it didn't account for.
+pub fn compare_and_set_allowance<R: Runtime>(
+    rt: &R,
+    params: CompareAndSetAllowanceParams,
+) -> Result<(), ActorError> {
+    let mut state = State::load_from(rt)?;
+    state.check_unlocked()?;
+
+    let resolver = |addr: &Address| rt.resolve_address(addr);
+    let ctx = runtime_context(rt, &resolver);
+    let spender = ctx.resolve(&params.spender)?;
+    if spender == ctx.caller {
+        return Err(actor_error!(USR_ILLEGAL_ARGUMENT, "an account cannot approve itself"));
+    }
+    let allowance = get_allowance(rt.store(), &state, ctx.caller, spender)?;
+    if allowance != params.expected {
+        return Err(actor_error!(
+            kind: CompareFailed,
+            USR_ILLEGAL_ARGUMENT,
+            "allowance is {}, not the expected {}",
+            allowance,
+            params.expected
+        ));
+    }
+    check_amount_cap(&params.new, "allowance")?;
+
+    set_allowance(rt.store(), &mut state, ctx.caller, spender, params.new)?;
+    state.save_to(rt)?;
+    Ok(())
+}
+
+/// The input parameters for querying an allowance.
+#[derive(Serialize_tuple, Deserialize_tuple, Clone, Debug)]
+pub struct AllowanceParams {
+    pub owner: Address,
+    pub spender: Address,
+}
+
+impl Cbor for AllowanceParams {}
+
+/// Return what `spender` may still move out of `owner`'s balance. Addresses
+/// that don't resolve have granted, and been granted, nothing. An operator's
+/// unlimited spending isn't reflected here; see `is_operator`.
+pub fn allowance<R: Runtime>(rt: &R, params: AllowanceParams) -> Result<TokenAmount, ActorError> {
+    let state = State::load_from(rt)?;
+    match (rt.resolve_address(&params.owner), rt.resolve_address(&params.spender)) {
+        (Some(owner), Some(spender)) => get_allowance(rt.store(), &state, owner, spender),
+        _ => Ok(TokenAmount::from(0)),
+    }
+}
+
+/// The input parameters for one page of an owner's allowances.
+#[derive(Serialize_tuple, Deserialize_tuple, Clone, Debug)]
+pub struct AllowancesOfParams {
+    pub owner: Address,
+    /// The last spender of the previous page, or None for the first page.
+    pub start_after: Option<ActorID>,
+    /// The most allowances to return.
+    pub limit: u32,
+}
+
+impl Cbor for AllowancesOfParams {}
+
+/// Return up to `limit` of the allowances `owner` has granted, as spender
+/// and amount, in ascending spender ID order, starting after the spender
+/// `start_after`. A page shorter than `limit` is the last. An owner that
+/// has granted nothing, or an address that doesn't resolve, has no pages.
+///
+/// Allowances live in one HAMT keyed by owner and spender, so every page
+/// walks all of them.
+pub fn allowances_of<R: Runtime>(
+    rt: &R,
+    params: AllowancesOfParams,
+) -> Result<Vec<(ActorID, TokenAmount)>, ActorError> {
+    if params.limit == 0 {
+        return Err(actor_error!(USR_ILLEGAL_ARGUMENT, "page limit must be positive"));
+    }
+    let state = State::load_from(rt)?;
+    let (owner, root) = match (rt.resolve_address(&params.owner), &state.allowances) {
+        (Some(owner), Some(root)) => (owner, root),
+        _ => return Ok(Vec::new()),
+    };
+    let allowances: Allowances<_> =
+        load_hamt(rt.store(), root, state.hamt_bit_width, "allowances")?;
+
+    let prefix = owner.to_be_bytes();
+    let mut granted = Vec::new();
+    allowances
+        .for_each(|key, amount| {
+            if let Some(spender) = key.0.strip_prefix(&prefix[..]) {
+                let spender = ActorID::from_be_bytes(spender.try_into()?);
+                if params.start_after.map_or(true, |after| spender > after) {
+                    granted.push((spender, amount.0.clone()));
+                }
+            }
+            Ok(())
+        })
+        .map_err(|err| actor_error!(USR_ILLEGAL_STATE, "failed to list allowances: {:?}", err))?;
+    granted.sort_by_key(|(spender, _)| *spender);
+    granted.truncate(params.limit as usize);
+    Ok(granted)
+}
+
+/// The input parameters for approving or revoking an operator.
+#[derive(Serialize_tuple, Deserialize_tuple, Clone, Debug)]
+pub struct SetOperatorParams {
+    pub operator: Address,
+    pub approved: bool,
+}
+
+impl Cbor for SetOperatorParams {}
+
+/// Approve `operator` to move any amount of the caller's tokens, or revoke
+/// an earlier approval.
+pub fn set_operator<R: Runtime>(rt: &R, params: SetOperatorParams) -> Result<(), ActorError> {
+    let mut state = State::load_from(rt)?;
+    state.check_unlocked()?;
+    set_operator_in(rt, &mut state, params)?;
+    state.save_to(rt)?;
+    Ok(())
+}
+
+/// Approve or revoke an operator against `state` without saving it.
+pub(crate) fn set_operator_in<R: Runtime>(
+    rt: &R,
+    state: &mut State,
+    params: SetOperatorParams,
+) -> Result<(), ActorError> {
+    let resolver = |addr: &Address| rt.resolve_address(addr);
+    let ctx = runtime_context(rt, &resolver);
+    let operator = ctx.resolve(&params.operator)?;
+    if operator == ctx.caller {
+        return Err(actor_error!(USR_ILLEGAL_ARGUMENT, "an account cannot be its own operator"));
+    }
+
+    let store = rt.store();
+    let mut operators: Operators<&R::Blockstore> = match &state.operators {
+        Some(root) => load_hamt(store, root, state.hamt_bit_width, "operators")?,
+        None => Hamt::new_with_bit_width(store, state.hamt_bit_width),
+    };
+    let key = pair_key(ctx.caller, operator);
+    if params.approved {
+        operators.set(key, ()).map_err(|err| {
+            actor_error!(USR_ILLEGAL_STATE, "failed to set operator entry: {:?}", err)
+        })?;
+    } else {
+        operators.delete(&key).map_err(|err| {
+            actor_error!(USR_ILLEGAL_STATE, "failed to delete operator entry: {:?}", err)
+        })?;
+    }
+    let root = operators
+        .flush()
+        .map_err(|err| actor_error!(USR_ILLEGAL_STATE, "failed to flush operators: {:?}", err))?;
+    state.operators = Some(root);
+    Ok(())
+}
+
+/// The input parameters for asking whether `operator` may act for `owner`.
+#[derive(Serialize_tuple, Deserialize_tuple, Clone, Debug)]
+pub struct IsOperatorParams {
+    pub owner: Address,
+    pub operator: Address,
+}
+
+impl Cbor for IsOperatorParams {}
+
+/// Returns true if `owner` has approved `operator`. Addresses that don't
+/// resolve have approved, and been approved by, no one.
+pub fn is_operator<R: Runtime>(rt: &R, params: IsOperatorParams) -> Result<bool, ActorError> {
+    let state = State::load_from(rt)?;
+    let (owner, operator) =
+        match (rt.resolve_address(&params.owner), rt.resolve_address(&params.operator)) {
+            (Some(owner), Some(operator)) => (owner, operator),
+            _ => return Ok(false),
+        };
+    operator_approved(rt.store(), &state, owner, operator)
+}
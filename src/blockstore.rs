@@ -1,12 +1,15 @@
+use std::cell::RefCell;
+use std::collections::HashMap;
 use std::convert::TryFrom;
 
 use anyhow::{anyhow, Result};
-use cid::multihash::Code;
+use cid::multihash::{Code, MultihashDigest};
 use cid::Cid;
-use fvm_ipld_blockstore::Block;
+use fvm_ipld_blockstore::{Block, Blockstore as _};
 use fvm_sdk as sdk;
 
 /// A blockstore that delegates to IPLD syscalls.
+#[derive(Default)]
 pub struct Blockstore;
 
 impl fvm_ipld_blockstore::Blockstore for Blockstore {
@@ -38,3 +41,177 @@ impl fvm_ipld_blockstore::Blockstore for Blockstore {
         Ok(k)
     }
 }
+
+/// A write-back cache in front of another blockstore, by default the
+/// syscall-backed [`Blockstore`].
+///
+/// Reads are memoized, and writes are held in memory until
+/// [`CachingBlockstore::flush`] is called, so loading the same HAMT node
+/// several times within one invocation costs a single `ipld::get` syscall,
+/// and a block written several times costs a single `ipld::put`. CIDs for
+/// buffered writes are computed locally, which lets the HAMT link nodes
+/// before they have been handed to the runtime.
+///
+/// Buffered blocks are invisible to the runtime until flushed, so `flush` must
+/// run before any CID referencing them is passed to `sself::set_root`.
+#[derive(Default)]
+pub struct CachingBlockstore<BS = Blockstore> {
+    inner: BS,
+    cache: RefCell<HashMap<Cid, Vec<u8>>>,
+    dirty: RefCell<Vec<Cid>>,
+}
+
+impl<BS: fvm_ipld_blockstore::Blockstore> CachingBlockstore<BS> {
+    /// A cache in front of `inner`.
+    pub fn new(inner: BS) -> Self {
+        CachingBlockstore {
+            inner,
+            cache: RefCell::new(HashMap::new()),
+            dirty: RefCell::new(Vec::new()),
+        }
+    }
+
+    /// Write all buffered blocks through to the inner blockstore.
+    pub fn flush(&self) -> Result<()> {
+        let cache = self.cache.borrow();
+        for k in self.dirty.borrow_mut().drain(..) {
+            let block = cache
+                .get(&k)
+                .ok_or_else(|| anyhow!("buffered block {} missing from cache", k))?;
+            self.inner.put_keyed(&k, block)?;
+        }
+        Ok(())
+    }
+}
+
+impl<BS: fvm_ipld_blockstore::Blockstore> fvm_ipld_blockstore::Blockstore
+    for CachingBlockstore<BS>
+{
+    fn get(&self, cid: &Cid) -> Result<Option<Vec<u8>>> {
+        if let Some(block) = self.cache.borrow().get(cid) {
+            return Ok(Some(block.clone()));
+        }
+        let block = self.inner.get(cid)?;
+        if let Some(block) = &block {
+            self.cache.borrow_mut().insert(*cid, block.clone());
+        }
+        Ok(block)
+    }
+
+    fn put_keyed(&self, k: &Cid, block: &[u8]) -> Result<()> {
+        // Blocks are content addressed, so one that's already cached (whether
+        // read or written) never needs writing again.
+        if self.cache.borrow_mut().insert(*k, block.to_vec()).is_none() {
+            self.dirty.borrow_mut().push(*k);
+        }
+        Ok(())
+    }
+
+    fn put<D>(&self, code: Code, block: &Block<D>) -> Result<Cid>
+    where
+        D: AsRef<[u8]>,
+    {
+        let k = Cid::new_v1(block.codec, code.digest(block.data.as_ref()));
+        self.put_keyed(&k, block.data.as_ref())?;
+        Ok(k)
+    }
+}
+
+/// A blockstore backed by an in-memory map, for running the token logic in
+/// unit tests without a WASM runtime. It counts reads and writes, standing in
+/// for the `ipld::get` and `ipld::put` syscalls a real store would make.
+#[cfg(test)]
+#[derive(Default)]
+pub struct MemoryBlockstore {
+    blocks: RefCell<HashMap<Cid, Vec<u8>>>,
+    pub gets: std::cell::Cell<usize>,
+    pub puts: std::cell::Cell<usize>,
+}
+
+#[cfg(test)]
+impl fvm_ipld_blockstore::Blockstore for MemoryBlockstore {
+    fn get(&self, cid: &Cid) -> Result<Option<Vec<u8>>> {
+        self.gets.set(self.gets.get() + 1);
+        Ok(self.blocks.borrow().get(cid).cloned())
+    }
+
+    fn put_keyed(&self, k: &Cid, block: &[u8]) -> Result<()> {
+        self.puts.set(self.puts.get() + 1);
+        self.blocks.borrow_mut().insert(*k, block.to_vec());
+        Ok(())
+    }
+
+    fn put<D>(&self, code: Code, block: &Block<D>) -> Result<Cid>
+    where
+        D: AsRef<[u8]>,
+    {
+        let k = Cid::new_v1(block.codec, code.digest(block.data.as_ref()));
+        self.put_keyed(&k, block.data.as_ref())?;
+        Ok(k)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use fvm_ipld_blockstore::Blockstore as _;
+    use fvm_ipld_encoding::DAG_CBOR;
+    use fvm_ipld_hamt::Hamt;
+
+    /// Insert `count` entries into a HAMT, flushing after every insert the way
+    /// a run of single mints would, and return the final root.
+    fn fill_hamt<BS: fvm_ipld_blockstore::Blockstore>(store: &BS, count: u64) -> Cid {
+        let mut hamt: Hamt<&BS, u64, u64> = Hamt::new(store);
+        let mut root = hamt.flush().unwrap();
+        for i in 0..count {
+            hamt = Hamt::load(&root, store).unwrap();
+            hamt.set(i, i).unwrap();
+            root = hamt.flush().unwrap();
+            // Re-flushing an unchanged HAMT rewrites the same root block.
+            hamt.flush().unwrap();
+        }
+        root
+    }
+
+    #[test]
+    fn buffered_writes_reach_inner_store_on_flush() {
+        let store = CachingBlockstore::new(MemoryBlockstore::default());
+        let block = Block::new(DAG_CBOR, b"\x01".as_ref());
+        let cid = store.put(Code::Blake2b256, &block).unwrap();
+
+        assert_eq!(store.inner.puts.get(), 0);
+        assert_eq!(store.get(&cid).unwrap().as_deref(), Some(b"\x01".as_ref()));
+        assert_eq!(store.inner.get(&cid).unwrap(), None);
+
+        store.flush().unwrap();
+        assert_eq!(store.inner.get(&cid).unwrap().as_deref(), Some(b"\x01".as_ref()));
+    }
+
+    #[test]
+    fn repeated_gets_hit_the_cache() {
+        let inner = MemoryBlockstore::default();
+        let cid = inner.put(Code::Blake2b256, &Block::new(DAG_CBOR, b"\x01".as_ref())).unwrap();
+        let store = CachingBlockstore::new(inner);
+
+        assert_eq!(store.get(&cid).unwrap().as_deref(), Some(b"\x01".as_ref()));
+        assert_eq!(store.inner.gets.get(), 1);
+        assert_eq!(store.get(&cid).unwrap().as_deref(), Some(b"\x01".as_ref()));
+        assert_eq!(store.inner.gets.get(), 1);
+    }
+
+    #[test]
+    fn flush_writes_each_distinct_block_once() {
+        let direct = MemoryBlockstore::default();
+        let direct_root = fill_hamt(&direct, 200);
+
+        let cached = CachingBlockstore::new(MemoryBlockstore::default());
+        let cached_root = fill_hamt(&cached, 200);
+        assert_eq!(cached.inner.puts.get(), 0);
+        cached.flush().unwrap();
+
+        assert_eq!(cached_root, direct_root);
+        assert!(cached.inner.puts.get() < direct.puts.get());
+        let hamt: Hamt<_, u64, u64> = Hamt::load(&cached_root, &cached.inner).unwrap();
+        assert_eq!(hamt.get(&199).unwrap(), Some(&199));
+    }
+}
@@ -0,0 +1,261 @@
+//! Claims: a fixed amount anyone may take from a pool, once.
+//!
+//! The owner sets the `ClaimTerms` with `set_claim_terms`: the account the
+//! tokens come from and how much each claim pays. Every account may then
+//! `claim` once, until the pool runs dry or the terms' deadline passes, after
+//! which the owner can take back what is left with `reclaim_unclaimed`.
+
+use fvm_ipld_encoding::tuple::{Deserialize_tuple, Serialize_tuple};
+use fvm_ipld_encoding::Cbor;
+use fvm_ipld_hamt::Hamt;
+use fvm_shared::address::Address;
+use fvm_shared::bigint::bigint_ser;
+use fvm_shared::clock::ChainEpoch;
+use fvm_shared::econ::TokenAmount;
+use fvm_shared::ActorID;
+
+use crate::error::ActorError;
+use crate::runtime::Runtime;
+use crate::{
+    get_balance, load_balances, load_hamt, move_tokens, record_transfer, runtime_context, Balances,
+    State, TokenAmounts, TransferRecord,
+};
+
+/// What each claim pays out, and the account it is paid from.
+#[derive(Serialize_tuple, Deserialize_tuple, Clone, Debug, PartialEq)]
+pub struct ClaimTerms {
+    pub pool: ActorID,
+    #[serde(with = "bigint_ser")]
+    pub amount: TokenAmount,
+}
+
+impl Cbor for ClaimTerms {}
+
+/// The set of accounts that have claimed.
+pub type ClaimedSet<BS> = Hamt<BS, (), ActorID>;
+
+/// The input parameters for setting what a claim pays out.
+#[derive(Serialize_tuple, Deserialize_tuple, Clone, Debug)]
+pub struct SetClaimTermsParams {
+    pub pool: Address,
+    #[serde(with = "bigint_ser")]
+    pub amount: TokenAmount,
+    /// The epoch claiming ends at, after which the owner can reclaim what is
+    /// left with `reclaim_unclaimed`. None lets claims go on indefinitely.
+    pub deadline: Option<ChainEpoch>,
+}
+
+impl Cbor for SetClaimTermsParams {}
+
+impl TokenAmounts for SetClaimTermsParams {
+    fn token_amounts(&self) -> Vec<&TokenAmount> {
+        vec![&self.amount]
+    }
+}
+
+/// Let anyone claim `amount` from `pool`, once per account, until `deadline`
+/// if there is one. Only the owner may call this. A zero amount turns
+/// claiming off. Changing the terms doesn't let accounts that have already
+/// claimed claim again.
+pub fn set_claim_terms<R: Runtime>(rt: &R, params: SetClaimTermsParams) -> Result<(), ActorError> {
+    let mut state = State::load_from(rt)?;
+    state.check_unlocked()?;
+
+    let resolver = |addr: &Address| rt.resolve_address(addr);
+    let ctx = runtime_context(rt, &resolver);
+    ctx.require_owner(&state, "set_claim_terms")?;
+    if let Some(deadline) = params.deadline {
+        if deadline <= rt.curr_epoch() {
+            return Err(actor_error!(
+                USR_ILLEGAL_ARGUMENT,
+                "claim deadline {} is not after the current epoch {}",
+                deadline,
+                rt.curr_epoch()
+            ));
+        }
+    }
+
+    if params.amount == TokenAmount::from(0) {
+        state.claim_terms = None;
+        state.claim_deadline = None;
+    } else {
+        let pool = ctx.resolve(&params.pool)?;
+        state.claim_terms = Some(ClaimTerms { pool, amount: params.amount });
+        state.claim_deadline = params.deadline;
+    }
+    state.save_to(rt)?;
+    Ok(())
+}
+
+/// Claim the amount the claim terms pay out, moving it from the pool to the
+/// caller. Anyone may claim, but only once, and only while claiming is on
+/// and its deadline hasn't passed. A pool without enough left fails with
+/// USR_INSUFFICIENT_FUNDS.
+pub fn claim<R: Runtime>(rt: &R) -> Result<(), ActorError> {
+    let mut state = State::load_from(rt)?;
+    state.check_unlocked()?;
+
+    let terms = match &state.claim_terms {
+        Some(terms) => terms.clone(),
+        None => {
+            return Err(actor_error!(kind: Forbidden, USR_FORBIDDEN, "claiming is not enabled"))
+        }
+    };
+    if claim_expired(&state, rt.curr_epoch()) {
+        return Err(actor_error!(
+            kind: Forbidden,
+            USR_FORBIDDEN,
+            "claiming ended at epoch {:?}",
+            state.claim_deadline
+        ));
+    }
+
+    let resolver = |addr: &Address| rt.resolve_address(addr);
+    let ctx = runtime_context(rt, &resolver);
+    let store = rt.store();
+    let mut claimed: ClaimedSet<&R::Blockstore> = match &state.claimed {
+        Some(root) => load_hamt(store, root, state.hamt_bit_width, "claimed accounts")?,
+        None => Hamt::new_with_bit_width(store, state.hamt_bit_width),
+    };
+    let already = claimed
+        .get(&ctx.caller)
+        .map_err(|err| actor_error!(USR_ILLEGAL_STATE, "failed to get claimed entry: {:?}", err))?;
+    if already.is_some() {
+        return Err(actor_error!(
+            kind: Forbidden,
+            USR_FORBIDDEN,
+            "account {} has already claimed",
+            ctx.caller
+        ));
+    }
+
+    move_tokens(&ctx, &mut state, terms.pool, ctx.caller, &terms.amount)?;
+    claimed
+        .set(ctx.caller, ())
+        .map_err(|err| actor_error!(USR_ILLEGAL_STATE, "failed to set claimed entry: {:?}", err))?;
+    let root = claimed.flush().map_err(|err| {
+        actor_error!(USR_ILLEGAL_STATE, "failed to flush claimed accounts: {:?}", err)
+    })?;
+    state.claimed = Some(root);
+    record_transfer(
+        store,
+        &mut state,
+        TransferRecord {
+            from: terms.pool,
+            to: ctx.caller,
+            amount: terms.amount,
+            epoch: rt.curr_epoch(),
+        },
+    )?;
+    state.save_to(rt)?;
+    Ok(())
+}
+
+/// Returns true if the claim terms have a deadline, and it has passed.
+fn claim_expired(state: &State, epoch: ChainEpoch) -> bool {
+    matches!(state.claim_deadline, Some(deadline) if epoch >= deadline)
+}
+
+/// Once the claim deadline has passed, move whatever is left in the pool to
+/// the owner and turn claiming off, returning the amount moved. Only the
+/// owner may call this, and only after a deadline: claims without one never
+/// expire.
+pub fn reclaim_unclaimed<R: Runtime>(rt: &R) -> Result<TokenAmount, ActorError> {
+    let mut state = State::load_from(rt)?;
+    state.check_unlocked()?;
+
+    let resolver = |addr: &Address| rt.resolve_address(addr);
+    let ctx = runtime_context(rt, &resolver);
+    ctx.require_owner(&state, "reclaim_unclaimed")?;
+    let terms = match &state.claim_terms {
+        Some(terms) if claim_expired(&state, rt.curr_epoch()) => terms.clone(),
+        _ => {
+            return Err(actor_error!(
+                kind: Forbidden,
+                USR_FORBIDDEN,
+                "there is no expired claim period to reclaim"
+            ))
+        }
+    };
+
+    let owner = ctx.owner_id(&state)?;
+    let balances = load_balances(ctx.store, &state.balances, state.hamt_bit_width)?;
+    let left = get_balance(&balances, terms.pool)?;
+    let zero = TokenAmount::from(0);
+    // A pool the owner holds is already the owner's.
+    let moved = if terms.pool == owner { zero.clone() } else { left };
+    if moved > zero {
+        move_tokens(&ctx, &mut state, terms.pool, owner, &moved)?;
+        record_transfer(
+            ctx.store,
+            &mut state,
+            TransferRecord {
+                from: terms.pool,
+                to: owner,
+                amount: moved.clone(),
+                epoch: rt.curr_epoch(),
+            },
+        )?;
+    }
+    state.claim_terms = None;
+    state.claim_deadline = None;
+    state.save_to(rt)?;
+    Ok(moved)
+}
+
+/// The input parameters for a balance query that counts pending claims.
+#[derive(Serialize_tuple, Deserialize_tuple, Clone, Debug)]
+pub struct BalanceIncludingPendingParams {
+    pub account: Address,
+}
+
+impl Cbor for BalanceIncludingPendingParams {}
+
+/// An account's balance plus the claim it could still make, for display.
+/// This is not what the account can spend: `balance_of` is, and leaves the
+/// claim out until it is made. A claim counts while claiming is on and its
+/// deadline hasn't passed, if the account hasn't claimed yet and the pool
+/// can cover it.
+pub fn balance_including_pending<R: Runtime>(
+    rt: &R,
+    params: BalanceIncludingPendingParams,
+) -> Result<TokenAmount, ActorError> {
+    let state = State::load_from(rt)?;
+    let balances = load_balances(rt.store(), &state.balances, state.hamt_bit_width)?;
+    // An address that doesn't resolve has no balance, and hasn't claimed.
+    let id = rt.resolve_address(&params.account);
+    let settled = match id {
+        Some(id) => get_balance(&balances, id)?,
+        None => TokenAmount::from(0),
+    };
+    Ok(settled + pending_claim(rt.store(), &state, &balances, id, rt.curr_epoch())?)
+}
+
+/// What `id` could still claim under the current claim terms, or zero.
+fn pending_claim<BS: fvm_ipld_blockstore::Blockstore>(
+    store: &BS,
+    state: &State,
+    balances: &Balances<&BS>,
+    id: Option<ActorID>,
+    epoch: ChainEpoch,
+) -> Result<TokenAmount, ActorError> {
+    let zero = TokenAmount::from(0);
+    let terms = match &state.claim_terms {
+        Some(terms) if !claim_expired(state, epoch) => terms,
+        _ => return Ok(zero),
+    };
+    if id == Some(terms.pool) || get_balance(balances, terms.pool)? < terms.amount {
+        return Ok(zero);
+    }
+    if let (Some(id), Some(root)) = (id, &state.claimed) {
+        let claimed: ClaimedSet<&BS> =
+            load_hamt(store, root, state.hamt_bit_width, "claimed accounts")?;
+        let entry = claimed.get(&id).map_err(|err| {
+            actor_error!(USR_ILLEGAL_STATE, "failed to get claimed entry: {:?}", err)
+        })?;
+        if entry.is_some() {
+            return Ok(zero);
+        }
+    }
+    Ok(terms.amount.clone())
+}
@@ -0,0 +1,15 @@
+//! Well-known actor addresses.
+
+use fvm_shared::ActorID;
+
+/// The init actor, which runs the constructor of every actor created through
+/// InitActor#Exec. This should be part of the SDK.
+pub const INIT_ACTOR_ADDR: ActorID = 1;
+
+/// The Ethereum Address Manager, which runs the constructor of actors
+/// deployed through the EVM-compatible path instead of the init actor.
+pub const EAM_ACTOR_ADDR: ActorID = 10;
+
+/// The actors allowed to call the constructor. Each of them only does so
+/// while creating a new actor, so no one can re-run it on a live token.
+pub const CONSTRUCTOR_CALLERS: &[ActorID] = &[INIT_ACTOR_ADDR, EAM_ACTOR_ADDR];
@@ -0,0 +1,123 @@
+use fvm_shared::error::ExitCode;
+
+/// An error that aborts the current method with `exit_code`.
+///
+/// The core token logic returns these instead of aborting directly, so it can
+/// run (and fail) outside the FVM. The WASM entrypoint turns them into real
+/// aborts with [`or_abort`].
+#[derive(Clone, Debug, PartialEq)]
+pub struct ActorError {
+    pub exit_code: ExitCode,
+    pub msg: String,
+}
+
+impl ActorError {
+    /// Tag the error with a kind, by prefixing its message with
+    /// `[kind=<subcode>] `.
+    pub fn with_kind(mut self, kind: ErrorKind) -> Self {
+        self.msg = format!("[kind={}] {}", kind.subcode(), self.msg);
+        self
+    }
+
+    /// The kind the error was tagged with, if any.
+    pub fn kind(&self) -> Option<ErrorKind> {
+        let rest = self.msg.strip_prefix("[kind=")?;
+        let (subcode, _) = rest.split_once("] ")?;
+        ErrorKind::from_subcode(subcode.parse().ok()?)
+    }
+
+    /// Abort the current method with this error.
+    pub fn abort(self) -> ! {
+        fvm_sdk::vm::abort(self.exit_code.value(), Some(self.msg.as_str()))
+    }
+}
+
+/// Unwrap the result, aborting the current method on error.
+pub fn or_abort<T>(res: Result<T, ActorError>) -> T {
+    match res {
+        Ok(v) => v,
+        Err(err) => err.abort(),
+    }
+}
+
+/// What went wrong, as a stable numeric subcode that clients can branch on
+/// without parsing free text. An error of a given kind still carries its
+/// usual exit code; the subcode is prefixed to the abort message as
+/// `[kind=<subcode>] `.
+///
+/// | Subcode | Kind                  | Exit code              |
+/// |---------|-----------------------|------------------------|
+/// | 1       | InsufficientBalance   | USR_INSUFFICIENT_FUNDS |
+/// | 2       | InsufficientAllowance | USR_INSUFFICIENT_FUNDS |
+/// | 3       | Forbidden             | USR_FORBIDDEN          |
+/// | 4       | Paused                | USR_FORBIDDEN          |
+/// | 5       | Frozen                | USR_FORBIDDEN          |
+/// | 6       | InvalidAmount         | USR_ILLEGAL_ARGUMENT   |
+/// | 7       | ReceiverRejected      | USR_FORBIDDEN          |
+/// | 8       | RateLimited           | USR_FORBIDDEN          |
+/// | 9       | MissingStateRoot      | USR_ILLEGAL_STATE      |
+/// | 10      | CorruptHamt           | USR_ILLEGAL_STATE      |
+/// | 11      | HamtBitWidthMismatch  | USR_ILLEGAL_STATE      |
+/// | 12      | DuplicateMessage      | USR_ILLEGAL_ARGUMENT   |
+/// | 13      | Locked                | USR_INSUFFICIENT_FUNDS |
+/// | 14      | ScheduleExceeded      | USR_FORBIDDEN          |
+/// | 15      | HolderCapReached      | USR_FORBIDDEN          |
+/// | 16      | CompareFailed         | USR_ILLEGAL_ARGUMENT   |
+///
+/// Subcodes are never reused or renumbered.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ErrorKind {
+    InsufficientBalance = 1,
+    InsufficientAllowance = 2,
+    Forbidden = 3,
+    Paused = 4,
+    Frozen = 5,
+    InvalidAmount = 6,
+    ReceiverRejected = 7,
+    RateLimited = 8,
+    /// The actor has no state root, or the block it names isn't stored.
+    MissingStateRoot = 9,
+    /// A HAMT root block is missing or isn't a HAMT node.
+    CorruptHamt = 10,
+    /// A HAMT was written with a wider bit width than the state records.
+    HamtBitWidthMismatch = 11,
+    /// A mint or transfer reused an idempotency key that is still remembered.
+    DuplicateMessage = 12,
+    /// A transfer would spend tokens that are locked until a later epoch.
+    Locked = 13,
+    /// A mint falls outside every open window of the mint schedule, or would
+    /// exceed what they allow.
+    ScheduleExceeded = 14,
+    /// A mint or transfer would add a holder beyond `State::max_holders`.
+    HolderCapReached = 15,
+    /// A compare-and-set found a value other than the one it expected.
+    CompareFailed = 16,
+}
+
+impl ErrorKind {
+    pub fn subcode(self) -> u32 {
+        self as u32
+    }
+
+    pub fn from_subcode(subcode: u32) -> Option<Self> {
+        match subcode {
+            1 => Some(ErrorKind::InsufficientBalance),
+            2 => Some(ErrorKind::InsufficientAllowance),
+            3 => Some(ErrorKind::Forbidden),
+            4 => Some(ErrorKind::Paused),
+            5 => Some(ErrorKind::Frozen),
+            6 => Some(ErrorKind::InvalidAmount),
+            7 => Some(ErrorKind::ReceiverRejected),
+            8 => Some(ErrorKind::RateLimited),
+            9 => Some(ErrorKind::MissingStateRoot),
+            10 => Some(ErrorKind::CorruptHamt),
+            11 => Some(ErrorKind::HamtBitWidthMismatch),
+            12 => Some(ErrorKind::DuplicateMessage),
+            13 => Some(ErrorKind::Locked),
+            14 => Some(ErrorKind::ScheduleExceeded),
+            15 => Some(ErrorKind::HolderCapReached),
+            16 => Some(ErrorKind::CompareFailed),
+            _ => None,
+        }
+    }
+}
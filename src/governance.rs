@@ -0,0 +1,568 @@
+//! Weighted multi-owner governance.
+//!
+//! Once governance is configured the single `owner` no longer authorizes
+//! privileged methods. Instead a voter proposes a call to one of the
+//! [`PROPOSABLE_METHODS`] (a method number and its params), voters approve
+//! it, and once the approving weight reaches the threshold any voter can
+//! execute it. Execution is a send from the actor to itself, which
+//! `Context::require_owner` accepts only while `State::executing_proposal`
+//! is set.
+
+use cid::Cid;
+use fvm_ipld_encoding::tuple::{Deserialize_tuple, Serialize_tuple};
+use fvm_ipld_encoding::{Cbor, RawBytes};
+use fvm_ipld_hamt::Hamt;
+use fvm_shared::address::Address;
+use fvm_shared::econ::TokenAmount;
+use fvm_shared::{ActorID, MethodNum};
+
+use crate::error::ActorError;
+use crate::runtime::Runtime;
+use crate::{load_hamt, method, runtime_context, Context, State, DEFAULT_HAMT_BIT_WIDTH};
+
+/// The methods a proposal may call: the ones that check for the owner, or
+/// for a role the owner also holds. Anything else would run with the
+/// actor's own identity, e.g. a `Transfer` of tokens the actor holds, so it
+/// can't be proposed.
+pub const PROPOSABLE_METHODS: &[MethodNum] = &[
+    method::MINT,
+    method::MINT_BATCH,
+    method::MINT_IDEMPOTENT,
+    method::SET_MAX_SUPPLY,
+    method::SPLIT_BALANCE,
+    method::SET_UNIT_RATE,
+    method::CONFIGURE_GOVERNANCE,
+    method::MIGRATE,
+    method::SNAPSHOT,
+    method::SET_METADATA_URI,
+    method::FORCE_TRANSFER,
+    method::SET_RATE_LIMIT,
+    method::TRANSFER_OWNERSHIP,
+    method::CANCEL_OWNERSHIP_TRANSFER,
+    method::SET_TRANSFER_FEE,
+    method::RESCUE,
+    method::RECOMPUTE_TOTAL_SUPPLY,
+    method::SET_MIN_TRANSFER,
+    method::SET_HOOK_ALLOWED,
+    method::LOCK,
+    method::SET_MINT_SCHEDULE,
+    method::SET_CLAIM_TERMS,
+    method::RECLAIM_UNCLAIMED,
+    method::SET_MAX_HOLDERS,
+    method::RENOUNCE_OWNERSHIP,
+    method::GRANT_ROLE,
+    method::REVOKE_ROLE,
+    method::PAUSE,
+    method::UNPAUSE,
+    method::FREEZE_ACCOUNT,
+    method::UNFREEZE_ACCOUNT,
+];
+
+/// A governance participant and the weight of its vote.
+#[derive(Serialize_tuple, Deserialize_tuple, Clone, Debug, PartialEq)]
+pub struct Voter {
+    pub id: ActorID,
+    pub weight: u64,
+}
+
+/// The governance configuration stored in `State`.
+#[derive(Serialize_tuple, Deserialize_tuple, Clone, Debug)]
+pub struct Governance {
+    pub voters: Vec<Voter>,
+    /// The approving weight a proposal needs before it can be executed.
+    pub threshold: u64,
+    /// A HAMT of proposal ID to `Proposal`.
+    pub proposals: Cid,
+    pub next_proposal_id: u64,
+}
+
+impl Governance {
+    /// The weight of a voter, or None if the actor isn't a voter.
+    pub fn weight_of(&self, id: ActorID) -> Option<u64> {
+        self.voters.iter().find(|v| v.id == id).map(|v| v.weight)
+    }
+
+    /// The total weight of the current voters that approved a proposal.
+    /// Approvals from actors that have since been removed don't count. The sum
+    /// saturates, though `validate_config` already keeps the total weight
+    /// within a u64.
+    pub fn approved_weight(&self, proposal: &Proposal) -> u64 {
+        proposal.approvals.iter().filter_map(|id| self.weight_of(*id)).fold(0, u64::saturating_add)
+    }
+}
+
+/// A privileged call waiting for enough approvals.
+#[derive(Serialize_tuple, Deserialize_tuple, Clone, Debug)]
+pub struct Proposal {
+    pub proposer: ActorID,
+    pub method: MethodNum,
+    pub params: RawBytes,
+    pub approvals: Vec<ActorID>,
+    pub executed: bool,
+}
+
+pub type Proposals<BS> = Hamt<BS, Proposal, u64>;
+
+/// The input parameters for configuring governance.
+#[derive(Serialize_tuple, Deserialize_tuple, Clone, Debug)]
+pub struct ConfigureGovernanceParams {
+    pub voters: Vec<Voter>,
+    pub threshold: u64,
+}
+
+impl Cbor for ConfigureGovernanceParams {}
+
+/// The input parameters for proposing a privileged call.
+#[derive(Serialize_tuple, Deserialize_tuple, Clone, Debug)]
+pub struct ProposeParams {
+    pub method: MethodNum,
+    pub params: RawBytes,
+}
+
+impl Cbor for ProposeParams {}
+
+/// The input parameters for voting on or executing a proposal.
+#[derive(Serialize_tuple, Deserialize_tuple, Clone, Debug)]
+pub struct ProposalIdParams {
+    pub id: u64,
+}
+
+impl Cbor for ProposalIdParams {}
+
+pub(crate) fn governance(state: &State) -> Result<&Governance, ActorError> {
+    state
+        .governance
+        .as_ref()
+        .ok_or_else(|| actor_error!(USR_ILLEGAL_STATE, "governance is not configured"))
+}
+
+pub(crate) fn load_proposals<BS: fvm_ipld_blockstore::Blockstore>(
+    store: &BS,
+    root: &Cid,
+) -> Result<Proposals<&BS>, ActorError> {
+    // Proposals are stored with the HAMT's default bit width.
+    load_hamt(store, root, DEFAULT_HAMT_BIT_WIDTH, "proposals")
+}
+
+pub(crate) fn get_proposal<BS: fvm_ipld_blockstore::Blockstore>(
+    proposals: &Proposals<BS>,
+    id: u64,
+) -> Result<Proposal, ActorError> {
+    match proposals.get(&id) {
+        Ok(Some(proposal)) => Ok(proposal.clone()),
+        Ok(None) => Err(actor_error!(USR_NOT_FOUND, "no proposal with id {}", id)),
+        Err(err) => {
+            Err(actor_error!(USR_ILLEGAL_STATE, "failed to get proposal {}: {:?}", id, err))
+        }
+    }
+}
+
+fn put_proposal<BS: fvm_ipld_blockstore::Blockstore>(
+    state: &mut State,
+    mut proposals: Proposals<BS>,
+    id: u64,
+    proposal: Proposal,
+) -> Result<(), ActorError> {
+    if let Err(err) = proposals.set(id, proposal) {
+        return Err(actor_error!(USR_ILLEGAL_STATE, "failed to set proposal {}: {:?}", id, err));
+    }
+    let root = proposals.flush().map_err(|err| {
+        actor_error!(USR_ILLEGAL_STATE, "failed to flush proposals hamt: {:?}", err)
+    })?;
+    if let Some(gov) = state.governance.as_mut() {
+        gov.proposals = root;
+    }
+    Ok(())
+}
+
+fn require_proposable(method: MethodNum) -> Result<(), ActorError> {
+    if !PROPOSABLE_METHODS.contains(&method) {
+        return Err(actor_error!(
+            USR_ILLEGAL_ARGUMENT,
+            "method {} is not a privileged method and can't be proposed",
+            method
+        ));
+    }
+    Ok(())
+}
+
+fn require_voter(gov: &Governance, caller: ActorID) -> Result<(), ActorError> {
+    if gov.weight_of(caller).is_none() {
+        return Err(actor_error!(
+            kind: Forbidden,
+            USR_FORBIDDEN,
+            "caller {} is not a governance voter",
+            caller
+        ));
+    }
+    Ok(())
+}
+
+/// Check a governance configuration: at least one voter, no zero weights or
+/// duplicates, a total weight that fits in a u64, and a threshold the voters
+/// can reach.
+pub(crate) fn validate_config(params: &ConfigureGovernanceParams) -> Result<(), ActorError> {
+    if params.voters.is_empty() {
+        return Err(actor_error!(USR_ILLEGAL_ARGUMENT, "governance needs at least one voter"));
+    }
+    for (i, voter) in params.voters.iter().enumerate() {
+        if voter.weight == 0 {
+            return Err(actor_error!(USR_ILLEGAL_ARGUMENT, "voter {} has zero weight", voter.id));
+        }
+        if params.voters[..i].iter().any(|v| v.id == voter.id) {
+            return Err(actor_error!(USR_ILLEGAL_ARGUMENT, "voter {} listed twice", voter.id));
+        }
+    }
+    let total = match params.voters.iter().map(|v| v.weight).try_fold(0, u64::checked_add) {
+        Some(total) => total,
+        None => {
+            return Err(actor_error!(USR_ILLEGAL_ARGUMENT, "the voters' total weight overflows"))
+        }
+    };
+    if params.threshold == 0 || params.threshold > total {
+        return Err(actor_error!(
+            USR_ILLEGAL_ARGUMENT,
+            "threshold {} must be between 1 and the total weight {}",
+            params.threshold,
+            total
+        ));
+    }
+    Ok(())
+}
+
+/// Replace the voter set and threshold. Before governance is on this is an
+/// owner call; afterwards it can only happen through an executed proposal.
+/// Existing proposals are kept, but they're counted against the new weights.
+pub fn configure_governance_in<BS: fvm_ipld_blockstore::Blockstore>(
+    ctx: &Context<BS>,
+    state: &mut State,
+    params: ConfigureGovernanceParams,
+) -> Result<(), ActorError> {
+    ctx.require_owner(state, "configure_governance")?;
+
+    validate_config(&params)?;
+
+    state.governance = Some(match state.governance.take() {
+        Some(gov) => Governance { voters: params.voters, threshold: params.threshold, ..gov },
+        None => {
+            let mut proposals: Proposals<&BS> = Hamt::new(ctx.store);
+            let proposals = proposals.flush().map_err(|err| {
+                actor_error!(USR_ILLEGAL_STATE, "failed to create proposals hamt: {:?}", err)
+            })?;
+            Governance {
+                voters: params.voters,
+                threshold: params.threshold,
+                proposals,
+                next_proposal_id: 0,
+            }
+        }
+    });
+    Ok(())
+}
+
+/// Record a new proposal, approved by its proposer, and return its ID.
+pub fn propose_in<BS: fvm_ipld_blockstore::Blockstore>(
+    ctx: &Context<BS>,
+    state: &mut State,
+    params: ProposeParams,
+) -> Result<u64, ActorError> {
+    let gov = governance(state)?;
+    require_voter(gov, ctx.caller)?;
+    require_proposable(params.method)?;
+
+    let id = gov.next_proposal_id;
+    let proposals = load_proposals(ctx.store, &gov.proposals)?;
+    let proposal = Proposal {
+        proposer: ctx.caller,
+        method: params.method,
+        params: params.params,
+        approvals: vec![ctx.caller],
+        executed: false,
+    };
+    put_proposal(state, proposals, id, proposal)?;
+
+    if let Some(gov) = state.governance.as_mut() {
+        gov.next_proposal_id += 1;
+    }
+    Ok(id)
+}
+
+/// Add the caller's approval to a pending proposal.
+pub fn vote_in<BS: fvm_ipld_blockstore::Blockstore>(
+    ctx: &Context<BS>,
+    state: &mut State,
+    params: ProposalIdParams,
+) -> Result<(), ActorError> {
+    let gov = governance(state)?;
+    require_voter(gov, ctx.caller)?;
+
+    let proposals = load_proposals(ctx.store, &gov.proposals)?;
+    let mut proposal = get_proposal(&proposals, params.id)?;
+    if proposal.executed {
+        return Err(actor_error!(USR_ILLEGAL_STATE, "proposal {} already executed", params.id));
+    }
+    if proposal.approvals.contains(&ctx.caller) {
+        return Err(actor_error!(
+            USR_ILLEGAL_ARGUMENT,
+            "caller already voted on proposal {}",
+            params.id
+        ));
+    }
+
+    proposal.approvals.push(ctx.caller);
+    put_proposal(state, proposals, params.id, proposal)
+}
+
+/// Mark a sufficiently approved proposal as executed and return it, ready to
+/// be sent. Fails if the approving weight is below the threshold, or if the
+/// proposal calls a method that can no longer be proposed.
+pub fn take_executable_in<BS: fvm_ipld_blockstore::Blockstore>(
+    ctx: &Context<BS>,
+    state: &mut State,
+    params: ProposalIdParams,
+) -> Result<Proposal, ActorError> {
+    let gov = governance(state)?;
+    require_voter(gov, ctx.caller)?;
+
+    let proposals = load_proposals(ctx.store, &gov.proposals)?;
+    let mut proposal = get_proposal(&proposals, params.id)?;
+    if proposal.executed {
+        return Err(actor_error!(USR_ILLEGAL_STATE, "proposal {} already executed", params.id));
+    }
+    require_proposable(proposal.method)?;
+    let approved = gov.approved_weight(&proposal);
+    if approved < gov.threshold {
+        return Err(actor_error!(
+            kind: Forbidden,
+            USR_FORBIDDEN,
+            "proposal {} has {} of the {} approving weight it needs",
+            params.id,
+            approved,
+            gov.threshold
+        ));
+    }
+
+    proposal.executed = true;
+    put_proposal(state, proposals, params.id, proposal.clone())?;
+    Ok(proposal)
+}
+
+/// Configure weighted governance.
+pub fn configure_governance<R: Runtime>(
+    rt: &R,
+    params: ConfigureGovernanceParams,
+) -> Result<(), ActorError> {
+    let mut state = State::load_from(rt)?;
+    state.check_unlocked()?;
+
+    let resolver = |addr: &Address| rt.resolve_address(addr);
+    configure_governance_in(&runtime_context(rt, &resolver), &mut state, params)?;
+    state.save_to(rt)?;
+    Ok(())
+}
+
+/// Propose a privileged call, returning the proposal ID.
+pub fn propose<R: Runtime>(rt: &R, params: ProposeParams) -> Result<u64, ActorError> {
+    let mut state = State::load_from(rt)?;
+    state.check_unlocked()?;
+
+    let resolver = |addr: &Address| rt.resolve_address(addr);
+    let id = propose_in(&runtime_context(rt, &resolver), &mut state, params)?;
+    state.save_to(rt)?;
+    Ok(id)
+}
+
+/// Approve a pending proposal.
+pub fn vote<R: Runtime>(rt: &R, params: ProposalIdParams) -> Result<(), ActorError> {
+    let mut state = State::load_from(rt)?;
+    state.check_unlocked()?;
+
+    let resolver = |addr: &Address| rt.resolve_address(addr);
+    vote_in(&runtime_context(rt, &resolver), &mut state, params)?;
+    state.save_to(rt)?;
+    Ok(())
+}
+
+/// Execute an approved proposal by sending it from the actor to itself, and
+/// return whatever the proposed method returned.
+pub fn execute<R: Runtime>(rt: &R, params: ProposalIdParams) -> Result<RawBytes, ActorError> {
+    let mut state = State::load_from(rt)?;
+    state.check_unlocked()?;
+
+    let resolver = |addr: &Address| rt.resolve_address(addr);
+    let id = params.id;
+    let proposal = take_executable_in(&runtime_context(rt, &resolver), &mut state, params)?;
+
+    // Persist the executed flag before sending, so the proposed method can't
+    // execute it again, along with the marker that lets this one self-call
+    // past the owner check. The send doesn't take the reentrancy lock, which
+    // the proposed method would reject. Nothing else can run in the meantime:
+    // the sends a proposable method makes either take the lock or only
+    // create an account.
+    state.executing_proposal = Some(id);
+    state.save_to(rt)?;
+
+    let receiver = Address::new_id(rt.receiver());
+    let res = rt.send(&receiver, proposal.method, proposal.params, TokenAmount::from(0));
+
+    // The proposed method saved its own changes, so clear the marker on top
+    // of those, whatever the outcome.
+    let mut state = State::load_from(rt)?;
+    state.executing_proposal = None;
+    state.save_to(rt)?;
+
+    let receipt = res?;
+    if !receipt.exit_code.is_success() {
+        return Err(actor_error!(
+            USR_ILLEGAL_STATE,
+            "proposal execution failed with exit code {:?}",
+            receipt.exit_code
+        ));
+    }
+    Ok(receipt.return_data)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::constants::INIT_ACTOR_ADDR;
+    use crate::runtime::MockRuntime;
+    use crate::{constructor, set_max_supply, ConstructorParams, SetMaxSupplyParams};
+    use fvm_shared::error::ExitCode;
+    use fvm_shared::receipt::Receipt;
+
+    const OWNER: ActorID = 100;
+    const ALICE: ActorID = 101;
+    const BOB: ActorID = 102;
+    const TOKEN: ActorID = 1000;
+
+    /// A token governed by ALICE (weight 1) and BOB (weight 2), needing both.
+    /// Self-sends are dispatched back into the actor like the real runtime
+    /// would, for the one method the tests propose.
+    fn governed_runtime() -> MockRuntime {
+        let rt = MockRuntime::new(TOKEN);
+        rt.set_caller(INIT_ACTOR_ADDR);
+        let params = ConstructorParams {
+            name: "Test Token".to_string(),
+            symbol: "TEST".to_string(),
+            max_supply: TokenAmount::from(1_000_000),
+            owner: Address::new_id(OWNER),
+            supply_oracle: None,
+            hamt_bitwidth: DEFAULT_HAMT_BIT_WIDTH,
+            metadata_uri: None,
+            require_receiver_hook: false,
+            wrapped: false,
+            decimals: 6,
+        };
+        constructor(&rt, params).unwrap();
+
+        rt.set_caller(OWNER);
+        let voters = vec![Voter { id: ALICE, weight: 1 }, Voter { id: BOB, weight: 2 }];
+        configure_governance(&rt, ConfigureGovernanceParams { voters, threshold: 3 }).unwrap();
+
+        rt.on_send(|rt, msg| {
+            assert_eq!((msg.to, msg.method), (Address::new_id(TOKEN), method::SET_MAX_SUPPLY));
+            let exit_code = match set_max_supply(rt, msg.params.deserialize().unwrap()) {
+                Ok(()) => ExitCode::OK,
+                Err(err) => err.exit_code,
+            };
+            Receipt { exit_code, return_data: RawBytes::default(), gas_used: 0 }
+        });
+        rt
+    }
+
+    fn propose_max_supply(rt: &MockRuntime, new_max: u64) -> Result<u64, ActorError> {
+        let new_max = TokenAmount::from(new_max);
+        let params = RawBytes::serialize(SetMaxSupplyParams { new_max }).unwrap();
+        propose(rt, ProposeParams { method: method::SET_MAX_SUPPLY, params })
+    }
+
+    #[test]
+    fn configs_whose_total_weight_overflows_are_rejected() {
+        let voter = |id, weight| Voter { id, weight };
+        let voters = vec![voter(ALICE, u64::MAX), voter(BOB, 1)];
+        let err = validate_config(&ConfigureGovernanceParams { voters, threshold: 1 });
+        assert_eq!(err.unwrap_err().exit_code, ExitCode::USR_ILLEGAL_ARGUMENT);
+
+        let voters = vec![voter(ALICE, u64::MAX - 1), voter(BOB, 1)];
+        validate_config(&ConfigureGovernanceParams { voters, threshold: u64::MAX }).unwrap();
+    }
+
+    #[test]
+    fn only_privileged_methods_can_be_proposed() {
+        let rt = governed_runtime();
+        rt.set_caller(ALICE);
+
+        let params = ProposeParams { method: method::TRANSFER, params: RawBytes::default() };
+        let err = propose(&rt, params).unwrap_err();
+        assert_eq!(err.exit_code, ExitCode::USR_ILLEGAL_ARGUMENT);
+        assert_eq!(propose_max_supply(&rt, 2_000_000).unwrap(), 0);
+    }
+
+    #[test]
+    fn proposals_below_the_threshold_cannot_execute() {
+        let rt = governed_runtime();
+        rt.set_caller(ALICE);
+        let id = propose_max_supply(&rt, 2_000_000).unwrap();
+
+        let mut state = State::load_from(&rt).unwrap();
+        let resolver = |addr: &Address| rt.resolve_address(addr);
+        let ctx = runtime_context(&rt, &resolver);
+        let err = take_executable_in(&ctx, &mut state, ProposalIdParams { id }).unwrap_err();
+        assert_eq!(err.exit_code, ExitCode::USR_FORBIDDEN);
+
+        let err = execute(&rt, ProposalIdParams { id }).unwrap_err();
+        assert_eq!(err.exit_code, ExitCode::USR_FORBIDDEN);
+        assert!(rt.sent.borrow().is_empty());
+        let state = State::load_from(&rt).unwrap();
+        let proposals = load_proposals(&rt.store, &governance(&state).unwrap().proposals).unwrap();
+        assert!(!get_proposal(&proposals, id).unwrap().executed);
+    }
+
+    #[test]
+    fn executed_proposals_pass_the_owner_check_and_nothing_else_does() {
+        let rt = governed_runtime();
+        let set = |new_max: u64| {
+            set_max_supply(&rt, SetMaxSupplyParams { new_max: TokenAmount::from(new_max) })
+        };
+
+        // Neither the old owner nor the actor calling itself outside an
+        // execution gets past the owner check.
+        rt.set_caller(OWNER);
+        assert_eq!(set(2_000_000).unwrap_err().exit_code, ExitCode::USR_FORBIDDEN);
+        rt.set_caller(TOKEN);
+        assert_eq!(set(2_000_000).unwrap_err().exit_code, ExitCode::USR_FORBIDDEN);
+
+        rt.set_caller(ALICE);
+        let id = propose_max_supply(&rt, 2_000_000).unwrap();
+        rt.set_caller(BOB);
+        vote(&rt, ProposalIdParams { id }).unwrap();
+        execute(&rt, ProposalIdParams { id }).unwrap();
+
+        let state = State::load_from(&rt).unwrap();
+        assert_eq!(state.max_supply, TokenAmount::from(2_000_000));
+        assert_eq!(state.executing_proposal, None);
+        let err = execute(&rt, ProposalIdParams { id }).unwrap_err();
+        assert_eq!(err.exit_code, ExitCode::USR_ILLEGAL_STATE);
+    }
+
+    #[test]
+    fn failed_executions_clear_the_marker() {
+        let rt = governed_runtime();
+        let mut state = State::load_from(&rt).unwrap();
+        state.total_supply = TokenAmount::from(1);
+        state.save_to(&rt).unwrap();
+
+        // A cap below the total supply is refused, failing the execution.
+        rt.set_caller(ALICE);
+        let id = propose_max_supply(&rt, 0).unwrap();
+        rt.set_caller(BOB);
+        vote(&rt, ProposalIdParams { id }).unwrap();
+
+        let err = execute(&rt, ProposalIdParams { id }).unwrap_err();
+        assert_eq!(err.exit_code, ExitCode::USR_ILLEGAL_STATE);
+        let state = State::load_from(&rt).unwrap();
+        assert_eq!(state.executing_proposal, None);
+        assert_eq!(state.max_supply, TokenAmount::from(1_000_000));
+    }
+}
@@ -1,20 +1,3 @@
-mod blockstore;
-
-use crate::blockstore::Blockstore;
-use cid::multihash::Code;
-use cid::Cid;
-use fvm_ipld_encoding::tuple::{Deserialize_tuple, Serialize_tuple};
-use fvm_ipld_encoding::{to_vec, CborStore, Cbor, RawBytes, DAG_CBOR, from_slice};
-use fvm_sdk as sdk;
-use fvm_sdk::message::{params_raw, NO_DATA_BLOCK_ID};
-use fvm_shared::ActorID;
-use fvm_shared::econ::TokenAmount;
-use fvm_shared::bigint::{bigint_ser};
-use fvm_shared::bigint::bigint_ser::{BigIntDe};
-use fvm_shared::address::Address;
-use fvm_ipld_hamt::Hamt;
-
-
 /// A macro to abort concisely.
 /// This should be part of the SDK as it's very handy.
 macro_rules! abort {
@@ -26,350 +9,7906 @@ macro_rules! abort {
     };
 }
 
+/// Build an [`ActorError`] with a formatted message, mirroring `abort!`. A
+/// leading `kind: <ErrorKind>,` tags it with that kind's subcode.
+macro_rules! actor_error {
+    (kind: $kind:ident, $code:ident, $msg:literal $(, $ex:expr)*) => {
+        actor_error!($code, $msg $(, $ex)*).with_kind($crate::error::ErrorKind::$kind)
+    };
+    ($code:ident, $msg:literal $(, $ex:expr)*) => {
+        $crate::error::ActorError {
+            exit_code: fvm_shared::error::ExitCode::$code,
+            msg: format!($msg, $($ex,)*),
+        }
+    };
+}
+
+pub mod access_control;
+pub mod allowance;
+mod blockstore;
+pub mod claim;
+pub mod constants;
+pub mod error;
+pub mod governance;
+pub mod method;
+pub mod migration;
+pub mod multicall;
+pub mod params;
+pub mod permit;
+pub mod runtime;
+pub mod schema;
+pub mod token;
+pub mod wrap;
+
+use crate::access_control::{require_role, Role};
+use crate::allowance::{get_allowance, spend_allowance};
+use crate::claim::ClaimTerms;
+use crate::constants::CONSTRUCTOR_CALLERS;
+use crate::error::{or_abort, ActorError};
+use crate::governance::Governance;
+use crate::migration::VersionedState;
+use crate::runtime::{FvmRuntime, Runtime};
+use crate::wrap::require_unwrapped;
+use cid::multihash::{Code, MultihashDigest};
+use cid::Cid;
+use fvm_ipld_encoding::tuple::{Deserialize_tuple, Serialize_tuple};
+use fvm_ipld_encoding::{to_vec, BytesDe, CborStore, Cbor, RawBytes, DAG_CBOR, from_slice};
+use fvm_sdk as sdk;
+use fvm_sdk::message::params_raw;
+use fvm_shared::{ActorID, MethodNum, METHOD_SEND};
+use fvm_shared::econ::TokenAmount;
+use fvm_shared::error::ExitCode;
+use fvm_shared::bigint::{bigint_ser};
+use fvm_shared::bigint::bigint_ser::{BigIntDe, BigIntSer};
+use fvm_shared::address::{Address, Protocol};
+use fvm_shared::clock::ChainEpoch;
+use fvm_shared::receipt::Receipt;
+use fvm_ipld_hamt::{BytesKey, Hamt};
+use serde::de::{DeserializeOwned, IgnoredAny};
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::hash::Hash;
+
+
+/// The version of the `State` layout written by this code. Bump it whenever
+/// `State` changes shape. New fields go at the end with `#[serde(default)]`,
+/// so older versioned states still decode. A field whose default isn't right
+/// for existing tokens also needs a step in `VersionedState::upgrade` that
+/// fills it in.
+pub const STATE_VERSION: u32 = 26;
+
+/// The bit width `Hamt::new` uses, and so the one every balances HAMT created
+/// before the bit width was configurable has.
+pub const DEFAULT_HAMT_BIT_WIDTH: u32 = 8;
+
+/// The range of bit widths a token can choose for its balances HAMT.
+pub const HAMT_BIT_WIDTH_RANGE: std::ops::RangeInclusive<u32> = 3..=8;
+
+fn default_hamt_bit_width() -> u32 {
+    DEFAULT_HAMT_BIT_WIDTH
+}
+
+/// The decimals of a token that doesn't choose its own. Like FIL, its base
+/// unit is 10^-18 of a whole token.
+pub const DEFAULT_DECIMALS: u8 = 18;
+
+fn default_decimals() -> u8 {
+    DEFAULT_DECIMALS
+}
+
+pub(crate) fn validate_hamt_bit_width(bit_width: u32) -> Result<(), ActorError> {
+    if !HAMT_BIT_WIDTH_RANGE.contains(&bit_width) {
+        return Err(actor_error!(
+            USR_ILLEGAL_ARGUMENT,
+            "hamt bit width {} is outside {:?}",
+            bit_width,
+            HAMT_BIT_WIDTH_RANGE
+        ));
+    }
+    Ok(())
+}
+
 /// The state object.
 #[derive(Serialize_tuple, Deserialize_tuple, Clone, Debug)]
 pub struct State {
+    /// Always the first field, so any layout can be told apart by it.
+    pub version: u32,
     pub name: String,
     pub symbol: String,
     #[serde(with = "bigint_ser")]
     pub max_supply: TokenAmount,
+    #[serde(with = "bigint_ser")]
+    pub total_supply: TokenAmount,
     pub owner: Address,
     pub balances: Cid,
+    pub supply_oracle: Option<Address>,
+    /// Set while this actor has an outbound send in flight, so that any call
+    /// back into the actor during that send can be rejected.
+    pub reentrancy_locked: bool,
+    pub secondary_unit: Option<SecondaryUnit>,
+    /// When set, privileged methods are authorized by weighted proposals
+    /// instead of by the owner. See the `governance` module.
+    pub governance: Option<Governance>,
+    /// The proposal governance is executing, set only while the send that
+    /// executes it is in flight. See `Context::require_owner`.
+    pub executing_proposal: Option<u64>,
+    /// The number of accounts with a non-zero balance.
+    #[serde(default)]
+    pub holder_count: u64,
+    /// The bit width of the balances HAMT. It must be used for every load,
+    /// since loading with a different one misreads the HAMT.
+    #[serde(default = "default_hamt_bit_width")]
+    pub hamt_bit_width: u32,
+    /// Balances HAMT roots recorded by `snapshot`, with the epochs they were
+    /// taken at, oldest first.
+    #[serde(default)]
+    pub snapshots: Vec<(ChainEpoch, Cid)>,
+    /// Where wallets can fetch an off-chain metadata document for the token.
+    #[serde(default)]
+    pub metadata_uri: Option<String>,
+    /// Whether transfers call the recipient's receiver hook. When unset, every
+    /// transfer is fire-and-forget.
+    #[serde(default)]
+    pub require_receiver_hook: bool,
+    /// The most each account may transfer per window of epochs, if limited.
+    #[serde(default)]
+    pub rate_limit: Option<RateLimit>,
+    /// A HAMT of account ID to its current `TransferWindow`, created by the
+    /// first rate-limited transfer.
+    #[serde(default)]
+    pub transfer_windows: Option<Cid>,
+    /// The address ownership is being handed to, until it accepts.
+    #[serde(default)]
+    pub pending_owner: Option<Address>,
+    /// The share of each transfer taken as a fee, in basis points. Zero means
+    /// transfers carry no fee.
+    #[serde(default)]
+    pub fee_bps: u16,
+    /// The ID address transfer fees are credited to. Always set while
+    /// `fee_bps` is non-zero.
+    #[serde(default)]
+    pub fee_collector: Option<Address>,
+    /// A `recompute_total_supply` run that is partway through the balances.
+    #[serde(default)]
+    pub pending_total_supply: Option<SupplyRecount>,
+    /// The smallest amount a transfer may move, to keep dust out of the
+    /// balances. Zero turns the check off. Mints are exempt.
+    #[serde(default, with = "bigint_ser")]
+    pub min_transfer: TokenAmount,
+    /// A block holding the last `MAX_RECENT_TRANSFERS` transfers, oldest
+    /// first, written by the first transfer.
+    #[serde(default)]
+    pub recent_transfers: Option<Cid>,
+    /// The idempotency keys used by recent mints and transfers, written by
+    /// the first keyed call.
+    #[serde(default)]
+    pub used_keys: Option<UsedKeys>,
+    /// A HAMT set of the contracts that receiver hooks are called on. Until
+    /// the owner first sets it, hooks are called on every contract.
+    #[serde(default)]
+    pub hook_allowlist: Option<Cid>,
+    /// A HAMT of account ID to the `Lock`s on its balance, created by the
+    /// first lock.
+    #[serde(default)]
+    pub locks: Option<Cid>,
+    /// The windows minting is restricted to, if any. Empty means minting is
+    /// unrestricted.
+    #[serde(default)]
+    pub mint_schedule: Vec<MintWindow>,
+    /// A HAMT set of (owner, operator) pairs, written by the first operator
+    /// approval.
+    #[serde(default)]
+    pub operators: Option<Cid>,
+    /// The actor ID `owner` resolved to when it became the owner. Ownership
+    /// is checked against this rather than by resolving `owner` again, which
+    /// is kept as the address the owner was given as. Older states don't
+    /// have it, and fall back to resolving `owner`.
+    #[serde(default)]
+    pub owner_id: Option<ActorID>,
+    /// Whether this is a wrapped token, minted by depositing FIL and burned
+    /// by withdrawing it.
+    #[serde(default)]
+    pub wrapped: bool,
+    /// What a claim pays out, and from where. None means claiming is off.
+    #[serde(default)]
+    pub claim_terms: Option<ClaimTerms>,
+    /// A HAMT set of the accounts that have claimed, written by the first
+    /// claim.
+    #[serde(default)]
+    pub claimed: Option<Cid>,
+    /// The most accounts that may hold a balance, if there is a cap.
+    #[serde(default)]
+    pub max_holders: Option<u64>,
+    /// A HAMT of (owner, spender) pairs to what the spender may still move
+    /// out of the owner's balance, written by the first approval.
+    #[serde(default)]
+    pub allowances: Option<Cid>,
+    /// The number of base-unit digits after the decimal point, for display.
+    /// See the `token` module.
+    #[serde(default = "default_decimals")]
+    pub decimals: u8,
+    /// Set once the owner has renounced ownership, which leaves every
+    /// owner-only method, minting included, disabled for good.
+    #[serde(default)]
+    pub ownership_renounced: bool,
+    /// A HAMT of the roles granted, written by the first grant. See the
+    /// `access_control` module.
+    #[serde(default)]
+    pub roles: Option<Cid>,
+    /// Set while no balance may change.
+    #[serde(default)]
+    pub paused: bool,
+    /// A HAMT of the accounts that may neither send nor receive transfers,
+    /// written by the first freeze.
+    #[serde(default)]
+    pub frozen: Option<Cid>,
+    /// The epoch claiming ends at, if the claim terms set one.
+    #[serde(default)]
+    pub claim_deadline: Option<ChainEpoch>,
+    /// A HAMT of each owner's permit nonce, written by the first permit.
+    #[serde(default)]
+    pub nonces: Option<Cid>,
+    /// The owner and spender of the allowance `approve_and_call` is calling
+    /// the spender about, set only while that call is in flight. The spender
+    /// may draw on it through `transfer_from` although the lock is held.
+    #[serde(default)]
+    pub approved_call: Option<(ActorID, ActorID)>,
+}
+
+/// A secondary denomination that balances can be quoted in, e.g. the peg of
+/// a stablecoin. An amount of base units converts to
+/// `amount * rate_numerator / rate_denominator` units of `unit`.
+#[derive(Serialize_tuple, Deserialize_tuple, Clone, Debug)]
+pub struct SecondaryUnit {
+    pub unit: String,
+    #[serde(with = "bigint_ser")]
+    pub rate_numerator: TokenAmount,
+    #[serde(with = "bigint_ser")]
+    pub rate_denominator: TokenAmount,
+}
+
+impl Cbor for SecondaryUnit {}
+
+impl TokenAmounts for SecondaryUnit {
+    fn token_amounts(&self) -> Vec<&TokenAmount> {
+        vec![&self.rate_numerator, &self.rate_denominator]
+    }
 }
 
 /// We should probably have a derive macro to mark an object as a state object,
 /// and have load and save methods automatically generated for them as part of a
 /// StateObject trait (i.e. impl StateObject for State).
 impl State {
-    pub fn load() -> Self {
+    /// Load the state rooted at the runtime's current state root.
+    pub fn load_from<R: Runtime>(rt: &R) -> Result<Self, ActorError> {
         // First, load the current state root.
-        let root = match sdk::sself::root() {
-            Ok(root) => root,
-            Err(err) => abort!(USR_ILLEGAL_STATE, "failed to get root: {:?}", err),
-        };
+        let root = rt.root()?;
 
-        // Load the actor state from the state tree.
-        match Blockstore.get_cbor::<Self>(&root) {
-            Ok(Some(state)) => state,
-            Ok(None) => abort!(USR_ILLEGAL_STATE, "state does not exist"),
-            Err(err) => abort!(USR_ILLEGAL_STATE, "failed to get state: {}", err),
+        // Load the actor state from the state tree, refusing older layouts
+        // until they have been migrated.
+        match VersionedState::load(rt.store(), &root)? {
+            VersionedState::Versioned(state) if state.version == STATE_VERSION => Ok(state),
+            versioned => Err(actor_error!(
+                USR_ILLEGAL_STATE,
+                "state is at version {} and must be migrated to version {}",
+                versioned.version(),
+                STATE_VERSION
+            )),
         }
     }
 
-    pub fn save(&self) -> Cid {
-        let serialized = match to_vec(self) {
-            Ok(s) => s,
-            Err(err) => abort!(USR_SERIALIZATION, "failed to serialize state: {:?}", err),
-        };
-        let cid = match sdk::ipld::put(Code::Blake2b256.into(), 32, DAG_CBOR, serialized.as_slice())
-        {
-            Ok(cid) => cid,
-            Err(err) => abort!(USR_SERIALIZATION, "failed to store initial state: {:}", err),
-        };
-        if let Err(err) = sdk::sself::set_root(&cid) {
-            abort!(USR_ILLEGAL_STATE, "failed to set root ciid: {:}", err);
-        }
-        cid
+    /// Store the state and make it the runtime's new state root.
+    ///
+    /// This is the only place the state root is written, so every method
+    /// persists state in the same order: blocks first, then the root.
+    pub fn save_to<R: Runtime>(&self, rt: &R) -> Result<Cid, ActorError> {
+        let cid = rt
+            .store()
+            .put_cbor(self, Code::Blake2b256)
+            .map_err(|err| actor_error!(USR_SERIALIZATION, "failed to store state: {:}", err))?;
+        rt.set_root(&cid)?;
+        Ok(cid)
     }
 
-    pub fn new(p: ConstructorParams) -> State {
-        let mut balances : Hamt<Blockstore, BigIntDe, ActorID> = Hamt::new(Blockstore);
+    pub fn new<BS: fvm_ipld_blockstore::Blockstore>(
+        store: &BS,
+        p: ConstructorParams,
+    ) -> Result<State, ActorError> {
+        validate_hamt_bit_width(p.hamt_bitwidth)?;
+        if let Some(uri) = &p.metadata_uri {
+            validate_metadata_uri(uri)?;
+        }
+        let mut balances: Balances<&BS> = Hamt::new_with_bit_width(store, p.hamt_bitwidth);
 
         let balances = match balances.flush() {
             Ok(map) => map,
-            Err(_e) => abort!(USR_ILLEGAL_STATE, "failed to create balances hamt"),
+            Err(_e) => return Err(actor_error!(USR_ILLEGAL_STATE, "failed to create balances hamt")),
         };
 
-        State {
+        Ok(State {
+            version: STATE_VERSION,
             name: p.name,
             symbol: p.symbol,
             max_supply: p.max_supply,
+            total_supply: TokenAmount::from(0),
             owner: p.owner,
-            balances
+            balances,
+            supply_oracle: p.supply_oracle,
+            reentrancy_locked: false,
+            secondary_unit: None,
+            governance: None,
+            executing_proposal: None,
+            holder_count: 0,
+            hamt_bit_width: p.hamt_bitwidth,
+            snapshots: Vec::new(),
+            metadata_uri: p.metadata_uri,
+            require_receiver_hook: p.require_receiver_hook,
+            rate_limit: None,
+            transfer_windows: None,
+            pending_owner: None,
+            fee_bps: 0,
+            fee_collector: None,
+            pending_total_supply: None,
+            min_transfer: TokenAmount::from(0),
+            recent_transfers: None,
+            used_keys: None,
+            hook_allowlist: None,
+            locks: None,
+            mint_schedule: Vec::new(),
+            operators: None,
+            owner_id: p.owner.id().ok(),
+            wrapped: p.wrapped,
+            claim_terms: None,
+            claimed: None,
+            max_holders: None,
+            allowances: None,
+            decimals: p.decimals,
+            ownership_renounced: false,
+            roles: None,
+            paused: false,
+            frozen: None,
+            claim_deadline: None,
+            nonces: None,
+            approved_call: None,
+        })
+    }
+
+    /// Fail if this actor is further up the call stack, waiting on a send.
+    /// Every state-mutating method must call this right after loading state.
+    pub fn check_unlocked(&self) -> Result<(), ActorError> {
+        if self.reentrancy_locked {
+            return Err(actor_error!(USR_ILLEGAL_STATE, "reentrant call rejected"));
+        }
+        Ok(())
+    }
+
+    /// Fail while the token is paused. Every function that changes a balance
+    /// checks this first.
+    pub fn check_not_paused(&self) -> Result<(), ActorError> {
+        if self.paused {
+            return Err(actor_error!(kind: Paused, USR_FORBIDDEN, "the token is paused"));
         }
+        Ok(())
+    }
+
+    /// Send a message to another actor while holding the reentrancy lock.
+    ///
+    /// The lock is written to the state root before sending, so it is visible
+    /// to any call that re-enters this actor, and it is put back as it was
+    /// whatever the outcome of the send, before the caller gets a chance to
+    /// abort. A send made while an outer call holds the lock leaves it held,
+    /// and a spender `approve_and_call` is calling loses its exemption from
+    /// the lock for as long as this send is out.
+    pub fn locked_send<R: Runtime>(
+        &mut self,
+        rt: &R,
+        to: &Address,
+        method: MethodNum,
+        params: RawBytes,
+    ) -> Result<Receipt, ActorError> {
+        self.locked_send_value(rt, to, method, params, TokenAmount::from(0))
+    }
+
+    /// Like `locked_send`, but sending `value` along with the message.
+    pub fn locked_send_value<R: Runtime>(
+        &mut self,
+        rt: &R,
+        to: &Address,
+        method: MethodNum,
+        params: RawBytes,
+        value: TokenAmount,
+    ) -> Result<Receipt, ActorError> {
+        let was_locked = self.reentrancy_locked;
+        let approved_call = self.approved_call.take();
+        self.reentrancy_locked = true;
+        self.save_to(rt)?;
+        let res = rt.send(to, method, params, value);
+        self.reentrancy_locked = was_locked;
+        self.approved_call = approved_call;
+        self.save_to(rt)?;
+        res
     }
 }
 
-/// The actor's WASM entrypoint. It takes the ID of the parameters block,
-/// and returns the ID of the return value block, or NO_DATA_BLOCK_ID if no
-/// return value.
+/// The balances HAMT, mapping actor IDs to their balances.
+// TODO Using BitIntDe because it's both Ser and De; this is a misnomer and
+//  we should fix it.
+pub type Balances<BS> = Hamt<BS, BigIntDe, ActorID>;
+
+/// Load the `what` HAMT rooted at `root`, which must have been created with
+/// `bit_width`.
 ///
-/// Should probably have macros similar to the ones on fvm.filecoin.io snippets.
-/// Put all methods inside an impl struct and annotate it with a derive macro
-/// that handles state serde and dispatch.
-#[no_mangle]
-pub fn invoke(params_id: u32) -> u32 {
-    // Conduct method dispatch. Handle input parameters and return data.
-    let ret: Option<RawBytes> = match sdk::message::method_number() {
-        1 => {
-            let params: ConstructorParams = match params_raw(params_id) {
-                Ok(params) => {
-                    match from_slice(params.1.as_slice()) {
-                        Ok(v) => v,
-                        Err(err) => abort!(USR_SERIALIZATION, "failed to parse params: {:?}", err),
-                    }
-                },
-                Err(err) => abort!(USR_ILLEGAL_ARGUMENT, "failed to parse address: {:?}", err),
-            };
-            constructor(params);
-            None
-        },
-        2 => {
-            let params: TransferParams = match params_raw(params_id) {
-                Ok(params) => {
-                    match from_slice(params.1.as_slice()) {
-                        Ok(v) => v,
-                        Err(err) => abort!(USR_SERIALIZATION, "failed to parse params: {:?}", err),
-                    }
-                },
-                Err(err) => abort!(USR_ILLEGAL_ARGUMENT, "failed to parse params: {:?}", err),
-            };
-            mint(params);
-            None
-        },
-        // 2 => {
-        //     let params: TransferParams = match params_raw(params_id) {
-        //         Ok(params) => {
-        //             match from_slice(params.1.as_slice()) {
-        //                 Ok(v) => v,
-        //                 Err(err) => abort!(USR_SERIALIZATION, "failed to parse params: {:?}", err),
-        //             }
-        //         },
-        //         Err(err) => abort!(USR_ILLEGAL_ARGUMENT, "failed to parse params: {:?}", err),
-        //     };
-        //     transfer(params);
-        //     None
-        // }
-        _ => abort!(USR_UNHANDLED_MESSAGE, "unrecognized method"),
+/// A root block that is missing or isn't a HAMT node fails as
+/// `ErrorKind::CorruptHamt`. A root with a slot beyond the `2^bit_width` that
+/// `bit_width` allows fails as `ErrorKind::HamtBitWidthMismatch`; a HAMT
+/// written with a narrower bit width than expected can't be told apart this
+/// way, and only shows up as missing entries.
+pub(crate) fn load_hamt<'a, BS, K, V>(
+    store: &'a BS,
+    root: &Cid,
+    bit_width: u32,
+    what: &str,
+) -> Result<Hamt<&'a BS, V, K>, ActorError>
+where
+    BS: fvm_ipld_blockstore::Blockstore,
+    K: Hash + Eq + PartialOrd + Serialize + DeserializeOwned,
+    V: Serialize + DeserializeOwned,
+{
+    let bytes = match store.get(root) {
+        Ok(Some(bytes)) => bytes,
+        Ok(None) => {
+            return Err(actor_error!(
+                kind: CorruptHamt,
+                USR_ILLEGAL_STATE,
+                "{} hamt root {} does not exist",
+                what,
+                root
+            ))
+        }
+        Err(err) => {
+            return Err(actor_error!(
+                kind: CorruptHamt,
+                USR_ILLEGAL_STATE,
+                "failed to get {} hamt root {}: {:?}",
+                what,
+                root,
+                err
+            ))
+        }
     };
 
-    // Insert the return data block if necessary, and return the correct
-    // block ID.
-    match ret {
-        None => NO_DATA_BLOCK_ID,
-        Some(v) => match sdk::ipld::put_block(DAG_CBOR, v.bytes()) {
-            Ok(id) => id,
-            Err(err) => abort!(USR_SERIALIZATION, "failed to store return value: {}", err),
-        },
+    // A node encodes as its slot bitfield followed by its pointers. The
+    // bitfield is a big-endian 256-bit integer with leading zero bytes trimmed,
+    // with bit i set when slot i is occupied.
+    let (bitfield, _) = from_slice::<(BytesDe, IgnoredAny)>(&bytes).map_err(|err| {
+        actor_error!(
+            kind: CorruptHamt,
+            USR_ILLEGAL_STATE,
+            "{} hamt root {} is not a hamt node: {}",
+            what,
+            root,
+            err
+        )
+    })?;
+    let highest_slot = bitfield.0.iter().position(|byte| *byte != 0).map(|first| {
+        let byte = bitfield.0[first];
+        (bitfield.0.len() - first - 1) as u32 * 8 + 7 - byte.leading_zeros()
+    });
+    if let Some(slot) = highest_slot {
+        if slot >= 1 << bit_width {
+            return Err(actor_error!(
+                kind: HamtBitWidthMismatch,
+                USR_ILLEGAL_STATE,
+                "{} hamt root {} uses slot {}, which bit width {} doesn't have",
+                what,
+                root,
+                slot,
+                bit_width
+            ));
+        }
     }
+
+    Hamt::load_with_bit_width(root, store, bit_width).map_err(|err| {
+        actor_error!(
+            kind: CorruptHamt,
+            USR_ILLEGAL_STATE,
+            "failed to load {} hamt: {:?}",
+            what,
+            err
+        )
+    })
 }
 
-// hGRHTElGY0dMRkQAD0JAQgBk
-#[derive(Serialize_tuple, Deserialize_tuple)]
-pub struct ConstructorParams {
-    pub name: String,
-    pub symbol: String,
-    #[serde(with = "bigint_ser")]
-    pub max_supply: TokenAmount,
-    pub owner: Address,
+/// Load the balances HAMT rooted at `root`, which must have been created with
+/// `bit_width`.
+pub fn load_balances<BS: fvm_ipld_blockstore::Blockstore>(
+    store: &BS,
+    root: &Cid,
+    bit_width: u32,
+) -> Result<Balances<&BS>, ActorError> {
+    load_hamt(store, root, bit_width, "balances")
 }
 
-/// The constructor populates the initial state.
-///
-/// Method num 1. This is part of the Filecoin calling convention.
-/// InitActor#Exec will call the constructor on method_num = 1.
-pub fn constructor(params: ConstructorParams) -> Option<RawBytes> {
-    // This constant should be part of the SDK.
-    const INIT_ACTOR_ADDR: ActorID = 1;
+/// Fail unless `amount` is strictly positive. Moving zero tokens has no effect
+/// but would still rewrite the state, and negative amounts would run a
+/// transfer backwards.
+pub fn require_positive(amount: &TokenAmount) -> Result<(), ActorError> {
+    if *amount <= TokenAmount::from(0) {
+        return Err(actor_error!(
+            kind: InvalidAmount,
+            USR_ILLEGAL_ARGUMENT,
+            "amount {} must be positive",
+            amount
+        ));
+    }
+    Ok(())
+}
 
-    // Should add SDK sugar to perform ACL checks more succinctly.
-    // i.e. the equivalent of the validate_* builtin-actors runtime methods.
-    // https://github.com/filecoin-project/builtin-actors/blob/master/actors/runtime/src/runtime/fvm.rs#L110-L146
-    if sdk::message::caller() != INIT_ACTOR_ADDR {
-        abort!(USR_FORBIDDEN, "constructor invoked by non-init actor");
+/// Look up a balance, treating an absent entry as zero.
+pub fn get_balance<BS: fvm_ipld_blockstore::Blockstore>(
+    balances: &Balances<BS>,
+    id: ActorID,
+) -> Result<TokenAmount, ActorError> {
+    match balances.get(&id) {
+        Ok(Some(bal)) => Ok(bal.0.clone()),
+        Ok(None) => Ok(TokenAmount::from(0)),
+        Err(err) => Err(actor_error!(
+            USR_ILLEGAL_STATE,
+            "failed to query hamt when getting balance of {}: {:?}",
+            id,
+            err
+        )),
     }
+}
 
-    let state = State::new(params);
-    state.save();
-    None
+/// The largest balance or total supply that can be stored. With the
+/// `evm-compat` feature this is `2^256 - 1`, so every amount fits in an EVM
+/// `uint256`; by default amounts are unbounded.
+pub fn max_token_amount() -> Option<TokenAmount> {
+    if cfg!(feature = "evm-compat") {
+        Some((TokenAmount::from(1) << 256usize) - 1)
+    } else {
+        None
+    }
 }
 
-pub fn mint(params: TransferParams) {
-    let mut state = State::load();
+/// Fail if `amount`, the new value of `what`, is larger than
+/// `max_token_amount()` allows.
+pub fn check_amount_cap(amount: &TokenAmount, what: &str) -> Result<(), ActorError> {
+    match max_token_amount() {
+        Some(max) if *amount > max => Err(actor_error!(
+            kind: InvalidAmount,
+            USR_ILLEGAL_ARGUMENT,
+            "{} of {} would exceed the maximum token amount of {}",
+            what,
+            amount,
+            max
+        )),
+        _ => Ok(()),
+    }
+}
 
-    // Resolve the recipient into an ID address.
-    // TODO See addressing section on module docs.
-    let owner_id = match fvm_sdk::actor::resolve_address(&state.owner) {
-        Some(id) => id,
-        None => abort!(USR_ILLEGAL_ARGUMENT, "failed to resolve address"),
-    };
+/// Overwrite a balance. Fails if the new balance is over `max_token_amount()`.
+pub fn set_balance<BS: fvm_ipld_blockstore::Blockstore>(
+    balances: &mut Balances<BS>,
+    id: ActorID,
+    amount: TokenAmount,
+) -> Result<(), ActorError> {
+    check_amount_cap(&amount, "balance")?;
+    balances.set(id, BigIntDe(amount)).map(|_| ()).map_err(|err| {
+        actor_error!(
+            USR_ILLEGAL_STATE,
+            "failed to set new balance of {} in balances hamt: {:?}",
+            id,
+            err
+        )
+    })
+}
 
-    if owner_id != fvm_sdk::message::caller() {
-        abort!(USR_FORBIDDEN, "mint invoked by non-owner");
+/// Overwrite a balance that was `old`, keeping `holder_count` in step with
+/// accounts moving to or from a zero balance.
+pub fn set_holder_balance<BS: fvm_ipld_blockstore::Blockstore>(
+    holder_count: &mut u64,
+    balances: &mut Balances<BS>,
+    id: ActorID,
+    old: &TokenAmount,
+    new: TokenAmount,
+) -> Result<(), ActorError> {
+    let zero = TokenAmount::from(0);
+    if *old == zero && new != zero {
+        *holder_count += 1;
+    } else if *old != zero && new == zero {
+        *holder_count -= 1;
     }
+    set_balance(balances, id, new)
+}
 
-    // Load the balances HAMT.
-    // TODO Using BitIntDe because it's both Ser and De; this is a misnomer and
-    //  we should fix it.
-    let mut balances = match Hamt::<Blockstore, BigIntDe, ActorID>::load(&state.balances, Blockstore) {
-        Ok(map) => map,
-        Err(err) => abort!(USR_ILLEGAL_STATE, "failed to load balances hamt: {:?}", err),
-    };
+/// Fail if a change took the holder count from `before` to above
+/// `State::max_holders`. A change that adds no holders always passes, so
+/// existing holders can keep trading among themselves whatever the cap.
+pub fn check_holder_cap(state: &State, before: u64) -> Result<(), ActorError> {
+    match state.max_holders {
+        Some(max) if state.holder_count > before && state.holder_count > max => {
+            Err(actor_error!(
+                kind: HolderCapReached,
+                USR_FORBIDDEN,
+                "the token already has its maximum of {} holders",
+                max
+            ))
+        }
+        _ => Ok(()),
+    }
+}
 
-    let recipient_id = match fvm_sdk::actor::resolve_address(&params.recipient) {
-        Some(id) => id,
-        None => abort!(USR_ILLEGAL_ARGUMENT, "failed to resolve address"),
-    };
+/// Count the accounts with a non-zero balance by walking the whole HAMT.
+pub fn count_holders<BS: fvm_ipld_blockstore::Blockstore>(
+    balances: &Balances<BS>,
+) -> Result<u64, ActorError> {
+    let zero = TokenAmount::from(0);
+    let mut count = 0;
+    balances
+        .for_each(|_, bal| {
+            if bal.0 != zero {
+                count += 1;
+            }
+            Ok(())
+        })
+        .map_err(|err| actor_error!(USR_ILLEGAL_STATE, "failed to count holders: {:?}", err))?;
+    Ok(count)
+}
 
-    let mut recipient_bal = match balances.get(&recipient_id) {
-        Ok(Some(bal)) => bal.clone(),
-        Ok(None) => BigIntDe(TokenAmount::from(0)),
-        Err(err) => abort!(
-            USR_ILLEGAL_STATE,
-            "failed to query hamt when getting recipient balance: {:?}",
-            err
-        ),
-    };
+/// Flush the HAMT to generate the new root CID to update the actor's state.
+pub fn flush_balances<BS: fvm_ipld_blockstore::Blockstore>(
+    balances: &mut Balances<BS>,
+) -> Result<Cid, ActorError> {
+    balances
+        .flush()
+        .map_err(|err| actor_error!(USR_ILLEGAL_STATE, "failed to flush balances hamt: {:?}", err))
+}
 
-    recipient_bal.0 += &params.amount;
+/// What the token logic needs from its environment: who is calling, how to
+/// resolve addresses, and where blocks live. The WASM entrypoint builds one
+/// over the SDK, and unit tests build one over an in-memory blockstore.
+pub struct Context<'a, BS> {
+    pub caller: ActorID,
+    /// The ID of this actor.
+    pub receiver: ActorID,
+    pub store: &'a BS,
+    pub resolver: &'a dyn Fn(&Address) -> Option<ActorID>,
+}
 
-    if let Err(err) = balances.set(recipient_id, recipient_bal.clone()) {
-        abort!(
-            USR_ILLEGAL_STATE,
-            "failed to set new recipient balance in balances hamt: {:?}",
-            err
-        )
+impl<'a, BS: fvm_ipld_blockstore::Blockstore> Context<'a, BS> {
+    /// Resolve an address into an actor ID.
+    // TODO See addressing section on module docs.
+    pub fn resolve(&self, addr: &Address) -> Result<ActorID, ActorError> {
+        (self.resolver)(addr)
+            .ok_or_else(|| actor_error!(USR_ILLEGAL_ARGUMENT, "failed to resolve address {}", addr))
     }
 
-    // Flush the HAMT to generate the new root CID to update the actor's state.
-    let cid = match balances.flush() {
-        Ok(cid) => cid,
-        Err(err) => abort!(
-            USR_ILLEGAL_STATE,
-            "failed to query hamt when getting recipient balance: {:?}",
-            err
-        ),
-    };
+    /// The token owner's actor ID.
+    pub fn owner_id(&self, state: &State) -> Result<ActorID, ActorError> {
+        match state.owner_id {
+            Some(id) => Ok(id),
+            None => self.resolve(&state.owner),
+        }
+    }
 
-    // Update the actor's state.
-    state.balances = cid;
-    let root = match Blockstore.put_cbor(&state, Code::Blake2b256) {
-        Ok(cid) => cid,
-        Err(err) => abort!(USR_ILLEGAL_STATE, "failed to write new state: {:?}", err),
-    };
+    /// Fail unless the caller is the token's owner. Once governance is
+    /// configured, the only authorized caller is the actor itself, and only
+    /// while it is executing an approved proposal: a call the actor makes to
+    /// itself for any other reason is refused.
+    pub fn require_owner(&self, state: &State, method: &str) -> Result<(), ActorError> {
+        if state.ownership_renounced {
+            return Err(actor_error!(
+                kind: Forbidden,
+                USR_FORBIDDEN,
+                "{} is disabled: ownership has been renounced",
+                method
+            ));
+        }
+        if state.governance.is_some() {
+            if self.caller != self.receiver || state.executing_proposal.is_none() {
+                return Err(actor_error!(
+                    kind: Forbidden,
+                    USR_FORBIDDEN,
+                    "{} requires an approved proposal",
+                    method
+                ));
+            }
+            return Ok(());
+        }
+        if self.owner_id(state)? != self.caller {
+            return Err(actor_error!(
+                kind: Forbidden,
+                USR_FORBIDDEN,
+                "{} invoked by non-owner",
+                method
+            ));
+        }
+        Ok(())
+    }
+}
 
-    if let Err(err) = fvm_sdk::sself::set_root(&root) {
-        abort!(USR_ILLEGAL_STATE, "failed to set new state root: {:?}", err)
+/// A context over a runtime. The resolver has to outlive the context, so the
+/// caller passes in a closure delegating to `rt.resolve_address`.
+fn runtime_context<'a, R: Runtime>(
+    rt: &'a R,
+    resolver: &'a dyn Fn(&Address) -> Option<ActorID>,
+) -> Context<'a, R::Blockstore> {
+    Context {
+        caller: rt.caller(),
+        receiver: rt.receiver(),
+        store: rt.store(),
+        resolver,
     }
 }
 
-/// The input parameters for a transfer.
-#[derive(Serialize_tuple, Deserialize_tuple, Clone, Debug)]
-pub struct TransferParams {
-    pub recipient: Address,
-    #[serde(with = "bigint_ser")]
-    pub amount: TokenAmount,
+/// Resolve a recipient address into the actor ID its balance is keyed by,
+/// creating the recipient's account first if it doesn't exist yet.
+///
+/// How each address class is handled:
+/// - ID (f0): used as is, without checking that the actor exists, since tokens
+///   can be held for any ID.
+/// - secp256k1 (f1) and BLS (f3): resolved if the account exists. Otherwise a
+///   zero-value send creates the account, which assigns it an ID.
+/// - actor (f2): resolved if the actor exists. Actor addresses can't be created
+///   by sending to them, so an unknown one fails with USR_NOT_FOUND.
+///
+/// This network version has no delegated (f4) addresses.
+pub fn normalize_recipient<R: Runtime>(rt: &R, addr: &Address) -> Result<ActorID, ActorError> {
+    if let Some(id) = check_recipient(rt, addr)? {
+        return Ok(id);
+    }
+
+    let receipt = rt.send(addr, METHOD_SEND, RawBytes::default(), TokenAmount::from(0))?;
+    if !receipt.exit_code.is_success() {
+        return Err(actor_error!(
+            USR_ILLEGAL_STATE,
+            "failed to create account for {} with exit code {:?}",
+            addr,
+            receipt.exit_code
+        ));
+    }
+    rt.resolve_address(addr)
+        .ok_or_else(|| actor_error!(USR_ILLEGAL_STATE, "created account {} did not resolve", addr))
 }
 
-impl Cbor for TransferParams {}
+/// Check that `addr` can be sent tokens under the rules of
+/// [`normalize_recipient`], without creating anything. Returns the
+/// recipient's ID, or None for a key address whose account the transfer would
+/// create.
+fn check_recipient<R: Runtime>(rt: &R, addr: &Address) -> Result<Option<ActorID>, ActorError> {
+    if let Ok(id) = addr.id() {
+        return Ok(Some(id));
+    }
+    if let Some(id) = rt.resolve_address(addr) {
+        return Ok(Some(id));
+    }
 
-// /// Transfer a token amount.
-// pub fn transfer(params: TransferParams) {
-//     let mut state = State::load();
-
-//     // Load the balances HAMT.
-//     // TODO Using BitIntDe because it's both Ser and De; this is a misnomer and
-//     //  we should fix it.
-//     let mut balances =
-//         match Hamt::<Blockstore, BigIntDe, ActorID>::load(&state.balances, Blockstore) {
-//             Ok(map) => map,
-//             Err(err) => abort!(USR_ILLEGAL_STATE, "failed to load balances hamt: {:?}", err),
-//         };
-
-//     // Load the sender's balance.
-//     let sender_id = fvm_sdk::message::caller();
-//     let mut sender_bal = match balances.get(&sender_id) {
-//         Ok(Some(bal)) => bal.clone(),
-//         Ok(None) => BigIntDe(TokenAmount::from(0)),
-//         Err(err) => abort!(USR_ILLEGAL_STATE, "failed to get balance: {:?}", err),
-//     };
-
-//     // Sender has insufficient balance.
-//     if sender_bal.0 < params.amount {
-//         abort!(USR_INSUFFICIENT_FUNDS, "sender has insufficient balance")
-//     }
-
-//     // Resolve the recipient into an ID address.
-//     // TODO See addressing section on module docs.
-//     let recipient_id = match fvm_sdk::actor::resolve_address(&params.recipient) {
-//         Some(id) => id,
-//         None => abort!(USR_ILLEGAL_ARGUMENT, "failed to resolve address"),
-//     };
-
-//     // Forbid sends to self.
-//     if sender_id == recipient_id {
-//         abort!(USR_ILLEGAL_ARGUMENT, "cannot send to self");
-//     }
-
-//     // // Ensure that the recipient is an account actor; otherwise they will never
-//     // // be able to spend the funds. (At least in the current protocol)
-//     // match sdk::actor::get_actor_code_cid(&params.recipient) {
-//     //     None => abort!(USR_ILLEGAL_ARGUMENT, "cannot resolve actor type of recipient"),
-//     //     Some(cid) => {
-//     //         /// The multicodec value for raw data.
-//     //         const IPLD_CODEC_RAW: u64 = 0x55;
-//     //         // TODO this is embarrassingly wrong, as hardcoding the actor version doesn't allow evolution.
-//     //         //  but we need to figure out the upgradability story to solve this.
-//     //         let other = Cid::new_v1(IPLD_CODEC_RAW, Code::Identity.digest(b"fil/6/account"));
-//     //         if cid != other {
-//     //             abort!(USR_ILLEGAL_ARGUMENT, "recipient is not an account actor")
-//     //         }
-//     //     }
-//     // }
-
-//     // Load the recipient's balance.
-//     let mut recipient_bal = match balances.get(&recipient_id) {
-//         Ok(Some(bal)) => bal.clone(),
-//         Ok(None) => BigIntDe(TokenAmount::from(0)),
-//         Err(err) => abort!(
-//             USR_ILLEGAL_STATE,
-//             "failed to query hamt when getting recipient balance: {:?}",
-//             err
-//         ),
-//     };
-
-//     // Update balances.
-//     sender_bal.0 -= &params.amount;
-//     recipient_bal.0 += &params.amount;
-
-//     // Set the updated sender balance in the balances HAMT.
-//     if let Err(err) = balances.set(sender_id, sender_bal.clone()) {
-//         abort!(
-//             USR_ILLEGAL_STATE,
-//             "failed to set new sender balance in balances hamt: {:?}",
-//             err
-//         )
-//     }
-
-//     // Set the updated recipient balance in the balances HAMT.
-//     if let Err(err) = balances.set(recipient_id, recipient_bal.clone()) {
-//         abort!(
-//             USR_ILLEGAL_STATE,
-//             "failed to set new recipient balance in balances hamt: {:?}",
-//             err
-//         )
-//     }
-
-//     // Flush the HAMT to generate the new root CID to update the actor's state.
-//     let cid = match balances.flush() {
-//         Ok(cid) => cid,
-//         Err(err) => abort!(
-//             USR_ILLEGAL_STATE,
-//             "failed to query hamt when getting recipient balance: {:?}",
-//             err
-//         ),
-//     };
-
-//     // Update the actor's state.
-//     state.balances = cid;
-//     let root = match Blockstore.put_cbor(&state, Code::Blake2b256) {
-//         Ok(cid) => cid,
-//         Err(err) => abort!(USR_ILLEGAL_STATE, "failed to write new state: {:?}", err),
-//     };
-
-//     if let Err(err) = fvm_sdk::sself::set_root(&root) {
-//         abort!(USR_ILLEGAL_STATE, "failed to set new state root: {:?}", err)
-//     }
-// }
+    match addr.protocol() {
+        Protocol::Secp256k1 | Protocol::BLS => Ok(None),
+        _ => Err(actor_error!(USR_NOT_FOUND, "recipient {} does not exist", addr)),
+    }
+}
 
+/// Replace the address in `params` with the ID address of its normalized
+/// recipient, so the core logic only ever sees addresses that resolve.
+fn normalize_params<R: Runtime>(
+    rt: &R,
+    params: &TransferParams,
+) -> Result<TransferParams, ActorError> {
+    Ok(TransferParams {
+        recipient: Address::new_id(normalize_recipient(rt, &params.recipient)?),
+        amount: params.amount.clone(),
+    })
+}
 
+/// The actor's WASM entrypoint. It takes the ID of the parameters block,
+/// and returns the ID of the return value block, or NO_DATA_BLOCK_ID if no
+/// return value.
+///
+/// Should probably have macros similar to the ones on fvm.filecoin.io snippets.
+/// Put all methods inside an impl struct and annotate it with a derive macro
+/// that handles state serde and dispatch.
+#[no_mangle]
+pub fn invoke(params_id: u32) -> u32 {
+    // Conduct method dispatch. Handle input parameters and return data.
+    let number = sdk::message::method_number();
+    let ret: Option<RawBytes> = match number {
+        method::CONSTRUCTOR => {
+            let ret =
+                or_abort(constructor(&FvmRuntime::default(), deserialize_amount_params(params_id)));
+            return_cbor(&ret)
+        }
+        method::MINT => {
+            let supply =
+                or_abort(mint(&FvmRuntime::default(), deserialize_amount_params(params_id)));
+            return_cbor(&BigIntSer(&supply))
+        }
+        method::SET_MAX_SUPPLY => {
+            or_abort(set_max_supply(&FvmRuntime::default(), deserialize_amount_params(params_id)));
+            None
+        }
+        method::TRANSFER => {
+            let params = deserialize_amount_params(params_id);
+            return_cbor(&or_abort(transfer(&FvmRuntime::default(), params)))
+        }
+        method::SNAPSHOT => {
+            or_abort(snapshot(&FvmRuntime::default()));
+            None
+        }
+        method::BALANCE_INCLUDING_PENDING => {
+            let params = deserialize_params(params_id);
+            let rt = FvmRuntime::default();
+            return_cbor(&BigIntSer(&or_abort(claim::balance_including_pending(&rt, params))))
+        }
+        method::BALANCE_OF => {
+            let balance =
+                or_abort(balance_of(&FvmRuntime::default(), deserialize_params(params_id)));
+            return_cbor(&BigIntSer(&balance))
+        }
+        method::BALANCE_OF_ID => {
+            let balance =
+                or_abort(balance_of_id(&FvmRuntime::default(), deserialize_params(params_id)));
+            return_cbor(&BigIntSer(&balance))
+        }
+        method::BALANCE_AT_SNAPSHOT => {
+            let balance = or_abort(balance_at_snapshot(
+                &FvmRuntime::default(),
+                deserialize_params(params_id),
+            ));
+            return_cbor(&BigIntSer(&balance))
+        }
+        method::SET_METADATA_URI => {
+            or_abort(set_metadata_uri(&FvmRuntime::default(), deserialize_params(params_id)));
+            None
+        }
+        method::METADATA_URI => return_cbor(&or_abort(metadata_uri(&FvmRuntime::default()))),
+        method::OWNER => return_cbor(&or_abort(owner(&FvmRuntime::default()))),
+        method::NAME => return_cbor(&or_abort(name(&FvmRuntime::default()))),
+        method::SYMBOL => return_cbor(&or_abort(symbol(&FvmRuntime::default()))),
+        method::DECIMALS => return_cbor(&or_abort(decimals(&FvmRuntime::default()))),
+        method::TOTAL_SUPPLY => {
+            return_cbor(&BigIntSer(&or_abort(total_supply(&FvmRuntime::default()))))
+        }
+        method::MAX_SUPPLY => {
+            return_cbor(&BigIntSer(&or_abort(max_supply(&FvmRuntime::default()))))
+        }
+        method::ACTOR_INFO => return_cbor(&or_abort(actor_info(&FvmRuntime::default()))),
+        method::STATE_SUMMARY => {
+            return_cbor(&or_abort(state_summary(&FvmRuntime::default())))
+        }
+        method::TRANSFER_OWNERSHIP => {
+            or_abort(transfer_ownership(&FvmRuntime::default(), deserialize_params(params_id)));
+            None
+        }
+        method::ACCEPT_OWNERSHIP => {
+            or_abort(accept_ownership(&FvmRuntime::default()));
+            None
+        }
+        method::CANCEL_OWNERSHIP_TRANSFER => {
+            or_abort(cancel_ownership_transfer(&FvmRuntime::default()));
+            None
+        }
+        method::RENOUNCE_OWNERSHIP => {
+            or_abort(renounce_ownership(&FvmRuntime::default()));
+            None
+        }
+        method::GRANT_ROLE => {
+            let params = deserialize_params(params_id);
+            or_abort(access_control::grant_role(&FvmRuntime::default(), params));
+            None
+        }
+        method::REVOKE_ROLE => {
+            let params = deserialize_params(params_id);
+            or_abort(access_control::revoke_role(&FvmRuntime::default(), params));
+            None
+        }
+        method::HAS_ROLE => {
+            let params = deserialize_params(params_id);
+            return_cbor(&or_abort(access_control::has_role(&FvmRuntime::default(), params)))
+        }
+        method::PAUSE => {
+            or_abort(pause(&FvmRuntime::default()));
+            None
+        }
+        method::UNPAUSE => {
+            or_abort(unpause(&FvmRuntime::default()));
+            None
+        }
+        method::FREEZE_ACCOUNT => {
+            or_abort(freeze_account(&FvmRuntime::default(), deserialize_params(params_id)));
+            None
+        }
+        method::UNFREEZE_ACCOUNT => {
+            or_abort(unfreeze_account(&FvmRuntime::default(), deserialize_params(params_id)));
+            None
+        }
+        method::IS_FROZEN => {
+            let params = deserialize_params(params_id);
+            return_cbor(&or_abort(is_frozen(&FvmRuntime::default(), params)))
+        }
+        method::MINT_BATCH => {
+            or_abort(mint_batch(&FvmRuntime::default(), deserialize_amount_params(params_id)));
+            None
+        }
+        method::FORCE_TRANSFER => {
+            or_abort(force_transfer(&FvmRuntime::default(), deserialize_amount_params(params_id)));
+            None
+        }
+        method::RESCUE => {
+            or_abort(rescue(&FvmRuntime::default(), deserialize_params(params_id)));
+            None
+        }
+        method::VERIFY_INVARIANTS => {
+            return_cbor(&or_abort(verify_invariants(&FvmRuntime::default())))
+        }
+        method::RECOMPUTE_TOTAL_SUPPLY => {
+            let ret = or_abort(recompute_total_supply(
+                &FvmRuntime::default(),
+                deserialize_params(params_id),
+            ));
+            return_cbor(&ret)
+        }
+        method::SET_RATE_LIMIT => {
+            or_abort(set_rate_limit(&FvmRuntime::default(), deserialize_amount_params(params_id)));
+            None
+        }
+        method::SET_MINT_SCHEDULE => {
+            or_abort(set_mint_schedule(
+                &FvmRuntime::default(),
+                deserialize_amount_params(params_id),
+            ));
+            None
+        }
+        method::LOCK => {
+            or_abort(lock(&FvmRuntime::default(), deserialize_amount_params(params_id)));
+            None
+        }
+        method::SET_HOOK_ALLOWED => {
+            or_abort(set_hook_allowed(&FvmRuntime::default(), deserialize_params(params_id)));
+            None
+        }
+        method::SET_MIN_TRANSFER => {
+            or_abort(set_min_transfer(
+                &FvmRuntime::default(),
+                deserialize_amount_params(params_id),
+            ));
+            None
+        }
+        method::SET_TRANSFER_FEE => {
+            or_abort(set_transfer_fee(&FvmRuntime::default(), deserialize_params(params_id)));
+            None
+        }
+        method::RECENT_TRANSFERS => {
+            let records = or_abort(recent_transfers(
+                &FvmRuntime::default(),
+                deserialize_params(params_id),
+            ));
+            return_cbor(&records)
+        }
+        method::SIMULATE_TRANSFER => {
+            let result = or_abort(simulate_transfer(
+                &FvmRuntime::default(),
+                deserialize_amount_params(params_id),
+            ));
+            return_cbor(&result)
+        }
+        method::SAFE_TRANSFER => {
+            let accepted = or_abort(safe_transfer(
+                &FvmRuntime::default(),
+                deserialize_amount_params(params_id),
+            ));
+            return_cbor(&accepted)
+        }
+        method::SET_MAX_HOLDERS => {
+            or_abort(set_max_holders(&FvmRuntime::default(), deserialize_params(params_id)));
+            None
+        }
+        method::SET_CLAIM_TERMS => {
+            let params = deserialize_amount_params(params_id);
+            or_abort(claim::set_claim_terms(&FvmRuntime::default(), params));
+            None
+        }
+        method::CLAIM => {
+            or_abort(claim::claim(&FvmRuntime::default()));
+            None
+        }
+        method::RECLAIM_UNCLAIMED => {
+            return_cbor(&BigIntSer(&or_abort(claim::reclaim_unclaimed(&FvmRuntime::default()))))
+        }
+        method::DEPOSIT => {
+            or_abort(wrap::deposit(&FvmRuntime::default()));
+            None
+        }
+        method::WITHDRAW => {
+            or_abort(wrap::withdraw(&FvmRuntime::default(), deserialize_amount_params(params_id)));
+            None
+        }
+        method::INCREASE_ALLOWANCE => {
+            let params = deserialize_amount_params(params_id);
+            let allowance = or_abort(allowance::increase_allowance(&FvmRuntime::default(), params));
+            return_cbor(&BigIntSer(&allowance))
+        }
+        method::DECREASE_ALLOWANCE => {
+            let params = deserialize_amount_params(params_id);
+            let allowance = or_abort(allowance::decrease_allowance(&FvmRuntime::default(), params));
+            return_cbor(&BigIntSer(&allowance))
+        }
+        method::REVOKE_ALLOWANCE => {
+            let params = deserialize_params(params_id);
+            or_abort(allowance::revoke_allowance(&FvmRuntime::default(), params));
+            None
+        }
+        method::GRANULARITY => return_cbor(&TOKEN_GRANULARITY),
+        method::BURN => {
+            or_abort(burn(&FvmRuntime::default(), deserialize_amount_params(params_id)));
+            None
+        }
+        method::BURN_FROM => {
+            or_abort(burn_from(&FvmRuntime::default(), deserialize_amount_params(params_id)));
+            None
+        }
+        method::APPROVE => {
+            let params = deserialize_amount_params(params_id);
+            or_abort(allowance::approve(&FvmRuntime::default(), params));
+            None
+        }
+        method::ALLOWANCE => {
+            let params = deserialize_params(params_id);
+            let ret = or_abort(allowance::allowance(&FvmRuntime::default(), params));
+            return_cbor(&BigIntSer(&ret))
+        }
+        method::SET_OPERATOR => {
+            let params = deserialize_params(params_id);
+            or_abort(allowance::set_operator(&FvmRuntime::default(), params));
+            None
+        }
+        method::IS_OPERATOR => {
+            let params = deserialize_params(params_id);
+            let ret = or_abort(allowance::is_operator(&FvmRuntime::default(), params));
+            return_cbor(&ret)
+        }
+        method::TRANSFER_FROM => {
+            let params = deserialize_amount_params(params_id);
+            return_cbor(&or_abort(transfer_from(&FvmRuntime::default(), params)))
+        }
+        method::CAN_RECEIVE => {
+            let params = deserialize_amount_params(params_id);
+            return_cbor(&or_abort(can_receive(&FvmRuntime::default(), params)))
+        }
+        method::MINT_IDEMPOTENT => {
+            or_abort(mint_idempotent(&FvmRuntime::default(), deserialize_amount_params(params_id)));
+            None
+        }
+        method::TRANSFER_IDEMPOTENT => Some(or_abort(transfer_idempotent(
+            &FvmRuntime::default(),
+            deserialize_amount_params(params_id),
+        ))),
+        method::TRANSFER_WITH_RECEIPT => Some(or_abort(transfer_with_receipt(
+            &FvmRuntime::default(),
+            deserialize_amount_params(params_id),
+        ))),
+        method::TRANSFER_WITH_MEMO => Some(or_abort(transfer_with_memo(
+            &FvmRuntime::default(),
+            deserialize_amount_params(params_id),
+        ))),
+        method::TRANSFER_UNTIL => Some(or_abort(transfer_until(
+            &FvmRuntime::default(),
+            deserialize_amount_params(params_id),
+        ))),
+        method::TRANSFER_SPLIT => {
+            or_abort(transfer_split(&FvmRuntime::default(), deserialize_amount_params(params_id)));
+            None
+        }
+        method::TRANSFER_BATCH => {
+            or_abort(transfer_batch(&FvmRuntime::default(), deserialize_amount_params(params_id)));
+            None
+        }
+        method::MULTICALL => {
+            let params = deserialize_params(params_id);
+            return_cbor(&or_abort(multicall::multicall(&FvmRuntime::default(), params)))
+        }
+        method::PERMIT => {
+            or_abort(permit::permit(&FvmRuntime::default(), deserialize_amount_params(params_id)));
+            None
+        }
+        method::APPROVE_AND_CALL => {
+            let params = deserialize_amount_params(params_id);
+            Some(or_abort(allowance::approve_and_call(&FvmRuntime::default(), params)))
+        }
+        method::COMPARE_AND_SET_ALLOWANCE => {
+            let params = deserialize_amount_params(params_id);
+            or_abort(allowance::compare_and_set_allowance(&FvmRuntime::default(), params));
+            None
+        }
+        method::ALLOWANCES_OF => {
+            let params = deserialize_params(params_id);
+            let page = or_abort(allowance::allowances_of(&FvmRuntime::default(), params));
+            let page: Vec<_> = page.iter().map(|(id, amount)| (*id, BigIntSer(amount))).collect();
+            return_cbor(&page)
+        }
+        method::TRANSFER_AND_APPROVE => {
+            let params = deserialize_amount_params(params_id);
+            return_cbor(&or_abort(allowance::transfer_and_approve(&FvmRuntime::default(), params)))
+        }
+        method::NONCE_OF => {
+            let params = deserialize_params(params_id);
+            return_cbor(&or_abort(permit::nonce_of(&FvmRuntime::default(), params)))
+        }
+        method::SPLIT_BALANCE => {
+            or_abort(split_balance(&FvmRuntime::default(), deserialize_amount_params(params_id)));
+            None
+        }
+        method::SET_UNIT_RATE => {
+            or_abort(set_unit_rate(&FvmRuntime::default(), deserialize_amount_params(params_id)));
+            None
+        }
+        method::BALANCE_OF_IN_UNIT => {
+            let params = deserialize_params(params_id);
+            let balance = or_abort(balance_of_in_unit(&FvmRuntime::default(), params));
+            return_cbor(&BigIntSer(&balance))
+        }
+        method::READ_FIELDS => {
+            let params = deserialize_params(params_id);
+            let fields = or_abort(read_fields(&FvmRuntime::default(), params));
+            return_cbor(&fields)
+        }
+        method::CONFIGURE_GOVERNANCE => {
+            let params = deserialize_params(params_id);
+            or_abort(governance::configure_governance(&FvmRuntime::default(), params));
+            None
+        }
+        method::PROPOSE => {
+            let params = deserialize_params(params_id);
+            return_cbor(&or_abort(governance::propose(&FvmRuntime::default(), params)))
+        }
+        method::VOTE => {
+            or_abort(governance::vote(&FvmRuntime::default(), deserialize_params(params_id)));
+            None
+        }
+        method::EXECUTE => {
+            let params = deserialize_params(params_id);
+            Some(or_abort(governance::execute(&FvmRuntime::default(), params)))
+        }
+        method::PENDING_OPERATION => {
+            let params = deserialize_params(params_id);
+            return_cbor(&or_abort(pending_operation(&FvmRuntime::default(), params)))
+        }
+        method::HOLDER_COUNT => return_cbor(&or_abort(holder_count(&FvmRuntime::default()))),
+        method::MIGRATE => {
+            or_abort(migration::migrate(&FvmRuntime::default()));
+            None
+        }
+        method::DESCRIBE => return_cbor(&schema::describe()),
+        _ => abort!(USR_UNHANDLED_MESSAGE, "unrecognized method"),
+    };
+
+    // Wrap the return data in the envelope, unless the method returns it bare,
+    // and insert it as the return block.
+    let ret = if method::returns_bare(number) {
+        match ret {
+            Some(ret) => ret,
+            None => return sdk::NO_DATA_BLOCK_ID,
+        }
+    } else {
+        match RawBytes::serialize(MethodReturn::ok(ret)) {
+            Ok(ret) => ret,
+            Err(err) => abort!(USR_SERIALIZATION, "failed to serialize return value: {:?}", err),
+        }
+    };
+    match sdk::ipld::put_block(DAG_CBOR, ret.bytes()) {
+        Ok(id) => id,
+        Err(err) => abort!(USR_SERIALIZATION, "failed to store return value: {}", err),
+    }
+}
+
+/// The envelope a method's return value is wrapped in, so clients always
+/// decode the same shape. `data` holds the method's own return value, and is
+/// None for methods that don't return anything.
+///
+/// The FRC-0046 methods and those that forward another actor's return value
+/// go out bare instead; see [`method::returns_bare`].
+///
+/// A failing method aborts, which reverts its state changes and leaves the
+/// exit code in the message receipt, so a returned envelope always carries
+/// `ExitCode::OK`.
+#[derive(Serialize_tuple, Deserialize_tuple, Clone, Debug, PartialEq)]
+pub struct MethodReturn {
+    pub code: u32,
+    pub data: Option<RawBytes>,
+}
+
+impl Cbor for MethodReturn {}
+
+impl MethodReturn {
+    /// The envelope for a method that completed successfully.
+    pub fn ok(data: Option<RawBytes>) -> Self {
+        MethodReturn { code: ExitCode::OK.value(), data }
+    }
+}
+
+/// Encode a method's return value, aborting if it can't be serialized.
+pub fn return_cbor<T: Serialize>(value: &T) -> Option<RawBytes> {
+    match RawBytes::serialize(value) {
+        Ok(ret) => Some(ret),
+        Err(err) => abort!(USR_SERIALIZATION, "failed to serialize return value: {:?}", err),
+    }
+}
+
+/// Read and decode the parameters block for the current method.
+pub fn deserialize_params<T: DeserializeOwned>(params_id: u32) -> T {
+    // A call without params has no block to read at all.
+    if params_id == sdk::NO_DATA_BLOCK_ID {
+        return or_abort(decode_params(&[]));
+    }
+    let params = match params_raw(params_id) {
+        Ok(params) => params,
+        Err(err) => abort!(USR_ILLEGAL_ARGUMENT, "failed to parse params: {:?}", err),
+    };
+    or_abort(decode_params(params.1.as_slice()))
+}
+
+/// Decode a method's params. Missing params fail with USR_ILLEGAL_ARGUMENT,
+/// and params that are present but malformed with USR_SERIALIZATION, so a
+/// client can tell forgetting the params from encoding them wrong.
+pub fn decode_params<T: DeserializeOwned>(bytes: &[u8]) -> Result<T, ActorError> {
+    if bytes.is_empty() {
+        return Err(actor_error!(USR_ILLEGAL_ARGUMENT, "method requires parameters"));
+    }
+    from_slice(bytes)
+        .map_err(|err| actor_error!(USR_SERIALIZATION, "failed to parse params: {:?}", err))
+}
+
+/// Params that carry token amounts.
+pub trait TokenAmounts {
+    /// Every token amount in the params.
+    fn token_amounts(&self) -> Vec<&TokenAmount>;
+}
+
+/// Fail if any token amount in `params` is negative. A negative amount
+/// decodes like any other, and would turn checks like `balance < amount`
+/// around, so it is rejected before the method ever sees it.
+pub fn check_non_negative<T: TokenAmounts>(params: &T) -> Result<(), ActorError> {
+    for amount in params.token_amounts() {
+        if *amount < TokenAmount::from(0) {
+            return Err(actor_error!(
+                kind: InvalidAmount,
+                USR_ILLEGAL_ARGUMENT,
+                "amount {} must not be negative",
+                amount
+            ));
+        }
+    }
+    Ok(())
+}
+
+/// Read and decode the parameters block for the current method, aborting if
+/// any token amount in it is negative.
+pub fn deserialize_amount_params<T: DeserializeOwned + TokenAmounts>(params_id: u32) -> T {
+    let params = deserialize_params(params_id);
+    or_abort(check_non_negative(&params));
+    params
+}
+
+/// The input parameters for the constructor. Off-chain callers can build and
+/// encode them with [`params::ConstructorParamsBuilder`].
+#[derive(Serialize_tuple, Deserialize_tuple, Clone, Debug)]
+pub struct ConstructorParams {
+    pub name: String,
+    pub symbol: String,
+    #[serde(with = "bigint_ser")]
+    pub max_supply: TokenAmount,
+    pub owner: Address,
+    pub supply_oracle: Option<Address>,
+    /// The bit width of the balances HAMT, within `HAMT_BIT_WIDTH_RANGE`.
+    /// Wider nodes suit tokens with many holders.
+    pub hamt_bitwidth: u32,
+    pub metadata_uri: Option<String>,
+    /// Whether contract recipients must accept tokens through their receiver
+    /// hook. `ConstructorParamsBuilder` turns it on unless told otherwise;
+    /// turning it off credits contracts without notifying them.
+    pub require_receiver_hook: bool,
+    /// Whether this is a wrapped token. Wrapped tokens are minted 1:1 for FIL
+    /// deposited through `deposit`, which `max_supply` doesn't limit.
+    #[serde(default)]
+    pub wrapped: bool,
+    /// The number of base-unit digits after the decimal point.
+    #[serde(default = "default_decimals")]
+    pub decimals: u8,
+}
+
+impl Cbor for ConstructorParams {}
+
+impl TokenAmounts for ConstructorParams {
+    fn token_amounts(&self) -> Vec<&TokenAmount> {
+        vec![&self.max_supply]
+    }
+}
+
+// Caller checks, after the validate_* methods of the builtin-actors runtime.
+// https://github.com/filecoin-project/builtin-actors/blob/master/actors/runtime/src/runtime/fvm.rs#L110-L146
+
+/// Fail unless the caller is `id`.
+pub fn validate_caller_is<R: Runtime>(rt: &R, id: ActorID) -> Result<(), ActorError> {
+    let caller = rt.caller();
+    if caller != id {
+        return Err(actor_error!(
+            kind: Forbidden,
+            USR_FORBIDDEN,
+            "caller {} is not actor {}",
+            caller,
+            id
+        ));
+    }
+    Ok(())
+}
+
+/// Fail unless the caller is one of `ids`.
+pub fn validate_caller_is_one_of<R: Runtime>(rt: &R, ids: &[ActorID]) -> Result<(), ActorError> {
+    let caller = rt.caller();
+    if !ids.contains(&caller) {
+        return Err(actor_error!(
+            kind: Forbidden,
+            USR_FORBIDDEN,
+            "caller {} is not one of actors {:?}",
+            caller,
+            ids
+        ));
+    }
+    Ok(())
+}
+
+/// Fail unless the caller is an account actor.
+pub fn validate_caller_is_account<R: Runtime>(rt: &R) -> Result<(), ActorError> {
+    let caller = rt.caller();
+    if !is_account_actor(rt, caller) {
+        return Err(actor_error!(
+            kind: Forbidden,
+            USR_FORBIDDEN,
+            "caller {} is not an account actor",
+            caller
+        ));
+    }
+    Ok(())
+}
+
+/// Returns true if `id` is an account actor.
+fn is_account_actor<R: Runtime>(rt: &R, id: ActorID) -> bool {
+    // Hardcoding the account code CID would tie us to one actors version,
+    // but the message origin is always an account actor, so its code CID
+    // is the account code CID of whatever version is running.
+    let origin = rt.origin();
+    if id == origin {
+        return true;
+    }
+    let code = rt.get_actor_code_cid(&Address::new_id(id));
+    code.is_some() && code == rt.get_actor_code_cid(&Address::new_id(origin))
+}
+
+/// What the constructor created, so deployers can confirm it without a
+/// follow-up read.
+#[derive(Serialize_tuple, Deserialize_tuple, Clone, Debug, PartialEq)]
+pub struct ConstructorReturn {
+    pub state_root: Cid,
+    /// The ID the owner address resolved to.
+    pub owner_id: ActorID,
+    pub balances_root: Cid,
+}
+
+impl Cbor for ConstructorReturn {}
+
+/// The constructor populates the initial state.
+///
+/// Method num 1. This is part of the Filecoin calling convention.
+/// InitActor#Exec will call the constructor on method_num = 1, as does the
+/// EAM when the token is deployed through the EVM-compatible path.
+pub fn constructor<R: Runtime>(
+    rt: &R,
+    params: ConstructorParams,
+) -> Result<ConstructorReturn, ActorError> {
+    validate_caller_is_one_of(rt, CONSTRUCTOR_CALLERS)?;
+
+    // Resolve the owner now, so an owner given by a non-ID address is
+    // authorized by the ID it has today, however the address resolves later.
+    let owner_id = rt.resolve_address(&params.owner).ok_or_else(|| {
+        actor_error!(USR_ILLEGAL_ARGUMENT, "failed to resolve owner address {}", params.owner)
+    })?;
+    let mut state = State::new(rt.store(), params)?;
+    state.owner_id = Some(owner_id);
+    let state_root = state.save_to(rt)?;
+    verify_constructed(rt)?;
+    Ok(ConstructorReturn { state_root, owner_id, balances_root: state.balances })
+}
+
+/// Read a freshly constructed state back through the runtime, with the HAMTs
+/// it references. A token whose state can't be reloaded, because of a wrong
+/// bit width or a blockstore that lost blocks, then fails to deploy instead
+/// of failing its first transfer. The other HAMTs are all created later, on
+/// first use.
+fn verify_constructed<R: Runtime>(rt: &R) -> Result<(), ActorError> {
+    let state = State::load_from(rt).map_err(|err| {
+        actor_error!(USR_ILLEGAL_STATE, "constructed state failed to reload: {}", err.msg)
+    })?;
+    load_balances(rt.store(), &state.balances, state.hamt_bit_width).map_err(|err| {
+        actor_error!(USR_ILLEGAL_STATE, "constructed balances failed to reload: {}", err.msg)
+    })?;
+    Ok(())
+}
+
+/// The method number a supply oracle must export, i.e. the FRC42 hash of
+/// "SupplyCap". It takes no parameters and returns the currently allowed supply
+/// ceiling as a CBOR-encoded TokenAmount.
+pub const ORACLE_SUPPLY_CAP_METHOD: MethodNum = 219477108;
+
+/// Ask the supply oracle for the current supply ceiling.
+pub fn query_supply_cap<R: Runtime>(
+    rt: &R,
+    state: &mut State,
+    oracle: &Address,
+) -> Result<TokenAmount, ActorError> {
+    let receipt = state
+        .locked_send(rt, oracle, ORACLE_SUPPLY_CAP_METHOD, RawBytes::default())
+        .map_err(|err| {
+            actor_error!(USR_ILLEGAL_STATE, "failed to query supply oracle: {}", err.msg)
+        })?;
+
+    if !receipt.exit_code.is_success() {
+        return Err(actor_error!(
+            USR_ILLEGAL_STATE,
+            "supply oracle failed with exit code {:?}",
+            receipt.exit_code
+        ));
+    }
+
+    match receipt.return_data.deserialize::<BigIntDe>() {
+        Ok(cap) => Ok(cap.0),
+        Err(err) => Err(actor_error!(
+            USR_SERIALIZATION,
+            "failed to parse supply oracle cap: {:?}",
+            err
+        )),
+    }
+}
+
+/// Mint to the recipient, returning the total supply after the mint.
+pub fn mint<R: Runtime>(rt: &R, params: TransferParams) -> Result<TokenAmount, ActorError> {
+    mint_with_key(rt, params, None)
+}
+
+fn mint_with_key<R: Runtime>(
+    rt: &R,
+    params: TransferParams,
+    key: Option<&IdempotencyKey>,
+) -> Result<TokenAmount, ActorError> {
+    let mut state = State::load_from(rt)?;
+    state.check_unlocked()?;
+    let supply = mint_in(rt, &mut state, params, key)?;
+    state.save_to(rt)?;
+    Ok(supply)
+}
+
+/// Mint against `state` without saving it, returning the total supply after
+/// the mint.
+pub(crate) fn mint_in<R: Runtime>(
+    rt: &R,
+    state: &mut State,
+    params: TransferParams,
+    key: Option<&IdempotencyKey>,
+) -> Result<TokenAmount, ActorError> {
+    // Checked here as well as in mint_tokens, so a zero mint never gets as far
+    // as querying the oracle.
+    require_positive(&params.amount)?;
+    require_unwrapped(state, "mint")?;
+
+    let resolver = |addr: &Address| rt.resolve_address(addr);
+    let ctx = runtime_context(rt, &resolver);
+    require_role(&ctx, state, Role::Minter, "mint")?;
+    let params = normalize_params(rt, &params)?;
+
+    // If an oracle is configured, it has the final word on how much supply may
+    // exist. The cap is queried once and reused for the rest of the call.
+    if let Some(oracle) = state.supply_oracle.clone() {
+        let cap = query_supply_cap(rt, state, &oracle)?;
+        if &state.total_supply + &params.amount > cap {
+            return Err(actor_error!(
+                USR_ILLEGAL_ARGUMENT,
+                "mint would exceed the oracle supply cap of {}",
+                cap
+            ));
+        }
+    }
+
+    if &state.total_supply + &params.amount > state.max_supply {
+        return Err(actor_error!(
+            USR_ILLEGAL_ARGUMENT,
+            "mint of {} would exceed the max supply of {}",
+            params.amount,
+            state.max_supply
+        ));
+    }
+
+    let mut after = state.clone();
+    if let Some(key) = key {
+        consume_idempotency_key(rt.store(), &mut after, key, rt.curr_epoch())?;
+    }
+    charge_mint_schedule(&mut after, &params.amount, rt.curr_epoch())?;
+    mint_tokens(&ctx, &mut after, &params)?;
+
+    *state = after;
+    call_mint_hooks(rt, &ctx, state, std::slice::from_ref(&params))?;
+    Ok(state.total_supply.clone())
+}
+
+/// Notify each recipient of a mint through its receiver hook, as coming from
+/// this actor with the minter as operator.
+///
+/// As with a transfer, `state` must already hold the minted tokens, which the
+/// hooks see saved under the lock. If one rejects, the abort undoes the mint.
+fn call_mint_hooks<R: Runtime>(
+    rt: &R,
+    ctx: &Context<R::Blockstore>,
+    state: &mut State,
+    mints: &[TransferParams],
+) -> Result<(), ActorError> {
+    for entry in mints {
+        let hook = TokenReceivedParams {
+            from: ctx.receiver,
+            to: ctx.resolve(&entry.recipient)?,
+            operator: ctx.caller,
+            amount: entry.amount.clone(),
+            operator_data: RawBytes::default(),
+            token_data: RawBytes::default(),
+        };
+        call_receiver_hook(rt, state, hook)?;
+    }
+    Ok(())
+}
+
+/// Credit newly minted tokens to the recipient and grow the total supply.
+/// The caller's right to mint must already have been checked.
+pub fn mint_tokens<BS: fvm_ipld_blockstore::Blockstore>(
+    ctx: &Context<BS>,
+    state: &mut State,
+    params: &TransferParams,
+) -> Result<(), ActorError> {
+    state.check_not_paused()?;
+    require_positive(&params.amount)?;
+    check_amount_cap(&(&state.total_supply + &params.amount), "total supply")?;
+    let recipient_id = ctx.resolve(&params.recipient)?;
+
+    let holders_before = state.holder_count;
+    let mut balances = load_balances(ctx.store, &state.balances, state.hamt_bit_width)?;
+    let recipient_bal = get_balance(&balances, recipient_id)?;
+    let new_bal = &recipient_bal + &params.amount;
+    let holders = &mut state.holder_count;
+    set_holder_balance(holders, &mut balances, recipient_id, &recipient_bal, new_bal)?;
+
+    check_holder_cap(state, holders_before)?;
+    state.balances = flush_balances(&mut balances)?;
+    state.total_supply += &params.amount;
+    Ok(())
+}
+
+/// The input parameters for minting to several recipients at once. Each entry
+/// encodes as an `(address, amount)` pair.
+#[derive(Serialize_tuple, Deserialize_tuple, Clone, Debug)]
+pub struct MintBatchParams {
+    pub mints: Vec<TransferParams>,
+}
+
+impl Cbor for MintBatchParams {}
+
+impl TokenAmounts for MintBatchParams {
+    fn token_amounts(&self) -> Vec<&TokenAmount> {
+        self.mints.iter().map(|mint| &mint.amount).collect()
+    }
+}
+
+/// Mint to every recipient in the batch, or to none of them. Only the owner may
+/// call this.
+pub fn mint_batch<R: Runtime>(rt: &R, params: MintBatchParams) -> Result<(), ActorError> {
+    for entry in &params.mints {
+        require_positive(&entry.amount)?;
+    }
+
+    let mut state = State::load_from(rt)?;
+    state.check_unlocked()?;
+    require_unwrapped(&state, "mint_batch")?;
+
+    let resolver = |addr: &Address| rt.resolve_address(addr);
+    let ctx = runtime_context(rt, &resolver);
+    require_role(&ctx, &state, Role::Minter, "mint_batch")?;
+    let mints = params
+        .mints
+        .iter()
+        .map(|entry| normalize_params(rt, entry))
+        .collect::<Result<Vec<_>, _>>()?;
+
+    if let Some(oracle) = state.supply_oracle.clone() {
+        let cap = query_supply_cap(rt, &mut state, &oracle)?;
+        let total = mints.iter().fold(state.total_supply.clone(), |acc, m| acc + &m.amount);
+        if total > cap {
+            return Err(actor_error!(
+                USR_ILLEGAL_ARGUMENT,
+                "mint would exceed the oracle supply cap of {}",
+                cap
+            ));
+        }
+    }
+
+    let mut after = state.clone();
+    let total = mints.iter().fold(TokenAmount::from(0), |acc, m| acc + &m.amount);
+    charge_mint_schedule(&mut after, &total, rt.curr_epoch())?;
+    mint_batch_tokens(&ctx, &mut after, &mints)?;
+
+    call_mint_hooks(rt, &ctx, &mut after, &mints)?;
+    after.save_to(rt)?;
+    Ok(())
+}
+
+/// Credit each batch entry, loading and flushing the balances HAMT once.
+/// Recipients listed more than once receive the sum of their entries. Fails
+/// without changing anything if the batch would take the total supply past
+/// `max_supply`.
+pub fn mint_batch_tokens<BS: fvm_ipld_blockstore::Blockstore>(
+    ctx: &Context<BS>,
+    state: &mut State,
+    mints: &[TransferParams],
+) -> Result<(), ActorError> {
+    state.check_not_paused()?;
+    let mut minted = TokenAmount::from(0);
+    for entry in mints {
+        require_positive(&entry.amount)?;
+        minted += &entry.amount;
+    }
+    if &state.total_supply + &minted > state.max_supply {
+        return Err(actor_error!(
+            USR_ILLEGAL_ARGUMENT,
+            "batch of {} would exceed the max supply of {}",
+            minted,
+            state.max_supply
+        ));
+    }
+    check_amount_cap(&(&state.total_supply + &minted), "total supply")?;
+
+    let holders_before = state.holder_count;
+    let mut balances = load_balances(ctx.store, &state.balances, state.hamt_bit_width)?;
+    for entry in mints {
+        let recipient_id = ctx.resolve(&entry.recipient)?;
+        let recipient_bal = get_balance(&balances, recipient_id)?;
+        let new_bal = &recipient_bal + &entry.amount;
+        let holders = &mut state.holder_count;
+        set_holder_balance(holders, &mut balances, recipient_id, &recipient_bal, new_bal)?;
+    }
+
+    check_holder_cap(state, holders_before)?;
+    state.balances = flush_balances(&mut balances)?;
+    state.total_supply += minted;
+    Ok(())
+}
+
+/// A window of epochs in which up to `max_in_window` tokens may be minted.
+/// It opens at `start_epoch` and closes at `end_epoch`, exclusive.
+#[derive(Serialize_tuple, Deserialize_tuple, Clone, Debug, PartialEq)]
+pub struct MintWindow {
+    pub start_epoch: ChainEpoch,
+    pub end_epoch: ChainEpoch,
+    #[serde(with = "bigint_ser")]
+    pub max_in_window: TokenAmount,
+    /// How much has been minted in the window so far.
+    #[serde(with = "bigint_ser")]
+    pub minted: TokenAmount,
+}
+
+impl MintWindow {
+    fn is_open(&self, epoch: ChainEpoch) -> bool {
+        self.start_epoch <= epoch && epoch < self.end_epoch
+    }
+}
+
+/// The most windows a mint schedule may have.
+pub const MAX_MINT_WINDOWS: usize = 64;
+
+/// Charge a mint of `amount` at `epoch` to the first open window of the mint
+/// schedule with room for all of it. With no schedule, minting is
+/// unrestricted. Otherwise a mint that no open window allows fails as
+/// `ErrorKind::ScheduleExceeded`, including any mint between windows.
+pub fn charge_mint_schedule(
+    state: &mut State,
+    amount: &TokenAmount,
+    epoch: ChainEpoch,
+) -> Result<(), ActorError> {
+    if state.mint_schedule.is_empty() {
+        return Ok(());
+    }
+    let mut open = state.mint_schedule.iter_mut().filter(|window| window.is_open(epoch)).peekable();
+    if open.peek().is_none() {
+        return Err(actor_error!(
+            kind: ScheduleExceeded,
+            USR_FORBIDDEN,
+            "no mint window is open at epoch {}",
+            epoch
+        ));
+    }
+    match open.find(|window| &window.minted + amount <= window.max_in_window) {
+        Some(window) => {
+            window.minted += amount;
+            Ok(())
+        }
+        None => Err(actor_error!(
+            kind: ScheduleExceeded,
+            USR_FORBIDDEN,
+            "mint of {} would exceed every open mint window",
+            amount
+        )),
+    }
+}
+
+/// A window in a new mint schedule, before anything is minted in it.
+#[derive(Serialize_tuple, Deserialize_tuple, Clone, Debug)]
+pub struct MintWindowParams {
+    pub start_epoch: ChainEpoch,
+    pub end_epoch: ChainEpoch,
+    #[serde(with = "bigint_ser")]
+    pub max_in_window: TokenAmount,
+}
+
+/// The input parameters for replacing the mint schedule. An empty schedule
+/// lifts every restriction.
+#[derive(Serialize_tuple, Deserialize_tuple, Clone, Debug)]
+pub struct SetMintScheduleParams {
+    pub windows: Vec<MintWindowParams>,
+}
+
+impl Cbor for SetMintScheduleParams {}
+
+impl TokenAmounts for SetMintScheduleParams {
+    fn token_amounts(&self) -> Vec<&TokenAmount> {
+        self.windows.iter().map(|window| &window.max_in_window).collect()
+    }
+}
+
+pub(crate) fn validate_mint_schedule(params: &SetMintScheduleParams) -> Result<(), ActorError> {
+    if params.windows.len() > MAX_MINT_WINDOWS {
+        return Err(actor_error!(
+            USR_ILLEGAL_ARGUMENT,
+            "mint schedule has {} windows, more than the maximum of {}",
+            params.windows.len(),
+            MAX_MINT_WINDOWS
+        ));
+    }
+    for window in &params.windows {
+        if window.start_epoch >= window.end_epoch {
+            return Err(actor_error!(
+                USR_ILLEGAL_ARGUMENT,
+                "mint window [{}, {}) is empty",
+                window.start_epoch,
+                window.end_epoch
+            ));
+        }
+        require_positive(&window.max_in_window)?;
+    }
+    Ok(())
+}
+
+/// Replace the mint schedule. Only the owner may call this. Windows may
+/// overlap, in which case a mint needs room in just one of them. Every window
+/// starts out with nothing minted in it, including one carried over from the
+/// old schedule.
+pub fn set_mint_schedule<R: Runtime>(
+    rt: &R,
+    params: SetMintScheduleParams,
+) -> Result<(), ActorError> {
+    validate_mint_schedule(&params)?;
+
+    let mut state = State::load_from(rt)?;
+    state.check_unlocked()?;
+
+    let resolver = |addr: &Address| rt.resolve_address(addr);
+    runtime_context(rt, &resolver).require_owner(&state, "set_mint_schedule")?;
+
+    state.mint_schedule = params
+        .windows
+        .into_iter()
+        .map(|window| MintWindow {
+            start_epoch: window.start_epoch,
+            end_epoch: window.end_epoch,
+            max_in_window: window.max_in_window,
+            minted: TokenAmount::from(0),
+        })
+        .collect();
+    state.save_to(rt)?;
+    Ok(())
+}
+
+/// The input parameters for a transfer.
+#[derive(Serialize_tuple, Deserialize_tuple, Clone, Debug)]
+pub struct TransferParams {
+    pub recipient: Address,
+    #[serde(with = "bigint_ser")]
+    pub amount: TokenAmount,
+}
+
+impl Cbor for TransferParams {}
+
+impl TokenAmounts for TransferParams {
+    fn token_amounts(&self) -> Vec<&TokenAmount> {
+        vec![&self.amount]
+    }
+}
+
+/// The input parameters for adjusting the supply cap.
+#[derive(Serialize_tuple, Deserialize_tuple, Clone, Debug)]
+pub struct SetMaxSupplyParams {
+    #[serde(with = "bigint_ser")]
+    pub new_max: TokenAmount,
+}
+
+impl Cbor for SetMaxSupplyParams {}
+
+impl TokenAmounts for SetMaxSupplyParams {
+    fn token_amounts(&self) -> Vec<&TokenAmount> {
+        vec![&self.new_max]
+    }
+}
+
+/// Raise or lower the supply cap. Only the owner may call this, and the new
+/// cap can never be set below the tokens already in circulation.
+pub fn set_max_supply<R: Runtime>(rt: &R, params: SetMaxSupplyParams) -> Result<(), ActorError> {
+    let mut state = State::load_from(rt)?;
+    state.check_unlocked()?;
+    let resolver = |addr: &Address| rt.resolve_address(addr);
+    runtime_context(rt, &resolver).require_owner(&state, "set_max_supply")?;
+
+    if params.new_max < state.total_supply {
+        return Err(actor_error!(
+            USR_ILLEGAL_ARGUMENT,
+            "new max supply {} is below the current total supply {}",
+            params.new_max,
+            state.total_supply
+        ));
+    }
+
+    state.max_supply = params.new_max;
+    state.save_to(rt)?;
+    Ok(())
+}
+
+/// The FRC42 method number of the FRC46 receiver hook, i.e. the hash of "Receive".
+pub const RECEIVER_HOOK_METHOD_NUM: MethodNum = 3726118371;
+
+/// The receiver hook type FRC46 tokens are delivered with, i.e. the FRC42
+/// hash of "FRC46".
+pub const FRC46_TOKEN_TYPE: u32 = 0x85223bdf;
+
+/// What a receiver hook is called with: a payload tagged with the standard
+/// it follows, so one hook can tell tokens of different standards apart.
+#[derive(Serialize_tuple, Deserialize_tuple, Clone, Debug)]
+pub struct UniversalReceiverParams {
+    pub type_: u32,
+    pub payload: RawBytes,
+}
+
+impl Cbor for UniversalReceiverParams {}
+
+/// The FRC46 payload delivered to a recipient's receiver hook.
+///
+/// The hook runs once the transfer or mint is saved, under the reentrancy
+/// lock, so a recipient that queries its balance from the hook gets the new
+/// value. If the hook rejects, the transfer fails, and the abort reverts
+/// everything it saved. `operator_data` carries the operator's memo, and
+/// `token_data` is always empty.
+#[derive(Serialize_tuple, Deserialize_tuple, Clone, Debug)]
+pub struct TokenReceivedParams {
+    pub from: ActorID,
+    pub to: ActorID,
+    pub operator: ActorID,
+    #[serde(with = "bigint_ser")]
+    pub amount: TokenAmount,
+    pub operator_data: RawBytes,
+    pub token_data: RawBytes,
+}
+
+impl Cbor for TokenReceivedParams {}
+
+/// The smallest amount tokens can be divided into, in base units. Every
+/// amount is a multiple of it.
+pub const TOKEN_GRANULARITY: u64 = 1;
+
+/// Returns true if the actor is an account actor or has no code yet. Neither
+/// can implement the receiver hook, so they always accept incoming tokens.
+fn accepts_without_hook<R: Runtime>(rt: &R, id: ActorID) -> bool {
+    rt.get_actor_code_cid(&Address::new_id(id)).is_none() || is_account_actor(rt, id)
+}
+
+/// Notify the recipient through its receiver hook, failing (and so reverting
+/// the whole transfer) if it rejects the tokens. Every contract recipient is
+/// notified unless the owner opted out: by constructing the token without
+/// receiver hooks, or by setting up a hook allowlist that leaves it out.
+/// Returns what the hook returned, or nothing if it wasn't called.
+fn call_receiver_hook<R: Runtime>(
+    rt: &R,
+    state: &mut State,
+    params: TokenReceivedParams,
+) -> Result<RawBytes, ActorError> {
+    if !state.require_receiver_hook || accepts_without_hook(rt, params.to) {
+        return Ok(RawBytes::default());
+    }
+    if !hook_allowed(rt.store(), state, params.to)? {
+        return Ok(RawBytes::default());
+    }
+
+    let receipt = send_receiver_hook(rt, state, &params)?;
+    if !receipt.exit_code.is_success() {
+        return Err(actor_error!(
+            kind: ReceiverRejected,
+            USR_FORBIDDEN,
+            "recipient rejected the transfer with exit code {:?}",
+            receipt.exit_code
+        ));
+    }
+    Ok(receipt.return_data)
+}
+
+/// Call the recipient's receiver hook under the reentrancy lock, whether or
+/// not it accepts.
+fn send_receiver_hook<R: Runtime>(
+    rt: &R,
+    state: &mut State,
+    params: &TokenReceivedParams,
+) -> Result<Receipt, ActorError> {
+    let serialization_error =
+        |err| actor_error!(USR_SERIALIZATION, "failed to serialize hook params: {:?}", err);
+    let payload = RawBytes::serialize(params).map_err(serialization_error)?;
+    let payload = UniversalReceiverParams { type_: FRC46_TOKEN_TYPE, payload };
+    let payload = RawBytes::serialize(payload).map_err(serialization_error)?;
+
+    state
+        .locked_send(rt, &Address::new_id(params.to), RECEIVER_HOOK_METHOD_NUM, payload)
+        .map_err(|err| {
+            actor_error!(USR_ILLEGAL_STATE, "failed to call receiver hook: {}", err.msg)
+        })
+}
+
+/// The set of contracts receiver hooks are called on, keyed by actor ID.
+pub type HookAllowlist<BS> = Hamt<BS, (), ActorID>;
+
+/// Returns true if receiver hooks may be called on `id`: always, while there
+/// is no allowlist, and otherwise only if it is on it.
+pub fn hook_allowed<BS: fvm_ipld_blockstore::Blockstore>(
+    store: &BS,
+    state: &State,
+    id: ActorID,
+) -> Result<bool, ActorError> {
+    let root = match &state.hook_allowlist {
+        Some(root) => root,
+        None => return Ok(true),
+    };
+    let allowlist: HookAllowlist<&BS> =
+        load_hamt(store, root, state.hamt_bit_width, "hook allowlist")?;
+    let entry = allowlist.get(&id).map_err(|err| {
+        actor_error!(USR_ILLEGAL_STATE, "failed to get hook allowlist entry: {:?}", err)
+    })?;
+    Ok(entry.is_some())
+}
+
+/// The input parameters for adding a contract to, or removing it from, the
+/// hook allowlist.
+#[derive(Serialize_tuple, Deserialize_tuple, Clone, Debug)]
+pub struct SetHookAllowedParams {
+    pub actor: Address,
+    pub allowed: bool,
+}
+
+impl Cbor for SetHookAllowedParams {}
+
+/// Add a contract to the hook allowlist, or remove it. Only the owner may
+/// call this. The first call creates the allowlist, and from then on hooks
+/// are only called on the contracts in it; contracts outside it receive
+/// transfers without being notified. Hooks are still only called at all if
+/// the token requires them.
+pub fn set_hook_allowed<R: Runtime>(
+    rt: &R,
+    params: SetHookAllowedParams,
+) -> Result<(), ActorError> {
+    let mut state = State::load_from(rt)?;
+    state.check_unlocked()?;
+
+    let resolver = |addr: &Address| rt.resolve_address(addr);
+    let ctx = runtime_context(rt, &resolver);
+    ctx.require_owner(&state, "set_hook_allowed")?;
+    let id = ctx.resolve(&params.actor)?;
+
+    let store = rt.store();
+    let mut allowlist: HookAllowlist<&R::Blockstore> = match &state.hook_allowlist {
+        Some(root) => load_hamt(store, root, state.hamt_bit_width, "hook allowlist")?,
+        None => Hamt::new_with_bit_width(store, state.hamt_bit_width),
+    };
+    if params.allowed {
+        allowlist.set(id, ()).map_err(|err| {
+            actor_error!(USR_ILLEGAL_STATE, "failed to set hook allowlist entry: {:?}", err)
+        })?;
+    } else {
+        allowlist.delete(&id).map_err(|err| {
+            actor_error!(USR_ILLEGAL_STATE, "failed to delete hook allowlist entry: {:?}", err)
+        })?;
+    }
+    let root = allowlist.flush().map_err(|err| {
+        actor_error!(USR_ILLEGAL_STATE, "failed to flush hook allowlist: {:?}", err)
+    })?;
+    state.hook_allowlist = Some(root);
+    state.save_to(rt)?;
+    Ok(())
+}
+
+/// The accounts that may neither send nor receive transfers, keyed by actor ID.
+pub type FrozenAccounts<BS> = Hamt<BS, (), ActorID>;
+
+/// Returns true if the owner has frozen `id`.
+pub fn is_account_frozen<BS: fvm_ipld_blockstore::Blockstore>(
+    store: &BS,
+    state: &State,
+    id: ActorID,
+) -> Result<bool, ActorError> {
+    let root = match &state.frozen {
+        Some(root) => root,
+        None => return Ok(false),
+    };
+    let frozen: FrozenAccounts<&BS> =
+        load_hamt(store, root, state.hamt_bit_width, "frozen accounts")?;
+    let entry = frozen.get(&id).map_err(|err| {
+        actor_error!(USR_ILLEGAL_STATE, "failed to get frozen account entry: {:?}", err)
+    })?;
+    Ok(entry.is_some())
+}
+
+/// Fail if `id` is frozen. Transfers check both ends.
+fn check_not_frozen<BS: fvm_ipld_blockstore::Blockstore>(
+    store: &BS,
+    state: &State,
+    id: ActorID,
+) -> Result<(), ActorError> {
+    if is_account_frozen(store, state, id)? {
+        return Err(actor_error!(kind: Frozen, USR_FORBIDDEN, "account {} is frozen", id));
+    }
+    Ok(())
+}
+
+/// The input parameters for freezing, unfreezing or checking an account.
+#[derive(Serialize_tuple, Deserialize_tuple, Clone, Debug)]
+pub struct FreezeAccountParams {
+    pub account: Address,
+}
+
+impl Cbor for FreezeAccountParams {}
+
+fn set_frozen<R: Runtime>(
+    rt: &R,
+    params: FreezeAccountParams,
+    frozen: bool,
+    method: &str,
+) -> Result<(), ActorError> {
+    let mut state = State::load_from(rt)?;
+    state.check_unlocked()?;
+
+    let resolver = |addr: &Address| rt.resolve_address(addr);
+    let ctx = runtime_context(rt, &resolver);
+    ctx.require_owner(&state, method)?;
+    let id = ctx.resolve(&params.account)?;
+
+    let store = rt.store();
+    let mut accounts: FrozenAccounts<&R::Blockstore> = match &state.frozen {
+        Some(root) => load_hamt(store, root, state.hamt_bit_width, "frozen accounts")?,
+        None => Hamt::new_with_bit_width(store, state.hamt_bit_width),
+    };
+    if frozen {
+        accounts.set(id, ()).map_err(|err| {
+            actor_error!(USR_ILLEGAL_STATE, "failed to set frozen account entry: {:?}", err)
+        })?;
+    } else {
+        accounts.delete(&id).map_err(|err| {
+            actor_error!(USR_ILLEGAL_STATE, "failed to delete frozen account entry: {:?}", err)
+        })?;
+    }
+    let root = accounts.flush().map_err(|err| {
+        actor_error!(USR_ILLEGAL_STATE, "failed to flush frozen accounts: {:?}", err)
+    })?;
+    state.frozen = Some(root);
+    state.save_to(rt)?;
+    Ok(())
+}
+
+/// Freeze `account`, so transfers and split transfers from or to it fail.
+/// Only the owner may call this. Its balance is left alone, and the owner
+/// can still move it with `force_transfer` or `split_balance`.
+pub fn freeze_account<R: Runtime>(rt: &R, params: FreezeAccountParams) -> Result<(), ActorError> {
+    set_frozen(rt, params, true, "freeze_account")
+}
+
+/// Lift a freeze on `account`. Only the owner may call this; unfreezing an
+/// account that isn't frozen does nothing.
+pub fn unfreeze_account<R: Runtime>(
+    rt: &R,
+    params: FreezeAccountParams,
+) -> Result<(), ActorError> {
+    set_frozen(rt, params, false, "unfreeze_account")
+}
+
+/// Returns true if `account` is frozen. Addresses that don't resolve aren't.
+pub fn is_frozen<R: Runtime>(rt: &R, params: FreezeAccountParams) -> Result<bool, ActorError> {
+    let state = State::load_from(rt)?;
+    match rt.resolve_address(&params.account) {
+        Some(id) => is_account_frozen(rt.store(), &state, id),
+        None => Ok(false),
+    }
+}
+
+/// The details of a completed transfer, tied to the state root it produced.
+#[derive(Serialize_tuple, Deserialize_tuple, Clone, Debug)]
+pub struct TransferReceipt {
+    pub from: ActorID,
+    pub to: ActorID,
+    #[serde(with = "bigint_ser")]
+    pub amount: TokenAmount,
+    pub epoch: ChainEpoch,
+    pub state_root: Cid,
+}
+
+impl Cbor for TransferReceipt {}
+
+/// The return value of a transfer that asks for a receipt: the receipt plus
+/// the blake2b-256 hash of its CBOR encoding, which clients can recompute to
+/// check the receipt is intact.
+#[derive(Serialize_tuple, Deserialize_tuple, Clone, Debug)]
+pub struct HashedReceipt {
+    pub receipt: TransferReceipt,
+    pub hash: Vec<u8>,
+}
+
+impl Cbor for HashedReceipt {}
+
+/// The input parameters for an FRC-0046 `Transfer`. `operator_data` is handed
+/// to the recipient's receiver hook and never stored. `TransferParams`, which
+/// has no room for it, remains the entry type of mints and batches.
+#[derive(Serialize_tuple, Deserialize_tuple, Clone, Debug)]
+pub struct Frc46TransferParams {
+    pub to: Address,
+    #[serde(with = "bigint_ser")]
+    pub amount: TokenAmount,
+    pub operator_data: RawBytes,
+}
+
+impl Cbor for Frc46TransferParams {}
+
+impl TokenAmounts for Frc46TransferParams {
+    fn token_amounts(&self) -> Vec<&TokenAmount> {
+        vec![&self.amount]
+    }
+}
+
+/// The FRC-0046 return value of a transfer: both parties' balances after it,
+/// and whatever the recipient's receiver hook returned, or nothing if no hook
+/// was called.
+#[derive(Serialize_tuple, Deserialize_tuple, Clone, Debug)]
+pub struct TransferReturn {
+    #[serde(with = "bigint_ser")]
+    pub from_balance: TokenAmount,
+    #[serde(with = "bigint_ser")]
+    pub to_balance: TokenAmount,
+    pub recipient_data: RawBytes,
+}
+
+impl Cbor for TransferReturn {}
+
+/// Transfer a token amount to `to`, passing `operator_data` to its receiver
+/// hook.
+pub fn transfer<R: Runtime>(
+    rt: &R,
+    params: Frc46TransferParams,
+) -> Result<TransferReturn, ActorError> {
+    let mut state = State::load_from(rt)?;
+    state.check_unlocked()?;
+    let ret = transfer_in(rt, &mut state, params)?;
+    state.save_to(rt)?;
+    Ok(ret)
+}
+
+/// Transfer a token amount to `to` as `transfer` does, but return the
+/// receipt for it, tied to the state root the transfer produced, together with
+/// its hash.
+pub fn transfer_with_receipt<R: Runtime>(
+    rt: &R,
+    params: Frc46TransferParams,
+) -> Result<RawBytes, ActorError> {
+    let transfer = TransferParams { recipient: params.to, amount: params.amount };
+    receipted_transfer(rt, transfer, params.operator_data, None)
+}
+
+/// Transfer against `state` without saving it.
+pub(crate) fn transfer_in<R: Runtime>(
+    rt: &R,
+    state: &mut State,
+    params: Frc46TransferParams,
+) -> Result<TransferReturn, ActorError> {
+    let transfer = TransferParams { recipient: params.to, amount: params.amount };
+    let data = params.operator_data;
+    let done = transfer_with_hook_data_in(rt, state, rt.caller(), &transfer, data, None)?;
+    Ok(TransferReturn {
+        from_balance: done.from_balance,
+        to_balance: done.to_balance,
+        recipient_data: done.recipient_data,
+    })
+}
+
+/// The longest memo a transfer may carry, in bytes.
+pub const MAX_MEMO_LEN: usize = 256;
+
+/// The input parameters for a transfer carrying a payment reference.
+#[derive(Serialize_tuple, Deserialize_tuple, Clone, Debug)]
+pub struct TransferMemoParams {
+    pub recipient: Address,
+    #[serde(with = "bigint_ser")]
+    pub amount: TokenAmount,
+    pub memo: Vec<u8>,
+}
+
+impl Cbor for TransferMemoParams {}
+
+impl TokenAmounts for TransferMemoParams {
+    fn token_amounts(&self) -> Vec<&TokenAmount> {
+        vec![&self.amount]
+    }
+}
+
+pub(crate) fn validate_memo(memo: &[u8]) -> Result<(), ActorError> {
+    if memo.len() > MAX_MEMO_LEN {
+        return Err(actor_error!(
+            USR_ILLEGAL_ARGUMENT,
+            "memo is {} bytes, more than the maximum of {}",
+            memo.len(),
+            MAX_MEMO_LEN
+        ));
+    }
+    Ok(())
+}
+
+/// Transfer a token amount with a memo for reconciliation. The memo is never
+/// stored: it is recorded on chain in the message params, and handed to the
+/// recipient's receiver hook as its `data`.
+pub fn transfer_with_memo<R: Runtime>(
+    rt: &R,
+    params: TransferMemoParams,
+) -> Result<RawBytes, ActorError> {
+    validate_memo(&params.memo)?;
+    let transfer = TransferParams { recipient: params.recipient, amount: params.amount };
+    receipted_transfer(rt, transfer, RawBytes::new(params.memo), None)
+}
+
+/// The input parameters for a transfer that expires.
+#[derive(Serialize_tuple, Deserialize_tuple, Clone, Debug)]
+pub struct TransferUntilParams {
+    pub recipient: Address,
+    #[serde(with = "bigint_ser")]
+    pub amount: TokenAmount,
+    /// The last epoch the transfer may execute in.
+    pub deadline: ChainEpoch,
+}
+
+impl Cbor for TransferUntilParams {}
+
+impl TokenAmounts for TransferUntilParams {
+    fn token_amounts(&self) -> Vec<&TokenAmount> {
+        vec![&self.amount]
+    }
+}
+
+/// Transfer a token amount, unless the message is included after `deadline`.
+/// A transfer in the deadline epoch itself still goes through.
+pub fn transfer_until<R: Runtime>(
+    rt: &R,
+    params: TransferUntilParams,
+) -> Result<RawBytes, ActorError> {
+    let epoch = rt.curr_epoch();
+    if epoch > params.deadline {
+        return Err(actor_error!(
+            USR_ILLEGAL_ARGUMENT,
+            "transfer expired at epoch {}, and it is now epoch {}",
+            params.deadline,
+            epoch
+        ));
+    }
+    let transfer = TransferParams { recipient: params.recipient, amount: params.amount };
+    receipted_transfer(rt, transfer, RawBytes::default(), None)
+}
+
+/// What a completed transfer left behind.
+struct CompletedTransfer {
+    sender: ActorID,
+    recipient: ActorID,
+    from_balance: TokenAmount,
+    to_balance: TokenAmount,
+    /// What the recipient's receiver hook returned, if it was called.
+    recipient_data: RawBytes,
+}
+
+/// Transfer from the caller and return the receipt for it, tied to the state
+/// root the transfer produced, together with its hash.
+fn receipted_transfer<R: Runtime>(
+    rt: &R,
+    params: TransferParams,
+    data: RawBytes,
+    key: Option<&IdempotencyKey>,
+) -> Result<RawBytes, ActorError> {
+    let mut state = State::load_from(rt)?;
+    state.check_unlocked()?;
+    let done = transfer_with_hook_data_in(rt, &mut state, rt.caller(), &params, data, key)?;
+    let root = state.save_to(rt)?;
+
+    let receipt = TransferReceipt {
+        from: done.sender,
+        to: done.recipient,
+        amount: params.amount,
+        epoch: rt.curr_epoch(),
+        state_root: root,
+    };
+    let serialized = to_vec(&receipt)
+        .map_err(|err| actor_error!(USR_SERIALIZATION, "failed to serialize receipt: {:?}", err))?;
+    let ret =
+        HashedReceipt { hash: Code::Blake2b256.digest(&serialized).digest().to_vec(), receipt };
+    RawBytes::serialize(&ret)
+        .map_err(|err| actor_error!(USR_SERIALIZATION, "failed to serialize receipt: {:?}", err))
+}
+
+/// Transfer a token amount out of `from` against `state`, passing `data` to
+/// the recipient's receiver hook, and leave saving `state` to the caller.
+/// Unless `from` is the caller, the caller must be one of its operators or
+/// have enough allowance from it.
+fn transfer_with_hook_data_in<R: Runtime>(
+    rt: &R,
+    state: &mut State,
+    from: ActorID,
+    params: &TransferParams,
+    data: RawBytes,
+    key: Option<&IdempotencyKey>,
+) -> Result<CompletedTransfer, ActorError> {
+    let prepared = prepare_transfer(rt, state, from, params, data, key)?;
+    let after = &prepared.after;
+    let balances = load_balances(rt.store(), &after.balances, after.hamt_bit_width)?;
+    let from_balance = get_balance(&balances, prepared.sender)?;
+    let to_balance = get_balance(&balances, prepared.recipient)?;
+    *state = prepared.after;
+
+    // Let the recipient react to the incoming tokens. The hook runs on the
+    // credited state, saved under the lock. If it rejects, or the send fails,
+    // the error aborts the message, which reverts the save.
+    let recipient_data = call_receiver_hook(rt, state, prepared.hook)?;
+    Ok(CompletedTransfer {
+        sender: prepared.sender,
+        recipient: prepared.recipient,
+        from_balance,
+        to_balance,
+        recipient_data,
+    })
+}
+
+/// A transfer worked out against the current state, but not yet persisted.
+struct PreparedTransfer {
+    /// The state after the transfer. Its blocks are written to the store, but
+    /// nothing references them until it is saved.
+    after: State,
+    sender: ActorID,
+    recipient: ActorID,
+    /// What the recipient's receiver hook is told it received.
+    hook: TokenReceivedParams,
+}
+
+/// Work out a transfer out of `from` without persisting it. A caller other
+/// than `from` spends its allowance, unless it is an operator.
+fn prepare_transfer<R: Runtime>(
+    rt: &R,
+    state: &State,
+    from: ActorID,
+    params: &TransferParams,
+    data: RawBytes,
+    key: Option<&IdempotencyKey>,
+) -> Result<PreparedTransfer, ActorError> {
+    let resolver = |addr: &Address| rt.resolve_address(addr);
+    // The token logic moves tokens out of the context's caller.
+    let ctx = Context { caller: from, ..runtime_context(rt, &resolver) };
+    let sender_id = from;
+    let mut after = state.clone();
+    if from != rt.caller() {
+        spend_allowance(rt.store(), &mut after, from, rt.caller(), &params.amount)?;
+    }
+    let params = normalize_params(rt, params)?;
+
+    if let Some(key) = key {
+        consume_idempotency_key(rt.store(), &mut after, key, rt.curr_epoch())?;
+    }
+    check_locks(rt.store(), &mut after, sender_id, &params.amount, rt.curr_epoch())?;
+    charge_rate_limit(rt.store(), &mut after, sender_id, &params.amount, rt.curr_epoch())?;
+    let recipient_id = transfer_tokens(&ctx, &mut after, &params)?;
+    record_transfer(rt.store(), &mut after, TransferRecord {
+        from: sender_id,
+        to: recipient_id,
+        amount: params.amount.clone(),
+        epoch: rt.curr_epoch(),
+    })?;
+
+    let hook = TokenReceivedParams {
+        from: sender_id,
+        to: recipient_id,
+        operator: rt.caller(),
+        amount: &params.amount - transfer_fee(&after, &params.amount),
+        operator_data: data,
+        token_data: RawBytes::default(),
+    };
+    Ok(PreparedTransfer { after, sender: sender_id, recipient: recipient_id, hook })
+}
+
+/// Transfer only if the recipient accepts, and return whether it did, rather
+/// than aborting when it declines. A contract recipient is always asked
+/// through its receiver hook, whatever the token's hook settings; an account
+/// recipient always accepts. A declined transfer leaves the state untouched.
+///
+/// Unlike other transfers, this asks the hook before saving the transfer: a
+/// decline returns instead of aborting, so there would be nothing to revert
+/// a save made first.
+pub fn safe_transfer<R: Runtime>(rt: &R, params: TransferParams) -> Result<bool, ActorError> {
+    let mut state = State::load_from(rt)?;
+    state.check_unlocked()?;
+    let prepared = prepare_transfer(rt, &state, rt.caller(), &params, RawBytes::default(), None)?;
+
+    if !accepts_without_hook(rt, prepared.recipient) {
+        let receipt = send_receiver_hook(rt, &mut state, &prepared.hook)?;
+        if !receipt.exit_code.is_success() {
+            return Ok(false);
+        }
+    }
+    prepared.after.save_to(rt)?;
+    Ok(true)
+}
+
+/// The input parameters for an FRC-0046 transfer out of another account.
+/// `operator_data` is handed to the recipient's receiver hook and never
+/// stored.
+#[derive(Serialize_tuple, Deserialize_tuple, Clone, Debug)]
+pub struct TransferFromParams {
+    pub from: Address,
+    pub to: Address,
+    #[serde(with = "bigint_ser")]
+    pub amount: TokenAmount,
+    pub operator_data: RawBytes,
+}
+
+impl Cbor for TransferFromParams {}
+
+impl TokenAmounts for TransferFromParams {
+    fn token_amounts(&self) -> Vec<&TokenAmount> {
+        vec![&self.amount]
+    }
+}
+
+/// The FRC-0046 return value of a transfer out of another account: both
+/// parties' balances and the caller's remaining allowance from `from` after
+/// it, and whatever the recipient's receiver hook returned.
+#[derive(Serialize_tuple, Deserialize_tuple, Clone, Debug)]
+pub struct TransferFromReturn {
+    #[serde(with = "bigint_ser")]
+    pub from_balance: TokenAmount,
+    #[serde(with = "bigint_ser")]
+    pub to_balance: TokenAmount,
+    #[serde(with = "bigint_ser")]
+    pub allowance: TokenAmount,
+    pub recipient_data: RawBytes,
+}
+
+impl Cbor for TransferFromReturn {}
+
+/// Transfer a token amount out of `from`. The caller must be `from`, one of
+/// its operators, or a spender whose allowance covers the amount, which the
+/// transfer then draws down. Everything else about the transfer, from locks
+/// and rate limits to fees and receiver hooks, applies as if `from` had sent
+/// it; the hook is told the caller was the operator.
+pub fn transfer_from<R: Runtime>(
+    rt: &R,
+    params: TransferFromParams,
+) -> Result<TransferFromReturn, ActorError> {
+    let mut state = State::load_from(rt)?;
+    // A spender `approve_and_call` is calling may spend what it was approved
+    // for, although the lock is held.
+    let approved = |(owner, spender): (ActorID, ActorID)| {
+        spender == rt.caller() && rt.resolve_address(&params.from) == Some(owner)
+    };
+    if !state.approved_call.map_or(false, approved) {
+        state.check_unlocked()?;
+    }
+    let ret = transfer_from_in(rt, &mut state, params)?;
+    state.save_to(rt)?;
+    Ok(ret)
+}
+
+/// Transfer out of another account against `state` without saving it.
+pub(crate) fn transfer_from_in<R: Runtime>(
+    rt: &R,
+    state: &mut State,
+    params: TransferFromParams,
+) -> Result<TransferFromReturn, ActorError> {
+    let resolver = |addr: &Address| rt.resolve_address(addr);
+    let from = runtime_context(rt, &resolver).resolve(&params.from)?;
+    let transfer = TransferParams { recipient: params.to, amount: params.amount };
+    let done = transfer_with_hook_data_in(rt, state, from, &transfer, params.operator_data, None)?;
+    Ok(TransferFromReturn {
+        from_balance: done.from_balance,
+        to_balance: done.to_balance,
+        allowance: get_allowance(rt.store(), state, from, rt.caller())?,
+        recipient_data: done.recipient_data,
+    })
+}
+
+/// The most transfers `State::recent_transfers` keeps.
+pub const MAX_RECENT_TRANSFERS: usize = 128;
+
+/// A transfer as kept in `State::recent_transfers`.
+#[derive(Serialize_tuple, Deserialize_tuple, Clone, Debug, PartialEq)]
+pub struct TransferRecord {
+    pub from: ActorID,
+    pub to: ActorID,
+    #[serde(with = "bigint_ser")]
+    pub amount: TokenAmount,
+    pub epoch: ChainEpoch,
+}
+
+fn load_recent_transfers<BS: fvm_ipld_blockstore::Blockstore>(
+    store: &BS,
+    state: &State,
+) -> Result<Vec<TransferRecord>, ActorError> {
+    let root = match &state.recent_transfers {
+        Some(root) => root,
+        None => return Ok(Vec::new()),
+    };
+    match store.get_cbor(root) {
+        Ok(Some(records)) => Ok(records),
+        Ok(None) => Err(actor_error!(
+            USR_ILLEGAL_STATE,
+            "recent transfers block {} does not exist",
+            root
+        )),
+        Err(err) => {
+            Err(actor_error!(USR_ILLEGAL_STATE, "failed to load recent transfers: {:?}", err))
+        }
+    }
+}
+
+/// Append a transfer to the recent transfers, evicting the oldest once there
+/// are `MAX_RECENT_TRANSFERS` of them.
+fn record_transfer<BS: fvm_ipld_blockstore::Blockstore>(
+    store: &BS,
+    state: &mut State,
+    record: TransferRecord,
+) -> Result<(), ActorError> {
+    let mut records = load_recent_transfers(store, state)?;
+    let excess = (records.len() + 1).saturating_sub(MAX_RECENT_TRANSFERS);
+    records.drain(..excess);
+    records.push(record);
+    let root = store.put_cbor(&records, Code::Blake2b256).map_err(|err| {
+        actor_error!(USR_ILLEGAL_STATE, "failed to store recent transfers: {:?}", err)
+    })?;
+    state.recent_transfers = Some(root);
+    Ok(())
+}
+
+/// The input parameters for reading the recent transfers.
+#[derive(Serialize_tuple, Deserialize_tuple, Clone, Debug)]
+pub struct RecentTransfersParams {
+    pub limit: u32,
+}
+
+impl Cbor for RecentTransfersParams {}
+
+/// Return up to `limit` of the most recent transfers, oldest first. Only the
+/// last `MAX_RECENT_TRANSFERS` are kept.
+pub fn recent_transfers<R: Runtime>(
+    rt: &R,
+    params: RecentTransfersParams,
+) -> Result<Vec<TransferRecord>, ActorError> {
+    let state = State::load_from(rt)?;
+    let mut records = load_recent_transfers(rt.store(), &state)?;
+    let skip = records.len().saturating_sub(params.limit as usize);
+    Ok(records.split_off(skip))
+}
+
+/// A key a client attaches to a mint or transfer so a retried or relayed
+/// duplicate of the same message can't take effect twice.
+pub type IdempotencyKey = [u8; 32];
+
+/// The idempotency keys seen in one window, as a HAMT set. Each key maps to
+/// the epoch it was used at.
+pub type UsedKeySet<BS> = Hamt<BS, ChainEpoch, BytesKey>;
+
+/// How many epochs a used idempotency key is remembered for, at least. A key
+/// is forgotten at the end of the window after the one it was used in, so
+/// the stored keys never span more than two windows.
+pub const IDEMPOTENCY_WINDOW: ChainEpoch = 2880;
+
+/// The idempotency keys used in the current window and the one before it.
+#[derive(Serialize_tuple, Deserialize_tuple, Clone, Debug, PartialEq)]
+pub struct UsedKeys {
+    /// The window `current` covers, as `epoch / IDEMPOTENCY_WINDOW`.
+    pub window: ChainEpoch,
+    pub current: Cid,
+    /// The keys from window `window - 1`, if any were used then.
+    pub previous: Option<Cid>,
+}
+
+/// Record `key` as used at `epoch`, failing as `ErrorKind::DuplicateMessage`
+/// if it was already used within the last `IDEMPOTENCY_WINDOW` epochs.
+/// Moving into a new window drops the keys from two windows back.
+pub fn consume_idempotency_key<BS: fvm_ipld_blockstore::Blockstore>(
+    store: &BS,
+    state: &mut State,
+    key: &IdempotencyKey,
+    epoch: ChainEpoch,
+) -> Result<(), ActorError> {
+    let window = epoch / IDEMPOTENCY_WINDOW;
+    let (current_root, previous_root) = match &state.used_keys {
+        Some(used) if used.window == window => (Some(used.current), used.previous),
+        Some(used) if used.window + 1 == window => (None, Some(used.current)),
+        _ => (None, None),
+    };
+
+    let key = BytesKey(key.to_vec());
+    let what = "idempotency keys";
+    let mut current: UsedKeySet<&BS> = match &current_root {
+        Some(root) => load_hamt(store, root, DEFAULT_HAMT_BIT_WIDTH, what)?,
+        None => Hamt::new_with_bit_width(store, DEFAULT_HAMT_BIT_WIDTH),
+    };
+    let previous: Option<UsedKeySet<&BS>> = match &previous_root {
+        Some(root) => Some(load_hamt(store, root, DEFAULT_HAMT_BIT_WIDTH, what)?),
+        None => None,
+    };
+    for keys in std::iter::once(&current).chain(previous.as_ref()) {
+        let used_at = keys.get(&key).map_err(|err| {
+            actor_error!(USR_ILLEGAL_STATE, "failed to get idempotency key: {:?}", err)
+        })?;
+        if let Some(used_at) = used_at {
+            return Err(actor_error!(
+                kind: DuplicateMessage,
+                USR_ILLEGAL_ARGUMENT,
+                "idempotency key was already used at epoch {}",
+                used_at
+            ));
+        }
+    }
+
+    current.set(key, epoch).map_err(|err| {
+        actor_error!(USR_ILLEGAL_STATE, "failed to set idempotency key: {:?}", err)
+    })?;
+    let current = current.flush().map_err(|err| {
+        actor_error!(USR_ILLEGAL_STATE, "failed to flush idempotency keys: {:?}", err)
+    })?;
+    state.used_keys = Some(UsedKeys { window, current, previous: previous_root });
+    Ok(())
+}
+
+/// The input parameters for a mint or transfer that takes effect at most
+/// once per idempotency key. Without a key it behaves like a plain mint or
+/// transfer.
+#[derive(Serialize_tuple, Deserialize_tuple, Clone, Debug)]
+pub struct IdempotentTransferParams {
+    pub recipient: Address,
+    #[serde(with = "bigint_ser")]
+    pub amount: TokenAmount,
+    pub idempotency_key: Option<IdempotencyKey>,
+}
+
+impl Cbor for IdempotentTransferParams {}
+
+impl TokenAmounts for IdempotentTransferParams {
+    fn token_amounts(&self) -> Vec<&TokenAmount> {
+        vec![&self.amount]
+    }
+}
+
+/// Mint, rejecting a replay of an idempotency key already used within the
+/// last `IDEMPOTENCY_WINDOW` epochs.
+pub fn mint_idempotent<R: Runtime>(
+    rt: &R,
+    params: IdempotentTransferParams,
+) -> Result<(), ActorError> {
+    let key = params.idempotency_key;
+    let mint_params = TransferParams { recipient: params.recipient, amount: params.amount };
+    mint_with_key(rt, mint_params, key.as_ref())?;
+    Ok(())
+}
+
+/// Transfer, rejecting a replay of an idempotency key already used within the
+/// last `IDEMPOTENCY_WINDOW` epochs.
+pub fn transfer_idempotent<R: Runtime>(
+    rt: &R,
+    params: IdempotentTransferParams,
+) -> Result<RawBytes, ActorError> {
+    let key = params.idempotency_key;
+    let transfer = TransferParams { recipient: params.recipient, amount: params.amount };
+    receipted_transfer(rt, transfer, RawBytes::default(), key.as_ref())
+}
+
+/// The predicted outcome of a transfer.
+#[derive(Serialize_tuple, Deserialize_tuple, Clone, Debug, PartialEq)]
+pub struct SimulationResult {
+    pub would_succeed: bool,
+    /// Why the transfer would fail, if it would.
+    pub reason: Option<String>,
+    #[serde(with = "bigint_ser")]
+    pub from_balance_after: TokenAmount,
+    #[serde(with = "bigint_ser")]
+    pub to_balance_after: TokenAmount,
+}
+
+impl Cbor for SimulationResult {}
+
+/// The ID a simulated transfer credits a recipient that doesn't exist yet,
+/// which a real transfer would create an account for first.
+const SIMULATED_NEW_ACCOUNT: ActorID = ActorID::MAX;
+
+/// Run the caller's transfer through the same logic as `transfer`, without
+/// persisting anything, and report how it would turn out. If it would fail,
+/// the balances reported are the current ones.
+///
+/// The HAMT nodes it writes go to the runtime's blockstore, which for the FVM
+/// buffers them until the state root is set, and this never sets it. The
+/// recipient's receiver hook isn't called, so a transfer the hook would
+/// reject is still reported as succeeding.
+pub fn simulate_transfer<R: Runtime>(
+    rt: &R,
+    params: TransferParams,
+) -> Result<SimulationResult, ActorError> {
+    let state = State::load_from(rt)?;
+
+    // A key address recipient that doesn't resolve would be created by the
+    // transfer, so it's simulated as a fresh account.
+    let creates_account = rt.resolve_address(&params.recipient).is_none()
+        && matches!(params.recipient.protocol(), Protocol::Secp256k1 | Protocol::BLS);
+    let resolver = |addr: &Address| match rt.resolve_address(addr) {
+        None if creates_account && *addr == params.recipient => Some(SIMULATED_NEW_ACCOUNT),
+        id => id,
+    };
+    let ctx = runtime_context(rt, &resolver);
+
+    let mut after = state.clone();
+    let epoch = rt.curr_epoch();
+    let outcome = check_locks(ctx.store, &mut after, ctx.caller, &params.amount, epoch)
+        .and_then(|_| charge_rate_limit(ctx.store, &mut after, ctx.caller, &params.amount, epoch))
+        .and_then(|_| transfer_tokens(&ctx, &mut after, &params));
+    let (would_succeed, reason, balances_root) = match outcome {
+        Ok(_) => (true, None, after.balances),
+        Err(err) => (false, Some(err.msg), state.balances),
+    };
+
+    let balances = load_balances(ctx.store, &balances_root, state.hamt_bit_width)?;
+    let to_balance_after = match ctx.resolve(&params.recipient) {
+        Ok(id) => get_balance(&balances, id)?,
+        Err(_) => TokenAmount::from(0),
+    };
+    Ok(SimulationResult {
+        would_succeed,
+        reason,
+        from_balance_after: get_balance(&balances, ctx.caller)?,
+        to_balance_after,
+    })
+}
+
+/// The input parameters for checking whether an account can receive tokens.
+#[derive(Serialize_tuple, Deserialize_tuple, Clone, Debug)]
+pub struct CanReceiveParams {
+    pub account: Address,
+    /// The amount the transfer would send.
+    #[serde(with = "bigint_ser")]
+    pub amount: TokenAmount,
+}
+
+impl Cbor for CanReceiveParams {}
+
+impl TokenAmounts for CanReceiveParams {
+    fn token_amounts(&self) -> Vec<&TokenAmount> {
+        vec![&self.amount]
+    }
+}
+
+/// Whether an account can receive tokens, and if not, why.
+#[derive(Serialize_tuple, Deserialize_tuple, Clone, Debug, PartialEq)]
+pub struct Eligibility {
+    pub eligible: bool,
+    pub reason: Option<String>,
+}
+
+impl Cbor for Eligibility {}
+
+/// Run the checks a transfer of `amount` makes on its recipient, without
+/// moving tokens or creating an account: the token must not be paused, the
+/// amount must meet the minimum, and the recipient must exist, not be frozen
+/// and not take the token past its holder cap. A contract's receiver hook
+/// can still reject the transfer itself.
+pub fn can_receive<R: Runtime>(
+    rt: &R,
+    params: CanReceiveParams,
+) -> Result<Eligibility, ActorError> {
+    let state = State::load_from(rt)?;
+    Ok(match check_can_receive(rt, &state, &params) {
+        Ok(()) => Eligibility { eligible: true, reason: None },
+        Err(err) => Eligibility { eligible: false, reason: Some(err.msg) },
+    })
+}
+
+fn check_can_receive<R: Runtime>(
+    rt: &R,
+    state: &State,
+    params: &CanReceiveParams,
+) -> Result<(), ActorError> {
+    state.check_not_paused()?;
+    require_positive(&params.amount)?;
+    check_min_transfer(state, &params.amount)?;
+
+    // An account the transfer would create has no balance and can't be frozen.
+    let new_holder = match check_recipient(rt, &params.account)? {
+        Some(id) => {
+            check_not_frozen(rt.store(), state, id)?;
+            let balances = load_balances(rt.store(), &state.balances, state.hamt_bit_width)?;
+            get_balance(&balances, id)? == TokenAmount::from(0)
+        }
+        None => true,
+    };
+    if new_holder {
+        let mut after = state.clone();
+        after.holder_count += 1;
+        check_holder_cap(&after, state.holder_count)?;
+    }
+    Ok(())
+}
+
+/// Fail if `amount` is below `State::min_transfer`.
+fn check_min_transfer(state: &State, amount: &TokenAmount) -> Result<(), ActorError> {
+    if *amount < state.min_transfer {
+        return Err(actor_error!(
+            kind: InvalidAmount,
+            USR_ILLEGAL_ARGUMENT,
+            "transfer of {} is below the minimum of {}",
+            amount,
+            state.min_transfer
+        ));
+    }
+    Ok(())
+}
+
+/// Move tokens from the caller to the recipient, returning the recipient's ID.
+/// If the token charges a transfer fee, the fee goes to the fee collector and
+/// the recipient is credited the rest.
+pub fn transfer_tokens<BS: fvm_ipld_blockstore::Blockstore>(
+    ctx: &Context<BS>,
+    state: &mut State,
+    params: &TransferParams,
+) -> Result<ActorID, ActorError> {
+    require_positive(&params.amount)?;
+    check_min_transfer(state, &params.amount)?;
+    let recipient_id = ctx.resolve(&params.recipient)?;
+    check_not_frozen(ctx.store, state, ctx.caller)?;
+    check_not_frozen(ctx.store, state, recipient_id)?;
+
+    let fee = transfer_fee(state, &params.amount);
+    let zero = TokenAmount::from(0);
+    let net = &params.amount - &fee;
+    if fee == zero {
+        move_tokens(ctx, state, ctx.caller, recipient_id, &params.amount)?;
+        return Ok(recipient_id);
+    }
+
+    // Check the whole amount up front, so the two moves succeed or fail
+    // together.
+    let balances = load_balances(ctx.store, &state.balances, state.hamt_bit_width)?;
+    if get_balance(&balances, ctx.caller)? < params.amount {
+        return Err(actor_error!(
+            kind: InsufficientBalance,
+            USR_INSUFFICIENT_FUNDS,
+            "sender has insufficient balance"
+        ));
+    }
+    if ctx.caller == recipient_id {
+        return Err(actor_error!(USR_ILLEGAL_ARGUMENT, "cannot send to self"));
+    }
+
+    if net > zero {
+        move_tokens(ctx, state, ctx.caller, recipient_id, &net)?;
+    }
+    let collector = match &state.fee_collector {
+        Some(collector) => ctx.resolve(collector)?,
+        None => return Err(actor_error!(USR_ILLEGAL_STATE, "transfer fee has no collector")),
+    };
+    // A collector sending tokens keeps its own fee.
+    if collector != ctx.caller {
+        move_tokens(ctx, state, ctx.caller, collector, &fee)?;
+    }
+    Ok(recipient_id)
+}
+
+/// The largest transfer fee, in basis points: the whole transfer.
+pub const MAX_FEE_BPS: u16 = 10_000;
+
+/// The fee charged on a transfer of `amount`, rounded down to a whole base
+/// unit.
+pub fn transfer_fee(state: &State, amount: &TokenAmount) -> TokenAmount {
+    if state.fee_bps == 0 {
+        return TokenAmount::from(0);
+    }
+    amount * TokenAmount::from(state.fee_bps) / TokenAmount::from(MAX_FEE_BPS)
+}
+
+/// The input parameters for setting the transfer fee.
+#[derive(Serialize_tuple, Deserialize_tuple, Clone, Debug)]
+pub struct SetTransferFeeParams {
+    pub fee_bps: u16,
+    /// Where fees go. Required unless `fee_bps` is zero.
+    pub fee_collector: Option<Address>,
+}
+
+impl Cbor for SetTransferFeeParams {}
+
+pub(crate) fn validate_transfer_fee(params: &SetTransferFeeParams) -> Result<(), ActorError> {
+    if params.fee_bps > MAX_FEE_BPS {
+        return Err(actor_error!(
+            USR_ILLEGAL_ARGUMENT,
+            "fee of {} bps is more than the maximum of {}",
+            params.fee_bps,
+            MAX_FEE_BPS
+        ));
+    }
+    if params.fee_bps > 0 && params.fee_collector.is_none() {
+        return Err(actor_error!(USR_ILLEGAL_ARGUMENT, "a transfer fee needs a fee collector"));
+    }
+    Ok(())
+}
+
+/// Set the transfer fee and where it goes. Only the owner may call this. A
+/// zero fee turns fees off.
+pub fn set_transfer_fee<R: Runtime>(
+    rt: &R,
+    params: SetTransferFeeParams,
+) -> Result<(), ActorError> {
+    validate_transfer_fee(&params)?;
+
+    let mut state = State::load_from(rt)?;
+    state.check_unlocked()?;
+
+    let resolver = |addr: &Address| rt.resolve_address(addr);
+    runtime_context(rt, &resolver).require_owner(&state, "set_transfer_fee")?;
+
+    let collector = match &params.fee_collector {
+        Some(addr) => Some(Address::new_id(normalize_recipient(rt, addr)?)),
+        None => None,
+    };
+    state.fee_bps = params.fee_bps;
+    state.fee_collector = collector;
+    state.save_to(rt)?;
+    Ok(())
+}
+
+/// The input parameters for setting the minimum transfer amount.
+#[derive(Serialize_tuple, Deserialize_tuple, Clone, Debug)]
+pub struct SetMinTransferParams {
+    #[serde(with = "bigint_ser")]
+    pub min_transfer: TokenAmount,
+}
+
+impl Cbor for SetMinTransferParams {}
+
+impl TokenAmounts for SetMinTransferParams {
+    fn token_amounts(&self) -> Vec<&TokenAmount> {
+        vec![&self.min_transfer]
+    }
+}
+
+/// Set the smallest amount a transfer may move. Only the owner may call this.
+/// Zero turns the minimum off.
+pub fn set_min_transfer<R: Runtime>(
+    rt: &R,
+    params: SetMinTransferParams,
+) -> Result<(), ActorError> {
+    if params.min_transfer < TokenAmount::from(0) {
+        return Err(actor_error!(
+            USR_ILLEGAL_ARGUMENT,
+            "minimum transfer {} must not be negative",
+            params.min_transfer
+        ));
+    }
+
+    let mut state = State::load_from(rt)?;
+    state.check_unlocked()?;
+
+    let resolver = |addr: &Address| rt.resolve_address(addr);
+    runtime_context(rt, &resolver).require_owner(&state, "set_min_transfer")?;
+
+    state.min_transfer = params.min_transfer;
+    state.save_to(rt)?;
+    Ok(())
+}
+
+/// The input parameters for capping the number of holders.
+#[derive(Serialize_tuple, Deserialize_tuple, Clone, Debug)]
+pub struct SetMaxHoldersParams {
+    pub max_holders: Option<u64>,
+}
+
+impl Cbor for SetMaxHoldersParams {}
+
+/// Cap the number of accounts that may hold a balance, or clear the cap with
+/// None. Only the owner may call this. Mints and transfers that would bring
+/// in a holder beyond the cap fail. A cap below the current holder count
+/// doesn't take balances away from anyone; it only keeps new holders out.
+pub fn set_max_holders<R: Runtime>(
+    rt: &R,
+    params: SetMaxHoldersParams,
+) -> Result<(), ActorError> {
+    let mut state = State::load_from(rt)?;
+    state.check_unlocked()?;
+
+    let resolver = |addr: &Address| rt.resolve_address(addr);
+    runtime_context(rt, &resolver).require_owner(&state, "set_max_holders")?;
+
+    state.max_holders = params.max_holders;
+    state.save_to(rt)?;
+    Ok(())
+}
+
+/// Debit `amount` from one account and credit it to another. Any check on
+/// who may move the sender's tokens must already have been made.
+pub fn move_tokens<BS: fvm_ipld_blockstore::Blockstore>(
+    ctx: &Context<BS>,
+    state: &mut State,
+    sender_id: ActorID,
+    recipient_id: ActorID,
+    amount: &TokenAmount,
+) -> Result<(), ActorError> {
+    state.check_not_paused()?;
+    require_positive(amount)?;
+    let holders_before = state.holder_count;
+    let mut balances = load_balances(ctx.store, &state.balances, state.hamt_bit_width)?;
+
+    // Sender has insufficient balance.
+    let sender_bal = get_balance(&balances, sender_id)?;
+    if sender_bal < *amount {
+        return Err(actor_error!(
+            kind: InsufficientBalance,
+            USR_INSUFFICIENT_FUNDS,
+            "sender has insufficient balance"
+        ));
+    }
+
+    // Forbid sends to self.
+    if sender_id == recipient_id {
+        return Err(actor_error!(USR_ILLEGAL_ARGUMENT, "cannot send to self"));
+    }
+
+    let recipient_bal = get_balance(&balances, recipient_id)?;
+    let holders = &mut state.holder_count;
+    let new_bal = &sender_bal - amount;
+    set_holder_balance(holders, &mut balances, sender_id, &sender_bal, new_bal)?;
+    let new_bal = &recipient_bal + amount;
+    set_holder_balance(holders, &mut balances, recipient_id, &recipient_bal, new_bal)?;
+
+    check_holder_cap(state, holders_before)?;
+    state.balances = flush_balances(&mut balances)?;
+    Ok(())
+}
+
+/// Take `amount` out of `account`'s balance and the total supply.
+fn burn_tokens<BS: fvm_ipld_blockstore::Blockstore>(
+    store: &BS,
+    state: &mut State,
+    account: ActorID,
+    amount: &TokenAmount,
+) -> Result<(), ActorError> {
+    state.check_not_paused()?;
+    let mut balances = load_balances(store, &state.balances, state.hamt_bit_width)?;
+    let bal = get_balance(&balances, account)?;
+    if bal < *amount {
+        return Err(actor_error!(
+            kind: InsufficientBalance,
+            USR_INSUFFICIENT_FUNDS,
+            "sender has insufficient balance"
+        ));
+    }
+    let new_bal = &bal - amount;
+    set_holder_balance(&mut state.holder_count, &mut balances, account, &bal, new_bal)?;
+    state.balances = flush_balances(&mut balances)?;
+    state.total_supply -= amount;
+    Ok(())
+}
+
+/// The input parameters for burning the caller's own tokens.
+#[derive(Serialize_tuple, Deserialize_tuple, Clone, Debug)]
+pub struct BurnParams {
+    #[serde(with = "bigint_ser")]
+    pub amount: TokenAmount,
+}
+
+impl Cbor for BurnParams {}
+
+impl TokenAmounts for BurnParams {
+    fn token_amounts(&self) -> Vec<&TokenAmount> {
+        vec![&self.amount]
+    }
+}
+
+/// Destroy `amount` of the caller's tokens, taking it out of the total
+/// supply. Locked tokens can't be burned.
+pub fn burn<R: Runtime>(rt: &R, params: BurnParams) -> Result<(), ActorError> {
+    let mut state = State::load_from(rt)?;
+    state.check_unlocked()?;
+    burn_in(rt, &mut state, params)?;
+    state.save_to(rt)?;
+    Ok(())
+}
+
+/// Burn against `state` without saving it.
+pub(crate) fn burn_in<R: Runtime>(
+    rt: &R,
+    state: &mut State,
+    params: BurnParams,
+) -> Result<(), ActorError> {
+    require_positive(&params.amount)?;
+
+    let caller = rt.caller();
+    check_locks(rt.store(), state, caller, &params.amount, rt.curr_epoch())?;
+    burn_tokens(rt.store(), state, caller, &params.amount)
+}
+
+/// The input parameters for burning tokens on behalf of their holder.
+#[derive(Serialize_tuple, Deserialize_tuple, Clone, Debug)]
+pub struct BurnFromParams {
+    pub owner: Address,
+    #[serde(with = "bigint_ser")]
+    pub amount: TokenAmount,
+}
+
+impl Cbor for BurnFromParams {}
+
+impl TokenAmounts for BurnFromParams {
+    fn token_amounts(&self) -> Vec<&TokenAmount> {
+        vec![&self.amount]
+    }
+}
+
+/// Destroy `amount` of `owner`'s tokens. As with `transfer_from`, a caller
+/// other than `owner` must be its operator or spend an allowance covering
+/// `amount`.
+pub fn burn_from<R: Runtime>(rt: &R, params: BurnFromParams) -> Result<(), ActorError> {
+    let mut state = State::load_from(rt)?;
+    state.check_unlocked()?;
+    burn_from_in(rt, &mut state, params)?;
+    state.save_to(rt)?;
+    Ok(())
+}
+
+/// Burn on behalf of `owner` against `state` without saving it.
+pub(crate) fn burn_from_in<R: Runtime>(
+    rt: &R,
+    state: &mut State,
+    params: BurnFromParams,
+) -> Result<(), ActorError> {
+    require_positive(&params.amount)?;
+
+    let resolver = |addr: &Address| rt.resolve_address(addr);
+    let ctx = runtime_context(rt, &resolver);
+    let owner = ctx.resolve(&params.owner)?;
+    if owner != ctx.caller {
+        spend_allowance(rt.store(), state, owner, ctx.caller, &params.amount)?;
+    }
+    check_locks(rt.store(), state, owner, &params.amount, rt.curr_epoch())?;
+    burn_tokens(rt.store(), state, owner, &params.amount)
+}
+
+/// The input parameters for a forced transfer.
+#[derive(Serialize_tuple, Deserialize_tuple, Clone, Debug)]
+pub struct ForceTransferParams {
+    pub from: Address,
+    pub to: Address,
+    #[serde(with = "bigint_ser")]
+    pub amount: TokenAmount,
+}
+
+impl Cbor for ForceTransferParams {}
+
+impl TokenAmounts for ForceTransferParams {
+    fn token_amounts(&self) -> Vec<&TokenAmount> {
+        vec![&self.amount]
+    }
+}
+
+/// Move tokens out of any account, for recovery or legal orders. Only the
+/// owner may call this, and it needs no consent from the account it debits.
+///
+/// The recipient's receiver hook isn't called, so a recipient can't block a
+/// recovery, and neither locks nor freezing stop it; see `lock`. There is no
+/// event syscall to record the action with, so auditors find it by its
+/// method number on chain.
+pub fn force_transfer<R: Runtime>(rt: &R, params: ForceTransferParams) -> Result<(), ActorError> {
+    let mut state = State::load_from(rt)?;
+    state.check_unlocked()?;
+
+    let resolver = |addr: &Address| rt.resolve_address(addr);
+    let ctx = runtime_context(rt, &resolver);
+    ctx.require_owner(&state, "force_transfer")?;
+
+    let from_id = ctx.resolve(&params.from)?;
+    let to_id = normalize_recipient(rt, &params.to)?;
+    move_tokens(&ctx, &mut state, from_id, to_id, &params.amount)?;
+
+    state.save_to(rt)?;
+    Ok(())
+}
+
+/// The input parameters for rescuing the tokens held by the token actor.
+#[derive(Serialize_tuple, Deserialize_tuple, Clone, Debug)]
+pub struct RescueParams {
+    pub to: Address,
+}
+
+impl Cbor for RescueParams {}
+
+/// Move every token sent to the token actor's own address on to `to`. Only the
+/// owner may call this, and it fails with USR_ILLEGAL_ARGUMENT if the actor
+/// holds nothing.
+///
+/// As with `force_transfer`, the recipient's receiver hook isn't called, and
+/// there is no event syscall to record the rescue with.
+pub fn rescue<R: Runtime>(rt: &R, params: RescueParams) -> Result<(), ActorError> {
+    let mut state = State::load_from(rt)?;
+    state.check_unlocked()?;
+
+    let resolver = |addr: &Address| rt.resolve_address(addr);
+    let ctx = runtime_context(rt, &resolver);
+    ctx.require_owner(&state, "rescue")?;
+
+    let balances = load_balances(ctx.store, &state.balances, state.hamt_bit_width)?;
+    let held = get_balance(&balances, ctx.receiver)?;
+    if held == TokenAmount::from(0) {
+        return Err(actor_error!(USR_ILLEGAL_ARGUMENT, "the token actor holds no tokens to rescue"));
+    }
+
+    let to_id = normalize_recipient(rt, &params.to)?;
+    move_tokens(&ctx, &mut state, ctx.receiver, to_id, &held)?;
+
+    state.save_to(rt)?;
+    Ok(())
+}
+
+/// A single destination of a balance split.
+#[derive(Serialize_tuple, Deserialize_tuple, Clone, Debug)]
+pub struct SplitTarget {
+    pub recipient: Address,
+    #[serde(with = "bigint_ser")]
+    pub amount: TokenAmount,
+}
+
+/// The input parameters for a balance split.
+#[derive(Serialize_tuple, Deserialize_tuple, Clone, Debug)]
+pub struct SplitBalanceParams {
+    pub source: Address,
+    pub targets: Vec<SplitTarget>,
+}
+
+impl Cbor for SplitBalanceParams {}
+
+impl TokenAmounts for SplitBalanceParams {
+    fn token_amounts(&self) -> Vec<&TokenAmount> {
+        self.targets.iter().map(|target| &target.amount).collect()
+    }
+}
+
+/// Move amounts from one account to several targets in a single flush. This is
+/// an owner-only tool for reorganizing treasury holdings across sub-accounts,
+/// and like `force_transfer` it ignores locks and freezing.
+pub fn split_balance<R: Runtime>(rt: &R, params: SplitBalanceParams) -> Result<(), ActorError> {
+    let mut state = State::load_from(rt)?;
+    state.check_unlocked()?;
+
+    let resolver = |addr: &Address| rt.resolve_address(addr);
+    let ctx = runtime_context(rt, &resolver);
+    ctx.require_owner(&state, "split_balance")?;
+    split_tokens(&ctx, &mut state, &params)?;
+    state.save_to(rt)?;
+    Ok(())
+}
+
+/// Move the split amounts out of the source account and into the targets.
+pub fn split_tokens<BS: fvm_ipld_blockstore::Blockstore>(
+    ctx: &Context<BS>,
+    state: &mut State,
+    params: &SplitBalanceParams,
+) -> Result<(), ActorError> {
+    state.check_not_paused()?;
+    for target in &params.targets {
+        require_positive(&target.amount)?;
+    }
+    let source_id = ctx.resolve(&params.source)?;
+
+    let holders_before = state.holder_count;
+    let mut balances = load_balances(ctx.store, &state.balances, state.hamt_bit_width)?;
+    let source_bal = get_balance(&balances, source_id)?;
+
+    // The source must cover the whole split up front.
+    let total = params
+        .targets
+        .iter()
+        .fold(TokenAmount::from(0), |acc, target| acc + &target.amount);
+    if source_bal < total {
+        return Err(actor_error!(
+            kind: InsufficientBalance,
+            USR_INSUFFICIENT_FUNDS,
+            "source has insufficient balance"
+        ));
+    }
+
+    // Debit the source before crediting anyone, so a target that happens to be
+    // the source itself reads the already-debited balance.
+    let holders = &mut state.holder_count;
+    set_holder_balance(holders, &mut balances, source_id, &source_bal, &source_bal - &total)?;
+
+    for target in &params.targets {
+        let recipient_id = ctx.resolve(&target.recipient)?;
+        let recipient_bal = get_balance(&balances, recipient_id)?;
+        let new_bal = &recipient_bal + &target.amount;
+        set_holder_balance(holders, &mut balances, recipient_id, &recipient_bal, new_bal)?;
+    }
+
+    check_holder_cap(state, holders_before)?;
+    state.balances = flush_balances(&mut balances)?;
+    Ok(())
+}
+
+/// The input parameters for splitting a transfer by weight. Each recipient
+/// encodes as an `(address, weight)` pair.
+#[derive(Serialize_tuple, Deserialize_tuple, Clone, Debug)]
+pub struct TransferSplitParams {
+    pub recipients: Vec<(Address, u32)>,
+    #[serde(with = "bigint_ser")]
+    pub amount: TokenAmount,
+}
+
+impl Cbor for TransferSplitParams {}
+
+impl TokenAmounts for TransferSplitParams {
+    fn token_amounts(&self) -> Vec<&TokenAmount> {
+        vec![&self.amount]
+    }
+}
+
+/// Fail unless there is at least one recipient and every weight is positive,
+/// returning the total weight.
+pub(crate) fn validate_split_weights(recipients: &[(Address, u32)]) -> Result<u64, ActorError> {
+    if recipients.is_empty() {
+        return Err(actor_error!(USR_ILLEGAL_ARGUMENT, "a split needs at least one recipient"));
+    }
+    if let Some((addr, _)) = recipients.iter().find(|(_, weight)| *weight == 0) {
+        return Err(actor_error!(USR_ILLEGAL_ARGUMENT, "recipient {} has a zero weight", addr));
+    }
+    Ok(recipients.iter().map(|(_, weight)| u64::from(*weight)).sum())
+}
+
+/// Split `total` by weight. Each share is `total * weight / total_weight`,
+/// rounded down, and the last share also takes the rounding remainder, so the
+/// shares always add up to `total`.
+pub fn split_by_weight(total: &TokenAmount, weights: &[u32]) -> Vec<TokenAmount> {
+    let total_weight: u64 = weights.iter().map(|weight| u64::from(*weight)).sum();
+    let mut shares: Vec<TokenAmount> = weights
+        .iter()
+        .map(|weight| total * TokenAmount::from(*weight) / TokenAmount::from(total_weight))
+        .collect();
+    if let Some((last, rest)) = shares.split_last_mut() {
+        let assigned = rest.iter().fold(TokenAmount::from(0), |acc, share| acc + share);
+        *last = total - assigned;
+    }
+    shares
+}
+
+/// Transfer `amount` from the caller, split across the recipients by weight
+/// (see `split_by_weight`), flushing the balances once. Any transfer fee is
+/// taken from the whole amount before it is split, and a share that rounds
+/// down to zero is skipped.
+pub fn transfer_split<R: Runtime>(rt: &R, params: TransferSplitParams) -> Result<(), ActorError> {
+    require_positive(&params.amount)?;
+    validate_split_weights(&params.recipients)?;
+
+    let mut state = State::load_from(rt)?;
+    state.check_unlocked()?;
+    let recipients: Vec<&Address> = params.recipients.iter().map(|(addr, _)| addr).collect();
+    let weights: Vec<u32> = params.recipients.iter().map(|(_, weight)| *weight).collect();
+    transfer_to_many(rt, &mut state, &recipients, &[&params.amount], |state| {
+        let fee = transfer_fee(state, &params.amount);
+        (split_by_weight(&(&params.amount - &fee), &weights), fee)
+    })?;
+    state.save_to(rt)?;
+    Ok(())
+}
+
+/// The shared path of `transfer_split` and `transfer_batch`: pay each
+/// recipient its net amount from the caller, and the fees to the collector,
+/// in one flush of the balances, leaving saving `state` to the caller.
+///
+/// Each of `amounts` must be at least the minimum transfer. `pay` works out
+/// the net amounts, one per recipient, and the total fee from the loaded
+/// state; the caller is charged their sum. Recipients whose net amount is
+/// zero are skipped. The receiver hooks run once the transfers are saved,
+/// under the lock, as for a single transfer; a rejection aborts and reverts
+/// them.
+fn transfer_to_many<R: Runtime>(
+    rt: &R,
+    state: &mut State,
+    recipients: &[&Address],
+    amounts: &[&TokenAmount],
+    pay: impl FnOnce(&State) -> (Vec<TokenAmount>, TokenAmount),
+) -> Result<(), ActorError> {
+    if let Some(amount) = amounts.iter().find(|amount| **amount < &state.min_transfer) {
+        return Err(actor_error!(
+            kind: InvalidAmount,
+            USR_ILLEGAL_ARGUMENT,
+            "transfer of {} is below the minimum of {}",
+            amount,
+            state.min_transfer
+        ));
+    }
+
+    let resolver = |addr: &Address| rt.resolve_address(addr);
+    let ctx = runtime_context(rt, &resolver);
+    let sender_id = ctx.caller;
+    let recipient_ids = recipients
+        .iter()
+        .map(|addr| normalize_recipient(rt, addr))
+        .collect::<Result<Vec<_>, _>>()?;
+    if recipient_ids.contains(&sender_id) {
+        return Err(actor_error!(USR_ILLEGAL_ARGUMENT, "cannot send to self"));
+    }
+    for id in std::iter::once(&sender_id).chain(&recipient_ids) {
+        check_not_frozen(rt.store(), state, *id)?;
+    }
+
+    let (nets, fees) = pay(state);
+    let zero = TokenAmount::from(0);
+    let total = nets.iter().fold(fees.clone(), |acc, net| acc + net);
+    let mut targets: Vec<SplitTarget> = recipient_ids
+        .iter()
+        .zip(&nets)
+        .filter(|(_, net)| **net > zero)
+        .map(|(id, net)| SplitTarget { recipient: Address::new_id(*id), amount: net.clone() })
+        .collect();
+    if fees > zero {
+        let collector = match &state.fee_collector {
+            Some(collector) => ctx.resolve(collector)?,
+            None => return Err(actor_error!(USR_ILLEGAL_STATE, "transfer fee has no collector")),
+        };
+        // As with a single transfer, a sender collecting its own fee keeps it.
+        if collector != sender_id {
+            targets.push(SplitTarget { recipient: Address::new_id(collector), amount: fees });
+        }
+    }
+
+    let mut after = state.clone();
+    check_locks(rt.store(), &mut after, sender_id, &total, rt.curr_epoch())?;
+    charge_rate_limit(rt.store(), &mut after, sender_id, &total, rt.curr_epoch())?;
+    let split = SplitBalanceParams { source: Address::new_id(sender_id), targets };
+    split_tokens(&ctx, &mut after, &split)?;
+    let epoch = rt.curr_epoch();
+    for (id, net) in recipient_ids.iter().zip(&nets) {
+        if *net > zero {
+            let record = TransferRecord { from: sender_id, to: *id, amount: net.clone(), epoch };
+            record_transfer(rt.store(), &mut after, record)?;
+        }
+    }
+
+    *state = after;
+    for (id, net) in recipient_ids.iter().zip(nets) {
+        if net > zero {
+            call_receiver_hook(rt, state, TokenReceivedParams {
+                from: sender_id,
+                to: *id,
+                operator: sender_id,
+                amount: net,
+                operator_data: RawBytes::default(),
+                token_data: RawBytes::default(),
+            })?;
+        }
+    }
+    Ok(())
+}
+
+/// The input parameters for transferring to several recipients at once. They
+/// encode as a bare list of `(address, amount)` pairs, not wrapped in an
+/// array of their own like other params.
+#[derive(Serialize, Deserialize, Clone, Debug)]
+#[serde(transparent)]
+pub struct TransferBatchParams {
+    pub transfers: Vec<TransferParams>,
+}
+
+impl Cbor for TransferBatchParams {}
+
+impl TokenAmounts for TransferBatchParams {
+    fn token_amounts(&self) -> Vec<&TokenAmount> {
+        self.transfers.iter().map(|transfer| &transfer.amount).collect()
+    }
+}
+
+/// Make every transfer in the batch from the caller, or none of them, loading
+/// and flushing the balances once. Each entry is checked and charged like a
+/// single transfer, and the fees are paid to the collector in one credit.
+pub fn transfer_batch<R: Runtime>(rt: &R, params: TransferBatchParams) -> Result<(), ActorError> {
+    let mut state = State::load_from(rt)?;
+    state.check_unlocked()?;
+    transfer_batch_in(rt, &mut state, params)?;
+    state.save_to(rt)?;
+    Ok(())
+}
+
+/// Make a batch of transfers against `state` without saving it.
+pub(crate) fn transfer_batch_in<R: Runtime>(
+    rt: &R,
+    state: &mut State,
+    params: TransferBatchParams,
+) -> Result<(), ActorError> {
+    if params.transfers.is_empty() {
+        return Err(actor_error!(USR_ILLEGAL_ARGUMENT, "a batch needs at least one transfer"));
+    }
+    for entry in &params.transfers {
+        require_positive(&entry.amount)?;
+    }
+
+    let recipients: Vec<&Address> = params.transfers.iter().map(|entry| &entry.recipient).collect();
+    let amounts: Vec<&TokenAmount> = params.transfers.iter().map(|entry| &entry.amount).collect();
+    transfer_to_many(rt, state, &recipients, &amounts, |state| {
+        let mut fees = TokenAmount::from(0);
+        let nets = amounts
+            .iter()
+            .map(|amount| {
+                let fee = transfer_fee(state, amount);
+                let net = *amount - &fee;
+                fees += fee;
+                net
+            })
+            .collect();
+        (nets, fees)
+    })
+}
+
+/// The input parameters for setting the secondary unit conversion rate.
+pub type SetUnitRateParams = SecondaryUnit;
+
+/// Set the secondary unit and its conversion rate. Only the owner may call
+/// this; the owner may be an oracle actor that keeps the rate current.
+pub fn set_unit_rate<R: Runtime>(rt: &R, params: SetUnitRateParams) -> Result<(), ActorError> {
+    let mut state = State::load_from(rt)?;
+    state.check_unlocked()?;
+    let resolver = |addr: &Address| rt.resolve_address(addr);
+    runtime_context(rt, &resolver).require_owner(&state, "set_unit_rate")?;
+
+    if params.rate_denominator <= TokenAmount::from(0) {
+        return Err(actor_error!(USR_ILLEGAL_ARGUMENT, "rate denominator must be positive"));
+    }
+
+    state.secondary_unit = Some(params);
+    state.save_to(rt)?;
+    Ok(())
+}
+
+/// The input parameters for a balance query in the secondary unit.
+#[derive(Serialize_tuple, Deserialize_tuple, Clone, Debug)]
+pub struct BalanceOfInUnitParams {
+    pub account: Address,
+    pub unit: String,
+}
+
+impl Cbor for BalanceOfInUnitParams {}
+
+/// Return an account's balance converted into the secondary unit. The
+/// conversion rounds towards zero.
+pub fn balance_of_in_unit<R: Runtime>(
+    rt: &R,
+    params: BalanceOfInUnitParams,
+) -> Result<TokenAmount, ActorError> {
+    let state = State::load_from(rt)?;
+
+    let unit = match &state.secondary_unit {
+        Some(unit) if unit.unit == params.unit => unit,
+        _ => return Err(actor_error!(USR_ILLEGAL_ARGUMENT, "unknown unit {}", params.unit)),
+    };
+
+    let balances = load_balances(rt.store(), &state.balances, state.hamt_bit_width)?;
+
+    // An address that doesn't resolve has never held tokens.
+    let balance = match rt.resolve_address(&params.account) {
+        Some(id) => get_balance(&balances, id)?,
+        None => TokenAmount::from(0),
+    };
+
+    Ok(balance * &unit.rate_numerator / &unit.rate_denominator)
+}
+
+/// Record the current balances at the current epoch. Only the owner may call
+/// this. HAMT nodes are immutable and addressed by CID, so the recorded root
+/// keeps describing the balances as they are now, whatever happens later.
+pub fn snapshot<R: Runtime>(rt: &R) -> Result<(), ActorError> {
+    let mut state = State::load_from(rt)?;
+    state.check_unlocked()?;
+
+    let resolver = |addr: &Address| rt.resolve_address(addr);
+    runtime_context(rt, &resolver).require_owner(&state, "snapshot")?;
+
+    state.snapshots.push((rt.curr_epoch(), state.balances));
+    state.save_to(rt)?;
+    Ok(())
+}
+
+/// Return an account's balance. An address that doesn't resolve has never
+/// held tokens, so its balance is zero.
+pub fn balance_of<R: Runtime>(rt: &R, account: Address) -> Result<TokenAmount, ActorError> {
+    let state = State::load_from(rt)?;
+    let balances = load_balances(rt.store(), &state.balances, state.hamt_bit_width)?;
+    match rt.resolve_address(&account) {
+        Some(id) => get_balance(&balances, id),
+        None => Ok(TokenAmount::from(0)),
+    }
+}
+
+/// The input parameters for a balance query by actor ID.
+#[derive(Serialize_tuple, Deserialize_tuple, Clone, Debug)]
+pub struct BalanceOfIdParams {
+    pub id: ActorID,
+}
+
+impl Cbor for BalanceOfIdParams {}
+
+/// Return the balance held under an actor ID, without resolving an address.
+///
+/// The ID is looked up as given, so the caller must pass the account's
+/// canonical ID; an ID that isn't a holder reads as zero.
+pub fn balance_of_id<R: Runtime>(
+    rt: &R,
+    params: BalanceOfIdParams,
+) -> Result<TokenAmount, ActorError> {
+    let state = State::load_from(rt)?;
+    let balances = load_balances(rt.store(), &state.balances, state.hamt_bit_width)?;
+    get_balance(&balances, params.id)
+}
+
+/// The input parameters for a balance query against a snapshot.
+#[derive(Serialize_tuple, Deserialize_tuple, Clone, Debug)]
+pub struct BalanceAtSnapshotParams {
+    pub account: Address,
+    /// The position of the snapshot in `State::snapshots`.
+    pub snapshot_index: u32,
+}
+
+impl Cbor for BalanceAtSnapshotParams {}
+
+/// Return an account's balance as of a snapshot. An address that doesn't
+/// resolve has never held tokens, so its balance is zero.
+pub fn balance_at_snapshot<R: Runtime>(
+    rt: &R,
+    params: BalanceAtSnapshotParams,
+) -> Result<TokenAmount, ActorError> {
+    let state = State::load_from(rt)?;
+
+    let root = match state.snapshots.get(params.snapshot_index as usize) {
+        Some((_, root)) => root,
+        None => {
+            return Err(actor_error!(
+                USR_NOT_FOUND,
+                "no snapshot with index {}",
+                params.snapshot_index
+            ))
+        }
+    };
+
+    let balances = load_balances(rt.store(), root, state.hamt_bit_width)?;
+    match rt.resolve_address(&params.account) {
+        Some(id) => get_balance(&balances, id),
+        None => Ok(TokenAmount::from(0)),
+    }
+}
+
+/// The longest metadata URI a token may have, in bytes.
+pub const MAX_METADATA_URI_LEN: usize = 2048;
+
+pub(crate) fn validate_metadata_uri(uri: &str) -> Result<(), ActorError> {
+    if uri.is_empty() {
+        return Err(actor_error!(USR_ILLEGAL_ARGUMENT, "metadata uri must not be empty"));
+    }
+    if uri.len() > MAX_METADATA_URI_LEN {
+        return Err(actor_error!(
+            USR_ILLEGAL_ARGUMENT,
+            "metadata uri is {} bytes, more than the maximum of {}",
+            uri.len(),
+            MAX_METADATA_URI_LEN
+        ));
+    }
+    Ok(())
+}
+
+/// The input parameters for setting the metadata URI. None clears it.
+#[derive(Serialize_tuple, Deserialize_tuple, Clone, Debug)]
+pub struct SetMetadataUriParams {
+    pub uri: Option<String>,
+}
+
+impl Cbor for SetMetadataUriParams {}
+
+/// Set or clear the token's metadata URI. Only the owner may call this.
+pub fn set_metadata_uri<R: Runtime>(
+    rt: &R,
+    params: SetMetadataUriParams,
+) -> Result<(), ActorError> {
+    let mut state = State::load_from(rt)?;
+    state.check_unlocked()?;
+
+    let resolver = |addr: &Address| rt.resolve_address(addr);
+    runtime_context(rt, &resolver).require_owner(&state, "set_metadata_uri")?;
+
+    if let Some(uri) = &params.uri {
+        validate_metadata_uri(uri)?;
+    }
+    state.metadata_uri = params.uri;
+    state.save_to(rt)?;
+    Ok(())
+}
+
+/// Return the token's metadata URI, if it has one.
+pub fn metadata_uri<R: Runtime>(rt: &R) -> Result<Option<String>, ActorError> {
+    Ok(State::load_from(rt)?.metadata_uri)
+}
+
+/// Return the token's owner.
+pub fn owner<R: Runtime>(rt: &R) -> Result<Address, ActorError> {
+    Ok(State::load_from(rt)?.owner)
+}
+
+/// Return the token's name.
+pub fn name<R: Runtime>(rt: &R) -> Result<String, ActorError> {
+    Ok(State::load_from(rt)?.name)
+}
+
+/// Return the token's symbol.
+pub fn symbol<R: Runtime>(rt: &R) -> Result<String, ActorError> {
+    Ok(State::load_from(rt)?.symbol)
+}
+
+/// Return how many base-unit digits follow the decimal point.
+pub fn decimals<R: Runtime>(rt: &R) -> Result<u8, ActorError> {
+    Ok(State::load_from(rt)?.decimals)
+}
+
+/// Return the number of tokens in circulation, in base units.
+pub fn total_supply<R: Runtime>(rt: &R) -> Result<TokenAmount, ActorError> {
+    Ok(State::load_from(rt)?.total_supply)
+}
+
+/// Return the most tokens that may be minted, in base units.
+pub fn max_supply<R: Runtime>(rt: &R) -> Result<TokenAmount, ActorError> {
+    Ok(State::load_from(rt)?.max_supply)
+}
+
+/// The token's scalar state in one read, for dashboards. HAMT roots and other
+/// bookkeeping are left out.
+#[derive(Serialize_tuple, Deserialize_tuple, Clone, Debug, PartialEq)]
+pub struct StateSummary {
+    pub name: String,
+    pub symbol: String,
+    pub owner: Address,
+    #[serde(with = "bigint_ser")]
+    pub max_supply: TokenAmount,
+    #[serde(with = "bigint_ser")]
+    pub total_supply: TokenAmount,
+    pub holder_count: u64,
+    pub metadata_uri: Option<String>,
+    pub require_receiver_hook: bool,
+    pub fee_bps: u16,
+    #[serde(with = "bigint_ser")]
+    pub min_transfer: TokenAmount,
+}
+
+impl Cbor for StateSummary {}
+
+/// Return the token's scalar state.
+pub fn state_summary<R: Runtime>(rt: &R) -> Result<StateSummary, ActorError> {
+    let state = State::load_from(rt)?;
+    Ok(StateSummary {
+        name: state.name,
+        symbol: state.symbol,
+        owner: state.owner,
+        max_supply: state.max_supply,
+        total_supply: state.total_supply,
+        holder_count: state.holder_count,
+        metadata_uri: state.metadata_uri,
+        require_receiver_hook: state.require_receiver_hook,
+        fee_bps: state.fee_bps,
+        min_transfer: state.min_transfer,
+    })
+}
+
+/// The token actor's own identity.
+#[derive(Serialize_tuple, Deserialize_tuple, Clone, Debug, PartialEq)]
+pub struct ActorInfo {
+    pub id: ActorID,
+    pub code_cid: Cid,
+}
+
+impl Cbor for ActorInfo {}
+
+/// Return the token actor's ID and code CID. This reads neither the state nor
+/// the balances.
+pub fn actor_info<R: Runtime>(rt: &R) -> Result<ActorInfo, ActorError> {
+    let id = rt.receiver();
+    match rt.get_actor_code_cid(&Address::new_id(id)) {
+        Some(code_cid) => Ok(ActorInfo { id, code_cid }),
+        None => Err(actor_error!(USR_ILLEGAL_STATE, "no code cid for the token actor {}", id)),
+    }
+}
+
+/// The input parameters for handing ownership to a new owner.
+#[derive(Serialize_tuple, Deserialize_tuple, Clone, Debug)]
+pub struct TransferOwnershipParams {
+    pub new_owner: Address,
+}
+
+impl Cbor for TransferOwnershipParams {}
+
+/// Offer ownership to `new_owner`, who becomes the owner once it calls
+/// `accept_ownership`. Only the owner may call this. Offering ownership to
+/// the current owner cancels any pending offer, as does a new offer to
+/// someone else.
+pub fn transfer_ownership<R: Runtime>(
+    rt: &R,
+    params: TransferOwnershipParams,
+) -> Result<(), ActorError> {
+    let mut state = State::load_from(rt)?;
+    state.check_unlocked()?;
+
+    let resolver = |addr: &Address| rt.resolve_address(addr);
+    let ctx = runtime_context(rt, &resolver);
+    ctx.require_owner(&state, "transfer_ownership")?;
+
+    let current = ctx.owner_id(&state)?;
+    state.pending_owner = if rt.resolve_address(&params.new_owner) == Some(current) {
+        None
+    } else {
+        Some(params.new_owner)
+    };
+    state.save_to(rt)?;
+    Ok(())
+}
+
+/// Become the owner. Only the pending owner may call this.
+pub fn accept_ownership<R: Runtime>(rt: &R) -> Result<(), ActorError> {
+    let mut state = State::load_from(rt)?;
+    state.check_unlocked()?;
+
+    let pending = match state.pending_owner {
+        Some(pending) if rt.resolve_address(&pending) == Some(rt.caller()) => pending,
+        _ => {
+            return Err(actor_error!(
+                kind: Forbidden,
+                USR_FORBIDDEN,
+                "accept_ownership invoked by non-pending owner"
+            ))
+        }
+    };
+    state.owner = pending;
+    state.owner_id = Some(rt.caller());
+    state.pending_owner = None;
+    state.save_to(rt)?;
+    Ok(())
+}
+
+/// Withdraw a pending ownership offer. Only the owner may call this.
+pub fn cancel_ownership_transfer<R: Runtime>(rt: &R) -> Result<(), ActorError> {
+    let mut state = State::load_from(rt)?;
+    state.check_unlocked()?;
+
+    let resolver = |addr: &Address| rt.resolve_address(addr);
+    runtime_context(rt, &resolver).require_owner(&state, "cancel_ownership_transfer")?;
+
+    if state.pending_owner.is_none() {
+        return Err(actor_error!(USR_ILLEGAL_STATE, "no ownership transfer is pending"));
+    }
+    state.pending_owner = None;
+    state.save_to(rt)?;
+    Ok(())
+}
+
+/// Give up ownership for good. Only the owner may call this. Afterwards no
+/// one can mint or call any other owner-only method, and any pending
+/// ownership offer is withdrawn. `owner` keeps its last value.
+pub fn renounce_ownership<R: Runtime>(rt: &R) -> Result<(), ActorError> {
+    let mut state = State::load_from(rt)?;
+    state.check_unlocked()?;
+
+    let resolver = |addr: &Address| rt.resolve_address(addr);
+    runtime_context(rt, &resolver).require_owner(&state, "renounce_ownership")?;
+    // No one could unpause the token afterwards.
+    if state.paused {
+        return Err(actor_error!(
+            USR_ILLEGAL_STATE,
+            "ownership cannot be renounced while the token is paused"
+        ));
+    }
+
+    state.ownership_renounced = true;
+    state.pending_owner = None;
+    state.save_to(rt)?;
+    Ok(())
+}
+
+/// Stop every balance from changing: no transfers, mints or burns go through
+/// until `unpause`. Needs the owner or a `Role::Pauser`.
+pub fn pause<R: Runtime>(rt: &R) -> Result<(), ActorError> {
+    set_paused(rt, true, "pause")
+}
+
+/// Let balances change again after `pause`. Needs the owner or a
+/// `Role::Pauser`.
+pub fn unpause<R: Runtime>(rt: &R) -> Result<(), ActorError> {
+    set_paused(rt, false, "unpause")
+}
+
+fn set_paused<R: Runtime>(rt: &R, paused: bool, method: &str) -> Result<(), ActorError> {
+    let mut state = State::load_from(rt)?;
+    state.check_unlocked()?;
+
+    let resolver = |addr: &Address| rt.resolve_address(addr);
+    require_role(&runtime_context(rt, &resolver), &state, Role::Pauser, method)?;
+    if state.paused == paused {
+        let status = if paused { "already" } else { "not" };
+        return Err(actor_error!(USR_ILLEGAL_STATE, "the token is {} paused", status));
+    }
+
+    state.paused = paused;
+    state.save_to(rt)?;
+    Ok(())
+}
+
+/// A cap on how much each account may transfer within a window of epochs.
+#[derive(Serialize_tuple, Deserialize_tuple, Clone, Debug, PartialEq)]
+pub struct RateLimit {
+    #[serde(with = "bigint_ser")]
+    pub max_amount: TokenAmount,
+    pub window_epochs: ChainEpoch,
+}
+
+impl Cbor for RateLimit {}
+
+/// How much an account has transferred in its current window.
+#[derive(Serialize_tuple, Deserialize_tuple, Clone, Debug, PartialEq)]
+pub struct TransferWindow {
+    pub window_start: ChainEpoch,
+    #[serde(with = "bigint_ser")]
+    pub spent: TokenAmount,
+}
+
+pub type TransferWindows<BS> = Hamt<BS, TransferWindow, ActorID>;
+
+/// The account's window as of `epoch`. Once a window has ended, the next one
+/// starts at the last window boundary at or before `epoch`, with nothing
+/// spent, so windows stay aligned to the first however long the account
+/// goes without transferring.
+fn roll_window(
+    window: Option<TransferWindow>,
+    limit: &RateLimit,
+    epoch: ChainEpoch,
+) -> TransferWindow {
+    match window {
+        Some(w) if epoch < w.window_start + limit.window_epochs => w,
+        Some(w) => TransferWindow {
+            window_start: epoch - (epoch - w.window_start) % limit.window_epochs,
+            spent: TokenAmount::from(0),
+        },
+        None => TransferWindow { window_start: epoch, spent: TokenAmount::from(0) },
+    }
+}
+
+/// Count `amount` against the sender's rate limit at `epoch`, failing if it
+/// would take the sender over the limit for the current window. Does nothing
+/// if the token has no rate limit.
+pub fn charge_rate_limit<BS: fvm_ipld_blockstore::Blockstore>(
+    store: &BS,
+    state: &mut State,
+    sender_id: ActorID,
+    amount: &TokenAmount,
+    epoch: ChainEpoch,
+) -> Result<(), ActorError> {
+    let limit = match &state.rate_limit {
+        Some(limit) => limit.clone(),
+        None => return Ok(()),
+    };
+
+    let mut windows: TransferWindows<&BS> = match &state.transfer_windows {
+        Some(root) => load_hamt(store, root, state.hamt_bit_width, "transfer windows")?,
+        None => Hamt::new_with_bit_width(store, state.hamt_bit_width),
+    };
+    let window = windows
+        .get(&sender_id)
+        .map_err(|err| {
+            actor_error!(USR_ILLEGAL_STATE, "failed to get transfer window: {:?}", err)
+        })?
+        .cloned();
+
+    let mut window = roll_window(window, &limit, epoch);
+    let spent = &window.spent + amount;
+    if spent > limit.max_amount {
+        return Err(actor_error!(
+            kind: RateLimited,
+            USR_FORBIDDEN,
+            "transfer would exceed the limit of {} per {} epochs, {} already spent",
+            limit.max_amount,
+            limit.window_epochs,
+            window.spent
+        ));
+    }
+    window.spent = spent;
+
+    windows.set(sender_id, window).map_err(|err| {
+        actor_error!(USR_ILLEGAL_STATE, "failed to set transfer window: {:?}", err)
+    })?;
+    let root = windows.flush().map_err(|err| {
+        actor_error!(USR_ILLEGAL_STATE, "failed to flush transfer windows: {:?}", err)
+    })?;
+    state.transfer_windows = Some(root);
+    Ok(())
+}
+
+/// The input parameters for setting the rate limit. None clears it.
+#[derive(Serialize_tuple, Deserialize_tuple, Clone, Debug)]
+pub struct SetRateLimitParams {
+    pub limit: Option<RateLimit>,
+}
+
+impl Cbor for SetRateLimitParams {}
+
+impl TokenAmounts for SetRateLimitParams {
+    fn token_amounts(&self) -> Vec<&TokenAmount> {
+        self.limit.iter().map(|limit| &limit.max_amount).collect()
+    }
+}
+
+pub(crate) fn validate_rate_limit(limit: &RateLimit) -> Result<(), ActorError> {
+    require_positive(&limit.max_amount)?;
+    if limit.window_epochs <= 0 {
+        return Err(actor_error!(USR_ILLEGAL_ARGUMENT, "rate limit window must be positive"));
+    }
+    Ok(())
+}
+
+/// Set or clear the per-account transfer rate limit. Only the owner may call
+/// this. Every account starts a fresh window under the new limit.
+pub fn set_rate_limit<R: Runtime>(rt: &R, params: SetRateLimitParams) -> Result<(), ActorError> {
+    let mut state = State::load_from(rt)?;
+    state.check_unlocked()?;
+
+    let resolver = |addr: &Address| rt.resolve_address(addr);
+    runtime_context(rt, &resolver).require_owner(&state, "set_rate_limit")?;
+
+    if let Some(limit) = &params.limit {
+        validate_rate_limit(limit)?;
+    }
+    state.rate_limit = params.limit;
+    state.transfer_windows = None;
+    state.save_to(rt)?;
+    Ok(())
+}
+
+/// Tokens in an account that can't be transferred before `unlock_epoch`.
+#[derive(Serialize_tuple, Deserialize_tuple, Clone, Debug, PartialEq)]
+pub struct Lock {
+    #[serde(with = "bigint_ser")]
+    pub amount: TokenAmount,
+    pub unlock_epoch: ChainEpoch,
+}
+
+/// The locks on each account, keyed by account ID.
+pub type Locks<BS> = Hamt<BS, Vec<Lock>, ActorID>;
+
+/// The most locks an account may have in force at once.
+pub const MAX_LOCKS_PER_ACCOUNT: usize = 32;
+
+/// Load the locks on `account` still in force at `epoch`. The second value
+/// is true if any expired locks were dropped.
+fn live_locks<BS: fvm_ipld_blockstore::Blockstore>(
+    locks: &Locks<BS>,
+    account: ActorID,
+    epoch: ChainEpoch,
+) -> Result<(Vec<Lock>, bool), ActorError> {
+    let all = locks
+        .get(&account)
+        .map_err(|err| actor_error!(USR_ILLEGAL_STATE, "failed to get locks: {:?}", err))?
+        .cloned()
+        .unwrap_or_default();
+    let count = all.len();
+    let live: Vec<Lock> = all.into_iter().filter(|lock| epoch < lock.unlock_epoch).collect();
+    let expired = live.len() < count;
+    Ok((live, expired))
+}
+
+/// Replace the locks on `account`, deleting its entry once none are left.
+fn put_locks<BS: fvm_ipld_blockstore::Blockstore>(
+    locks: &mut Locks<BS>,
+    account: ActorID,
+    live: Vec<Lock>,
+) -> Result<Cid, ActorError> {
+    if live.is_empty() {
+        locks
+            .delete(&account)
+            .map_err(|err| actor_error!(USR_ILLEGAL_STATE, "failed to delete locks: {:?}", err))?;
+    } else {
+        locks
+            .set(account, live)
+            .map_err(|err| actor_error!(USR_ILLEGAL_STATE, "failed to set locks: {:?}", err))?;
+    }
+    locks.flush().map_err(|err| actor_error!(USR_ILLEGAL_STATE, "failed to flush locks: {:?}", err))
+}
+
+/// Fail unless `account` can send `amount` at `epoch` without touching tokens
+/// that are still locked. Locks that have expired are cleared on the way.
+pub fn check_locks<BS: fvm_ipld_blockstore::Blockstore>(
+    store: &BS,
+    state: &mut State,
+    account: ActorID,
+    amount: &TokenAmount,
+    epoch: ChainEpoch,
+) -> Result<(), ActorError> {
+    let root = match &state.locks {
+        Some(root) => *root,
+        None => return Ok(()),
+    };
+    let mut locks: Locks<&BS> = load_hamt(store, &root, state.hamt_bit_width, "locks")?;
+    let (live, expired) = live_locks(&locks, account, epoch)?;
+
+    let locked = live.iter().fold(TokenAmount::from(0), |acc, lock| acc + &lock.amount);
+    let balances = load_balances(store, &state.balances, state.hamt_bit_width)?;
+    let balance = get_balance(&balances, account)?;
+    if &balance - &locked < *amount {
+        return Err(actor_error!(
+            kind: Locked,
+            USR_INSUFFICIENT_FUNDS,
+            "transfer of {} would touch locked tokens, {} of {} are locked",
+            amount,
+            locked,
+            balance
+        ));
+    }
+
+    if expired {
+        state.locks = Some(put_locks(&mut locks, account, live)?);
+    }
+    Ok(())
+}
+
+/// The input parameters for locking part of an account's balance.
+#[derive(Serialize_tuple, Deserialize_tuple, Clone, Debug)]
+pub struct LockParams {
+    pub account: Address,
+    #[serde(with = "bigint_ser")]
+    pub amount: TokenAmount,
+    pub unlock_epoch: ChainEpoch,
+}
+
+impl Cbor for LockParams {}
+
+impl TokenAmounts for LockParams {
+    fn token_amounts(&self) -> Vec<&TokenAmount> {
+        vec![&self.amount]
+    }
+}
+
+/// Lock `amount` of an account's tokens until `unlock_epoch`, for vesting or
+/// escrow. Only the owner may call this. Locks add up, and each one stops
+/// counting at its own unlock epoch. The account needn't hold the tokens yet;
+/// while locks exceed its balance, none of it can be transferred.
+///
+/// Locks bind every transfer the account makes. The owner's `force_transfer`
+/// and `split_balance` tools are exempt, as they are from freezing: they
+/// exist to move tokens the account can't, so they ignore its locks and
+/// neither clear nor shrink them.
+pub fn lock<R: Runtime>(rt: &R, params: LockParams) -> Result<(), ActorError> {
+    require_positive(&params.amount)?;
+    let epoch = rt.curr_epoch();
+    if params.unlock_epoch <= epoch {
+        return Err(actor_error!(
+            USR_ILLEGAL_ARGUMENT,
+            "unlock epoch {} is not after the current epoch {}",
+            params.unlock_epoch,
+            epoch
+        ));
+    }
+
+    let mut state = State::load_from(rt)?;
+    state.check_unlocked()?;
+
+    let resolver = |addr: &Address| rt.resolve_address(addr);
+    let ctx = runtime_context(rt, &resolver);
+    ctx.require_owner(&state, "lock")?;
+    let account = ctx.resolve(&params.account)?;
+
+    let store = rt.store();
+    let mut locks: Locks<&R::Blockstore> = match &state.locks {
+        Some(root) => load_hamt(store, root, state.hamt_bit_width, "locks")?,
+        None => Hamt::new_with_bit_width(store, state.hamt_bit_width),
+    };
+    let (mut live, _) = live_locks(&locks, account, epoch)?;
+    if live.len() >= MAX_LOCKS_PER_ACCOUNT {
+        return Err(actor_error!(
+            USR_ILLEGAL_ARGUMENT,
+            "account {} already has the maximum of {} locks",
+            account,
+            MAX_LOCKS_PER_ACCOUNT
+        ));
+    }
+    live.push(Lock { amount: params.amount, unlock_epoch: params.unlock_epoch });
+    state.locks = Some(put_locks(&mut locks, account, live)?);
+    state.save_to(rt)?;
+    Ok(())
+}
+
+/// The input parameters for reading a set of state fields.
+#[derive(Serialize_tuple, Deserialize_tuple, Clone, Debug)]
+pub struct ReadFieldsParams {
+    pub names: Vec<String>,
+}
+
+impl Cbor for ReadFieldsParams {}
+
+/// Return the requested state fields as a map from field name to the CBOR
+/// encoding of that field's value. Any unknown field name fails the call.
+pub fn read_fields<R: Runtime>(
+    rt: &R,
+    params: ReadFieldsParams,
+) -> Result<BTreeMap<String, RawBytes>, ActorError> {
+    let state = State::load_from(rt)?;
+
+    fn encode<T: Serialize>(value: &T) -> Result<RawBytes, ActorError> {
+        RawBytes::serialize(value)
+            .map_err(|err| actor_error!(USR_SERIALIZATION, "failed to serialize field: {:?}", err))
+    }
+
+    let mut fields = BTreeMap::new();
+    for name in params.names {
+        let value = match name.as_str() {
+            "version" => encode(&state.version),
+            "name" => encode(&state.name),
+            "symbol" => encode(&state.symbol),
+            "max_supply" => encode(&BigIntSer(&state.max_supply)),
+            "total_supply" => encode(&BigIntSer(&state.total_supply)),
+            "owner" => encode(&state.owner),
+            "balances" => encode(&state.balances),
+            "supply_oracle" => encode(&state.supply_oracle),
+            "reentrancy_locked" => encode(&state.reentrancy_locked),
+            "secondary_unit" => encode(&state.secondary_unit),
+            "governance" => encode(&state.governance),
+            "executing_proposal" => encode(&state.executing_proposal),
+            "holder_count" => encode(&state.holder_count),
+            "hamt_bit_width" => encode(&state.hamt_bit_width),
+            "snapshots" => encode(&state.snapshots),
+            "metadata_uri" => encode(&state.metadata_uri),
+            "require_receiver_hook" => encode(&state.require_receiver_hook),
+            "rate_limit" => encode(&state.rate_limit),
+            "transfer_windows" => encode(&state.transfer_windows),
+            "pending_owner" => encode(&state.pending_owner),
+            "fee_bps" => encode(&state.fee_bps),
+            "fee_collector" => encode(&state.fee_collector),
+            "pending_total_supply" => encode(&state.pending_total_supply),
+            "min_transfer" => encode(&BigIntSer(&state.min_transfer)),
+            "recent_transfers" => encode(&state.recent_transfers),
+            "used_keys" => encode(&state.used_keys),
+            "hook_allowlist" => encode(&state.hook_allowlist),
+            "locks" => encode(&state.locks),
+            "mint_schedule" => encode(&state.mint_schedule),
+            "operators" => encode(&state.operators),
+            "owner_id" => encode(&state.owner_id),
+            "wrapped" => encode(&state.wrapped),
+            "claim_terms" => encode(&state.claim_terms),
+            "claimed" => encode(&state.claimed),
+            "max_holders" => encode(&state.max_holders),
+            "allowances" => encode(&state.allowances),
+            "decimals" => encode(&state.decimals),
+            "ownership_renounced" => encode(&state.ownership_renounced),
+            "roles" => encode(&state.roles),
+            "paused" => encode(&state.paused),
+            "frozen" => encode(&state.frozen),
+            "claim_deadline" => encode(&state.claim_deadline),
+            "nonces" => encode(&state.nonces),
+            "approved_call" => encode(&state.approved_call),
+            _ => {
+                return Err(actor_error!(
+                    USR_ILLEGAL_ARGUMENT,
+                    "unknown state field '{}'",
+                    name
+                ))
+            }
+        }?;
+        fields.insert(name, value);
+    }
+
+    Ok(fields)
+}
+
+/// Return the number of accounts with a non-zero balance.
+pub fn holder_count<R: Runtime>(rt: &R) -> Result<u64, ActorError> {
+    Ok(State::load_from(rt)?.holder_count)
+}
+
+/// The result of checking the token's accounting against itself.
+#[derive(Serialize_tuple, Deserialize_tuple, Clone, Debug, PartialEq)]
+pub struct InvariantReport {
+    #[serde(with = "bigint_ser")]
+    pub balances_sum: TokenAmount,
+    #[serde(with = "bigint_ser")]
+    pub total_supply: TokenAmount,
+    /// Whether the balances add up to the total supply, none of them is
+    /// negative, and the total supply is within the cap.
+    pub consistent: bool,
+}
+
+impl Cbor for InvariantReport {}
+
+/// Walk every balance and check the accounting is self-consistent. This only
+/// reads state, so anyone may call it.
+pub fn verify_invariants<R: Runtime>(rt: &R) -> Result<InvariantReport, ActorError> {
+    let state = State::load_from(rt)?;
+    let balances = load_balances(rt.store(), &state.balances, state.hamt_bit_width)?;
+
+    let zero = TokenAmount::from(0);
+    let mut balances_sum = TokenAmount::from(0);
+    let mut any_negative = false;
+    balances
+        .for_each(|_, bal| {
+            any_negative |= bal.0 < zero;
+            balances_sum += &bal.0;
+            Ok(())
+        })
+        .map_err(|err| actor_error!(USR_ILLEGAL_STATE, "failed to sum balances: {:?}", err))?;
+
+    let consistent = !any_negative
+        && balances_sum == state.total_supply
+        && state.total_supply <= state.max_supply;
+    Ok(InvariantReport { balances_sum, total_supply: state.total_supply, consistent })
+}
+
+/// A total supply recount in progress. See `recompute_total_supply`.
+#[derive(Serialize_tuple, Deserialize_tuple, Clone, Debug, PartialEq)]
+pub struct SupplyRecount {
+    /// The balances root being summed, as it was when the recount started.
+    pub balances: Cid,
+    /// The total supply when the recount started, so that tokens minted since
+    /// can be carried into the result.
+    #[serde(with = "bigint_ser")]
+    pub started_supply: TokenAmount,
+    /// The sum of the balances counted so far.
+    #[serde(with = "bigint_ser")]
+    pub counted: TokenAmount,
+    /// The last account counted, which the next page starts after.
+    pub cursor: ActorID,
+}
+
+/// The input parameters for one page of a total supply recount.
+#[derive(Serialize_tuple, Deserialize_tuple, Clone, Debug)]
+pub struct RecomputeTotalSupplyParams {
+    /// None starts a new recount, discarding any in progress. Otherwise this
+    /// must be the `next` cursor the previous page returned.
+    pub start_after: Option<ActorID>,
+    /// The most balances to sum in this call.
+    pub limit: u32,
+}
+
+impl Cbor for RecomputeTotalSupplyParams {}
+
+/// The outcome of one page of a total supply recount.
+#[derive(Serialize_tuple, Deserialize_tuple, Clone, Debug, PartialEq)]
+pub struct RecomputeTotalSupplyReturn {
+    /// The cursor to pass as `start_after` for the next page, or None once
+    /// every balance has been summed and `total_supply` rewritten.
+    pub next: Option<ActorID>,
+}
+
+impl Cbor for RecomputeTotalSupplyReturn {}
+
+/// Rebuild `total_supply` from the balances, summing at most `limit` of them
+/// per call, so that a drifted total can be repaired on a token with too many
+/// holders to sum in one message. Only the owner may call this.
+///
+/// The recount walks the balances root as it was when the recount started,
+/// and keeps its running sum in `pending_total_supply` between calls.
+/// Transfers made meanwhile don't change the total, and tokens minted
+/// meanwhile are added on when the recount finishes. Each page still walks
+/// past the balances earlier pages counted, since the HAMT can only be
+/// iterated from its start.
+pub fn recompute_total_supply<R: Runtime>(
+    rt: &R,
+    params: RecomputeTotalSupplyParams,
+) -> Result<RecomputeTotalSupplyReturn, ActorError> {
+    let mut state = State::load_from(rt)?;
+    state.check_unlocked()?;
+
+    let resolver = |addr: &Address| rt.resolve_address(addr);
+    runtime_context(rt, &resolver).require_owner(&state, "recompute_total_supply")?;
+
+    if params.limit == 0 {
+        return Err(actor_error!(USR_ILLEGAL_ARGUMENT, "page limit must be positive"));
+    }
+
+    let recount = match (params.start_after, state.pending_total_supply.take()) {
+        (None, _) => SupplyRecount {
+            balances: state.balances,
+            started_supply: state.total_supply.clone(),
+            counted: TokenAmount::from(0),
+            cursor: 0,
+        },
+        (Some(after), Some(recount)) if recount.cursor == after => recount,
+        (Some(after), _) => {
+            return Err(actor_error!(
+                USR_ILLEGAL_ARGUMENT,
+                "no total supply recount is paused after account {}",
+                after
+            ))
+        }
+    };
+
+    let balances = load_balances(rt.store(), &recount.balances, state.hamt_bit_width)?;
+    let mut skipping = params.start_after.is_some();
+    let mut counted = recount.counted;
+    let mut summed = 0;
+    let mut last = None;
+    let mut page_full = false;
+    let walked = balances.for_each(|id, bal| {
+        if skipping {
+            skipping = *id != recount.cursor;
+            return Ok(());
+        }
+        // Stop the walk early once the page is full.
+        if summed == params.limit {
+            page_full = true;
+            return Err(anyhow::anyhow!("page is full"));
+        }
+        counted += &bal.0;
+        summed += 1;
+        last = Some(*id);
+        Ok(())
+    });
+    match walked {
+        Err(_) if page_full => {}
+        Err(err) => {
+            return Err(actor_error!(USR_ILLEGAL_STATE, "failed to sum balances: {:?}", err))
+        }
+        Ok(()) if skipping => {
+            return Err(actor_error!(
+                USR_ILLEGAL_STATE,
+                "recount cursor {} is not in the balances",
+                recount.cursor
+            ))
+        }
+        Ok(()) => {}
+    }
+
+    let next = if page_full { last } else { None };
+    match next {
+        Some(cursor) => {
+            state.pending_total_supply = Some(SupplyRecount { counted, cursor, ..recount });
+        }
+        None => {
+            let minted_since = &state.total_supply - &recount.started_supply;
+            state.total_supply = counted + minted_since;
+        }
+    }
+
+    state.save_to(rt)?;
+    Ok(RecomputeTotalSupplyReturn { next })
+}
+
+/// The kind of an operation that waits on something before it can complete.
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq)]
+pub enum OperationKind {
+    /// A governance proposal, waiting on approvals.
+    Proposal,
+}
+
+/// Where a pending operation is in its lifecycle.
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq)]
+pub enum OperationStatus {
+    /// Still waiting on its preconditions.
+    Pending,
+    /// Its preconditions are met and it can be completed.
+    Ready,
+    /// It has been completed.
+    Executed,
+}
+
+/// The input parameters for querying a pending operation.
+#[derive(Serialize_tuple, Deserialize_tuple, Clone, Debug)]
+pub struct PendingOperationParams {
+    pub id: u64,
+}
+
+impl Cbor for PendingOperationParams {}
+
+/// The lifecycle state of a pending operation.
+#[derive(Serialize_tuple, Deserialize_tuple, Clone, Debug, PartialEq)]
+pub struct PendingOperation {
+    pub kind: OperationKind,
+    pub status: OperationStatus,
+    pub initiator: ActorID,
+    /// The epoch the operation becomes ready or expires at, if it has one.
+    pub deadline: Option<ChainEpoch>,
+    pub approved_weight: u64,
+    pub required_weight: u64,
+}
+
+impl Cbor for PendingOperation {}
+
+/// Look up a pending operation by ID. Governance proposals are currently the
+/// only kind of operation with a pending state.
+pub fn pending_operation_of<BS: fvm_ipld_blockstore::Blockstore>(
+    store: &BS,
+    state: &State,
+    id: u64,
+) -> Result<PendingOperation, ActorError> {
+    let gov = governance::governance(state)?;
+    let proposals = governance::load_proposals(store, &gov.proposals)?;
+    let proposal = governance::get_proposal(&proposals, id)?;
+
+    let approved_weight = gov.approved_weight(&proposal);
+    let status = if proposal.executed {
+        OperationStatus::Executed
+    } else if approved_weight >= gov.threshold {
+        OperationStatus::Ready
+    } else {
+        OperationStatus::Pending
+    };
+
+    Ok(PendingOperation {
+        kind: OperationKind::Proposal,
+        status,
+        initiator: proposal.proposer,
+        deadline: None,
+        approved_weight,
+        required_weight: gov.threshold,
+    })
+}
+
+/// Return the lifecycle state of a pending operation.
+pub fn pending_operation<R: Runtime>(
+    rt: &R,
+    params: PendingOperationParams,
+) -> Result<PendingOperation, ActorError> {
+    let state = State::load_from(rt)?;
+    pending_operation_of(rt.store(), &state, params.id)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::allowance::*;
+    use crate::blockstore::MemoryBlockstore;
+    use crate::claim::*;
+    use crate::constants::INIT_ACTOR_ADDR;
+    use crate::permit::*;
+    use crate::runtime::MockRuntime;
+    use crate::wrap::*;
+    use std::cell::RefCell;
+    use std::rc::Rc;
+
+    const OWNER: ActorID = 100;
+    const ALICE: ActorID = 101;
+    const BOB: ActorID = 102;
+    const TOKEN: ActorID = 1000;
+
+    /// Tests only use ID addresses, which resolve to themselves.
+    fn resolve_id(addr: &Address) -> Option<ActorID> {
+        addr.id().ok()
+    }
+
+    fn context(store: &MemoryBlockstore, caller: ActorID) -> Context<MemoryBlockstore> {
+        Context { caller, receiver: TOKEN, store, resolver: &resolve_id }
+    }
+
+    fn new_state(store: &MemoryBlockstore) -> State {
+        State::new(store, constructor_params()).unwrap()
+    }
+
+    fn transfer_params(recipient: ActorID, amount: u64) -> TransferParams {
+        TransferParams {
+            recipient: Address::new_id(recipient),
+            amount: TokenAmount::from(amount),
+        }
+    }
+
+    fn send_params(to: ActorID, amount: u64) -> Frc46TransferParams {
+        Frc46TransferParams {
+            to: Address::new_id(to),
+            amount: TokenAmount::from(amount),
+            operator_data: RawBytes::default(),
+        }
+    }
+
+    fn constructor_params() -> ConstructorParams {
+        ConstructorParams {
+            name: "Test Token".to_string(),
+            symbol: "TEST".to_string(),
+            max_supply: TokenAmount::from(1_000_000),
+            owner: Address::new_id(OWNER),
+            supply_oracle: None,
+            hamt_bitwidth: DEFAULT_HAMT_BIT_WIDTH,
+            metadata_uri: None,
+            require_receiver_hook: false,
+            wrapped: false,
+            decimals: 6,
+        }
+    }
+
+    /// A mock runtime with a freshly constructed token.
+    fn constructed_runtime() -> MockRuntime {
+        let rt = MockRuntime::new(TOKEN);
+        rt.set_caller(INIT_ACTOR_ADDR);
+        constructor(&rt, constructor_params()).unwrap();
+        rt
+    }
+
+    /// A mock runtime with a freshly constructed token that requires receiver
+    /// hooks.
+    fn hooked_runtime() -> MockRuntime {
+        let rt = MockRuntime::new(TOKEN);
+        rt.set_caller(INIT_ACTOR_ADDR);
+        let params = ConstructorParams { require_receiver_hook: true, ..constructor_params() };
+        constructor(&rt, params).unwrap();
+        rt
+    }
+
+    fn balance(store: &MemoryBlockstore, state: &State, id: ActorID) -> TokenAmount {
+        let balances = load_balances(store, &state.balances, state.hamt_bit_width).unwrap();
+        get_balance(&balances, id).unwrap()
+    }
+
+    #[test]
+    fn mint_credits_recipient_and_total_supply() {
+        let store = MemoryBlockstore::default();
+        let mut state = new_state(&store);
+
+        mint_tokens(&context(&store, OWNER), &mut state, &transfer_params(ALICE, 100)).unwrap();
+        mint_tokens(&context(&store, OWNER), &mut state, &transfer_params(ALICE, 50)).unwrap();
+
+        assert_eq!(balance(&store, &state, ALICE), TokenAmount::from(150));
+        assert_eq!(state.total_supply, TokenAmount::from(150));
+    }
+
+    #[test]
+    fn require_owner_rejects_other_callers() {
+        let store = MemoryBlockstore::default();
+        let state = new_state(&store);
+
+        assert!(context(&store, OWNER).require_owner(&state, "mint").is_ok());
+        let err = context(&store, ALICE).require_owner(&state, "mint").unwrap_err();
+        assert_eq!(err.exit_code, ExitCode::USR_FORBIDDEN);
+    }
+
+    #[test]
+    fn transfer_moves_balance() {
+        let store = MemoryBlockstore::default();
+        let mut state = new_state(&store);
+        mint_tokens(&context(&store, OWNER), &mut state, &transfer_params(ALICE, 100)).unwrap();
+
+        let recipient =
+            transfer_tokens(&context(&store, ALICE), &mut state, &transfer_params(BOB, 40)).unwrap();
+
+        assert_eq!(recipient, BOB);
+        assert_eq!(balance(&store, &state, ALICE), TokenAmount::from(60));
+        assert_eq!(balance(&store, &state, BOB), TokenAmount::from(40));
+        assert_eq!(state.total_supply, TokenAmount::from(100));
+    }
+
+    #[test]
+    fn transfer_with_insufficient_balance_fails() {
+        let store = MemoryBlockstore::default();
+        let mut state = new_state(&store);
+        mint_tokens(&context(&store, OWNER), &mut state, &transfer_params(ALICE, 10)).unwrap();
+        let root = state.balances;
+
+        let err = transfer_tokens(&context(&store, ALICE), &mut state, &transfer_params(BOB, 11))
+            .unwrap_err();
+
+        assert_eq!(err.exit_code, ExitCode::USR_INSUFFICIENT_FUNDS);
+        assert_eq!(state.balances, root);
+    }
+
+    #[test]
+    fn transfer_to_self_fails() {
+        let store = MemoryBlockstore::default();
+        let mut state = new_state(&store);
+        mint_tokens(&context(&store, OWNER), &mut state, &transfer_params(ALICE, 10)).unwrap();
+
+        let err = transfer_tokens(&context(&store, ALICE), &mut state, &transfer_params(ALICE, 1))
+            .unwrap_err();
+
+        assert_eq!(err.exit_code, ExitCode::USR_ILLEGAL_ARGUMENT);
+    }
+
+    #[test]
+    fn split_moves_amounts_to_every_target() {
+        let store = MemoryBlockstore::default();
+        let mut state = new_state(&store);
+        mint_tokens(&context(&store, OWNER), &mut state, &transfer_params(OWNER, 100)).unwrap();
+
+        let params = SplitBalanceParams {
+            source: Address::new_id(OWNER),
+            targets: vec![
+                SplitTarget { recipient: Address::new_id(ALICE), amount: TokenAmount::from(30) },
+                SplitTarget { recipient: Address::new_id(BOB), amount: TokenAmount::from(20) },
+                SplitTarget { recipient: Address::new_id(ALICE), amount: TokenAmount::from(5) },
+            ],
+        };
+        split_tokens(&context(&store, OWNER), &mut state, &params).unwrap();
+
+        assert_eq!(balance(&store, &state, OWNER), TokenAmount::from(45));
+        assert_eq!(balance(&store, &state, ALICE), TokenAmount::from(35));
+        assert_eq!(balance(&store, &state, BOB), TokenAmount::from(20));
+    }
+
+    #[test]
+    fn pending_operation_tracks_proposal_lifecycle() {
+        use crate::governance::*;
+
+        let store = MemoryBlockstore::default();
+        let mut state = new_state(&store);
+        let voters = vec![Voter { id: ALICE, weight: 1 }, Voter { id: BOB, weight: 2 }];
+        let params = ConfigureGovernanceParams { voters, threshold: 3 };
+        configure_governance_in(&context(&store, OWNER), &mut state, params).unwrap();
+
+        let propose = ProposeParams { method: method::SET_MAX_SUPPLY, params: RawBytes::default() };
+        let id = propose_in(&context(&store, ALICE), &mut state, propose).unwrap();
+
+        let op = pending_operation_of(&store, &state, id).unwrap();
+        assert_eq!(op.kind, OperationKind::Proposal);
+        assert_eq!(op.status, OperationStatus::Pending);
+        assert_eq!(op.initiator, ALICE);
+        assert_eq!((op.approved_weight, op.required_weight), (1, 3));
+
+        vote_in(&context(&store, BOB), &mut state, ProposalIdParams { id }).unwrap();
+        let op = pending_operation_of(&store, &state, id).unwrap();
+        assert_eq!(op.status, OperationStatus::Ready);
+        assert_eq!(op.approved_weight, 3);
+
+        take_executable_in(&context(&store, BOB), &mut state, ProposalIdParams { id }).unwrap();
+        let op = pending_operation_of(&store, &state, id).unwrap();
+        assert_eq!(op.status, OperationStatus::Executed);
+
+        let err = pending_operation_of(&store, &state, id + 1).unwrap_err();
+        assert_eq!(err.exit_code, ExitCode::USR_NOT_FOUND);
+    }
+
+    #[test]
+    fn constructor_rejects_non_init_caller() {
+        let rt = MockRuntime::new(TOKEN);
+        rt.set_caller(OWNER);
+
+        let err = constructor(&rt, constructor_params()).unwrap_err();
+
+        assert_eq!(err.exit_code, ExitCode::USR_FORBIDDEN);
+        assert!(rt.root.borrow().is_none());
+    }
+
+    #[test]
+    fn constructor_accepts_init_and_eam_callers() {
+        use crate::constants::EAM_ACTOR_ADDR;
+
+        for caller in [INIT_ACTOR_ADDR, EAM_ACTOR_ADDR] {
+            let rt = MockRuntime::new(TOKEN);
+            rt.set_caller(caller);
+            constructor(&rt, constructor_params()).unwrap();
+            assert!(rt.root.borrow().is_some());
+        }
+
+        let rt = MockRuntime::new(TOKEN);
+        rt.set_caller(4321);
+        let err = constructor(&rt, constructor_params()).unwrap_err();
+        assert_eq!(err.exit_code, ExitCode::USR_FORBIDDEN);
+    }
+
+    #[test]
+    fn mint_and_transfer_through_runtime() {
+        let rt = constructed_runtime();
+
+        rt.set_caller(OWNER);
+        mint(&rt, transfer_params(ALICE, 100)).unwrap();
+        rt.set_caller(ALICE);
+        let ret = transfer(&rt, send_params(BOB, 30)).unwrap();
+
+        let state = State::load_from(&rt).unwrap();
+        assert_eq!(balance(&rt.store, &state, ALICE), TokenAmount::from(70));
+        assert_eq!(balance(&rt.store, &state, BOB), TokenAmount::from(30));
+        assert_eq!([ret.from_balance, ret.to_balance], [70, 30].map(TokenAmount::from));
+        // Neither party has code, so no receiver hook was called.
+        assert!(ret.recipient_data.is_empty());
+        assert!(rt.sent.borrow().is_empty());
+    }
+
+    #[test]
+    fn mint_returns_the_new_supply_and_respects_max_supply() {
+        let rt = constructed_runtime();
+        rt.set_caller(OWNER);
+        assert_eq!(mint(&rt, transfer_params(ALICE, 600_000)).unwrap(), TokenAmount::from(600_000));
+        let root = rt.root().unwrap();
+
+        let err = mint(&rt, transfer_params(BOB, 400_001)).unwrap_err();
+        assert_eq!(err.exit_code, ExitCode::USR_ILLEGAL_ARGUMENT);
+        assert_eq!(*rt.root.borrow(), Some(root));
+
+        let supply = mint(&rt, transfer_params(BOB, 400_000)).unwrap();
+        assert_eq!(supply, TokenAmount::from(1_000_000));
+        let state = State::load_from(&rt).unwrap();
+        assert_eq!(state.total_supply, supply);
+        assert_eq!(balance(&rt.store, &state, BOB), TokenAmount::from(400_000));
+    }
+
+    #[test]
+    fn mint_calls_the_receiver_hook_of_contract_recipients() {
+        let rt = hooked_runtime();
+        rt.set_code_cid(BOB, Cid::new_v1(0x55, Code::Identity.digest(b"hook-actor")));
+        rt.set_caller(OWNER);
+        let root = rt.root().unwrap();
+
+        rt.expect_send_exit_code(ExitCode::USR_ILLEGAL_ARGUMENT);
+        let err = rt.apply(|rt| mint(rt, transfer_params(BOB, 30))).unwrap_err();
+        assert_eq!(err.exit_code, ExitCode::USR_FORBIDDEN);
+        assert_eq!(rt.root().unwrap(), root);
+        let mints = vec![transfer_params(ALICE, 10), transfer_params(BOB, 20)];
+        rt.expect_send_exit_code(ExitCode::USR_ILLEGAL_ARGUMENT);
+        rt.apply(|rt| mint_batch(rt, MintBatchParams { mints })).unwrap_err();
+        assert_eq!(rt.root().unwrap(), root);
+
+        // Accounts without code are credited without a hook call.
+        rt.sent.borrow_mut().clear();
+        mint(&rt, transfer_params(ALICE, 10)).unwrap();
+        assert!(rt.sent.borrow().is_empty());
+
+        mint(&rt, transfer_params(BOB, 30)).unwrap();
+        let sent = rt.sent.borrow();
+        assert_eq!(sent.len(), 1);
+        assert_eq!(sent[0].method, RECEIVER_HOOK_METHOD_NUM);
+        let hook: UniversalReceiverParams = sent[0].params.deserialize().unwrap();
+        let hook: TokenReceivedParams = hook.payload.deserialize().unwrap();
+        assert_eq!((hook.from, hook.to, hook.operator), (TOKEN, BOB, OWNER));
+        assert_eq!(hook.amount, TokenAmount::from(30));
+        let state = State::load_from(&rt).unwrap();
+        assert_eq!(balance(&rt.store, &state, BOB), TokenAmount::from(30));
+        assert_eq!(state.total_supply, TokenAmount::from(40));
+    }
+
+    #[test]
+    fn transfer_fails_when_receiver_hook_rejects() {
+        let rt = hooked_runtime();
+        rt.set_caller(OWNER);
+        mint(&rt, transfer_params(ALICE, 100)).unwrap();
+        let root = rt.root().unwrap();
+
+        rt.set_code_cid(BOB, Cid::new_v1(0x55, Code::Identity.digest(b"hook-actor")));
+        // The hook sees the credit saved under the lock, then rejects it.
+        rt.on_send(|rt, _| {
+            let state = State::load_from(rt).unwrap();
+            assert!(state.reentrancy_locked);
+            assert_eq!(balance(&rt.store, &state, BOB), TokenAmount::from(30));
+            let exit_code = ExitCode::USR_ILLEGAL_ARGUMENT;
+            Receipt { exit_code, return_data: RawBytes::default(), gas_used: 0 }
+        });
+        rt.set_caller(ALICE);
+        let err = rt.apply(|rt| transfer(rt, send_params(BOB, 30))).unwrap_err();
+
+        assert_eq!(err.exit_code, ExitCode::USR_FORBIDDEN);
+        let sent = rt.sent.borrow();
+        assert_eq!(sent.len(), 1);
+        assert_eq!(sent[0].to, Address::new_id(BOB));
+        assert_eq!(sent[0].method, RECEIVER_HOOK_METHOD_NUM);
+
+        // The abort reverted the credit.
+        assert_eq!(rt.root().unwrap(), root);
+        let state = State::load_from(&rt).unwrap();
+        assert_eq!(balance(&rt.store, &state, ALICE), TokenAmount::from(100));
+        assert_eq!(balance(&rt.store, &state, BOB), TokenAmount::from(0));
+        assert!(!state.reentrancy_locked);
+    }
+
+    #[test]
+    fn receiver_hooks_cannot_reenter_the_token() {
+        for hook_exit_code in [ExitCode::OK, ExitCode::USR_ILLEGAL_ARGUMENT] {
+            let rt = hooked_runtime();
+            rt.set_caller(OWNER);
+            mint(&rt, transfer_params(ALICE, 100)).unwrap();
+            rt.set_caller(ALICE);
+            let allowance =
+                ApproveParams { spender: Address::new_id(BOB), amount: TokenAmount::from(50) };
+            approve(&rt, allowance).unwrap();
+
+            // BOB's hook tries to move tokens again, then answers with
+            // hook_exit_code whatever the re-entries did.
+            let reentries = Rc::new(RefCell::new(Vec::new()));
+            let seen = reentries.clone();
+            rt.on_send(move |rt, _| {
+                let from = TransferFromParams {
+                    from: Address::new_id(ALICE),
+                    to: Address::new_id(BOB),
+                    amount: TokenAmount::from(10),
+                    operator_data: RawBytes::default(),
+                };
+                seen.borrow_mut().push(transfer(rt, send_params(ALICE, 10)).unwrap_err());
+                seen.borrow_mut().push(transfer_from(rt, from).unwrap_err());
+                Receipt { exit_code: hook_exit_code, return_data: RawBytes::default(), gas_used: 0 }
+            });
+            rt.set_code_cid(BOB, Cid::new_v1(0x55, Code::Identity.digest(b"hook-actor")));
+            let res = rt.apply(|rt| transfer(rt, send_params(BOB, 30)));
+
+            let reentries = reentries.borrow();
+            assert_eq!(reentries.len(), 2);
+            for err in reentries.iter() {
+                assert_eq!(err.exit_code, ExitCode::USR_ILLEGAL_STATE);
+                assert_eq!(err.msg, "reentrant call rejected");
+            }
+
+            let state = State::load_from(&rt).unwrap();
+            assert!(!state.reentrancy_locked);
+            let received = if hook_exit_code.is_success() {
+                res.unwrap();
+                30
+            } else {
+                res.unwrap_err();
+                0
+            };
+            assert_eq!(balance(&rt.store, &state, ALICE), TokenAmount::from(100 - received));
+            assert_eq!(balance(&rt.store, &state, BOB), TokenAmount::from(received));
+        }
+    }
+
+    #[test]
+    fn the_block_cache_cuts_a_ten_recipient_batch_to_one_read() {
+        use crate::blockstore::CachingBlockstore;
+
+        // A batch records a transfer per recipient, and each record reloads
+        // the recent-transfers block the one before it wrote. Straight from
+        // the store that's a read per recipient: 10 for 10 recipients.
+        // Through the cache, only the block left by an earlier message is
+        // read, once.
+        fn record_batch<BS: fvm_ipld_blockstore::Blockstore>(store: &BS, mut state: State) {
+            for to in 0..10 {
+                let record =
+                    TransferRecord { from: ALICE, to, amount: TokenAmount::from(1), epoch: 0 };
+                record_transfer(store, &mut state, record).unwrap();
+            }
+        }
+
+        let store = MemoryBlockstore::default();
+        let mut state = new_state(&store);
+        let earlier =
+            TransferRecord { from: ALICE, to: BOB, amount: TokenAmount::from(1), epoch: 0 };
+        record_transfer(&store, &mut state, earlier).unwrap();
+
+        store.gets.set(0);
+        record_batch(&store, state.clone());
+        assert_eq!(store.gets.get(), 10);
+
+        store.gets.set(0);
+        record_batch(&CachingBlockstore::new(&store), state);
+        assert_eq!(store.gets.get(), 1);
+    }
+
+    #[test]
+    fn state_root_is_written_once_per_save() {
+        let rt = hooked_runtime();
+        rt.set_caller(OWNER);
+        mint(&rt, transfer_params(ALICE, 100)).unwrap();
+
+        rt.root_writes.set(0);
+        rt.set_caller(ALICE);
+        transfer(&rt, send_params(BOB, 30)).unwrap();
+        assert_eq!(rt.root_writes.get(), 1);
+
+        // A hook call saves the lock, the unlock, and then the result.
+        rt.root_writes.set(0);
+        rt.set_code_cid(BOB, Cid::new_v1(0x55, Code::Identity.digest(b"hook-actor")));
+        transfer(&rt, send_params(BOB, 30)).unwrap();
+        assert_eq!(rt.root_writes.get(), 3);
+        let state = State::load_from(&rt).unwrap();
+        assert!(!state.reentrancy_locked);
+        assert_eq!(balance(&rt.store, &state, BOB), TokenAmount::from(60));
+    }
+
+    #[test]
+    fn receiver_hooks_only_reach_allowlisted_contracts() {
+        let rt = hooked_runtime();
+        rt.set_caller(OWNER);
+        mint(&rt, transfer_params(ALICE, 100)).unwrap();
+        let hook_code = Cid::new_v1(0x55, Code::Identity.digest(b"hook-actor"));
+        rt.set_code_cid(BOB, hook_code);
+        rt.set_code_cid(COLLECTOR, hook_code);
+
+        let allow = |id, allowed| SetHookAllowedParams { actor: Address::new_id(id), allowed };
+        set_hook_allowed(&rt, allow(BOB, true)).unwrap();
+
+        rt.set_caller(ALICE);
+        transfer(&rt, send_params(BOB, 10)).unwrap();
+        transfer(&rt, send_params(COLLECTOR, 10)).unwrap();
+        {
+            let sent = rt.sent.borrow();
+            assert_eq!(sent.len(), 1);
+            assert_eq!(sent[0].to, Address::new_id(BOB));
+        }
+
+        // Removing the last entry leaves an empty allowlist, not no allowlist.
+        rt.set_caller(OWNER);
+        set_hook_allowed(&rt, allow(BOB, false)).unwrap();
+        rt.set_caller(ALICE);
+        transfer(&rt, send_params(BOB, 10)).unwrap();
+        assert_eq!(rt.sent.borrow().len(), 1);
+
+        let state = State::load_from(&rt).unwrap();
+        assert_eq!(balance(&rt.store, &state, BOB), TokenAmount::from(20));
+        assert_eq!(balance(&rt.store, &state, COLLECTOR), TokenAmount::from(10));
+    }
+
+    #[test]
+    fn set_hook_allowed_is_owner_only() {
+        let rt = constructed_runtime();
+        rt.set_caller(ALICE);
+        let params = SetHookAllowedParams { actor: Address::new_id(BOB), allowed: true };
+
+        let err = set_hook_allowed(&rt, params).unwrap_err();
+        assert_eq!(err.exit_code, ExitCode::USR_FORBIDDEN);
+        assert_eq!(State::load_from(&rt).unwrap().hook_allowlist, None);
+    }
+
+    #[test]
+    fn locked_tokens_cannot_move_until_the_unlock_epoch() {
+        use crate::error::ErrorKind;
+
+        let rt = constructed_runtime();
+        rt.set_caller(OWNER);
+        mint(&rt, transfer_params(ALICE, 100)).unwrap();
+        let locked = |amount: u64, unlock_epoch| LockParams {
+            account: Address::new_id(ALICE),
+            amount: TokenAmount::from(amount),
+            unlock_epoch,
+        };
+        lock(&rt, locked(50, 10)).unwrap();
+        lock(&rt, locked(20, 20)).unwrap();
+
+        // 70 of the 100 are locked, so only 30 can move.
+        rt.set_caller(ALICE);
+        let err = transfer(&rt, send_params(BOB, 31)).unwrap_err();
+        assert_eq!(err.exit_code, ExitCode::USR_INSUFFICIENT_FUNDS);
+        assert_eq!(err.kind(), Some(ErrorKind::Locked));
+        transfer(&rt, send_params(BOB, 30)).unwrap();
+
+        // Once the first lock expires it is cleared, and only the second binds.
+        rt.epoch.set(10);
+        let err = transfer(&rt, send_params(BOB, 51)).unwrap_err();
+        assert_eq!(err.kind(), Some(ErrorKind::Locked));
+        transfer(&rt, send_params(BOB, 50)).unwrap();
+        let state = State::load_from(&rt).unwrap();
+        let root = state.locks.unwrap();
+        let locks: Locks<_> = load_hamt(&rt.store, &root, state.hamt_bit_width, "locks").unwrap();
+        assert_eq!(locks.get(&ALICE).unwrap().unwrap().len(), 1);
+
+        rt.epoch.set(20);
+        transfer(&rt, send_params(BOB, 20)).unwrap();
+        let state = State::load_from(&rt).unwrap();
+        assert_eq!(balance(&rt.store, &state, BOB), TokenAmount::from(100));
+    }
+
+    #[test]
+    fn lock_rejects_past_unlock_epochs_and_non_owners() {
+        let rt = constructed_runtime();
+        rt.epoch.set(10);
+        let params = LockParams {
+            account: Address::new_id(ALICE),
+            amount: TokenAmount::from(10),
+            unlock_epoch: 10,
+        };
+
+        rt.set_caller(ALICE);
+        let err = lock(&rt, LockParams { unlock_epoch: 11, ..params.clone() }).unwrap_err();
+        assert_eq!(err.exit_code, ExitCode::USR_FORBIDDEN);
+
+        rt.set_caller(OWNER);
+        let err = lock(&rt, params).unwrap_err();
+        assert_eq!(err.exit_code, ExitCode::USR_ILLEGAL_ARGUMENT);
+        assert_eq!(State::load_from(&rt).unwrap().locks, None);
+    }
+
+    #[test]
+    fn mints_are_held_to_the_schedule() {
+        use crate::error::ErrorKind;
+
+        let rt = constructed_runtime();
+        rt.set_caller(OWNER);
+        let window = |start_epoch, end_epoch, max: u64| MintWindowParams {
+            start_epoch,
+            end_epoch,
+            max_in_window: TokenAmount::from(max),
+        };
+        // Two windows overlap on [15, 20), and a third opens after a gap.
+        let windows = vec![window(10, 20, 100), window(15, 30, 50), window(40, 50, 10)];
+        set_mint_schedule(&rt, SetMintScheduleParams { windows }).unwrap();
+        fn exceeded<T: std::fmt::Debug>(res: Result<T, ActorError>) {
+            let err = res.unwrap_err();
+            assert_eq!(err.exit_code, ExitCode::USR_FORBIDDEN);
+            assert_eq!(err.kind(), Some(ErrorKind::ScheduleExceeded));
+        }
+
+        // Before the first window opens.
+        exceeded(mint(&rt, transfer_params(ALICE, 1)));
+
+        rt.epoch.set(10);
+        mint(&rt, transfer_params(ALICE, 90)).unwrap();
+        exceeded(mint(&rt, transfer_params(ALICE, 11)));
+
+        // In the overlap, the second window has room the first doesn't.
+        rt.epoch.set(15);
+        mint(&rt, transfer_params(ALICE, 40)).unwrap();
+        mint(&rt, transfer_params(ALICE, 10)).unwrap();
+        exceeded(mint(&rt, transfer_params(ALICE, 11)));
+
+        // Between windows, nothing can be minted.
+        rt.epoch.set(35);
+        exceeded(mint(&rt, transfer_params(ALICE, 1)));
+
+        // A batch is charged as one mint.
+        rt.epoch.set(40);
+        let mints = vec![transfer_params(ALICE, 6), transfer_params(BOB, 6)];
+        exceeded(mint_batch(&rt, MintBatchParams { mints }));
+        let mints = vec![transfer_params(ALICE, 5), transfer_params(BOB, 5)];
+        mint_batch(&rt, MintBatchParams { mints }).unwrap();
+
+        let state = State::load_from(&rt).unwrap();
+        assert_eq!(state.total_supply, TokenAmount::from(150));
+        let minted: Vec<_> = state.mint_schedule.iter().map(|w| w.minted.clone()).collect();
+        assert_eq!(minted, [100, 40, 10].map(TokenAmount::from));
+
+        // Clearing the schedule lifts the restriction.
+        set_mint_schedule(&rt, SetMintScheduleParams { windows: vec![] }).unwrap();
+        mint(&rt, transfer_params(ALICE, 1000)).unwrap();
+    }
+
+    #[test]
+    fn safe_transfer_reports_a_declined_transfer_instead_of_failing() {
+        // Hooks aren't required, but a safe transfer asks contracts anyway.
+        let rt = constructed_runtime();
+        rt.set_caller(OWNER);
+        mint(&rt, transfer_params(ALICE, 100)).unwrap();
+        rt.set_code_cid(BOB, Cid::new_v1(0x55, Code::Identity.digest(b"hook-actor")));
+        let root = rt.root().unwrap();
+
+        rt.set_caller(ALICE);
+        rt.expect_send_exit_code(ExitCode::USR_ILLEGAL_ARGUMENT);
+        assert!(!safe_transfer(&rt, transfer_params(BOB, 30)).unwrap());
+        assert_eq!(rt.sent.borrow()[0].method, RECEIVER_HOOK_METHOD_NUM);
+        // The lock was released, leaving the state as it was.
+        assert_eq!(rt.root().unwrap(), root);
+        let state = State::load_from(&rt).unwrap();
+        assert_eq!(balance(&rt.store, &state, ALICE), TokenAmount::from(100));
+        assert!(!state.reentrancy_locked);
+
+        assert!(safe_transfer(&rt, transfer_params(BOB, 30)).unwrap());
+        // An account recipient accepts without being asked.
+        assert!(safe_transfer(&rt, transfer_params(OWNER, 20)).unwrap());
+        assert_eq!(rt.sent.borrow().len(), 2);
+
+        let state = State::load_from(&rt).unwrap();
+        assert_eq!(balance(&rt.store, &state, ALICE), TokenAmount::from(50));
+        assert_eq!(balance(&rt.store, &state, BOB), TokenAmount::from(30));
+    }
+
+    #[test]
+    fn operators_can_transfer_until_revoked() {
+        use crate::error::ErrorKind;
+
+        let rt = constructed_runtime();
+        rt.set_caller(OWNER);
+        mint(&rt, transfer_params(ALICE, 100)).unwrap();
+        let spend = |amount: u64| TransferFromParams {
+            from: Address::new_id(ALICE),
+            to: Address::new_id(COLLECTOR),
+            amount: TokenAmount::from(amount),
+            operator_data: RawBytes::default(),
+        };
+        let approved = || {
+            let params =
+                IsOperatorParams { owner: Address::new_id(ALICE), operator: Address::new_id(BOB) };
+            is_operator(&rt, params).unwrap()
+        };
+
+        rt.set_caller(BOB);
+        let err = transfer_from(&rt, spend(10)).unwrap_err();
+        assert_eq!(err.exit_code, ExitCode::USR_INSUFFICIENT_FUNDS);
+        assert_eq!(err.kind(), Some(ErrorKind::InsufficientAllowance));
+
+        rt.set_caller(ALICE);
+        set_operator(&rt, SetOperatorParams { operator: Address::new_id(BOB), approved: true })
+            .unwrap();
+        assert!(approved());
+
+        // An operator may spend any amount, and the receipt is from the owner.
+        rt.set_caller(BOB);
+        let ret = transfer_from(&rt, spend(60)).unwrap();
+        assert_eq!([ret.from_balance, ret.to_balance], [40, 60].map(TokenAmount::from));
+        assert_eq!(ret.allowance, TokenAmount::from(0));
+        transfer_from(&rt, spend(40)).unwrap();
+        let state = State::load_from(&rt).unwrap();
+        assert_eq!(balance(&rt.store, &state, ALICE), TokenAmount::from(0));
+        assert_eq!(balance(&rt.store, &state, COLLECTOR), TokenAmount::from(100));
+
+        // Approval only runs one way.
+        rt.set_caller(ALICE);
+        let params = TransferFromParams {
+            from: Address::new_id(BOB),
+            to: Address::new_id(ALICE),
+            amount: TokenAmount::from(1),
+            operator_data: RawBytes::default(),
+        };
+        let err = transfer_from(&rt, params).unwrap_err();
+        assert_eq!(err.kind(), Some(ErrorKind::InsufficientAllowance));
+
+        set_operator(&rt, SetOperatorParams { operator: Address::new_id(BOB), approved: false })
+            .unwrap();
+        assert!(!approved());
+        rt.set_caller(COLLECTOR);
+        transfer(&rt, send_params(ALICE, 10)).unwrap();
+        rt.set_caller(BOB);
+        let err = transfer_from(&rt, spend(10)).unwrap_err();
+        assert_eq!(err.kind(), Some(ErrorKind::InsufficientAllowance));
+    }
+
+    #[test]
+    fn transfer_from_spends_the_allowance() {
+        use crate::error::ErrorKind;
+
+        let rt = constructed_runtime();
+        rt.set_caller(OWNER);
+        mint(&rt, transfer_params(ALICE, 100)).unwrap();
+        let spend = |amount: u64| TransferFromParams {
+            from: Address::new_id(ALICE),
+            to: Address::new_id(COLLECTOR),
+            amount: TokenAmount::from(amount),
+            operator_data: RawBytes::default(),
+        };
+        let approved = |amount: u64| ApproveParams {
+            spender: Address::new_id(BOB),
+            amount: TokenAmount::from(amount),
+        };
+        let remaining = || {
+            let params =
+                AllowanceParams { owner: Address::new_id(ALICE), spender: Address::new_id(BOB) };
+            allowance(&rt, params).unwrap()
+        };
+
+        rt.set_caller(ALICE);
+        approve(&rt, approved(50)).unwrap();
+        assert_eq!(remaining(), TokenAmount::from(50));
+
+        rt.set_caller(BOB);
+        transfer_from(&rt, spend(30)).unwrap();
+        assert_eq!(remaining(), TokenAmount::from(20));
+        let root = rt.root().unwrap();
+        let err = transfer_from(&rt, spend(21)).unwrap_err();
+        assert_eq!(err.exit_code, ExitCode::USR_INSUFFICIENT_FUNDS);
+        assert_eq!(err.kind(), Some(ErrorKind::InsufficientAllowance));
+        assert_eq!(rt.root().unwrap(), root);
+
+        // Spending all of it removes the entry.
+        transfer_from(&rt, spend(20)).unwrap();
+        assert_eq!(remaining(), TokenAmount::from(0));
+        let state = State::load_from(&rt).unwrap();
+        assert_eq!(balance(&rt.store, &state, ALICE), TokenAmount::from(50));
+        assert_eq!(balance(&rt.store, &state, COLLECTOR), TokenAmount::from(50));
+        let allowances: Allowances<_> =
+            load_hamt(&rt.store, &state.allowances.unwrap(), state.hamt_bit_width, "allowances")
+                .unwrap();
+        assert!(allowances.get(&pair_key(ALICE, BOB)).unwrap().is_none());
+
+        // Approving again replaces the allowance, and zero revokes it.
+        rt.set_caller(ALICE);
+        approve(&rt, approved(10)).unwrap();
+        approve(&rt, approved(5)).unwrap();
+        assert_eq!(remaining(), TokenAmount::from(5));
+        approve(&rt, approved(0)).unwrap();
+        rt.set_caller(BOB);
+        let err = transfer_from(&rt, spend(1)).unwrap_err();
+        assert_eq!(err.kind(), Some(ErrorKind::InsufficientAllowance));
+    }
+
+    #[test]
+    fn allowance_reads_back_approvals_and_is_zero_for_unknown_pairs() {
+        let rt = constructed_runtime();
+        rt.set_caller(ALICE);
+        let (alice, bob) = (Address::new_id(ALICE), Address::new_id(BOB));
+        approve(&rt, ApproveParams { spender: bob, amount: TokenAmount::from(25) }).unwrap();
+
+        let root = rt.root().unwrap();
+        let read = |owner, spender| allowance(&rt, AllowanceParams { owner, spender }).unwrap();
+        assert_eq!(read(alice, bob), TokenAmount::from(25));
+        // A pair nobody approved, or that doesn't resolve, has granted nothing.
+        let unresolvable = Address::new_actor(b"never-seen");
+        assert_eq!(read(bob, alice), TokenAmount::from(0));
+        assert_eq!(read(unresolvable, bob), TokenAmount::from(0));
+        assert_eq!(read(alice, unresolvable), TokenAmount::from(0));
+        // Reading changes nothing.
+        assert_eq!(rt.root().unwrap(), root);
+    }
+
+    #[test]
+    fn infinite_allowances_are_never_drawn_down() {
+        let rt = constructed_runtime();
+        rt.set_caller(OWNER);
+        mint(&rt, transfer_params(ALICE, 100)).unwrap();
+        let spend = |amount: u64| TransferFromParams {
+            from: Address::new_id(ALICE),
+            to: Address::new_id(COLLECTOR),
+            amount: TokenAmount::from(amount),
+            operator_data: RawBytes::default(),
+        };
+        let approve_bob = |amount: TokenAmount| {
+            rt.set_caller(ALICE);
+            approve(&rt, ApproveParams { spender: Address::new_id(BOB), amount }).unwrap();
+            rt.set_caller(BOB);
+        };
+
+        approve_bob(infinite_allowance());
+        assert_eq!(transfer_from(&rt, spend(30)).unwrap().allowance, infinite_allowance());
+        assert_eq!(transfer_from(&rt, spend(20)).unwrap().allowance, infinite_allowance());
+        let burn = BurnFromParams { owner: Address::new_id(ALICE), amount: TokenAmount::from(5) };
+        burn_from(&rt, burn).unwrap();
+        let params =
+            AllowanceParams { owner: Address::new_id(ALICE), spender: Address::new_id(BOB) };
+        assert_eq!(allowance(&rt, params).unwrap(), infinite_allowance());
+
+        // One less than the sentinel is an ordinary allowance.
+        approve_bob(infinite_allowance() - 1);
+        let ret = transfer_from(&rt, spend(10)).unwrap();
+        assert_eq!(ret.allowance, infinite_allowance() - 11);
+        approve_bob(TokenAmount::from(15));
+        assert_eq!(transfer_from(&rt, spend(10)).unwrap().allowance, TokenAmount::from(5));
+    }
+
+    /// Alice's permit for Bob to spend `amount` until `deadline`, signed for
+    /// `nonce`.
+    fn signed_permit(
+        rt: &MockRuntime,
+        amount: u64,
+        deadline: ChainEpoch,
+        nonce: u64,
+    ) -> PermitParams {
+        let (owner, spender) = (Address::new_id(ALICE), Address::new_id(BOB));
+        let amount = TokenAmount::from(amount);
+        let message = PermitMessage {
+            token: Address::new_id(rt.receiver()),
+            owner,
+            spender,
+            amount: token::to_evm_u256(&amount).unwrap().to_vec(),
+            deadline,
+            nonce,
+        };
+        let mut signature = vec![1];
+        signature.extend(MockRuntime::sign(&owner, &to_vec(&message).unwrap()).bytes());
+        PermitParams { owner, spender, amount, deadline, signature }
+    }
+
+    #[test]
+    fn permits_approve_on_the_owners_signature() {
+        let rt = constructed_runtime();
+        rt.set_caller(OWNER);
+        mint(&rt, transfer_params(ALICE, 100)).unwrap();
+        rt.epoch.set(10);
+
+        // Anyone may submit the owner's permit.
+        rt.set_caller(COLLECTOR);
+        permit(&rt, signed_permit(&rt, 40, 10, 0)).unwrap();
+        let state = State::load_from(&rt).unwrap();
+        assert_eq!(get_allowance(&rt.store, &state, ALICE, BOB).unwrap(), TokenAmount::from(40));
+        assert_eq!(get_nonce(&rt.store, &state, ALICE).unwrap(), 1);
+
+        rt.set_caller(BOB);
+        let spend = TransferFromParams {
+            from: Address::new_id(ALICE),
+            to: Address::new_id(BOB),
+            amount: TokenAmount::from(25),
+            operator_data: RawBytes::default(),
+        };
+        assert_eq!(transfer_from(&rt, spend).unwrap().allowance, TokenAmount::from(15));
+
+        // A signature for a different amount doesn't verify.
+        let forged = signed_permit(&rt, 40, 10, 1);
+        let forged = PermitParams { amount: TokenAmount::from(90), ..forged };
+        assert_eq!(permit(&rt, forged).unwrap_err().exit_code, ExitCode::USR_FORBIDDEN);
+
+        // Nor can an amount that doesn't fit in a uint256 be signed for.
+        let amount = TokenAmount::from(1) << 256usize;
+        let too_large = PermitParams { amount, ..signed_permit(&rt, 40, 10, 1) };
+        let err = permit(&rt, too_large).unwrap_err();
+        assert_eq!(err.exit_code, ExitCode::USR_ILLEGAL_ARGUMENT);
+    }
+
+    #[test]
+    fn expired_permits_are_rejected() {
+        let rt = constructed_runtime();
+        rt.set_caller(COLLECTOR);
+        rt.epoch.set(11);
+
+        let err = permit(&rt, signed_permit(&rt, 40, 10, 0)).unwrap_err();
+        assert_eq!(err.exit_code, ExitCode::USR_ILLEGAL_ARGUMENT);
+        let state = State::load_from(&rt).unwrap();
+        assert_eq!(get_allowance(&rt.store, &state, ALICE, BOB).unwrap(), TokenAmount::from(0));
+        assert_eq!(get_nonce(&rt.store, &state, ALICE).unwrap(), 0);
+    }
+
+    #[test]
+    fn permits_cannot_be_replayed() {
+        let rt = constructed_runtime();
+        rt.set_caller(COLLECTOR);
+        let alice = || NonceOfParams { owner: Address::new_id(ALICE) };
+        assert_eq!(nonce_of(&rt, alice()).unwrap(), 0);
+        let params = signed_permit(&rt, 40, 10, 0);
+        permit(&rt, params.clone()).unwrap();
+
+        // Alice lowers the allowance, and Bob tries to restore it with the same permit.
+        rt.set_caller(ALICE);
+        let lower = ApproveParams { spender: Address::new_id(BOB), amount: TokenAmount::from(5) };
+        approve(&rt, lower).unwrap();
+        rt.set_caller(BOB);
+        let err = permit(&rt, params).unwrap_err();
+        assert_eq!(err.exit_code, ExitCode::USR_FORBIDDEN);
+        let state = State::load_from(&rt).unwrap();
+        assert_eq!(get_allowance(&rt.store, &state, ALICE, BOB).unwrap(), TokenAmount::from(5));
+
+        // The next nonce is what a fresh permit must be signed for.
+        assert_eq!(nonce_of(&rt, alice()).unwrap(), 1);
+        permit(&rt, signed_permit(&rt, 60, 10, 1)).unwrap();
+        assert_eq!(nonce_of(&rt, alice()).unwrap(), 2);
+        let state = State::load_from(&rt).unwrap();
+        assert_eq!(get_allowance(&rt.store, &state, ALICE, BOB).unwrap(), TokenAmount::from(60));
+    }
+
+    #[test]
+    fn approve_and_call_lets_the_spender_draw_on_the_allowance_at_once() {
+        let rt = constructed_runtime();
+        rt.set_caller(OWNER);
+        mint(&rt, transfer_params(ALICE, 100)).unwrap();
+        // Bob's callback collects 30 of what it was just approved for.
+        rt.on_send(|rt, msg| {
+            assert_eq!((msg.to, msg.method), (Address::new_id(BOB), RECEIVER_HOOK_METHOD_NUM));
+            assert_eq!(msg.params, RawBytes::new(b"order 7".to_vec()));
+            let collect = TransferFromParams {
+                from: Address::new_id(ALICE),
+                to: Address::new_id(COLLECTOR),
+                amount: TokenAmount::from(30),
+                operator_data: RawBytes::default(),
+            };
+            assert_eq!(transfer_from(rt, collect).unwrap().allowance, TokenAmount::from(20));
+            // Nothing else gets past the lock meanwhile.
+            let err = transfer(rt, send_params(COLLECTOR, 1)).unwrap_err();
+            assert_eq!(err.exit_code, ExitCode::USR_ILLEGAL_STATE);
+            let return_data = RawBytes::new(b"paid".to_vec());
+            Receipt { exit_code: ExitCode::OK, return_data, gas_used: 0 }
+        });
+
+        rt.set_caller(ALICE);
+        let params = ApproveAndCallParams {
+            spender: Address::new_id(BOB),
+            amount: TokenAmount::from(50),
+            call_method: RECEIVER_HOOK_METHOD_NUM,
+            call_params: RawBytes::new(b"order 7".to_vec()),
+        };
+        let ret = approve_and_call(&rt, params.clone()).unwrap();
+        assert_eq!(ret, RawBytes::new(b"paid".to_vec()));
+        let state = State::load_from(&rt).unwrap();
+        assert!(!state.reentrancy_locked);
+        assert_eq!(state.approved_call, None);
+        let balances = [ALICE, COLLECTOR].map(|id| balance(&rt.store, &state, id));
+        assert_eq!(balances, [70, 30].map(TokenAmount::from));
+        assert_eq!(get_allowance(&rt.store, &state, ALICE, BOB).unwrap(), TokenAmount::from(20));
+
+        // A callback that fails fails the approval with it.
+        rt.on_send(|_, _| Receipt {
+            exit_code: ExitCode::USR_FORBIDDEN,
+            return_data: RawBytes::default(),
+            gas_used: 0,
+        });
+        let err = approve_and_call(&rt, params).unwrap_err();
+        assert_eq!(err.exit_code, ExitCode::USR_ILLEGAL_STATE);
+    }
+
+    #[test]
+    fn approve_and_call_only_calls_other_actors_receiver_hooks() {
+        let rt = constructed_runtime();
+        rt.set_caller(ALICE);
+        let params = |spender: ActorID, call_method: MethodNum| ApproveAndCallParams {
+            spender: Address::new_id(spender),
+            amount: TokenAmount::from(50),
+            call_method,
+            call_params: RawBytes::default(),
+        };
+
+        // The token can't be made to call itself.
+        let err = approve_and_call(&rt, params(TOKEN, RECEIVER_HOOK_METHOD_NUM)).unwrap_err();
+        assert_eq!(err.exit_code, ExitCode::USR_ILLEGAL_ARGUMENT);
+        // Nor can the spender be called on any other method.
+        let err = approve_and_call(&rt, params(BOB, method::TRANSFER)).unwrap_err();
+        assert_eq!(err.exit_code, ExitCode::USR_ILLEGAL_ARGUMENT);
+        assert!(rt.sent.borrow().is_empty());
+        let state = State::load_from(&rt).unwrap();
+        assert_eq!(get_allowance(&rt.store, &state, ALICE, BOB).unwrap(), TokenAmount::from(0));
+    }
+
+    #[test]
+    fn compare_and_set_only_replaces_the_expected_allowance() {
+        use crate::error::ErrorKind;
+
+        let rt = constructed_runtime();
+        rt.set_caller(ALICE);
+        let cas = |expected: u64, new: u64| CompareAndSetAllowanceParams {
+            spender: Address::new_id(BOB),
+            expected: TokenAmount::from(expected),
+            new: TokenAmount::from(new),
+        };
+        let allowance = || {
+            let state = State::load_from(&rt).unwrap();
+            get_allowance(&rt.store, &state, ALICE, BOB).unwrap()
+        };
+
+        // No allowance counts as zero.
+        let err = compare_and_set_allowance(&rt, cas(10, 20)).unwrap_err();
+        assert_eq!(err.kind(), Some(ErrorKind::CompareFailed));
+        compare_and_set_allowance(&rt, cas(0, 20)).unwrap();
+        assert_eq!(allowance(), TokenAmount::from(20));
+
+        // A mismatch leaves the allowance as it was.
+        let err = compare_and_set_allowance(&rt, cas(0, 50)).unwrap_err();
+        assert_eq!(err.exit_code, ExitCode::USR_ILLEGAL_ARGUMENT);
+        assert_eq!(err.kind(), Some(ErrorKind::CompareFailed));
+        assert_eq!(allowance(), TokenAmount::from(20));
+
+        compare_and_set_allowance(&rt, cas(20, 0)).unwrap();
+        assert_eq!(allowance(), TokenAmount::from(0));
+    }
+
+    #[test]
+    fn allowances_of_pages_through_an_owners_spenders() {
+        let rt = constructed_runtime();
+        let page = |owner: ActorID, start_after: Option<ActorID>, limit: u32| {
+            let owner = Address::new_id(owner);
+            allowances_of(&rt, AllowancesOfParams { owner, start_after, limit })
+        };
+        let approve_from = |owner: ActorID, spender: ActorID, amount: u64| {
+            rt.set_caller(owner);
+            let spender = Address::new_id(spender);
+            approve(&rt, ApproveParams { spender, amount: TokenAmount::from(amount) }).unwrap();
+        };
+        assert!(page(ALICE, None, 2).unwrap().is_empty());
+
+        approve_from(ALICE, BOB, 20);
+        approve_from(ALICE, OWNER, 10);
+        approve_from(ALICE, COLLECTOR, 30);
+        // Someone else's allowance isn't Alice's.
+        approve_from(BOB, ALICE, 5);
+
+        let first = page(ALICE, None, 2).unwrap();
+        assert_eq!(first, vec![(OWNER, TokenAmount::from(10)), (BOB, TokenAmount::from(20))]);
+        let second = page(ALICE, Some(BOB), 2).unwrap();
+        assert_eq!(second, vec![(COLLECTOR, TokenAmount::from(30))]);
+        assert!(page(ALICE, Some(COLLECTOR), 2).unwrap().is_empty());
+        assert!(page(COLLECTOR, None, 2).unwrap().is_empty());
+
+        let err = page(ALICE, None, 0).unwrap_err();
+        assert_eq!(err.exit_code, ExitCode::USR_ILLEGAL_ARGUMENT);
+    }
+
+    #[test]
+    fn transfer_and_approve_moves_tokens_and_grants_an_allowance_at_once() {
+        let rt = constructed_runtime();
+        rt.set_caller(OWNER);
+        mint(&rt, transfer_params(ALICE, 100)).unwrap();
+
+        // Alice funds Bob's escrow and lets it pull up to 30 back later.
+        rt.set_caller(ALICE);
+        let params = TransferAndApproveParams {
+            recipient: Address::new_id(BOB),
+            amount: TokenAmount::from(40),
+            grant_spender: Address::new_id(BOB),
+            grant_amount: TokenAmount::from(30),
+        };
+        let ret = transfer_and_approve(&rt, params.clone()).unwrap();
+        assert_eq!([ret.from_balance, ret.to_balance], [60, 40].map(TokenAmount::from));
+        let state = State::load_from(&rt).unwrap();
+        let balances = [ALICE, BOB].map(|id| balance(&rt.store, &state, id));
+        assert_eq!(balances, [60, 40].map(TokenAmount::from));
+        assert_eq!(get_allowance(&rt.store, &state, ALICE, BOB).unwrap(), TokenAmount::from(30));
+
+        // An approval that fails undoes the transfer with it.
+        let root = rt.root().unwrap();
+        let own = TransferAndApproveParams { grant_spender: Address::new_id(ALICE), ..params };
+        let err = transfer_and_approve(&rt, own).unwrap_err();
+        assert_eq!(err.exit_code, ExitCode::USR_ILLEGAL_ARGUMENT);
+        assert_eq!(rt.root().unwrap(), root);
+    }
+
+    #[test]
+    fn allowances_can_be_raised_lowered_and_revoked() {
+        let rt = constructed_runtime();
+        rt.set_caller(ALICE);
+        let bob = Address::new_id(BOB);
+        let increase = |amount: u64| IncreaseAllowanceParams {
+            spender: bob,
+            increase: TokenAmount::from(amount),
+        };
+        let decrease = |amount: u64| DecreaseAllowanceParams {
+            spender: bob,
+            decrease: TokenAmount::from(amount),
+        };
+
+        assert_eq!(increase_allowance(&rt, increase(30)).unwrap(), TokenAmount::from(30));
+        assert_eq!(increase_allowance(&rt, increase(20)).unwrap(), TokenAmount::from(50));
+        assert_eq!(decrease_allowance(&rt, decrease(15)).unwrap(), TokenAmount::from(35));
+        // Decreasing past zero stops at zero.
+        assert_eq!(decrease_allowance(&rt, decrease(100)).unwrap(), TokenAmount::from(0));
+
+        increase_allowance(&rt, increase(5)).unwrap();
+        revoke_allowance(&rt, RevokeAllowanceParams { spender: bob }).unwrap();
+        let state = State::load_from(&rt).unwrap();
+        assert_eq!(get_allowance(&rt.store, &state, ALICE, BOB).unwrap(), TokenAmount::from(0));
+
+        let own = IncreaseAllowanceParams { spender: Address::new_id(ALICE), ..increase(1) };
+        let err = increase_allowance(&rt, own).unwrap_err();
+        assert_eq!(err.exit_code, ExitCode::USR_ILLEGAL_ARGUMENT);
+    }
+
+    #[test]
+    fn allowances_that_reach_zero_are_removed() {
+        let rt = constructed_runtime();
+        rt.set_caller(ALICE);
+        let (bob, collector) = (Address::new_id(BOB), Address::new_id(COLLECTOR));
+        approve(&rt, ApproveParams { spender: bob, amount: TokenAmount::from(10) }).unwrap();
+        approve(&rt, ApproveParams { spender: collector, amount: TokenAmount::from(10) }).unwrap();
+
+        let decrease = DecreaseAllowanceParams { spender: bob, decrease: TokenAmount::from(10) };
+        decrease_allowance(&rt, decrease).unwrap();
+        approve(&rt, ApproveParams { spender: collector, amount: TokenAmount::from(0) }).unwrap();
+
+        let state = State::load_from(&rt).unwrap();
+        let root = state.allowances.unwrap();
+        let allowances: Allowances<_> =
+            load_hamt(&rt.store, &root, state.hamt_bit_width, "allowances").unwrap();
+        assert!(allowances.get(&pair_key(ALICE, BOB)).unwrap().is_none());
+        assert!(allowances.get(&pair_key(ALICE, COLLECTOR)).unwrap().is_none());
+        let owner = Address::new_id(ALICE);
+        for spender in [bob, collector] {
+            let params = AllowanceParams { owner, spender };
+            assert_eq!(allowance(&rt, params).unwrap(), TokenAmount::from(0));
+        }
+    }
+
+    #[test]
+    fn operators_do_not_draw_down_allowances() {
+        let rt = constructed_runtime();
+        rt.set_caller(OWNER);
+        mint(&rt, transfer_params(ALICE, 100)).unwrap();
+        rt.set_caller(ALICE);
+        approve(&rt, ApproveParams { spender: Address::new_id(BOB), amount: TokenAmount::from(10) })
+            .unwrap();
+        set_operator(&rt, SetOperatorParams { operator: Address::new_id(BOB), approved: true })
+            .unwrap();
+
+        rt.set_caller(BOB);
+        let params = TransferFromParams {
+            from: Address::new_id(ALICE),
+            to: Address::new_id(BOB),
+            amount: TokenAmount::from(60),
+            operator_data: RawBytes::default(),
+        };
+        transfer_from(&rt, params).unwrap();
+        let state = State::load_from(&rt).unwrap();
+        assert_eq!(get_allowance(&rt.store, &state, ALICE, BOB).unwrap(), TokenAmount::from(10));
+    }
+
+    #[test]
+    fn burn_takes_tokens_out_of_the_supply() {
+        let rt = constructed_runtime();
+        rt.set_caller(OWNER);
+        mint(&rt, transfer_params(ALICE, 100)).unwrap();
+
+        rt.set_caller(ALICE);
+        burn(&rt, BurnParams { amount: TokenAmount::from(40) }).unwrap();
+        let state = State::load_from(&rt).unwrap();
+        assert_eq!(balance(&rt.store, &state, ALICE), TokenAmount::from(60));
+        assert_eq!(state.total_supply, TokenAmount::from(60));
+
+        let root = rt.root().unwrap();
+        let err = burn(&rt, BurnParams { amount: TokenAmount::from(61) }).unwrap_err();
+        assert_eq!(err.exit_code, ExitCode::USR_INSUFFICIENT_FUNDS);
+        let err = burn(&rt, BurnParams { amount: TokenAmount::from(0) }).unwrap_err();
+        assert_eq!(err.exit_code, ExitCode::USR_ILLEGAL_ARGUMENT);
+        assert_eq!(rt.root().unwrap(), root);
+
+        burn(&rt, BurnParams { amount: TokenAmount::from(60) }).unwrap();
+        let state = State::load_from(&rt).unwrap();
+        assert_eq!(state.total_supply, TokenAmount::from(0));
+        assert_eq!(state.holder_count, 0);
+    }
+
+    #[test]
+    fn burn_from_spends_allowances() {
+        use crate::error::ErrorKind;
+
+        let rt = constructed_runtime();
+        rt.set_caller(OWNER);
+        mint(&rt, transfer_params(ALICE, 100)).unwrap();
+        let burned = |amount: u64| BurnFromParams {
+            owner: Address::new_id(ALICE),
+            amount: TokenAmount::from(amount),
+        };
+
+        rt.set_caller(BOB);
+        let err = burn_from(&rt, burned(1)).unwrap_err();
+        assert_eq!(err.kind(), Some(ErrorKind::InsufficientAllowance));
+
+        rt.set_caller(ALICE);
+        approve(&rt, ApproveParams { spender: Address::new_id(BOB), amount: TokenAmount::from(30) })
+            .unwrap();
+        rt.set_caller(BOB);
+        burn_from(&rt, burned(20)).unwrap();
+        let root = rt.root().unwrap();
+        let err = burn_from(&rt, burned(11)).unwrap_err();
+        assert_eq!(err.kind(), Some(ErrorKind::InsufficientAllowance));
+        assert_eq!(rt.root().unwrap(), root);
+
+        let state = State::load_from(&rt).unwrap();
+        assert_eq!(balance(&rt.store, &state, ALICE), TokenAmount::from(80));
+        assert_eq!(state.total_supply, TokenAmount::from(80));
+        assert_eq!(get_allowance(&rt.store, &state, ALICE, BOB).unwrap(), TokenAmount::from(10));
+
+        // Holders may burn their own tokens through burn_from too.
+        rt.set_caller(ALICE);
+        burn_from(&rt, burned(80)).unwrap();
+        assert_eq!(State::load_from(&rt).unwrap().total_supply, TokenAmount::from(0));
+    }
+
+    #[test]
+    fn burn_from_needs_the_balance_as_well_as_the_allowance() {
+        use crate::error::ErrorKind;
+
+        let rt = constructed_runtime();
+        rt.set_caller(OWNER);
+        mint(&rt, transfer_params(ALICE, 20)).unwrap();
+        rt.set_caller(ALICE);
+        approve(&rt, ApproveParams { spender: Address::new_id(BOB), amount: TokenAmount::from(50) })
+            .unwrap();
+        let burned = |amount: u64| BurnFromParams {
+            owner: Address::new_id(ALICE),
+            amount: TokenAmount::from(amount),
+        };
+
+        rt.set_caller(BOB);
+        let root = rt.root().unwrap();
+        let err = burn_from(&rt, burned(30)).unwrap_err();
+        assert_eq!(err.exit_code, ExitCode::USR_INSUFFICIENT_FUNDS);
+        assert_eq!(err.kind(), Some(ErrorKind::InsufficientBalance));
+        assert_eq!(rt.root().unwrap(), root);
+
+        // A partial burn draws down the balance, the allowance and the supply.
+        burn_from(&rt, burned(15)).unwrap();
+        let state = State::load_from(&rt).unwrap();
+        assert_eq!(balance(&rt.store, &state, ALICE), TokenAmount::from(5));
+        assert_eq!(get_allowance(&rt.store, &state, ALICE, BOB).unwrap(), TokenAmount::from(35));
+        assert_eq!(state.total_supply, TokenAmount::from(5));
+    }
+
+    #[test]
+    fn an_account_cannot_approve_itself() {
+        let rt = constructed_runtime();
+        rt.set_caller(ALICE);
+        let spender = Address::new_id(ALICE);
+        let params = ApproveParams { spender, amount: TokenAmount::from(1) };
+
+        assert_eq!(approve(&rt, params).unwrap_err().exit_code, ExitCode::USR_ILLEGAL_ARGUMENT);
+        assert_eq!(State::load_from(&rt).unwrap().allowances, None);
+    }
+
+    #[test]
+    fn an_account_cannot_be_its_own_operator() {
+        let rt = constructed_runtime();
+        rt.set_caller(ALICE);
+        let params = SetOperatorParams { operator: Address::new_id(ALICE), approved: true };
+
+        let err = set_operator(&rt, params).unwrap_err();
+        assert_eq!(err.exit_code, ExitCode::USR_ILLEGAL_ARGUMENT);
+        assert_eq!(State::load_from(&rt).unwrap().operators, None);
+    }
+
+    #[test]
+    fn normalize_recipient_passes_id_addresses_through() {
+        let rt = MockRuntime::new(TOKEN);
+
+        assert_eq!(normalize_recipient(&rt, &Address::new_id(BOB)).unwrap(), BOB);
+        assert!(rt.sent.borrow().is_empty());
+    }
+
+    #[test]
+    fn normalize_recipient_resolves_existing_key_address() {
+        let rt = MockRuntime::new(TOKEN);
+        let addr = Address::new_secp256k1(&[1; 65]).unwrap();
+        rt.add_address(addr, BOB);
+
+        assert_eq!(normalize_recipient(&rt, &addr).unwrap(), BOB);
+        assert!(rt.sent.borrow().is_empty());
+    }
+
+    #[test]
+    fn mint_to_new_key_address_creates_account() {
+        let rt = constructed_runtime();
+        let addr = Address::new_secp256k1(&[2; 65]).unwrap();
+
+        rt.set_caller(OWNER);
+        mint(&rt, TransferParams { recipient: addr, amount: TokenAmount::from(10) }).unwrap();
+
+        let sent = rt.sent.borrow();
+        assert_eq!(sent.len(), 1);
+        assert_eq!((sent[0].to, sent[0].method), (addr, METHOD_SEND));
+        let id = rt.resolve_address(&addr).unwrap();
+        let state = State::load_from(&rt).unwrap();
+        assert_eq!(balance(&rt.store, &state, id), TokenAmount::from(10));
+    }
+
+    #[test]
+    fn can_receive_runs_the_recipient_checks_without_sending() {
+        let rt = constructed_runtime();
+        let root = rt.root().unwrap();
+        let check = |account| {
+            let params = CanReceiveParams { account, amount: TokenAmount::from(10) };
+            can_receive(&rt, params).unwrap()
+        };
+        let eligible = Eligibility { eligible: true, reason: None };
+
+        assert_eq!(check(Address::new_id(BOB)), eligible);
+        // An unknown key address would have its account created.
+        assert_eq!(check(Address::new_secp256k1(&[3; 65]).unwrap()), eligible);
+
+        let missing = Address::new_actor(b"not deployed");
+        let result = check(missing);
+        assert!(!result.eligible);
+        assert_eq!(result.reason, Some(format!("recipient {} does not exist", missing)));
+
+        assert!(rt.sent.borrow().is_empty());
+        assert_eq!(rt.root().unwrap(), root);
+    }
+
+    #[test]
+    fn can_receive_runs_the_same_checks_as_a_transfer() {
+        let rt = constructed_runtime();
+        let check = |account: ActorID, amount: u64| {
+            let params = CanReceiveParams {
+                account: Address::new_id(account),
+                amount: TokenAmount::from(amount),
+            };
+            can_receive(&rt, params).unwrap().eligible
+        };
+        rt.set_caller(OWNER);
+        mint(&rt, transfer_params(ALICE, 10)).unwrap();
+
+        // A frozen account can't receive anything.
+        freeze_account(&rt, FreezeAccountParams { account: Address::new_id(BOB) }).unwrap();
+        assert!(!check(BOB, 5));
+        unfreeze_account(&rt, FreezeAccountParams { account: Address::new_id(BOB) }).unwrap();
+        assert!(check(BOB, 5));
+
+        // Nor can anyone while the token is paused.
+        pause(&rt).unwrap();
+        assert!(!check(BOB, 5));
+        unpause(&rt).unwrap();
+
+        // The amount must meet the minimum.
+        let min = SetMinTransferParams { min_transfer: TokenAmount::from(5) };
+        set_min_transfer(&rt, min).unwrap();
+        assert!(!check(BOB, 4));
+        assert!(check(BOB, 5));
+
+        // At the holder cap, only existing holders can receive.
+        set_max_holders(&rt, SetMaxHoldersParams { max_holders: Some(1) }).unwrap();
+        assert!(!check(BOB, 5));
+        assert!(check(ALICE, 5));
+    }
+
+    #[test]
+    fn normalize_recipient_rejects_unknown_actor_address() {
+        let rt = MockRuntime::new(TOKEN);
+        let addr = Address::new_actor(b"not deployed");
+
+        let err = normalize_recipient(&rt, &addr).unwrap_err();
+
+        assert_eq!(err.exit_code, ExitCode::USR_NOT_FOUND);
+        assert!(rt.sent.borrow().is_empty());
+    }
+
+    #[test]
+    fn migrate_upgrades_v0_state() {
+        use crate::migration::{migrate, StateV0};
+
+        let rt = MockRuntime::new(TOKEN);
+        let mut v1 = new_state(&rt.store);
+        mint_tokens(&context(&rt.store, OWNER), &mut v1, &transfer_params(ALICE, 42)).unwrap();
+        let v0 = StateV0 {
+            name: v1.name,
+            symbol: v1.symbol,
+            max_supply: v1.max_supply,
+            total_supply: v1.total_supply,
+            owner: v1.owner,
+            balances: v1.balances,
+            supply_oracle: None,
+            reentrancy_locked: false,
+            secondary_unit: None,
+            governance: None,
+            executing_proposal: None,
+        };
+        let root = rt.store.put_cbor(&v0, Code::Blake2b256).unwrap();
+        rt.set_root(&root).unwrap();
+
+        let err = State::load_from(&rt).unwrap_err();
+        assert_eq!(err.exit_code, ExitCode::USR_ILLEGAL_STATE);
+
+        rt.set_caller(ALICE);
+        assert_eq!(migrate(&rt).unwrap_err().exit_code, ExitCode::USR_FORBIDDEN);
+
+        rt.set_caller(OWNER);
+        migrate(&rt).unwrap();
+        let state = State::load_from(&rt).unwrap();
+        assert_eq!(state.version, STATE_VERSION);
+        assert_eq!(state.name, "Test Token");
+        assert_eq!(state.total_supply, TokenAmount::from(42));
+        assert_eq!(state.holder_count, 1);
+        assert_eq!(state.decimals, DEFAULT_DECIMALS);
+        assert!(state.require_receiver_hook);
+
+        assert_eq!(migrate(&rt).unwrap_err().exit_code, ExitCode::USR_ILLEGAL_STATE);
+    }
+
+    #[test]
+    fn missing_params_are_told_apart_from_malformed_ones() {
+        let err = decode_params::<TransferParams>(&[]).unwrap_err();
+        assert_eq!(err.exit_code, ExitCode::USR_ILLEGAL_ARGUMENT);
+        assert_eq!(err.msg, "method requires parameters");
+
+        // A CBOR string where a tuple should be.
+        let err = decode_params::<TransferParams>(&[0x61, 0x61]).unwrap_err();
+        assert_eq!(err.exit_code, ExitCode::USR_SERIALIZATION);
+
+        let bytes = RawBytes::serialize(transfer_params(BOB, 5)).unwrap();
+        let params: TransferParams = decode_params(bytes.bytes()).unwrap();
+        assert_eq!(params.amount, TokenAmount::from(5));
+    }
+
+    #[test]
+    fn negative_amounts_are_rejected_once_decoded() {
+        use crate::error::ErrorKind;
+
+        // Encode the params as a crafted message would, bypassing the builders.
+        fn decoded<T: Serialize + DeserializeOwned>(params: T) -> T {
+            RawBytes::serialize(params).unwrap().deserialize().unwrap()
+        }
+        let negative = TokenAmount::from(-5);
+
+        let transfer = TransferParams { amount: negative.clone(), ..transfer_params(BOB, 0) };
+        let transfer = decoded(transfer);
+        let err = check_non_negative(&transfer).unwrap_err();
+        assert_eq!(err.exit_code, ExitCode::USR_ILLEGAL_ARGUMENT);
+        assert_eq!(err.kind(), Some(ErrorKind::InvalidAmount));
+
+        let p = ConstructorParams { max_supply: negative.clone(), ..constructor_params() };
+        assert!(check_non_negative(&decoded(p)).is_err());
+
+        let mints = vec![transfer_params(ALICE, 10), transfer];
+        assert!(check_non_negative(&decoded(MintBatchParams { mints })).is_err());
+
+        let limit = RateLimit { max_amount: negative, window_epochs: 10 };
+        assert!(check_non_negative(&decoded(SetRateLimitParams { limit: Some(limit) })).is_err());
+
+        // Zero and positive amounts pass, and are left to each method to judge.
+        check_non_negative(&decoded(transfer_params(BOB, 0))).unwrap();
+        check_non_negative(&decoded(constructor_params())).unwrap();
+        check_non_negative(&decoded(SetRateLimitParams { limit: None })).unwrap();
+    }
+
+    #[test]
+    fn return_cbor_round_trips() {
+        let result = SimulationResult {
+            would_succeed: false,
+            reason: Some("sender has insufficient balance".to_string()),
+            from_balance_after: TokenAmount::from(10),
+            to_balance_after: TokenAmount::from(0),
+        };
+        let decoded: SimulationResult = return_cbor(&result).unwrap().deserialize().unwrap();
+        assert_eq!(decoded, result);
+
+        let balance = return_cbor(&BigIntSer(&TokenAmount::from(42))).unwrap();
+        assert_eq!(balance.deserialize::<BigIntDe>().unwrap().0, TokenAmount::from(42));
+    }
+
+    #[test]
+    fn method_return_envelope_round_trips() {
+        let rt = constructed_runtime();
+        rt.set_caller(OWNER);
+        mint(&rt, transfer_params(ALICE, 10)).unwrap();
+        rt.set_caller(ALICE);
+        let data = return_cbor(&transfer(&rt, send_params(BOB, 4)).unwrap()).unwrap();
+
+        // A method with a return value.
+        let bytes = RawBytes::serialize(MethodReturn::ok(Some(data.clone()))).unwrap();
+        let ret: MethodReturn = bytes.deserialize().unwrap();
+        assert_eq!(ret.code, ExitCode::OK.value());
+        let transfer: TransferReturn = ret.data.unwrap().deserialize().unwrap();
+        assert_eq!(transfer.to_balance, TokenAmount::from(4));
+
+        // A method without one still returns the envelope.
+        let bytes = RawBytes::serialize(MethodReturn::ok(None)).unwrap();
+        let ret: MethodReturn = bytes.deserialize().unwrap();
+        assert_eq!(ret, MethodReturn { code: 0, data: None });
+    }
+
+    #[test]
+    fn holder_count_tracks_new_and_drained_holders() {
+        let store = MemoryBlockstore::default();
+        let mut state = new_state(&store);
+
+        // Minting to a new holder counts them once, however often it happens.
+        mint_tokens(&context(&store, OWNER), &mut state, &transfer_params(ALICE, 10)).unwrap();
+        mint_tokens(&context(&store, OWNER), &mut state, &transfer_params(ALICE, 10)).unwrap();
+        assert_eq!(state.holder_count, 1);
+
+        // So does transferring to one.
+        transfer_tokens(&context(&store, ALICE), &mut state, &transfer_params(BOB, 5)).unwrap();
+        assert_eq!(state.holder_count, 2);
+
+        // Draining an account stops counting it.
+        transfer_tokens(&context(&store, ALICE), &mut state, &transfer_params(BOB, 15)).unwrap();
+        assert_eq!(state.holder_count, 1);
+
+        let balances = load_balances(&store, &state.balances, state.hamt_bit_width).unwrap();
+        assert_eq!(count_holders(&balances).unwrap(), state.holder_count);
+    }
+
+    #[test]
+    fn transfers_hand_operator_data_to_the_hook_and_return_what_it_returned() {
+        let rt = hooked_runtime();
+        rt.set_caller(OWNER);
+        mint(&rt, transfer_params(ALICE, 100)).unwrap();
+        rt.set_code_cid(BOB, Cid::new_v1(0x55, Code::Identity.digest(b"hook-actor")));
+        rt.on_send(|_, _| Receipt {
+            exit_code: ExitCode::OK,
+            return_data: RawBytes::new(b"thanks".to_vec()),
+            gas_used: 0,
+        });
+        let hook = |index: usize| {
+            let sent = rt.sent.borrow();
+            let hook: UniversalReceiverParams = sent[index].params.deserialize().unwrap();
+            hook.payload.deserialize::<TokenReceivedParams>().unwrap()
+        };
+
+        rt.set_caller(ALICE);
+        let params = Frc46TransferParams {
+            operator_data: RawBytes::new(b"order 7".to_vec()),
+            ..send_params(BOB, 30)
+        };
+        let ret = transfer(&rt, params).unwrap();
+        assert_eq!([ret.from_balance, ret.to_balance], [70, 30].map(TokenAmount::from));
+        assert_eq!(ret.recipient_data.bytes(), b"thanks");
+        assert_eq!(hook(0).operator_data.bytes(), b"order 7");
+
+        let spender = Address::new_id(COLLECTOR);
+        approve(&rt, ApproveParams { spender, amount: TokenAmount::from(50) }).unwrap();
+        rt.set_caller(COLLECTOR);
+        let params = TransferFromParams {
+            from: Address::new_id(ALICE),
+            to: Address::new_id(BOB),
+            amount: TokenAmount::from(20),
+            operator_data: RawBytes::new(b"order 8".to_vec()),
+        };
+        let ret = transfer_from(&rt, params).unwrap();
+        assert_eq!([ret.from_balance, ret.to_balance], [50, 50].map(TokenAmount::from));
+        assert_eq!(ret.allowance, TokenAmount::from(30));
+        assert_eq!(ret.recipient_data.bytes(), b"thanks");
+        assert_eq!((hook(1).operator, hook(1).operator_data.bytes()), (COLLECTOR, &b"order 8"[..]));
+    }
+
+    #[test]
+    fn transfer_with_memo_hands_memo_to_hook_without_storing_it() {
+        let hook_code = Cid::new_v1(0x55, Code::Identity.digest(b"hook-actor"));
+        let plain = hooked_runtime();
+        let memo = hooked_runtime();
+        for rt in [&plain, &memo] {
+            rt.set_caller(OWNER);
+            mint(rt, transfer_params(ALICE, 100)).unwrap();
+            rt.set_code_cid(BOB, hook_code);
+            rt.set_caller(ALICE);
+        }
+
+        transfer(&plain, send_params(BOB, 30)).unwrap();
+        let params = TransferMemoParams {
+            recipient: Address::new_id(BOB),
+            amount: TokenAmount::from(30),
+            memo: b"invoice 1234".to_vec(),
+        };
+        transfer_with_memo(&memo, params).unwrap();
+
+        let sent = memo.sent.borrow();
+        let hook: UniversalReceiverParams = sent[0].params.deserialize().unwrap();
+        assert_eq!(hook.type_, FRC46_TOKEN_TYPE);
+        let hook: TokenReceivedParams = hook.payload.deserialize().unwrap();
+        assert_eq!((hook.from, hook.to, hook.operator), (ALICE, BOB, ALICE));
+        assert_eq!(hook.operator_data.bytes(), b"invoice 1234");
+        assert!(hook.token_data.bytes().is_empty());
+        // The memo leaves no trace in state.
+        assert_eq!(*memo.root.borrow(), *plain.root.borrow());
+    }
+
+    #[test]
+    fn transfer_with_memo_rejects_long_memo() {
+        let rt = constructed_runtime();
+        rt.set_caller(OWNER);
+        mint(&rt, transfer_params(ALICE, 100)).unwrap();
+
+        rt.set_caller(ALICE);
+        let params = TransferMemoParams {
+            recipient: Address::new_id(BOB),
+            amount: TokenAmount::from(30),
+            memo: vec![0; MAX_MEMO_LEN + 1],
+        };
+        let err = transfer_with_memo(&rt, params).unwrap_err();
+
+        assert_eq!(err.exit_code, ExitCode::USR_ILLEGAL_ARGUMENT);
+    }
+
+    #[test]
+    fn transfer_with_receipt_ties_the_receipt_to_the_new_state_root() {
+        let rt = constructed_runtime();
+        rt.set_caller(OWNER);
+        mint(&rt, transfer_params(ALICE, 10)).unwrap();
+        rt.set_caller(ALICE);
+        rt.epoch.set(7);
+
+        let ret = transfer_with_receipt(&rt, send_params(BOB, 4)).unwrap();
+        let ret: HashedReceipt = ret.deserialize().unwrap();
+        let receipt = &ret.receipt;
+        assert_eq!((receipt.from, receipt.to, receipt.epoch), (ALICE, BOB, 7));
+        assert_eq!(receipt.amount, TokenAmount::from(4));
+        assert_eq!(receipt.state_root, rt.root().unwrap());
+        let hash = Code::Blake2b256.digest(&to_vec(receipt).unwrap());
+        assert_eq!(ret.hash, hash.digest().to_vec());
+    }
+
+    #[test]
+    fn transfer_until_runs_up_to_and_including_its_deadline() {
+        let rt = constructed_runtime();
+        rt.set_caller(OWNER);
+        mint(&rt, transfer_params(ALICE, 100)).unwrap();
+
+        rt.set_caller(ALICE);
+        let params = |deadline| TransferUntilParams {
+            recipient: Address::new_id(BOB),
+            amount: TokenAmount::from(10),
+            deadline,
+        };
+        rt.epoch.set(50);
+        transfer_until(&rt, params(60)).unwrap();
+        transfer_until(&rt, params(50)).unwrap();
+
+        let state = State::load_from(&rt).unwrap();
+        assert_eq!(balance(&rt.store, &state, BOB), TokenAmount::from(20));
+    }
+
+    #[test]
+    fn transfer_until_rejects_an_expired_transfer() {
+        let rt = constructed_runtime();
+        rt.set_caller(OWNER);
+        mint(&rt, transfer_params(ALICE, 100)).unwrap();
+        let root = rt.root().unwrap();
+
+        rt.set_caller(ALICE);
+        rt.epoch.set(50);
+        let params = TransferUntilParams {
+            recipient: Address::new_id(BOB),
+            amount: TokenAmount::from(10),
+            deadline: 49,
+        };
+        let err = transfer_until(&rt, params).unwrap_err();
+
+        assert_eq!(err.exit_code, ExitCode::USR_ILLEGAL_ARGUMENT);
+        assert_eq!(rt.root().unwrap(), root);
+    }
+
+    #[test]
+    fn recent_transfers_are_read_back_in_order() {
+        let rt = constructed_runtime();
+        rt.set_caller(OWNER);
+        mint(&rt, transfer_params(ALICE, 1000)).unwrap();
+        let recent = |limit| recent_transfers(&rt, RecentTransfersParams { limit }).unwrap();
+        assert_eq!(recent(10), vec![]);
+
+        rt.set_caller(ALICE);
+        for (epoch, amount) in [(1, 10), (2, 20), (3, 30)] {
+            rt.epoch.set(epoch);
+            transfer(&rt, send_params(BOB, amount)).unwrap();
+        }
+
+        let record = |amount: u64, epoch| TransferRecord {
+            from: ALICE,
+            to: BOB,
+            amount: TokenAmount::from(amount),
+            epoch,
+        };
+        assert_eq!(recent(2), vec![record(20, 2), record(30, 3)]);
+        assert_eq!(recent(10), vec![record(10, 1), record(20, 2), record(30, 3)]);
+
+        for _ in 0..MAX_RECENT_TRANSFERS {
+            transfer(&rt, send_params(BOB, 1)).unwrap();
+        }
+        let all = recent(u32::MAX);
+        assert_eq!(all.len(), MAX_RECENT_TRANSFERS);
+        assert!(all.iter().all(|r| r.amount == TokenAmount::from(1)));
+    }
+
+    #[test]
+    fn keyed_mint_is_applied_once() {
+        use crate::error::ErrorKind;
+
+        let rt = constructed_runtime();
+        rt.set_caller(OWNER);
+        let keyed = |key| IdempotentTransferParams {
+            recipient: Address::new_id(ALICE),
+            amount: TokenAmount::from(100),
+            idempotency_key: Some(key),
+        };
+
+        mint_idempotent(&rt, keyed([1; 32])).unwrap();
+        let err = mint_idempotent(&rt, keyed([1; 32])).unwrap_err();
+        assert_eq!(err.kind(), Some(ErrorKind::DuplicateMessage));
+        assert_eq!(err.exit_code, ExitCode::USR_ILLEGAL_ARGUMENT);
+        let state = State::load_from(&rt).unwrap();
+        assert_eq!(balance(&rt.store, &state, ALICE), TokenAmount::from(100));
+        assert_eq!(state.total_supply, TokenAmount::from(100));
+
+        // A different key, or none at all, mints again.
+        mint_idempotent(&rt, keyed([2; 32])).unwrap();
+        let unkeyed = IdempotentTransferParams { idempotency_key: None, ..keyed([0; 32]) };
+        mint_idempotent(&rt, unkeyed.clone()).unwrap();
+        mint_idempotent(&rt, unkeyed).unwrap();
+
+        // Keys are shared with transfers, and still remembered a window later.
+        rt.epoch.set(IDEMPOTENCY_WINDOW);
+        rt.set_caller(ALICE);
+        let transfer =
+            IdempotentTransferParams { recipient: Address::new_id(BOB), ..keyed([1; 32]) };
+        let err = transfer_idempotent(&rt, transfer.clone()).unwrap_err();
+        assert_eq!(err.kind(), Some(ErrorKind::DuplicateMessage));
+
+        // Two windows on, the key has been forgotten.
+        rt.epoch.set(2 * IDEMPOTENCY_WINDOW);
+        transfer_idempotent(&rt, transfer).unwrap();
+        let state = State::load_from(&rt).unwrap();
+        assert_eq!(balance(&rt.store, &state, BOB), TokenAmount::from(100));
+        assert_eq!(state.used_keys.unwrap().previous, None);
+    }
+
+    #[test]
+    fn simulate_transfer_reports_outcome_without_persisting() {
+        let rt = constructed_runtime();
+        rt.set_caller(OWNER);
+        mint(&rt, transfer_params(ALICE, 10)).unwrap();
+        let root = *rt.root.borrow();
+
+        rt.set_caller(ALICE);
+        let ok = simulate_transfer(&rt, transfer_params(BOB, 4)).unwrap();
+        assert!(ok.would_succeed);
+        assert_eq!(ok.reason, None);
+        assert_eq!(ok.from_balance_after, TokenAmount::from(6));
+        assert_eq!(ok.to_balance_after, TokenAmount::from(4));
+
+        let short = simulate_transfer(&rt, transfer_params(BOB, 11)).unwrap();
+        assert!(!short.would_succeed);
+        assert!(short.reason.is_some());
+        assert_eq!(short.from_balance_after, TokenAmount::from(10));
+        assert_eq!(short.to_balance_after, TokenAmount::from(0));
+
+        assert_eq!(*rt.root.borrow(), root);
+    }
+
+    #[test]
+    fn transfers_work_with_non_default_hamt_bit_width() {
+        let store = MemoryBlockstore::default();
+        let params = ConstructorParams { hamt_bitwidth: 3, ..constructor_params() };
+        let mut state = State::new(&store, params).unwrap();
+        assert_eq!(state.hamt_bit_width, 3);
+
+        // Enough holders to make the narrow HAMT split into several levels.
+        for id in 200..264 {
+            mint_tokens(&context(&store, OWNER), &mut state, &transfer_params(id, 10)).unwrap();
+        }
+        for id in 200..263 {
+            transfer_tokens(&context(&store, id), &mut state, &transfer_params(id + 1, 5))
+                .unwrap();
+        }
+
+        assert_eq!(balance(&store, &state, 200), TokenAmount::from(5));
+        assert_eq!(balance(&store, &state, 231), TokenAmount::from(10));
+        assert_eq!(balance(&store, &state, 263), TokenAmount::from(15));
+    }
+
+    #[test]
+    fn constructor_rejects_out_of_range_hamt_bit_width() {
+        let store = MemoryBlockstore::default();
+        for bit_width in [2, 9] {
+            let params = ConstructorParams { hamt_bitwidth: bit_width, ..constructor_params() };
+            let err = State::new(&store, params).unwrap_err();
+            assert_eq!(err.exit_code, ExitCode::USR_ILLEGAL_ARGUMENT);
+        }
+    }
+
+    #[test]
+    fn snapshot_preserves_balances_after_transfers() {
+        let rt = constructed_runtime();
+        rt.set_caller(OWNER);
+        mint(&rt, transfer_params(ALICE, 100)).unwrap();
+
+        rt.epoch.set(7);
+        snapshot(&rt).unwrap();
+        rt.set_caller(ALICE);
+        transfer(&rt, send_params(BOB, 40)).unwrap();
+
+        let state = State::load_from(&rt).unwrap();
+        assert_eq!(state.snapshots.len(), 1);
+        assert_eq!(state.snapshots[0].0, 7);
+        assert_eq!(balance(&rt.store, &state, ALICE), TokenAmount::from(60));
+        assert_eq!(balance(&rt.store, &state, BOB), TokenAmount::from(40));
+
+        let at_snapshot = |id| {
+            let account = Address::new_id(id);
+            balance_at_snapshot(&rt, BalanceAtSnapshotParams { account, snapshot_index: 0 })
+                .unwrap()
+        };
+        assert_eq!(at_snapshot(ALICE), TokenAmount::from(100));
+        assert_eq!(at_snapshot(BOB), TokenAmount::from(0));
+
+        let params = BalanceAtSnapshotParams { account: Address::new_id(ALICE), snapshot_index: 1 };
+        let err = balance_at_snapshot(&rt, params).unwrap_err();
+        assert_eq!(err.exit_code, ExitCode::USR_NOT_FOUND);
+    }
+
+    #[test]
+    fn balance_of_id_matches_balance_of() {
+        let rt = constructed_runtime();
+        rt.set_caller(OWNER);
+        mint(&rt, transfer_params(ALICE, 100)).unwrap();
+
+        for id in [ALICE, BOB] {
+            let by_address = balance_of(&rt, Address::new_id(id));
+            let by_id = balance_of_id(&rt, BalanceOfIdParams { id });
+            assert_eq!(by_id.unwrap(), by_address.unwrap());
+        }
+        let alice = balance_of_id(&rt, BalanceOfIdParams { id: ALICE }).unwrap();
+        assert_eq!(alice, TokenAmount::from(100));
+    }
+
+    #[test]
+    fn balance_of_unknown_holders_is_zero() {
+        let rt = constructed_runtime();
+        rt.set_caller(OWNER);
+        mint(&rt, transfer_params(ALICE, 100)).unwrap();
+
+        let unresolvable = Address::new_actor(b"never-seen");
+        let balance = balance_of(&rt, unresolvable).unwrap();
+        assert_eq!(balance, TokenAmount::from(0));
+        let balance = balance_of(&rt, Address::new_id(BOB)).unwrap();
+        assert_eq!(balance, TokenAmount::from(0));
+    }
+
+    #[test]
+    fn snapshot_requires_owner() {
+        let rt = constructed_runtime();
+        rt.set_caller(ALICE);
+
+        assert_eq!(snapshot(&rt).unwrap_err().exit_code, ExitCode::USR_FORBIDDEN);
+    }
+
+    #[test]
+    fn value_moving_methods_reject_zero_amounts() {
+        let rt = constructed_runtime();
+        rt.set_caller(OWNER);
+        let err = mint(&rt, transfer_params(ALICE, 0)).unwrap_err();
+        assert_eq!(err.exit_code, ExitCode::USR_ILLEGAL_ARGUMENT);
+        mint(&rt, transfer_params(ALICE, 10)).unwrap();
+
+        rt.set_caller(ALICE);
+        let root = *rt.root.borrow();
+        let err = transfer(&rt, send_params(BOB, 0)).unwrap_err();
+        assert_eq!(err.exit_code, ExitCode::USR_ILLEGAL_ARGUMENT);
+        assert_eq!(*rt.root.borrow(), root);
+
+        let store = MemoryBlockstore::default();
+        let mut state = new_state(&store);
+        let params = SplitBalanceParams {
+            source: Address::new_id(OWNER),
+            targets: vec![SplitTarget {
+                recipient: Address::new_id(ALICE),
+                amount: TokenAmount::from(0),
+            }],
+        };
+        let err = split_tokens(&context(&store, OWNER), &mut state, &params).unwrap_err();
+        assert_eq!(err.exit_code, ExitCode::USR_ILLEGAL_ARGUMENT);
+    }
+
+    #[test]
+    fn state_summary_tracks_construction_and_mints() {
+        let rt = constructed_runtime();
+        let root = rt.root().unwrap();
+        let summary = state_summary(&rt).unwrap();
+        assert_eq!(rt.root().unwrap(), root);
+
+        let p = constructor_params();
+        assert_eq!(
+            summary,
+            StateSummary {
+                name: p.name,
+                symbol: p.symbol,
+                owner: p.owner,
+                max_supply: p.max_supply,
+                total_supply: TokenAmount::from(0),
+                holder_count: 0,
+                metadata_uri: None,
+                require_receiver_hook: false,
+                fee_bps: 0,
+                min_transfer: TokenAmount::from(0),
+            }
+        );
+
+        rt.set_caller(OWNER);
+        mint(&rt, transfer_params(ALICE, 100)).unwrap();
+        let after = state_summary(&rt).unwrap();
+        assert_eq!(after.total_supply, TokenAmount::from(100));
+        assert_eq!(after.holder_count, 1);
+        // Nothing else moved.
+        let unchanged =
+            StateSummary { total_supply: TokenAmount::from(0), holder_count: 0, ..after };
+        assert_eq!(unchanged, summary);
+    }
+
+    #[test]
+    fn actor_info_reports_the_token_itself() {
+        let rt = constructed_runtime();
+        let code = Cid::new_v1(0x55, Code::Identity.digest(b"token-actor"));
+        rt.set_code_cid(TOKEN, code);
+        let root = rt.root().unwrap();
+
+        let info = actor_info(&rt).unwrap();
+        assert_eq!(info, ActorInfo { id: TOKEN, code_cid: code });
+        assert_eq!(rt.root().unwrap(), root);
+    }
+
+    #[test]
+    fn getters_return_the_token_metadata_and_supply() {
+        let rt = constructed_runtime();
+        rt.set_caller(OWNER);
+        mint(&rt, transfer_params(ALICE, 250)).unwrap();
+        let root = rt.root().unwrap();
+
+        assert_eq!(name(&rt).unwrap(), "Test Token");
+        assert_eq!(symbol(&rt).unwrap(), "TEST");
+        assert_eq!(decimals(&rt).unwrap(), 6);
+        assert_eq!(total_supply(&rt).unwrap(), TokenAmount::from(250));
+        assert_eq!(max_supply(&rt).unwrap(), TokenAmount::from(1_000_000));
+        assert_eq!(rt.root().unwrap(), root);
+    }
+
+    #[test]
+    fn metadata_uri_can_be_set_read_and_cleared() {
+        let rt = constructed_runtime();
+        assert_eq!(metadata_uri(&rt).unwrap(), None);
+
+        rt.set_caller(OWNER);
+        let uri = "ipfs://bafy/token.json".to_string();
+        set_metadata_uri(&rt, SetMetadataUriParams { uri: Some(uri.clone()) }).unwrap();
+        assert_eq!(metadata_uri(&rt).unwrap(), Some(uri));
+
+        set_metadata_uri(&rt, SetMetadataUriParams { uri: None }).unwrap();
+        assert_eq!(metadata_uri(&rt).unwrap(), None);
+    }
+
+    #[test]
+    fn set_metadata_uri_validates_input_and_caller() {
+        let rt = constructed_runtime();
+        let set = |uri: &str| {
+            set_metadata_uri(&rt, SetMetadataUriParams { uri: Some(uri.to_string()) })
+        };
+
+        rt.set_caller(ALICE);
+        assert_eq!(set("ipfs://x").unwrap_err().exit_code, ExitCode::USR_FORBIDDEN);
+
+        rt.set_caller(OWNER);
+        assert_eq!(set("").unwrap_err().exit_code, ExitCode::USR_ILLEGAL_ARGUMENT);
+        let long = "x".repeat(MAX_METADATA_URI_LEN + 1);
+        assert_eq!(set(&long).unwrap_err().exit_code, ExitCode::USR_ILLEGAL_ARGUMENT);
+    }
+
+    #[test]
+    fn set_max_supply_raises_and_lowers_the_cap_but_not_below_supply() {
+        let rt = constructed_runtime();
+        rt.set_caller(OWNER);
+        mint(&rt, transfer_params(ALICE, 100)).unwrap();
+        let set = |new_max: u64| {
+            set_max_supply(&rt, SetMaxSupplyParams { new_max: TokenAmount::from(new_max) })
+        };
+
+        set(2_000_000).unwrap();
+        assert_eq!(State::load_from(&rt).unwrap().max_supply, TokenAmount::from(2_000_000));
+        set(100).unwrap();
+        assert_eq!(State::load_from(&rt).unwrap().max_supply, TokenAmount::from(100));
+        assert_eq!(set(99).unwrap_err().exit_code, ExitCode::USR_ILLEGAL_ARGUMENT);
+        assert_eq!(State::load_from(&rt).unwrap().max_supply, TokenAmount::from(100));
+
+        rt.set_caller(ALICE);
+        assert_eq!(set(1000).unwrap_err().exit_code, ExitCode::USR_FORBIDDEN);
+    }
+
+    #[test]
+    fn balance_of_in_unit_converts_at_the_set_rate() {
+        let rt = constructed_runtime();
+        rt.set_caller(OWNER);
+        mint(&rt, transfer_params(ALICE, 101)).unwrap();
+        let rate = |numerator: u64, denominator: u64| SecondaryUnit {
+            unit: "USD".to_string(),
+            rate_numerator: TokenAmount::from(numerator),
+            rate_denominator: TokenAmount::from(denominator),
+        };
+        let in_unit = |account: ActorID, unit: &str| {
+            let params =
+                BalanceOfInUnitParams { account: Address::new_id(account), unit: unit.into() };
+            balance_of_in_unit(&rt, params)
+        };
+
+        let err = set_unit_rate(&rt, rate(3, 0)).unwrap_err();
+        assert_eq!(err.exit_code, ExitCode::USR_ILLEGAL_ARGUMENT);
+        set_unit_rate(&rt, rate(3, 2)).unwrap();
+
+        // 101 * 3 / 2 rounds down to 151.
+        assert_eq!(in_unit(ALICE, "USD").unwrap(), TokenAmount::from(151));
+        assert_eq!(in_unit(BOB, "USD").unwrap(), TokenAmount::from(0));
+        let err = in_unit(ALICE, "EUR").unwrap_err();
+        assert_eq!(err.exit_code, ExitCode::USR_ILLEGAL_ARGUMENT);
+
+        rt.set_caller(ALICE);
+        let err = set_unit_rate(&rt, rate(1, 1)).unwrap_err();
+        assert_eq!(err.exit_code, ExitCode::USR_FORBIDDEN);
+    }
+
+    #[test]
+    fn read_fields_returns_known_fields_and_rejects_unknown_ones() {
+        let rt = constructed_runtime();
+        rt.set_caller(OWNER);
+        mint(&rt, transfer_params(ALICE, 100)).unwrap();
+        let read = |names: &[&str]| {
+            let names = names.iter().map(|name| name.to_string()).collect();
+            read_fields(&rt, ReadFieldsParams { names })
+        };
+
+        let fields = read(&["reentrancy_locked", "secondary_unit", "total_supply"]).unwrap();
+        assert_eq!(fields.len(), 3);
+        assert!(!fields["reentrancy_locked"].deserialize::<bool>().unwrap());
+        let unit: Option<SecondaryUnit> = fields["secondary_unit"].deserialize().unwrap();
+        assert!(unit.is_none());
+        let total: BigIntDe = fields["total_supply"].deserialize().unwrap();
+        assert_eq!(total.0, TokenAmount::from(100));
+
+        let err = read(&["total_supply", "no_such_field", "reentrancy_locked"]).unwrap_err();
+        assert_eq!(err.exit_code, ExitCode::USR_ILLEGAL_ARGUMENT);
+        assert!(err.msg.contains("no_such_field"), "{}", err.msg);
+    }
+
+    #[test]
+    fn force_transfer_moves_tokens_for_owner_only() {
+        let rt = constructed_runtime();
+        rt.set_caller(OWNER);
+        mint(&rt, transfer_params(ALICE, 100)).unwrap();
+
+        let params = |amount: u64| ForceTransferParams {
+            from: Address::new_id(ALICE),
+            to: Address::new_id(BOB),
+            amount: TokenAmount::from(amount),
+        };
+
+        rt.set_caller(BOB);
+        let err = force_transfer(&rt, params(60)).unwrap_err();
+        assert_eq!(err.exit_code, ExitCode::USR_FORBIDDEN);
+
+        rt.set_caller(OWNER);
+        force_transfer(&rt, params(60)).unwrap();
+        let state = State::load_from(&rt).unwrap();
+        assert_eq!(balance(&rt.store, &state, ALICE), TokenAmount::from(40));
+        assert_eq!(balance(&rt.store, &state, BOB), TokenAmount::from(60));
+
+        let err = force_transfer(&rt, params(41)).unwrap_err();
+        assert_eq!(err.exit_code, ExitCode::USR_INSUFFICIENT_FUNDS);
+    }
+
+    #[test]
+    fn owner_tools_move_locked_and_frozen_tokens() {
+        let rt = constructed_runtime();
+        rt.set_caller(OWNER);
+        mint(&rt, transfer_params(ALICE, 100)).unwrap();
+        let account = Address::new_id(ALICE);
+        let amount = TokenAmount::from(100);
+        lock(&rt, LockParams { account, amount, unlock_epoch: 10 }).unwrap();
+        freeze_account(&rt, FreezeAccountParams { account }).unwrap();
+
+        let forced = ForceTransferParams {
+            from: account,
+            to: Address::new_id(BOB),
+            amount: TokenAmount::from(60),
+        };
+        force_transfer(&rt, forced).unwrap();
+        let recipient = Address::new_id(COLLECTOR);
+        let target = SplitTarget { recipient, amount: TokenAmount::from(40) };
+        split_balance(&rt, SplitBalanceParams { source: account, targets: vec![target] }).unwrap();
+
+        let state = State::load_from(&rt).unwrap();
+        let balances = [ALICE, BOB, COLLECTOR].map(|id| balance(&rt.store, &state, id));
+        assert_eq!(balances, [0, 60, 40].map(TokenAmount::from));
+        // The lock is left as it was.
+        let root = state.locks.unwrap();
+        let locks: Locks<_> = load_hamt(&rt.store, &root, state.hamt_bit_width, "locks").unwrap();
+        assert_eq!(locks.get(&ALICE).unwrap().unwrap()[0].amount, TokenAmount::from(100));
+    }
+
+    #[test]
+    fn rescue_moves_tokens_sent_to_the_token_itself() {
+        let rt = constructed_runtime();
+        rt.set_caller(OWNER);
+        mint(&rt, transfer_params(ALICE, 100)).unwrap();
+
+        let params = || RescueParams { to: Address::new_id(ALICE) };
+        let err = rescue(&rt, params()).unwrap_err();
+        assert_eq!(err.exit_code, ExitCode::USR_ILLEGAL_ARGUMENT);
+
+        rt.set_caller(ALICE);
+        transfer(&rt, send_params(TOKEN, 30)).unwrap();
+        let err = rescue(&rt, params()).unwrap_err();
+        assert_eq!(err.exit_code, ExitCode::USR_FORBIDDEN);
+
+        rt.set_caller(OWNER);
+        rescue(&rt, params()).unwrap();
+        let state = State::load_from(&rt).unwrap();
+        assert_eq!(balance(&rt.store, &state, TOKEN), TokenAmount::from(0));
+        assert_eq!(balance(&rt.store, &state, ALICE), TokenAmount::from(100));
+        assert_eq!(state.holder_count, 1);
+    }
+
+    #[test]
+    fn transfer_split_loses_nothing_to_rounding() {
+        let rt = constructed_runtime();
+        rt.set_caller(OWNER);
+        mint(&rt, transfer_params(ALICE, 100)).unwrap();
+
+        rt.set_caller(ALICE);
+        let recipients = vec![
+            (Address::new_id(BOB), 1),
+            (Address::new_id(COLLECTOR), 1),
+            (Address::new_id(OWNER), 1),
+        ];
+        transfer_split(&rt, TransferSplitParams { recipients, amount: TokenAmount::from(100) })
+            .unwrap();
+
+        let state = State::load_from(&rt).unwrap();
+        assert_eq!(balance(&rt.store, &state, ALICE), TokenAmount::from(0));
+        assert_eq!(balance(&rt.store, &state, BOB), TokenAmount::from(33));
+        assert_eq!(balance(&rt.store, &state, COLLECTOR), TokenAmount::from(33));
+        assert_eq!(balance(&rt.store, &state, OWNER), TokenAmount::from(34));
+        assert_eq!(state.holder_count, 3);
+        assert!(verify_invariants(&rt).unwrap().consistent);
+
+        let shares = split_by_weight(&TokenAmount::from(10), &[3, 3, 1]);
+        assert_eq!(shares, vec![TokenAmount::from(4), TokenAmount::from(4), TokenAmount::from(2)]);
+    }
+
+    #[test]
+    fn transfer_split_needs_the_full_amount_and_positive_weights() {
+        let rt = constructed_runtime();
+        rt.set_caller(OWNER);
+        mint(&rt, transfer_params(ALICE, 50)).unwrap();
+        let root = rt.root().unwrap();
+
+        rt.set_caller(ALICE);
+        let split = |weight| TransferSplitParams {
+            recipients: vec![(Address::new_id(BOB), 1), (Address::new_id(OWNER), weight)],
+            amount: TokenAmount::from(51),
+        };
+        let err = transfer_split(&rt, split(1)).unwrap_err();
+        assert_eq!(err.exit_code, ExitCode::USR_INSUFFICIENT_FUNDS);
+        let err = transfer_split(&rt, split(0)).unwrap_err();
+        assert_eq!(err.exit_code, ExitCode::USR_ILLEGAL_ARGUMENT);
+        assert_eq!(rt.root().unwrap(), root);
+    }
+
+    #[test]
+    fn transfer_batch_pays_every_recipient_and_the_fees_at_once() {
+        let rt = constructed_runtime();
+        rt.set_caller(OWNER);
+        mint(&rt, transfer_params(ALICE, 1000)).unwrap();
+        let fee_collector = Some(Address::new_id(COLLECTOR));
+        set_transfer_fee(&rt, SetTransferFeeParams { fee_bps: 100, fee_collector }).unwrap();
+
+        rt.set_caller(ALICE);
+        let transfers = vec![transfer_params(BOB, 100), transfer_params(OWNER, 200)];
+        transfer_batch(&rt, TransferBatchParams { transfers }).unwrap();
+
+        let state = State::load_from(&rt).unwrap();
+        assert_eq!(balance(&rt.store, &state, ALICE), TokenAmount::from(700));
+        assert_eq!(balance(&rt.store, &state, BOB), TokenAmount::from(99));
+        assert_eq!(balance(&rt.store, &state, OWNER), TokenAmount::from(198));
+        assert_eq!(balance(&rt.store, &state, COLLECTOR), TokenAmount::from(3));
+        assert!(verify_invariants(&rt).unwrap().consistent);
+    }
+
+    #[test]
+    fn transfer_batch_makes_all_transfers_or_none() {
+        let rt = constructed_runtime();
+        rt.set_caller(OWNER);
+        mint(&rt, transfer_params(ALICE, 50)).unwrap();
+        let root = rt.root().unwrap();
+
+        rt.set_caller(ALICE);
+        let batch = |transfers| transfer_batch(&rt, TransferBatchParams { transfers }).unwrap_err();
+        let err = batch(vec![transfer_params(BOB, 30), transfer_params(OWNER, 21)]);
+        assert_eq!(err.exit_code, ExitCode::USR_INSUFFICIENT_FUNDS);
+        let err = batch(vec![transfer_params(BOB, 1), transfer_params(ALICE, 1)]);
+        assert_eq!(err.exit_code, ExitCode::USR_ILLEGAL_ARGUMENT);
+        let err = batch(vec![transfer_params(BOB, 1), transfer_params(OWNER, 0)]);
+        assert_eq!(err.exit_code, ExitCode::USR_ILLEGAL_ARGUMENT);
+        assert_eq!(batch(vec![]).exit_code, ExitCode::USR_ILLEGAL_ARGUMENT);
+        assert_eq!(rt.root().unwrap(), root);
+    }
+
+    #[test]
+    fn multicall_runs_every_call_or_none() {
+        use crate::multicall::{multicall, Call, MulticallParams};
+
+        let rt = constructed_runtime();
+        rt.set_caller(OWNER);
+        fn call(method: MethodNum, params: impl Serialize) -> Call {
+            Call { method, params: RawBytes::serialize(&params).unwrap() }
+        }
+        let calls = vec![
+            call(method::MINT, transfer_params(OWNER, 100)),
+            call(method::TRANSFER, send_params(BOB, 40)),
+        ];
+        let results = multicall(&rt, MulticallParams { calls }).unwrap();
+        assert_eq!(results[0].deserialize::<BigIntDe>().unwrap().0, TokenAmount::from(100));
+        let transfer: TransferReturn = results[1].deserialize().unwrap();
+        assert_eq!([transfer.from_balance, transfer.to_balance], [60, 40].map(TokenAmount::from));
+        let state = State::load_from(&rt).unwrap();
+        assert_eq!(balance(&rt.store, &state, OWNER), TokenAmount::from(60));
+        assert_eq!(balance(&rt.store, &state, BOB), TokenAmount::from(40));
+
+        let root = rt.root().unwrap();
+        let calls = vec![
+            call(method::MINT, transfer_params(OWNER, 10)),
+            call(method::TRANSFER, send_params(BOB, 71)),
+        ];
+        let err = multicall(&rt, MulticallParams { calls }).unwrap_err();
+        assert_eq!(err.exit_code, ExitCode::USR_INSUFFICIENT_FUNDS);
+        assert_eq!(rt.root().unwrap(), root);
+
+        let calls = vec![
+            call(method::MINT, transfer_params(OWNER, 10)),
+            call(method::MULTICALL, transfer_params(BOB, 1)),
+        ];
+        let err = multicall(&rt, MulticallParams { calls }).unwrap_err();
+        assert_eq!(err.exit_code, ExitCode::USR_ILLEGAL_ARGUMENT);
+        assert_eq!(rt.root().unwrap(), root);
+    }
+
+    #[test]
+    fn mint_batch_credits_every_recipient() {
+        let rt = constructed_runtime();
+        rt.set_caller(OWNER);
+
+        let mints =
+            vec![transfer_params(ALICE, 10), transfer_params(BOB, 20), transfer_params(ALICE, 5)];
+        mint_batch(&rt, MintBatchParams { mints }).unwrap();
+
+        let state = State::load_from(&rt).unwrap();
+        assert_eq!(balance(&rt.store, &state, ALICE), TokenAmount::from(15));
+        assert_eq!(balance(&rt.store, &state, BOB), TokenAmount::from(20));
+        assert_eq!(state.total_supply, TokenAmount::from(35));
+        assert_eq!(state.holder_count, 2);
+    }
+
+    #[test]
+    fn mint_batch_over_max_supply_mints_nothing() {
+        let rt = constructed_runtime();
+        rt.set_caller(OWNER);
+        let root = *rt.root.borrow();
+
+        let mints = vec![transfer_params(ALICE, 10), transfer_params(BOB, 1_000_000)];
+        let err = mint_batch(&rt, MintBatchParams { mints }).unwrap_err();
+
+        assert_eq!(err.exit_code, ExitCode::USR_ILLEGAL_ARGUMENT);
+        assert_eq!(*rt.root.borrow(), root);
+    }
+
+    #[cfg(feature = "evm-compat")]
+    #[test]
+    fn amounts_are_capped_at_u256_max_under_evm_compat() {
+        let store = MemoryBlockstore::default();
+        let max = max_token_amount().unwrap();
+        assert_eq!(max, (TokenAmount::from(1) << 256usize) - 1);
+        let mut state = State::new(
+            &store,
+            ConstructorParams { max_supply: &max * 2, ..constructor_params() },
+        )
+        .unwrap();
+
+        let ctx = context(&store, OWNER);
+        let to_max = TransferParams { recipient: Address::new_id(ALICE), amount: max.clone() };
+        mint_tokens(&ctx, &mut state, &to_max).unwrap();
+        let err = mint_tokens(&ctx, &mut state, &transfer_params(BOB, 1)).unwrap_err();
+        assert_eq!(err.exit_code, ExitCode::USR_ILLEGAL_ARGUMENT);
+        assert_eq!(state.total_supply, max);
+
+        let mut balances = load_balances(&store, &state.balances, state.hamt_bit_width).unwrap();
+        assert!(set_balance(&mut balances, BOB, &max + 1).is_err());
+    }
+
+    #[cfg(not(feature = "evm-compat"))]
+    #[test]
+    fn amounts_are_unbounded_by_default() {
+        let store = MemoryBlockstore::default();
+        assert_eq!(max_token_amount(), None);
+        let huge = TokenAmount::from(1) << 300usize;
+        let mut state = State::new(
+            &store,
+            ConstructorParams { max_supply: &huge * 2, ..constructor_params() },
+        )
+        .unwrap();
+
+        let ctx = context(&store, OWNER);
+        let params = TransferParams { recipient: Address::new_id(ALICE), amount: huge.clone() };
+        mint_tokens(&ctx, &mut state, &params).unwrap();
+        assert_eq!(balance(&store, &state, ALICE), huge);
+        assert_eq!(state.total_supply, huge);
+    }
+
+    #[test]
+    fn caller_validation_allows_and_forbids() {
+        let rt = constructed_runtime();
+        let state = State::load_from(&rt).unwrap();
+        let require_owner = || {
+            let resolver = |addr: &Address| rt.resolve_address(addr);
+            runtime_context(&rt, &resolver).require_owner(&state, "mint")
+        };
+
+        rt.set_caller(OWNER);
+        validate_caller_is(&rt, OWNER).unwrap();
+        require_owner().unwrap();
+        validate_caller_is_account(&rt).unwrap();
+
+        rt.set_caller(ALICE);
+        let err = validate_caller_is(&rt, OWNER).unwrap_err();
+        assert_eq!(err.exit_code, ExitCode::USR_FORBIDDEN);
+        let err = require_owner().unwrap_err();
+        assert_eq!(err.exit_code, ExitCode::USR_FORBIDDEN);
+
+        // BOB calls on ALICE's behalf. It only passes as an account actor
+        // when its code matches that of ALICE, the origin.
+        let account_code = Cid::new_v1(0x55, Code::Identity.digest(b"account"));
+        rt.set_code_cid(ALICE, account_code);
+        rt.set_code_cid(BOB, Cid::new_v1(0x55, Code::Identity.digest(b"contract")));
+        rt.caller.set(BOB);
+        let err = validate_caller_is_account(&rt).unwrap_err();
+        assert_eq!(err.exit_code, ExitCode::USR_FORBIDDEN);
+
+        rt.set_code_cid(BOB, account_code);
+        validate_caller_is_account(&rt).unwrap();
+    }
+
+    #[test]
+    fn errors_carry_stable_subcodes() {
+        use crate::error::ErrorKind;
+
+        let store = MemoryBlockstore::default();
+        let mut state = new_state(&store);
+        mint_tokens(&context(&store, OWNER), &mut state, &transfer_params(ALICE, 10)).unwrap();
+
+        let err = transfer_tokens(&context(&store, ALICE), &mut state, &transfer_params(BOB, 11))
+            .unwrap_err();
+        assert_eq!(err.exit_code, ExitCode::USR_INSUFFICIENT_FUNDS);
+        assert!(err.msg.starts_with("[kind=1] "));
+        assert_eq!(err.kind(), Some(ErrorKind::InsufficientBalance));
+
+        let err = context(&store, ALICE).require_owner(&state, "mint").unwrap_err();
+        assert_eq!(err.exit_code, ExitCode::USR_FORBIDDEN);
+        assert!(err.msg.starts_with("[kind=3] "));
+        assert_eq!(err.kind(), Some(ErrorKind::Forbidden));
+    }
+
+    #[test]
+    fn unloadable_state_fails_with_specific_kinds() {
+        use crate::error::ErrorKind;
+
+        let err = State::load_from(&MockRuntime::new(TOKEN)).err().unwrap();
+        assert_eq!(err.kind(), Some(ErrorKind::MissingStateRoot));
+
+        let rt = constructed_runtime();
+        let mut state = State::load_from(&rt).unwrap();
+
+        // A root whose block was never stored.
+        state.balances = Cid::new_v1(DAG_CBOR, Code::Blake2b256.digest(b"bogus"));
+        state.save_to(&rt).unwrap();
+        let err = balance_of_id(&rt, BalanceOfIdParams { id: ALICE }).unwrap_err();
+        assert_eq!(err.exit_code, ExitCode::USR_ILLEGAL_STATE);
+        assert_eq!(err.kind(), Some(ErrorKind::CorruptHamt));
+
+        // A block that exists but isn't a HAMT node.
+        state.balances = rt.store.put_cbor(&"not a hamt", Code::Blake2b256).unwrap();
+        state.save_to(&rt).unwrap();
+        let err = balance_of_id(&rt, BalanceOfIdParams { id: ALICE }).unwrap_err();
+        assert_eq!(err.kind(), Some(ErrorKind::CorruptHamt));
+    }
+
+    #[test]
+    fn balances_written_with_a_wider_bit_width_are_detected() {
+        use crate::error::ErrorKind;
+
+        let store = MemoryBlockstore::default();
+        let mut balances: Balances<_> = Hamt::new_with_bit_width(&store, 8);
+        for id in 0..20 {
+            balances.set(id, BigIntDe(TokenAmount::from(1))).unwrap();
+        }
+        let root = balances.flush().unwrap();
+
+        let err = load_balances(&store, &root, 5).err().unwrap();
+        assert_eq!(err.exit_code, ExitCode::USR_ILLEGAL_STATE);
+        assert_eq!(err.kind(), Some(ErrorKind::HamtBitWidthMismatch));
+        assert!(load_balances(&store, &root, 8).is_ok());
+    }
+
+    #[test]
+    fn transfer_to_new_key_address_credits_assigned_id() {
+        let rt = constructed_runtime();
+        rt.set_caller(OWNER);
+        mint(&rt, transfer_params(ALICE, 100)).unwrap();
+
+        let addr = Address::new_bls(&[3; 48]).unwrap();
+        assert_eq!(rt.resolve_address(&addr), None);
+        rt.set_caller(ALICE);
+        let ret = transfer(&rt, Frc46TransferParams { to: addr, ..send_params(BOB, 25) }).unwrap();
+
+        let id = rt.resolve_address(&addr).unwrap();
+        assert_eq!(ret.to_balance, TokenAmount::from(25));
+        let state = State::load_from(&rt).unwrap();
+        assert_eq!(balance(&rt.store, &state, id), TokenAmount::from(25));
+        assert_eq!(balance(&rt.store, &state, ALICE), TokenAmount::from(75));
+    }
+
+    #[test]
+    fn transfer_fails_if_account_creation_fails() {
+        let rt = constructed_runtime();
+        rt.set_caller(OWNER);
+        mint(&rt, transfer_params(ALICE, 100)).unwrap();
+
+        let addr = Address::new_secp256k1(&[4; 65]).unwrap();
+        rt.expect_send_exit_code(ExitCode::SYS_INSUFFICIENT_FUNDS);
+        rt.set_caller(ALICE);
+        let params = Frc46TransferParams { to: addr, ..send_params(BOB, 25) };
+        let err = transfer(&rt, params).unwrap_err();
+
+        assert_eq!(err.exit_code, ExitCode::USR_ILLEGAL_STATE);
+        assert_eq!(rt.resolve_address(&addr), None);
+    }
+
+    #[test]
+    fn invariants_hold_after_mints_and_transfers() {
+        let rt = constructed_runtime();
+        rt.set_caller(OWNER);
+        mint(&rt, transfer_params(ALICE, 100)).unwrap();
+        mint(&rt, transfer_params(BOB, 50)).unwrap();
+        rt.set_caller(ALICE);
+        transfer(&rt, send_params(BOB, 30)).unwrap();
+
+        let report = verify_invariants(&rt).unwrap();
+        assert_eq!(report.balances_sum, TokenAmount::from(150));
+        assert_eq!(report.total_supply, TokenAmount::from(150));
+        assert!(report.consistent);
+    }
+
+    #[test]
+    fn total_supply_is_recomputed_across_pages() {
+        let rt = constructed_runtime();
+        rt.set_caller(OWNER);
+        mint(&rt, transfer_params(ALICE, 100)).unwrap();
+        mint(&rt, transfer_params(BOB, 50)).unwrap();
+        mint(&rt, transfer_params(COLLECTOR, 25)).unwrap();
+
+        let mut state = State::load_from(&rt).unwrap();
+        state.total_supply = TokenAmount::from(120);
+        state.save_to(&rt).unwrap();
+
+        let page = |start_after| RecomputeTotalSupplyParams { start_after, limit: 2 };
+        let first = recompute_total_supply(&rt, page(None)).unwrap();
+        let cursor = first.next.unwrap();
+        let state = State::load_from(&rt).unwrap();
+        assert_eq!(state.total_supply, TokenAmount::from(120));
+        assert_eq!(state.pending_total_supply.as_ref().map(|r| r.cursor), Some(cursor));
+
+        // Only the cursor the last page returned continues the recount.
+        let err = recompute_total_supply(&rt, page(Some(cursor + 1))).unwrap_err();
+        assert_eq!(err.exit_code, ExitCode::USR_ILLEGAL_ARGUMENT);
+
+        // Tokens minted mid-recount are carried into the result.
+        mint(&rt, transfer_params(BOB, 10)).unwrap();
+        let second = recompute_total_supply(&rt, page(Some(cursor))).unwrap();
+        assert_eq!(second.next, None);
+
+        let state = State::load_from(&rt).unwrap();
+        assert_eq!(state.total_supply, TokenAmount::from(185));
+        assert_eq!(state.pending_total_supply, None);
+        assert!(verify_invariants(&rt).unwrap().consistent);
+    }
+
+    #[test]
+    fn invariants_catch_a_corrupted_total_supply() {
+        let rt = constructed_runtime();
+        rt.set_caller(OWNER);
+        mint(&rt, transfer_params(ALICE, 100)).unwrap();
+
+        let mut state = State::load_from(&rt).unwrap();
+        state.total_supply = TokenAmount::from(120);
+        let root = state.save_to(&rt).unwrap();
+
+        let report = verify_invariants(&rt).unwrap();
+        assert_eq!(report.balances_sum, TokenAmount::from(100));
+        assert_eq!(report.total_supply, TokenAmount::from(120));
+        assert!(!report.consistent);
+        assert_eq!(rt.root().unwrap(), root);
+    }
+
+    #[test]
+    fn transfer_skips_receiver_hook_unless_required() {
+        let rt = constructed_runtime();
+        rt.set_caller(OWNER);
+        mint(&rt, transfer_params(ALICE, 100)).unwrap();
+
+        // The recipient would reject, but is never asked.
+        rt.set_code_cid(BOB, Cid::new_v1(0x55, Code::Identity.digest(b"hook-actor")));
+        rt.expect_send_exit_code(ExitCode::USR_ILLEGAL_ARGUMENT);
+        rt.set_caller(ALICE);
+        transfer(&rt, send_params(BOB, 30)).unwrap();
+
+        assert!(rt.sent.borrow().is_empty());
+        let state = State::load_from(&rt).unwrap();
+        assert_eq!(balance(&rt.store, &state, BOB), TokenAmount::from(30));
+    }
+
+    #[test]
+    fn required_receiver_hook_still_accepts_account_recipients() {
+        let rt = hooked_runtime();
+        rt.set_caller(OWNER);
+        mint(&rt, transfer_params(ALICE, 100)).unwrap();
+
+        rt.set_caller(ALICE);
+        transfer(&rt, send_params(BOB, 30)).unwrap();
+
+        assert!(rt.sent.borrow().is_empty());
+        let state = State::load_from(&rt).unwrap();
+        assert_eq!(balance(&rt.store, &state, BOB), TokenAmount::from(30));
+    }
+
+    fn rate_limited_runtime(max_amount: u64, window_epochs: ChainEpoch) -> MockRuntime {
+        let rt = constructed_runtime();
+        rt.set_caller(OWNER);
+        mint(&rt, transfer_params(ALICE, 1000)).unwrap();
+        let limit = RateLimit { max_amount: TokenAmount::from(max_amount), window_epochs };
+        set_rate_limit(&rt, SetRateLimitParams { limit: Some(limit) }).unwrap();
+        rt.set_caller(ALICE);
+        rt
+    }
+
+    #[test]
+    fn rate_limit_blocks_transfers_until_the_window_rolls() {
+        use crate::error::ErrorKind;
+
+        let rt = rate_limited_runtime(100, 10);
+        rt.epoch.set(5);
+        transfer(&rt, send_params(BOB, 60)).unwrap();
+        transfer(&rt, send_params(BOB, 40)).unwrap();
+
+        let err = transfer(&rt, send_params(BOB, 1)).unwrap_err();
+        assert_eq!(err.exit_code, ExitCode::USR_FORBIDDEN);
+        assert_eq!(err.kind(), Some(ErrorKind::RateLimited));
+        assert!(!simulate_transfer(&rt, transfer_params(BOB, 1)).unwrap().would_succeed);
+
+        // The window opened at epoch 5, so it's still closed at 14.
+        rt.epoch.set(14);
+        assert!(transfer(&rt, send_params(BOB, 1)).is_err());
+
+        rt.epoch.set(15);
+        transfer(&rt, send_params(BOB, 100)).unwrap();
+        let state = State::load_from(&rt).unwrap();
+        assert_eq!(balance(&rt.store, &state, BOB), TokenAmount::from(200));
+    }
+
+    #[test]
+    fn rate_limit_windows_stay_aligned_after_idle_epochs() {
+        let rt = rate_limited_runtime(100, 10);
+        transfer(&rt, send_params(BOB, 100)).unwrap();
+
+        // Windows start at 0, 10, 20, ... so epoch 27 is in the one from 20.
+        rt.epoch.set(27);
+        transfer(&rt, send_params(BOB, 100)).unwrap();
+        rt.epoch.set(29);
+        assert!(transfer(&rt, send_params(BOB, 1)).is_err());
+        rt.epoch.set(30);
+        transfer(&rt, send_params(BOB, 1)).unwrap();
+    }
+
+    #[test]
+    fn rate_limit_is_per_account_and_can_be_cleared() {
+        let rt = rate_limited_runtime(100, 10);
+        transfer(&rt, send_params(BOB, 100)).unwrap();
+        assert!(transfer(&rt, send_params(BOB, 1)).is_err());
+
+        // Bob has a window of his own.
+        rt.set_caller(BOB);
+        transfer(&rt, send_params(ALICE, 100)).unwrap();
+
+        rt.set_caller(ALICE);
+        let err = set_rate_limit(&rt, SetRateLimitParams { limit: None }).unwrap_err();
+        assert_eq!(err.exit_code, ExitCode::USR_FORBIDDEN);
+
+        rt.set_caller(OWNER);
+        set_rate_limit(&rt, SetRateLimitParams { limit: None }).unwrap();
+        rt.set_caller(ALICE);
+        transfer(&rt, send_params(BOB, 500)).unwrap();
+    }
+
+    #[test]
+    fn owner_returns_the_constructed_owner() {
+        let rt = constructed_runtime();
+        let root = rt.root().unwrap();
+
+        assert_eq!(owner(&rt).unwrap(), Address::new_id(OWNER));
+        assert_eq!(rt.root().unwrap(), root);
+    }
+
+    fn offer_ownership(rt: &MockRuntime, to: ActorID) {
+        rt.set_caller(OWNER);
+        let params = TransferOwnershipParams { new_owner: Address::new_id(to) };
+        transfer_ownership(rt, params).unwrap();
+    }
+
+    #[test]
+    fn ownership_moves_once_the_pending_owner_accepts() {
+        let rt = constructed_runtime();
+        offer_ownership(&rt, ALICE);
+
+        // Nothing changes until Alice accepts.
+        assert_eq!(owner(&rt).unwrap(), Address::new_id(OWNER));
+        let state = State::load_from(&rt).unwrap();
+        assert_eq!(state.pending_owner, Some(Address::new_id(ALICE)));
+
+        rt.set_caller(ALICE);
+        accept_ownership(&rt).unwrap();
+        assert_eq!(owner(&rt).unwrap(), Address::new_id(ALICE));
+        assert_eq!(State::load_from(&rt).unwrap().pending_owner, None);
+
+        // The old owner has lost its rights, and the new one has them.
+        rt.set_caller(OWNER);
+        assert!(mint(&rt, transfer_params(BOB, 1)).is_err());
+        rt.set_caller(ALICE);
+        mint(&rt, transfer_params(BOB, 1)).unwrap();
+    }
+
+    #[test]
+    fn only_the_pending_owner_can_accept_ownership() {
+        let rt = constructed_runtime();
+        rt.set_caller(ALICE);
+        assert_eq!(accept_ownership(&rt).unwrap_err().exit_code, ExitCode::USR_FORBIDDEN);
+
+        offer_ownership(&rt, ALICE);
+        for caller in [BOB, OWNER] {
+            rt.set_caller(caller);
+            assert_eq!(accept_ownership(&rt).unwrap_err().exit_code, ExitCode::USR_FORBIDDEN);
+        }
+
+        rt.set_caller(BOB);
+        let params = TransferOwnershipParams { new_owner: Address::new_id(BOB) };
+        assert_eq!(transfer_ownership(&rt, params).unwrap_err().exit_code, ExitCode::USR_FORBIDDEN);
+        assert_eq!(owner(&rt).unwrap(), Address::new_id(OWNER));
+    }
+
+    #[test]
+    fn an_owner_given_by_address_is_authorized_by_its_id() {
+        // fvm_shared has no delegated addresses yet, so an actor address
+        // stands in for an EVM owner's f4 address.
+        let owner_addr = Address::new_actor(b"evm-owner");
+        let rt = MockRuntime::new(TOKEN);
+        rt.add_address(owner_addr, OWNER);
+        rt.set_caller(INIT_ACTOR_ADDR);
+        constructor(&rt, ConstructorParams { owner: owner_addr, ..constructor_params() }).unwrap();
+        let state = State::load_from(&rt).unwrap();
+        assert_eq!((state.owner, state.owner_id), (owner_addr, Some(OWNER)));
+
+        // Authorization uses the stored ID, even if the address would now
+        // resolve elsewhere.
+        rt.add_address(owner_addr, BOB);
+        rt.set_caller(BOB);
+        assert!(mint(&rt, transfer_params(ALICE, 1)).is_err());
+        rt.set_caller(OWNER);
+        mint(&rt, transfer_params(ALICE, 1)).unwrap();
+
+        // A new owner given by address is stored by the ID that accepted.
+        let alice_addr = Address::new_actor(b"evm-alice");
+        rt.add_address(alice_addr, ALICE);
+        transfer_ownership(&rt, TransferOwnershipParams { new_owner: alice_addr }).unwrap();
+        rt.set_caller(ALICE);
+        accept_ownership(&rt).unwrap();
+        let state = State::load_from(&rt).unwrap();
+        assert_eq!((state.owner, state.owner_id), (alice_addr, Some(ALICE)));
+        mint(&rt, transfer_params(ALICE, 1)).unwrap();
+    }
+
+    #[test]
+    fn the_holder_cap_keeps_new_holders_out() {
+        use crate::error::ErrorKind;
+
+        let rt = constructed_runtime();
+        rt.set_caller(OWNER);
+        set_max_holders(&rt, SetMaxHoldersParams { max_holders: Some(2) }).unwrap();
+        mint(&rt, transfer_params(ALICE, 100)).unwrap();
+        mint(&rt, transfer_params(BOB, 100)).unwrap();
+
+        let err = mint(&rt, transfer_params(COLLECTOR, 1)).unwrap_err();
+        assert_eq!(err.exit_code, ExitCode::USR_FORBIDDEN);
+        assert_eq!(err.kind(), Some(ErrorKind::HolderCapReached));
+        rt.set_caller(ALICE);
+        let err = transfer(&rt, send_params(COLLECTOR, 1)).unwrap_err();
+        assert_eq!(err.kind(), Some(ErrorKind::HolderCapReached));
+
+        // Existing holders trade freely, and an outgoing holder frees a slot.
+        transfer(&rt, send_params(BOB, 40)).unwrap();
+        rt.set_caller(OWNER);
+        mint(&rt, transfer_params(ALICE, 5)).unwrap();
+        rt.set_caller(BOB);
+        transfer(&rt, send_params(ALICE, 140)).unwrap();
+        rt.set_caller(ALICE);
+        transfer(&rt, send_params(COLLECTOR, 1)).unwrap();
+        assert_eq!(State::load_from(&rt).unwrap().holder_count, 2);
+
+        rt.set_caller(OWNER);
+        set_max_holders(&rt, SetMaxHoldersParams { max_holders: None }).unwrap();
+        mint(&rt, transfer_params(BOB, 1)).unwrap();
+        assert_eq!(State::load_from(&rt).unwrap().holder_count, 3);
+    }
+
+    #[test]
+    fn set_max_holders_is_owner_only() {
+        let rt = constructed_runtime();
+        rt.set_caller(ALICE);
+
+        let err = set_max_holders(&rt, SetMaxHoldersParams { max_holders: Some(1) }).unwrap_err();
+        assert_eq!(err.exit_code, ExitCode::USR_FORBIDDEN);
+        assert_eq!(State::load_from(&rt).unwrap().max_holders, None);
+    }
+
+    #[test]
+    fn each_account_can_claim_once_until_the_pool_runs_dry() {
+        let rt = constructed_runtime();
+        rt.set_caller(OWNER);
+        mint(&rt, transfer_params(OWNER, 25)).unwrap();
+        rt.set_caller(ALICE);
+        assert_eq!(claim(&rt).unwrap_err().exit_code, ExitCode::USR_FORBIDDEN);
+
+        let terms = |amount: u64| SetClaimTermsParams {
+            pool: Address::new_id(OWNER),
+            amount: TokenAmount::from(amount),
+            deadline: None,
+        };
+        rt.set_caller(OWNER);
+        set_claim_terms(&rt, terms(10)).unwrap();
+
+        rt.set_caller(ALICE);
+        claim(&rt).unwrap();
+        let state = State::load_from(&rt).unwrap();
+        assert_eq!(balance(&rt.store, &state, ALICE), TokenAmount::from(10));
+        assert_eq!(balance(&rt.store, &state, OWNER), TokenAmount::from(15));
+
+        let root = rt.root().unwrap();
+        assert_eq!(claim(&rt).unwrap_err().exit_code, ExitCode::USR_FORBIDDEN);
+        assert_eq!(rt.root().unwrap(), root);
+
+        rt.set_caller(BOB);
+        claim(&rt).unwrap();
+        // The pool has 5 left, less than a claim.
+        rt.set_caller(COLLECTOR);
+        assert_eq!(claim(&rt).unwrap_err().exit_code, ExitCode::USR_INSUFFICIENT_FUNDS);
+
+        // Turning claiming off stops claims, and back on doesn't reset them.
+        rt.set_caller(OWNER);
+        set_claim_terms(&rt, terms(0)).unwrap();
+        rt.set_caller(COLLECTOR);
+        assert_eq!(claim(&rt).unwrap_err().exit_code, ExitCode::USR_FORBIDDEN);
+        rt.set_caller(OWNER);
+        set_claim_terms(&rt, terms(5)).unwrap();
+        rt.set_caller(ALICE);
+        assert_eq!(claim(&rt).unwrap_err().exit_code, ExitCode::USR_FORBIDDEN);
+    }
+
+    #[test]
+    fn balance_including_pending_counts_the_claim_balance_of_leaves_out() {
+        let rt = constructed_runtime();
+        rt.set_caller(OWNER);
+        mint(&rt, transfer_params(OWNER, 15)).unwrap();
+        mint(&rt, transfer_params(ALICE, 3)).unwrap();
+        let pool = Address::new_id(OWNER);
+        let terms = SetClaimTermsParams { pool, amount: TokenAmount::from(10), deadline: Some(20) };
+        set_claim_terms(&rt, terms).unwrap();
+        let pending = |id| {
+            let params = BalanceIncludingPendingParams { account: Address::new_id(id) };
+            balance_including_pending(&rt, params).unwrap()
+        };
+
+        assert_eq!(balance_of(&rt, Address::new_id(ALICE)).unwrap(), TokenAmount::from(3));
+        assert_eq!(pending(ALICE), TokenAmount::from(13));
+        // The pool doesn't count a claim on itself.
+        assert_eq!(pending(OWNER), TokenAmount::from(15));
+
+        // Once claimed, the claim is part of the balance proper.
+        rt.set_caller(ALICE);
+        claim(&rt).unwrap();
+        assert_eq!(balance_of(&rt, Address::new_id(ALICE)).unwrap(), TokenAmount::from(13));
+        assert_eq!(pending(ALICE), TokenAmount::from(13));
+        // With 5 left, the pool can't cover another claim.
+        assert_eq!(pending(BOB), TokenAmount::from(0));
+        rt.set_caller(OWNER);
+        mint(&rt, transfer_params(OWNER, 5)).unwrap();
+        assert_eq!(pending(BOB), TokenAmount::from(10));
+        // Nor does an expired one.
+        rt.epoch.set(20);
+        assert_eq!(pending(BOB), TokenAmount::from(0));
+    }
+
+    #[test]
+    fn unclaimed_tokens_go_back_to_the_owner_after_the_deadline() {
+        let rt = constructed_runtime();
+        rt.set_caller(OWNER);
+        mint(&rt, transfer_params(COLLECTOR, 25)).unwrap();
+        let pool = Address::new_id(COLLECTOR);
+        let terms = |deadline| SetClaimTermsParams {
+            pool,
+            amount: TokenAmount::from(10),
+            deadline: Some(deadline),
+        };
+        rt.epoch.set(10);
+        let err = set_claim_terms(&rt, terms(10)).unwrap_err();
+        assert_eq!(err.exit_code, ExitCode::USR_ILLEGAL_ARGUMENT);
+        set_claim_terms(&rt, terms(20)).unwrap();
+
+        rt.set_caller(ALICE);
+        claim(&rt).unwrap();
+        rt.set_caller(OWNER);
+        let err = reclaim_unclaimed(&rt).unwrap_err();
+        assert_eq!(err.exit_code, ExitCode::USR_FORBIDDEN);
+
+        rt.epoch.set(20);
+        rt.set_caller(BOB);
+        assert_eq!(claim(&rt).unwrap_err().exit_code, ExitCode::USR_FORBIDDEN);
+        let err = reclaim_unclaimed(&rt).unwrap_err();
+        assert_eq!(err.exit_code, ExitCode::USR_FORBIDDEN);
+
+        rt.set_caller(OWNER);
+        assert_eq!(reclaim_unclaimed(&rt).unwrap(), TokenAmount::from(15));
+        let state = State::load_from(&rt).unwrap();
+        assert_eq!(balance(&rt.store, &state, OWNER), TokenAmount::from(15));
+        assert_eq!(balance(&rt.store, &state, COLLECTOR), TokenAmount::from(0));
+        assert_eq!((state.claim_terms, state.claim_deadline), (None, None));
+        // Claiming is off now, so there is nothing left to reclaim.
+        let err = reclaim_unclaimed(&rt).unwrap_err();
+        assert_eq!(err.exit_code, ExitCode::USR_FORBIDDEN);
+    }
+
+    #[test]
+    fn set_claim_terms_is_owner_only() {
+        let rt = constructed_runtime();
+        rt.set_caller(ALICE);
+        let pool = Address::new_id(ALICE);
+        let terms = SetClaimTermsParams { pool, amount: TokenAmount::from(1), deadline: None };
+
+        let err = set_claim_terms(&rt, terms).unwrap_err();
+        assert_eq!(err.exit_code, ExitCode::USR_FORBIDDEN);
+        assert_eq!(State::load_from(&rt).unwrap().claim_terms, None);
+    }
+
+    /// A mock runtime with a freshly constructed wrapped token.
+    fn wrapped_runtime() -> MockRuntime {
+        let rt = MockRuntime::new(TOKEN);
+        rt.set_caller(INIT_ACTOR_ADDR);
+        // The cap would be hit by the first deposit if it applied.
+        let max_supply = TokenAmount::from(1);
+        let params = ConstructorParams { wrapped: true, max_supply, ..constructor_params() };
+        constructor(&rt, params).unwrap();
+        rt
+    }
+
+    #[test]
+    fn deposits_can_be_withdrawn_as_fil() {
+        let rt = wrapped_runtime();
+        rt.set_caller(ALICE);
+        rt.set_value_received(TokenAmount::from(100));
+        deposit(&rt).unwrap();
+        let state = State::load_from(&rt).unwrap();
+        assert_eq!(balance(&rt.store, &state, ALICE), TokenAmount::from(100));
+        assert_eq!(state.total_supply, TokenAmount::from(100));
+
+        rt.set_value_received(TokenAmount::from(0));
+        withdraw(&rt, WithdrawParams { amount: TokenAmount::from(60) }).unwrap();
+        {
+            let sent = rt.sent.borrow();
+            assert_eq!(sent.len(), 1);
+            assert_eq!((sent[0].to, sent[0].method), (Address::new_id(ALICE), METHOD_SEND));
+            assert_eq!(sent[0].value, TokenAmount::from(60));
+        }
+        assert_eq!(rt.current_balance(), TokenAmount::from(40));
+        let state = State::load_from(&rt).unwrap();
+        assert_eq!(balance(&rt.store, &state, ALICE), TokenAmount::from(40));
+        assert_eq!(state.total_supply, TokenAmount::from(40));
+        assert!(!state.reentrancy_locked);
+    }
+
+    #[test]
+    fn withdrawals_need_tokens_and_fil() {
+        let rt = wrapped_runtime();
+        rt.set_caller(ALICE);
+        rt.set_value_received(TokenAmount::from(100));
+        deposit(&rt).unwrap();
+        let withdrawal = |amount: u64| WithdrawParams { amount: TokenAmount::from(amount) };
+
+        // Bob has no tokens to burn.
+        rt.set_caller(BOB);
+        let err = withdraw(&rt, withdrawal(10)).unwrap_err();
+        assert_eq!(err.exit_code, ExitCode::USR_INSUFFICIENT_FUNDS);
+
+        // Nor can more FIL leave than the actor holds.
+        rt.set_caller(ALICE);
+        *rt.balance.borrow_mut() = TokenAmount::from(50);
+        let err = withdraw(&rt, withdrawal(60)).unwrap_err();
+        assert_eq!(err.exit_code, ExitCode::USR_INSUFFICIENT_FUNDS);
+
+        // A failed send puts the burned tokens back.
+        rt.expect_send_exit_code(ExitCode::USR_FORBIDDEN);
+        let err = withdraw(&rt, withdrawal(50)).unwrap_err();
+        assert_eq!(err.exit_code, ExitCode::USR_ILLEGAL_STATE);
+        let state = State::load_from(&rt).unwrap();
+        assert_eq!(balance(&rt.store, &state, ALICE), TokenAmount::from(100));
+        assert_eq!(state.total_supply, TokenAmount::from(100));
+        assert!(!state.reentrancy_locked);
+        assert_eq!(rt.current_balance(), TokenAmount::from(50));
+    }
+
+    #[test]
+    fn unwrapped_tokens_refuse_deposits_and_withdrawals() {
+        let rt = constructed_runtime();
+        rt.set_caller(ALICE);
+        rt.set_value_received(TokenAmount::from(100));
+
+        assert_eq!(deposit(&rt).unwrap_err().exit_code, ExitCode::USR_FORBIDDEN);
+        let err = withdraw(&rt, WithdrawParams { amount: TokenAmount::from(1) }).unwrap_err();
+        assert_eq!(err.exit_code, ExitCode::USR_FORBIDDEN);
+    }
+
+    #[test]
+    fn wrapped_tokens_are_only_minted_by_deposits() {
+        use crate::error::ErrorKind;
+        use crate::multicall::{multicall, Call, MulticallParams};
+
+        let rt = wrapped_runtime();
+        rt.set_caller(OWNER);
+        let root = rt.root().unwrap();
+
+        let err = mint(&rt, transfer_params(ALICE, 1)).unwrap_err();
+        assert_eq!(err.kind(), Some(ErrorKind::Forbidden));
+        let batch = MintBatchParams { mints: vec![transfer_params(ALICE, 1)] };
+        let err = mint_batch(&rt, batch).unwrap_err();
+        assert_eq!(err.kind(), Some(ErrorKind::Forbidden));
+        let params = RawBytes::serialize(transfer_params(ALICE, 1)).unwrap();
+        let calls = vec![Call { method: method::MINT, params }];
+        let err = multicall(&rt, MulticallParams { calls }).unwrap_err();
+        assert_eq!(err.kind(), Some(ErrorKind::Forbidden));
+        assert_eq!(rt.root().unwrap(), root);
+    }
+
+    #[test]
+    fn constructor_returns_what_it_created() {
+        let rt = MockRuntime::new(TOKEN);
+        rt.set_caller(INIT_ACTOR_ADDR);
+        let ret = constructor(&rt, constructor_params()).unwrap();
+
+        assert_eq!(ret.state_root, rt.root().unwrap());
+        assert_eq!(ret.owner_id, OWNER);
+        assert_eq!(ret.balances_root, State::load_from(&rt).unwrap().balances);
+    }
+
+    #[test]
+    fn constructed_state_reloads_with_its_hamts() {
+        let rt = constructed_runtime();
+        let state = State::load_from(&rt).unwrap();
+
+        let balances = load_balances(&rt.store, &state.balances, state.hamt_bit_width).unwrap();
+        assert_eq!(get_balance(&balances, ALICE).unwrap(), TokenAmount::from(0));
+        // Every other HAMT waits for its first use.
+        for root in [
+            state.hook_allowlist,
+            state.locks,
+            state.operators,
+            state.claimed,
+            state.recent_transfers,
+            state.transfer_windows,
+        ] {
+            assert_eq!(root, None);
+        }
+    }
+
+    #[test]
+    fn verify_constructed_catches_unreadable_balances() {
+        // The state block is stored, but its balances HAMT went to another store.
+        let rt = MockRuntime::new(TOKEN);
+        let elsewhere = MemoryBlockstore::default();
+        State::new(&elsewhere, constructor_params()).unwrap().save_to(&rt).unwrap();
+
+        let err = verify_constructed(&rt).unwrap_err();
+        assert_eq!(err.exit_code, ExitCode::USR_ILLEGAL_STATE);
+        assert!(err.msg.contains("constructed balances"), "{}", err.msg);
+    }
+
+    #[test]
+    fn constructor_rejects_an_unresolvable_owner() {
+        let rt = MockRuntime::new(TOKEN);
+        rt.set_caller(INIT_ACTOR_ADDR);
+        let owner = Address::new_actor(b"nobody");
+        let params = ConstructorParams { owner, ..constructor_params() };
+
+        let err = constructor(&rt, params).unwrap_err();
+        assert_eq!(err.exit_code, ExitCode::USR_ILLEGAL_ARGUMENT);
+        assert!(rt.root().is_err());
+    }
+
+    #[test]
+    fn ownership_transfer_can_be_cancelled() {
+        let rt = constructed_runtime();
+        offer_ownership(&rt, ALICE);
+        cancel_ownership_transfer(&rt).unwrap();
+        rt.set_caller(ALICE);
+        assert!(accept_ownership(&rt).is_err());
+
+        rt.set_caller(OWNER);
+        let err = cancel_ownership_transfer(&rt).unwrap_err();
+        assert_eq!(err.exit_code, ExitCode::USR_ILLEGAL_STATE);
+
+        // Offering ownership to the current owner cancels too.
+        offer_ownership(&rt, ALICE);
+        offer_ownership(&rt, OWNER);
+        assert_eq!(State::load_from(&rt).unwrap().pending_owner, None);
+        rt.set_caller(ALICE);
+        assert!(accept_ownership(&rt).is_err());
+    }
+
+    #[test]
+    fn renouncing_ownership_disables_owner_methods_for_good() {
+        let rt = constructed_runtime();
+        rt.set_caller(ALICE);
+        let err = renounce_ownership(&rt).unwrap_err();
+        assert_eq!(err.exit_code, ExitCode::USR_FORBIDDEN);
+
+        rt.set_caller(OWNER);
+        mint(&rt, transfer_params(ALICE, 100)).unwrap();
+        offer_ownership(&rt, BOB);
+        renounce_ownership(&rt).unwrap();
+        let state = State::load_from(&rt).unwrap();
+        assert!(state.ownership_renounced);
+        assert_eq!(state.pending_owner, None);
+
+        let err = mint(&rt, transfer_params(ALICE, 1)).unwrap_err();
+        assert_eq!(err.exit_code, ExitCode::USR_FORBIDDEN);
+        let params = TransferOwnershipParams { new_owner: Address::new_id(BOB) };
+        assert!(transfer_ownership(&rt, params).is_err());
+        assert!(renounce_ownership(&rt).is_err());
+        rt.set_caller(BOB);
+        assert!(accept_ownership(&rt).is_err());
+
+        // Holders are unaffected.
+        rt.set_caller(ALICE);
+        transfer(&rt, send_params(BOB, 40)).unwrap();
+    }
+
+    fn role(role: Role, id: ActorID) -> crate::access_control::RoleParams {
+        crate::access_control::RoleParams { role: role as u8, account: Address::new_id(id) }
+    }
+
+    #[test]
+    fn minters_can_mint_without_being_owner() {
+        use crate::access_control::{grant_role, has_role, revoke_role};
+
+        let rt = constructed_runtime();
+        rt.set_caller(ALICE);
+        assert!(grant_role(&rt, role(Role::Minter, ALICE)).is_err());
+        assert!(mint(&rt, transfer_params(BOB, 10)).is_err());
+
+        rt.set_caller(OWNER);
+        grant_role(&rt, role(Role::Minter, ALICE)).unwrap();
+        assert!(has_role(&rt, role(Role::Minter, ALICE)).unwrap());
+        assert!(!has_role(&rt, role(Role::Pauser, ALICE)).unwrap());
+        let unknown = crate::access_control::RoleParams { role: 9, ..role(Role::Minter, ALICE) };
+        assert_eq!(has_role(&rt, unknown).unwrap_err().exit_code, ExitCode::USR_ILLEGAL_ARGUMENT);
+
+        rt.set_caller(ALICE);
+        mint(&rt, transfer_params(BOB, 10)).unwrap();
+        mint_batch(&rt, MintBatchParams { mints: vec![transfer_params(BOB, 5)] }).unwrap();
+        // The role covers minting, and nothing else the owner can do.
+        let params = TransferOwnershipParams { new_owner: Address::new_id(ALICE) };
+        assert!(transfer_ownership(&rt, params).is_err());
+
+        rt.set_caller(OWNER);
+        revoke_role(&rt, role(Role::Minter, ALICE)).unwrap();
+        rt.set_caller(ALICE);
+        let err = mint(&rt, transfer_params(BOB, 1)).unwrap_err();
+        assert_eq!(err.exit_code, ExitCode::USR_FORBIDDEN);
+        assert_eq!(State::load_from(&rt).unwrap().total_supply, TokenAmount::from(15));
+    }
+
+    #[test]
+    fn pausing_stops_every_balance_change() {
+        use crate::access_control::grant_role;
+        use crate::error::ErrorKind;
+
+        let rt = constructed_runtime();
+        rt.set_caller(OWNER);
+        mint(&rt, transfer_params(ALICE, 100)).unwrap();
+        rt.set_caller(BOB);
+        assert_eq!(pause(&rt).unwrap_err().exit_code, ExitCode::USR_FORBIDDEN);
+        rt.set_caller(OWNER);
+        grant_role(&rt, role(Role::Pauser, BOB)).unwrap();
+
+        rt.set_caller(BOB);
+        pause(&rt).unwrap();
+        assert_eq!(pause(&rt).unwrap_err().exit_code, ExitCode::USR_ILLEGAL_STATE);
+        let paused = |res: Result<(), ActorError>| {
+            let err = res.unwrap_err();
+            assert_eq!(err.exit_code, ExitCode::USR_FORBIDDEN);
+            assert_eq!(err.kind(), Some(ErrorKind::Paused));
+        };
+        rt.set_caller(ALICE);
+        paused(transfer(&rt, send_params(BOB, 1)).map(drop));
+        paused(burn(&rt, BurnParams { amount: TokenAmount::from(1) }));
+        set_operator(&rt, SetOperatorParams { operator: Address::new_id(BOB), approved: true })
+            .unwrap();
+        rt.set_caller(BOB);
+        let params = TransferFromParams {
+            from: Address::new_id(ALICE),
+            to: Address::new_id(BOB),
+            amount: TokenAmount::from(1),
+            operator_data: RawBytes::default(),
+        };
+        paused(transfer_from(&rt, params).map(drop));
+        rt.set_caller(OWNER);
+        paused(mint(&rt, transfer_params(ALICE, 1)).map(drop));
+        assert!(renounce_ownership(&rt).is_err());
+        // Only the operator approval, which is bookkeeping, went through.
+        let state = State::load_from(&rt).unwrap();
+        assert_eq!(balance(&rt.store, &state, ALICE), TokenAmount::from(100));
+        assert_eq!(state.total_supply, TokenAmount::from(100));
+
+        rt.set_caller(BOB);
+        unpause(&rt).unwrap();
+        assert_eq!(unpause(&rt).unwrap_err().exit_code, ExitCode::USR_ILLEGAL_STATE);
+        rt.set_caller(ALICE);
+        transfer(&rt, send_params(BOB, 1)).unwrap();
+    }
+
+    #[test]
+    fn the_burner_role_grants_no_allowance() {
+        use crate::access_control::grant_role;
+
+        let rt = constructed_runtime();
+        rt.set_caller(OWNER);
+        mint(&rt, transfer_params(ALICE, 100)).unwrap();
+        grant_role(&rt, role(Role::Burner, BOB)).unwrap();
+
+        rt.set_caller(BOB);
+        let amount = TokenAmount::from(60);
+        burn_from(&rt, BurnFromParams { owner: Address::new_id(ALICE), amount }).unwrap_err();
+        assert_eq!(State::load_from(&rt).unwrap().total_supply, TokenAmount::from(100));
+    }
+
+    #[test]
+    fn frozen_accounts_can_neither_send_nor_receive() {
+        use crate::error::ErrorKind;
+
+        let rt = constructed_runtime();
+        rt.set_caller(OWNER);
+        mint(&rt, transfer_params(ALICE, 100)).unwrap();
+        mint(&rt, transfer_params(BOB, 100)).unwrap();
+        let alice = || FreezeAccountParams { account: Address::new_id(ALICE) };
+        rt.set_caller(BOB);
+        assert_eq!(freeze_account(&rt, alice()).unwrap_err().exit_code, ExitCode::USR_FORBIDDEN);
+        rt.set_caller(OWNER);
+        freeze_account(&rt, alice()).unwrap();
+        assert!(is_frozen(&rt, alice()).unwrap());
+
+        let frozen = |res: Result<(), ActorError>| {
+            let err = res.unwrap_err();
+            assert_eq!(err.exit_code, ExitCode::USR_FORBIDDEN);
+            assert_eq!(err.kind(), Some(ErrorKind::Frozen));
+        };
+        rt.set_caller(ALICE);
+        frozen(transfer(&rt, send_params(BOB, 1)).map(drop));
+        set_operator(&rt, SetOperatorParams { operator: Address::new_id(BOB), approved: true })
+            .unwrap();
+        rt.set_caller(BOB);
+        frozen(transfer(&rt, send_params(ALICE, 1)).map(drop));
+        let params = TransferFromParams {
+            from: Address::new_id(ALICE),
+            to: Address::new_id(BOB),
+            amount: TokenAmount::from(1),
+            operator_data: RawBytes::default(),
+        };
+        frozen(transfer_from(&rt, params.clone()).map(drop));
+
+        // The owner can still move a frozen balance.
+        rt.set_caller(OWNER);
+        let force = ForceTransferParams {
+            from: Address::new_id(ALICE),
+            to: Address::new_id(COLLECTOR),
+            amount: TokenAmount::from(10),
+        };
+        force_transfer(&rt, force).unwrap();
+
+        unfreeze_account(&rt, alice()).unwrap();
+        assert!(!is_frozen(&rt, alice()).unwrap());
+        rt.set_caller(BOB);
+        transfer_from(&rt, params).unwrap();
+        let state = State::load_from(&rt).unwrap();
+        assert_eq!(balance(&rt.store, &state, ALICE), TokenAmount::from(89));
+        assert_eq!(balance(&rt.store, &state, BOB), TokenAmount::from(101));
+    }
+
+    const COLLECTOR: ActorID = 103;
+
+    fn fee_runtime(fee_bps: u16) -> MockRuntime {
+        let rt = constructed_runtime();
+        rt.set_caller(OWNER);
+        mint(&rt, transfer_params(ALICE, 10_000)).unwrap();
+        let fee_collector = Some(Address::new_id(COLLECTOR));
+        set_transfer_fee(&rt, SetTransferFeeParams { fee_bps, fee_collector }).unwrap();
+        rt.set_caller(ALICE);
+        rt
+    }
+
+    #[test]
+    fn transfer_fee_goes_to_the_collector() {
+        let rt = fee_runtime(250);
+        transfer(&rt, send_params(BOB, 1000)).unwrap();
+
+        let state = State::load_from(&rt).unwrap();
+        assert_eq!(balance(&rt.store, &state, ALICE), TokenAmount::from(9000));
+        assert_eq!(balance(&rt.store, &state, BOB), TokenAmount::from(975));
+        assert_eq!(balance(&rt.store, &state, COLLECTOR), TokenAmount::from(25));
+        assert_eq!(state.total_supply, TokenAmount::from(10_000));
+        assert_eq!(state.holder_count, 3);
+
+        // The whole amount has to be covered, fee included.
+        let err = transfer(&rt, send_params(BOB, 9001)).unwrap_err();
+        assert_eq!(err.exit_code, ExitCode::USR_INSUFFICIENT_FUNDS);
+    }
+
+    #[test]
+    fn zero_transfer_fee_leaves_the_collector_untouched() {
+        let rt = fee_runtime(0);
+        transfer(&rt, send_params(BOB, 1000)).unwrap();
+
+        let state = State::load_from(&rt).unwrap();
+        assert_eq!(balance(&rt.store, &state, BOB), TokenAmount::from(1000));
+        assert_eq!(balance(&rt.store, &state, COLLECTOR), TokenAmount::from(0));
+    }
+
+    #[test]
+    fn transfer_fee_rounds_down() {
+        let rt = fee_runtime(250);
+        // 2.5% of 39 is 0.975, and of 41 is 1.025.
+        transfer(&rt, send_params(BOB, 39)).unwrap();
+        transfer(&rt, send_params(BOB, 41)).unwrap();
+
+        let state = State::load_from(&rt).unwrap();
+        assert_eq!(balance(&rt.store, &state, BOB), TokenAmount::from(79));
+        assert_eq!(balance(&rt.store, &state, COLLECTOR), TokenAmount::from(1));
+    }
+
+    #[test]
+    fn transfer_fee_is_bounded_and_needs_a_collector() {
+        let rt = constructed_runtime();
+        rt.set_caller(OWNER);
+        let collector = Some(Address::new_id(COLLECTOR));
+        let too_high = SetTransferFeeParams { fee_bps: MAX_FEE_BPS + 1, fee_collector: collector };
+        let err = set_transfer_fee(&rt, too_high).unwrap_err();
+        assert_eq!(err.exit_code, ExitCode::USR_ILLEGAL_ARGUMENT);
+
+        let no_collector = SetTransferFeeParams { fee_bps: 1, fee_collector: None };
+        let err = set_transfer_fee(&rt, no_collector).unwrap_err();
+        assert_eq!(err.exit_code, ExitCode::USR_ILLEGAL_ARGUMENT);
+
+        rt.set_caller(ALICE);
+        let params = SetTransferFeeParams { fee_bps: 1, fee_collector: collector };
+        assert_eq!(set_transfer_fee(&rt, params).unwrap_err().exit_code, ExitCode::USR_FORBIDDEN);
+    }
+
+    #[test]
+    fn transfers_below_the_minimum_fail_but_mints_do_not() {
+        let rt = constructed_runtime();
+        rt.set_caller(OWNER);
+        let min_transfer = TokenAmount::from(10);
+        set_min_transfer(&rt, SetMinTransferParams { min_transfer }).unwrap();
+        mint(&rt, transfer_params(ALICE, 5)).unwrap();
+        mint(&rt, transfer_params(ALICE, 95)).unwrap();
+
+        rt.set_caller(ALICE);
+        let err = transfer(&rt, send_params(BOB, 9)).unwrap_err();
+        assert_eq!(err.exit_code, ExitCode::USR_ILLEGAL_ARGUMENT);
+        transfer(&rt, send_params(BOB, 10)).unwrap();
+
+        let state = State::load_from(&rt).unwrap();
+        assert_eq!(balance(&rt.store, &state, BOB), TokenAmount::from(10));
+
+        let min_transfer = TokenAmount::from(1);
+        let err = set_min_transfer(&rt, SetMinTransferParams { min_transfer }).unwrap_err();
+        assert_eq!(err.exit_code, ExitCode::USR_FORBIDDEN);
+    }
+
+    #[test]
+    fn failed_hook_reverts_fee_and_rate_limit() {
+        let rt = hooked_runtime();
+        rt.set_caller(OWNER);
+        mint(&rt, transfer_params(ALICE, 1000)).unwrap();
+        let fee_collector = Some(Address::new_id(COLLECTOR));
+        set_transfer_fee(&rt, SetTransferFeeParams { fee_bps: 100, fee_collector }).unwrap();
+        let limit = RateLimit { max_amount: TokenAmount::from(500), window_epochs: 10 };
+        set_rate_limit(&rt, SetRateLimitParams { limit: Some(limit) }).unwrap();
+        let root = rt.root().unwrap();
+
+        rt.set_code_cid(BOB, Cid::new_v1(0x55, Code::Identity.digest(b"hook-actor")));
+        rt.expect_send_exit_code(ExitCode::USR_ILLEGAL_ARGUMENT);
+        rt.set_caller(ALICE);
+        rt.apply(|rt| transfer(rt, send_params(BOB, 500))).unwrap_err();
+
+        assert_eq!(rt.root().unwrap(), root);
+        let state = State::load_from(&rt).unwrap();
+        assert_eq!(balance(&rt.store, &state, ALICE), TokenAmount::from(1000));
+        assert_eq!(balance(&rt.store, &state, COLLECTOR), TokenAmount::from(0));
+        assert_eq!(state.transfer_windows, None);
+
+        // Once the hook accepts, the whole window is still available.
+        rt.receipts.borrow_mut().clear();
+        transfer(&rt, send_params(BOB, 500)).unwrap();
+        let state = State::load_from(&rt).unwrap();
+        assert_eq!(balance(&rt.store, &state, BOB), TokenAmount::from(495));
+        assert_eq!(balance(&rt.store, &state, COLLECTOR), TokenAmount::from(5));
+    }
+}
@@ -0,0 +1,356 @@
+//! Method numbers used for dispatch.
+//!
+//! The constructor stays on method 1, as the Filecoin calling convention
+//! requires. Every other method number is the FRC42 hash of its exported name,
+//! so generic tooling can derive it without knowing about this actor. See
+//! https://github.com/filecoin-project/FIPs/blob/master/FRCs/frc-0042.md.
+
+use cid::multihash::{Code, MultihashDigest};
+use fvm_shared::MethodNum;
+
+/// InitActor#Exec calls the constructor on method 1.
+pub const CONSTRUCTOR: MethodNum = 1;
+/// `method_hash("Mint")`
+pub const MINT: MethodNum = 116935346;
+/// `method_hash("SetMaxSupply")`
+pub const SET_MAX_SUPPLY: MethodNum = 3618837927;
+/// `method_hash("Transfer")`
+pub const TRANSFER: MethodNum = 80475954;
+/// `method_hash("SplitBalance")`
+pub const SPLIT_BALANCE: MethodNum = 189307664;
+/// `method_hash("SetUnitRate")`
+pub const SET_UNIT_RATE: MethodNum = 3907466680;
+/// `method_hash("BalanceOfInUnit")`
+pub const BALANCE_OF_IN_UNIT: MethodNum = 609054766;
+/// `method_hash("ReadFields")`
+pub const READ_FIELDS: MethodNum = 3315778295;
+/// `method_hash("ConfigureGovernance")`
+pub const CONFIGURE_GOVERNANCE: MethodNum = 2231392660;
+/// `method_hash("Propose")`
+pub const PROPOSE: MethodNum = 1696838335;
+/// `method_hash("Vote")`
+pub const VOTE: MethodNum = 2621973148;
+/// `method_hash("Execute")`
+pub const EXECUTE: MethodNum = 1109989340;
+/// `method_hash("PendingOperation")`
+pub const PENDING_OPERATION: MethodNum = 703172230;
+/// `method_hash("Migrate")`
+pub const MIGRATE: MethodNum = 206449968;
+/// `method_hash("HolderCount")`
+pub const HOLDER_COUNT: MethodNum = 71664143;
+/// `method_hash("TransferWithMemo")`
+pub const TRANSFER_WITH_MEMO: MethodNum = 3726053177;
+/// `method_hash("SimulateTransfer")`
+pub const SIMULATE_TRANSFER: MethodNum = 446930648;
+/// `method_hash("Snapshot")`
+pub const SNAPSHOT: MethodNum = 2816149230;
+/// `method_hash("BalanceAtSnapshot")`
+pub const BALANCE_AT_SNAPSHOT: MethodNum = 3206937988;
+/// `method_hash("SetMetadataUri")`
+pub const SET_METADATA_URI: MethodNum = 2788445636;
+/// `method_hash("MetadataUri")`
+pub const METADATA_URI: MethodNum = 117076204;
+/// `method_hash("ForceTransfer")`
+pub const FORCE_TRANSFER: MethodNum = 2242978272;
+/// `method_hash("MintBatch")`
+pub const MINT_BATCH: MethodNum = 1946610960;
+/// `method_hash("VerifyInvariants")`
+pub const VERIFY_INVARIANTS: MethodNum = 2635411642;
+/// `method_hash("SetRateLimit")`
+pub const SET_RATE_LIMIT: MethodNum = 2116587901;
+/// `method_hash("Owner")`
+pub const OWNER: MethodNum = 739904285;
+/// `method_hash("TransferOwnership")`
+pub const TRANSFER_OWNERSHIP: MethodNum = 2916000231;
+/// `method_hash("AcceptOwnership")`
+pub const ACCEPT_OWNERSHIP: MethodNum = 4060905446;
+/// `method_hash("CancelOwnershipTransfer")`
+pub const CANCEL_OWNERSHIP_TRANSFER: MethodNum = 2933450757;
+/// `method_hash("SetTransferFee")`
+pub const SET_TRANSFER_FEE: MethodNum = 3186589917;
+/// `method_hash("Describe")`
+pub const DESCRIBE: MethodNum = 788585146;
+/// `method_hash("BalanceOf")`
+pub const BALANCE_OF: MethodNum = 2266030508;
+/// `method_hash("BalanceOfId")`
+pub const BALANCE_OF_ID: MethodNum = 2320292070;
+/// `method_hash("Rescue")`
+pub const RESCUE: MethodNum = 3813812875;
+/// `method_hash("RecomputeTotalSupply")`
+pub const RECOMPUTE_TOTAL_SUPPLY: MethodNum = 1819365458;
+/// `method_hash("TransferUntil")`
+pub const TRANSFER_UNTIL: MethodNum = 1325177240;
+/// `method_hash("ActorInfo")`
+pub const ACTOR_INFO: MethodNum = 3366811233;
+/// `method_hash("SetMinTransfer")`
+pub const SET_MIN_TRANSFER: MethodNum = 2083811049;
+/// `method_hash("TransferSplit")`
+pub const TRANSFER_SPLIT: MethodNum = 1301135228;
+/// `method_hash("RecentTransfers")`
+pub const RECENT_TRANSFERS: MethodNum = 778358789;
+/// `method_hash("MintIdempotent")`
+pub const MINT_IDEMPOTENT: MethodNum = 2489836762;
+/// `method_hash("TransferIdempotent")`
+pub const TRANSFER_IDEMPOTENT: MethodNum = 798327495;
+/// `method_hash("StateSummary")`
+pub const STATE_SUMMARY: MethodNum = 3173872774;
+/// `method_hash("SetHookAllowed")`
+pub const SET_HOOK_ALLOWED: MethodNum = 1600531445;
+/// `method_hash("Lock")`
+pub const LOCK: MethodNum = 339822128;
+/// `method_hash("CanReceive")`
+pub const CAN_RECEIVE: MethodNum = 90648343;
+/// `method_hash("SetMintSchedule")`
+pub const SET_MINT_SCHEDULE: MethodNum = 2879684370;
+/// `method_hash("SafeTransfer")`
+pub const SAFE_TRANSFER: MethodNum = 1395664107;
+/// `method_hash("SetOperator")`
+pub const SET_OPERATOR: MethodNum = 3064909134;
+/// `method_hash("IsOperator")`
+pub const IS_OPERATOR: MethodNum = 4239689161;
+/// `method_hash("TransferFrom")`
+pub const TRANSFER_FROM: MethodNum = 3621052141;
+/// `method_hash("Deposit")`
+pub const DEPOSIT: MethodNum = 1932531225;
+/// `method_hash("Withdraw")`
+pub const WITHDRAW: MethodNum = 854052278;
+/// `method_hash("SetClaimTerms")`
+pub const SET_CLAIM_TERMS: MethodNum = 2713555157;
+/// `method_hash("Claim")`
+pub const CLAIM: MethodNum = 4045527845;
+/// `method_hash("SetMaxHolders")`
+pub const SET_MAX_HOLDERS: MethodNum = 3833937462;
+/// `method_hash("Approve")`
+pub const APPROVE: MethodNum = 1289044053;
+/// `method_hash("Allowance")`
+pub const ALLOWANCE: MethodNum = 4205072950;
+/// `method_hash("Name")`
+pub const NAME: MethodNum = 48890204;
+/// `method_hash("Symbol")`
+pub const SYMBOL: MethodNum = 2061153854;
+/// `method_hash("Decimals")`
+pub const DECIMALS: MethodNum = 1567162231;
+/// `method_hash("TotalSupply")`
+pub const TOTAL_SUPPLY: MethodNum = 114981429;
+/// `method_hash("MaxSupply")`
+pub const MAX_SUPPLY: MethodNum = 1335595306;
+/// `method_hash("Burn")`
+pub const BURN: MethodNum = 1434719642;
+/// `method_hash("BurnFrom")`
+pub const BURN_FROM: MethodNum = 2979674018;
+/// `method_hash("IncreaseAllowance")`
+pub const INCREASE_ALLOWANCE: MethodNum = 1777121560;
+/// `method_hash("DecreaseAllowance")`
+pub const DECREASE_ALLOWANCE: MethodNum = 1529376545;
+/// `method_hash("RevokeAllowance")`
+pub const REVOKE_ALLOWANCE: MethodNum = 2765635761;
+/// `method_hash("Granularity")`
+pub const GRANULARITY: MethodNum = 3936767397;
+/// `method_hash("TransferWithReceipt")`
+pub const TRANSFER_WITH_RECEIPT: MethodNum = 3879079562;
+/// `method_hash("RenounceOwnership")`
+pub const RENOUNCE_OWNERSHIP: MethodNum = 3249398050;
+/// `method_hash("GrantRole")`
+pub const GRANT_ROLE: MethodNum = 3556308043;
+/// `method_hash("RevokeRole")`
+pub const REVOKE_ROLE: MethodNum = 580127286;
+/// `method_hash("HasRole")`
+pub const HAS_ROLE: MethodNum = 1001545894;
+/// `method_hash("Pause")`
+pub const PAUSE: MethodNum = 179840979;
+/// `method_hash("Unpause")`
+pub const UNPAUSE: MethodNum = 4283809567;
+/// `method_hash("FreezeAccount")`
+pub const FREEZE_ACCOUNT: MethodNum = 817544100;
+/// `method_hash("UnfreezeAccount")`
+pub const UNFREEZE_ACCOUNT: MethodNum = 4086559264;
+/// `method_hash("IsFrozen")`
+pub const IS_FROZEN: MethodNum = 1565939487;
+/// `method_hash("TransferBatch")`
+pub const TRANSFER_BATCH: MethodNum = 2077279054;
+/// `method_hash("Multicall")`
+pub const MULTICALL: MethodNum = 3042822052;
+/// `method_hash("BalanceIncludingPending")`
+pub const BALANCE_INCLUDING_PENDING: MethodNum = 1502767148;
+/// `method_hash("ReclaimUnclaimed")`
+pub const RECLAIM_UNCLAIMED: MethodNum = 1574545998;
+/// `method_hash("Permit")`
+pub const PERMIT: MethodNum = 4091876329;
+/// `method_hash("ApproveAndCall")`
+pub const APPROVE_AND_CALL: MethodNum = 2395043033;
+/// `method_hash("NonceOf")`
+pub const NONCE_OF: MethodNum = 3128078058;
+/// `method_hash("CompareAndSetAllowance")`
+pub const COMPARE_AND_SET_ALLOWANCE: MethodNum = 3447005734;
+/// `method_hash("AllowancesOf")`
+pub const ALLOWANCES_OF: MethodNum = 3054207629;
+/// `method_hash("TransferAndApprove")`
+pub const TRANSFER_AND_APPROVE: MethodNum = 1793363023;
+
+/// The FRC-0046 methods. Their return values go out bare, as the standard
+/// defines them, rather than in a `MethodReturn` envelope.
+pub const FRC46: &[MethodNum] = &[
+    NAME,
+    SYMBOL,
+    TOTAL_SUPPLY,
+    BALANCE_OF,
+    TRANSFER,
+    TRANSFER_FROM,
+    INCREASE_ALLOWANCE,
+    DECREASE_ALLOWANCE,
+    REVOKE_ALLOWANCE,
+    ALLOWANCE,
+    BURN,
+    BURN_FROM,
+    GRANULARITY,
+];
+
+/// Methods that return another actor's return data as they received it. It
+/// is already in the shape that actor chose, so it isn't wrapped again.
+pub const FORWARDING: &[MethodNum] = &[EXECUTE, APPROVE_AND_CALL];
+
+/// Whether `method` returns its value without the `MethodReturn` envelope.
+pub fn returns_bare(method: MethodNum) -> bool {
+    FRC46.contains(&method) || FORWARDING.contains(&method)
+}
+
+/// FRC42 numbers below this are reserved for builtin and legacy methods.
+const FIRST_METHOD_NUMBER: u32 = 1 << 24;
+
+/// Compute the FRC42 method number for an exported method name.
+///
+/// The name is prefixed with `1|` and hashed with blake2b-512; the first
+/// big-endian 4-byte chunk of the digest that isn't reserved is the method
+/// number. Returns None in the (astronomically unlikely) case that no chunk
+/// qualifies.
+pub fn method_hash(name: &str) -> Option<MethodNum> {
+    let hash = Code::Blake2b512.digest(format!("1|{}", name).as_bytes());
+    hash.digest()
+        .chunks(4)
+        .map(|chunk| u32::from_be_bytes([chunk[0], chunk[1], chunk[2], chunk[3]]))
+        .find(|n| *n >= FIRST_METHOD_NUMBER)
+        .map(MethodNum::from)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Every exported method, by the name its number is hashed from.
+    const EXPORTED: &[(&str, MethodNum)] = &[
+        ("Mint", MINT),
+        ("SetMaxSupply", SET_MAX_SUPPLY),
+        ("Transfer", TRANSFER),
+        ("SplitBalance", SPLIT_BALANCE),
+        ("SetUnitRate", SET_UNIT_RATE),
+        ("BalanceOfInUnit", BALANCE_OF_IN_UNIT),
+        ("ReadFields", READ_FIELDS),
+        ("ConfigureGovernance", CONFIGURE_GOVERNANCE),
+        ("Propose", PROPOSE),
+        ("Vote", VOTE),
+        ("Execute", EXECUTE),
+        ("PendingOperation", PENDING_OPERATION),
+        ("Migrate", MIGRATE),
+        ("HolderCount", HOLDER_COUNT),
+        ("TransferWithMemo", TRANSFER_WITH_MEMO),
+        ("SimulateTransfer", SIMULATE_TRANSFER),
+        ("Snapshot", SNAPSHOT),
+        ("BalanceAtSnapshot", BALANCE_AT_SNAPSHOT),
+        ("SetMetadataUri", SET_METADATA_URI),
+        ("MetadataUri", METADATA_URI),
+        ("ForceTransfer", FORCE_TRANSFER),
+        ("MintBatch", MINT_BATCH),
+        ("VerifyInvariants", VERIFY_INVARIANTS),
+        ("SetRateLimit", SET_RATE_LIMIT),
+        ("Owner", OWNER),
+        ("TransferOwnership", TRANSFER_OWNERSHIP),
+        ("AcceptOwnership", ACCEPT_OWNERSHIP),
+        ("CancelOwnershipTransfer", CANCEL_OWNERSHIP_TRANSFER),
+        ("SetTransferFee", SET_TRANSFER_FEE),
+        ("Describe", DESCRIBE),
+        ("BalanceOf", BALANCE_OF),
+        ("BalanceOfId", BALANCE_OF_ID),
+        ("Rescue", RESCUE),
+        ("RecomputeTotalSupply", RECOMPUTE_TOTAL_SUPPLY),
+        ("TransferUntil", TRANSFER_UNTIL),
+        ("ActorInfo", ACTOR_INFO),
+        ("SetMinTransfer", SET_MIN_TRANSFER),
+        ("TransferSplit", TRANSFER_SPLIT),
+        ("RecentTransfers", RECENT_TRANSFERS),
+        ("MintIdempotent", MINT_IDEMPOTENT),
+        ("TransferIdempotent", TRANSFER_IDEMPOTENT),
+        ("StateSummary", STATE_SUMMARY),
+        ("SetHookAllowed", SET_HOOK_ALLOWED),
+        ("Lock", LOCK),
+        ("CanReceive", CAN_RECEIVE),
+        ("SetMintSchedule", SET_MINT_SCHEDULE),
+        ("SafeTransfer", SAFE_TRANSFER),
+        ("SetOperator", SET_OPERATOR),
+        ("IsOperator", IS_OPERATOR),
+        ("TransferFrom", TRANSFER_FROM),
+        ("Deposit", DEPOSIT),
+        ("Withdraw", WITHDRAW),
+        ("SetClaimTerms", SET_CLAIM_TERMS),
+        ("Claim", CLAIM),
+        ("SetMaxHolders", SET_MAX_HOLDERS),
+        ("Approve", APPROVE),
+        ("Allowance", ALLOWANCE),
+        ("Name", NAME),
+        ("Symbol", SYMBOL),
+        ("Decimals", DECIMALS),
+        ("TotalSupply", TOTAL_SUPPLY),
+        ("MaxSupply", MAX_SUPPLY),
+        ("Burn", BURN),
+        ("BurnFrom", BURN_FROM),
+        ("IncreaseAllowance", INCREASE_ALLOWANCE),
+        ("DecreaseAllowance", DECREASE_ALLOWANCE),
+        ("RevokeAllowance", REVOKE_ALLOWANCE),
+        ("Granularity", GRANULARITY),
+        ("TransferWithReceipt", TRANSFER_WITH_RECEIPT),
+        ("RenounceOwnership", RENOUNCE_OWNERSHIP),
+        ("GrantRole", GRANT_ROLE),
+        ("RevokeRole", REVOKE_ROLE),
+        ("HasRole", HAS_ROLE),
+        ("Pause", PAUSE),
+        ("Unpause", UNPAUSE),
+        ("FreezeAccount", FREEZE_ACCOUNT),
+        ("UnfreezeAccount", UNFREEZE_ACCOUNT),
+        ("IsFrozen", IS_FROZEN),
+        ("TransferBatch", TRANSFER_BATCH),
+        ("Multicall", MULTICALL),
+        ("BalanceIncludingPending", BALANCE_INCLUDING_PENDING),
+        ("ReclaimUnclaimed", RECLAIM_UNCLAIMED),
+        ("Permit", PERMIT),
+        ("ApproveAndCall", APPROVE_AND_CALL),
+        ("NonceOf", NONCE_OF),
+        ("CompareAndSetAllowance", COMPARE_AND_SET_ALLOWANCE),
+        ("AllowancesOf", ALLOWANCES_OF),
+        ("TransferAndApprove", TRANSFER_AND_APPROVE),
+    ];
+
+    #[test]
+    fn method_numbers_match_their_names() {
+        for (name, number) in EXPORTED {
+            assert_eq!(method_hash(name), Some(*number), "{}", name);
+        }
+    }
+
+    #[test]
+    fn only_standard_and_forwarding_methods_return_bare() {
+        for number in [TRANSFER, BALANCE_OF, GRANULARITY, EXECUTE, APPROVE_AND_CALL] {
+            assert!(returns_bare(number), "{}", number);
+        }
+        for number in [CONSTRUCTOR, MINT, READ_FIELDS, TRANSFER_AND_APPROVE, DESCRIBE] {
+            assert!(!returns_bare(number), "{}", number);
+        }
+    }
+
+    #[test]
+    fn method_numbers_do_not_collide() {
+        let mut numbers: Vec<MethodNum> = EXPORTED.iter().map(|(_, n)| *n).collect();
+        numbers.push(CONSTRUCTOR);
+        numbers.sort_unstable();
+        numbers.dedup();
+        assert_eq!(numbers.len(), EXPORTED.len() + 1);
+    }
+}
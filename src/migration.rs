@@ -0,0 +1,209 @@
+//! State layout versions and the migrations between them.
+//!
+//! Every layout from version 1 on starts with its version number, while the
+//! unversioned layout (version 0) starts with the token name, so a stored state
+//! decodes as at most one of them. Versioned layouts only ever grow by fields
+//! appended with `#[serde(default)]`, so any of them decodes as `State`, and
+//! upgrading one means filling in the fields added since its version.
+
+use cid::Cid;
+use fvm_ipld_encoding::tuple::{Deserialize_tuple, Serialize_tuple};
+use fvm_ipld_encoding::from_slice;
+use fvm_shared::address::Address;
+use fvm_shared::bigint::bigint_ser;
+use fvm_shared::econ::TokenAmount;
+
+use crate::error::ActorError;
+use crate::governance::Governance;
+use crate::runtime::Runtime;
+use crate::{
+    count_holders, load_balances, runtime_context, SecondaryUnit, State, DEFAULT_DECIMALS,
+    DEFAULT_HAMT_BIT_WIDTH, STATE_VERSION,
+};
+
+/// The state layout before versioning: the version 1 fields, minus `version`.
+/// Upgrading it yields a version 1 state.
+#[derive(Serialize_tuple, Deserialize_tuple, Clone, Debug)]
+pub struct StateV0 {
+    pub name: String,
+    pub symbol: String,
+    #[serde(with = "bigint_ser")]
+    pub max_supply: TokenAmount,
+    #[serde(with = "bigint_ser")]
+    pub total_supply: TokenAmount,
+    pub owner: Address,
+    pub balances: Cid,
+    pub supply_oracle: Option<Address>,
+    pub reentrancy_locked: bool,
+    pub secondary_unit: Option<SecondaryUnit>,
+    pub governance: Option<Governance>,
+    pub executing_proposal: Option<u64>,
+}
+
+impl From<StateV0> for State {
+    fn from(s: StateV0) -> Self {
+        State {
+            version: 1,
+            name: s.name,
+            symbol: s.symbol,
+            max_supply: s.max_supply,
+            total_supply: s.total_supply,
+            owner: s.owner,
+            balances: s.balances,
+            supply_oracle: s.supply_oracle,
+            reentrancy_locked: s.reentrancy_locked,
+            secondary_unit: s.secondary_unit,
+            governance: s.governance,
+            executing_proposal: s.executing_proposal,
+            holder_count: 0,
+            hamt_bit_width: DEFAULT_HAMT_BIT_WIDTH,
+            snapshots: Vec::new(),
+            metadata_uri: None,
+            require_receiver_hook: false,
+            rate_limit: None,
+            transfer_windows: None,
+            pending_owner: None,
+            fee_bps: 0,
+            fee_collector: None,
+            pending_total_supply: None,
+            min_transfer: TokenAmount::from(0),
+            recent_transfers: None,
+            used_keys: None,
+            hook_allowlist: None,
+            locks: None,
+            mint_schedule: Vec::new(),
+            operators: None,
+            owner_id: None,
+            wrapped: false,
+            claim_terms: None,
+            claimed: None,
+            max_holders: None,
+            allowances: None,
+            decimals: DEFAULT_DECIMALS,
+            ownership_renounced: false,
+            roles: None,
+            paused: false,
+            frozen: None,
+            claim_deadline: None,
+            nonces: None,
+            approved_call: None,
+        }
+    }
+}
+
+/// A stored state in any of the layouts this code can read.
+#[derive(Clone, Debug)]
+pub enum VersionedState {
+    V0(StateV0),
+    /// A versioned state, possibly older than `STATE_VERSION`.
+    Versioned(State),
+}
+
+impl VersionedState {
+    /// Load and decode the state stored at `root`.
+    pub fn load<BS: fvm_ipld_blockstore::Blockstore>(
+        store: &BS,
+        root: &Cid,
+    ) -> Result<Self, ActorError> {
+        match store.get(root) {
+            Ok(Some(bytes)) => Self::decode(&bytes),
+            Ok(None) => Err(actor_error!(
+                kind: MissingStateRoot,
+                USR_ILLEGAL_STATE,
+                "state root {} does not exist",
+                root
+            )),
+            Err(err) => Err(actor_error!(USR_ILLEGAL_STATE, "failed to get state: {}", err)),
+        }
+    }
+
+    /// Decode a serialized state, detecting its layout.
+    pub fn decode(bytes: &[u8]) -> Result<Self, ActorError> {
+        if let Ok(state) = from_slice::<State>(bytes) {
+            if state.version > STATE_VERSION {
+                return Err(actor_error!(
+                    USR_ILLEGAL_STATE,
+                    "unsupported state version {}",
+                    state.version
+                ));
+            }
+            return Ok(VersionedState::Versioned(state));
+        }
+        match from_slice::<StateV0>(bytes) {
+            Ok(state) => Ok(VersionedState::V0(state)),
+            Err(err) => Err(actor_error!(USR_ILLEGAL_STATE, "failed to decode state: {}", err)),
+        }
+    }
+
+    /// The layout version of the stored state.
+    pub fn version(&self) -> u32 {
+        match self {
+            VersionedState::V0(_) => 0,
+            VersionedState::Versioned(state) => state.version,
+        }
+    }
+
+    /// Convert the state to the current layout, filling in any new fields one
+    /// version at a time.
+    pub fn upgrade<BS: fvm_ipld_blockstore::Blockstore>(
+        self,
+        store: &BS,
+    ) -> Result<State, ActorError> {
+        let mut state = match self {
+            VersionedState::V0(state) => state.into(),
+            VersionedState::Versioned(state) => state,
+        };
+
+        // Version 2 added the holder count.
+        if state.version < 2 {
+            let balances = load_balances(store, &state.balances, state.hamt_bit_width)?;
+            state.holder_count = count_holders(&balances)?;
+            state.version = 2;
+        }
+
+        // Versions 3 to 5 added the balances HAMT bit width, snapshots and the
+        // metadata URI. Their serde defaults already match older tokens: those
+        // HAMTs all have the default bit width, and there is nothing to fill in.
+        // Version 6 made receiver hooks optional. Older tokens always called
+        // them, so they keep doing so.
+        if state.version < 6 {
+            state.require_receiver_hook = true;
+            state.version = 6;
+        }
+
+        // Versions 7 to 26 only added fields whose serde defaults leave older
+        // tokens behaving as before. Rate limits, the pending owner, fees,
+        // recounts, minimum transfers, recent transfers, idempotency keys,
+        // allowlists, locks, mint schedules, operator approvals, wrapping,
+        // claims, the holder cap, allowances, the renounced flag, roles, pausing
+        // and freezing all start out off or empty. Decimals default to
+        // DEFAULT_DECIMALS, like FIL. The owner's ID is left unset, since there
+        // is no runtime to resolve it with here, so it is resolved from its
+        // address until ownership next changes hands.
+        state.version = STATE_VERSION;
+
+        Ok(state)
+    }
+}
+
+/// Rewrite an older stored state in the current layout. Only the owner may
+/// call this, and it fails if the state is already current.
+pub fn migrate<R: Runtime>(rt: &R) -> Result<(), ActorError> {
+    let versioned = VersionedState::load(rt.store(), &rt.root()?)?;
+    if versioned.version() == STATE_VERSION {
+        return Err(actor_error!(
+            USR_ILLEGAL_STATE,
+            "state is already at version {}",
+            STATE_VERSION
+        ));
+    }
+
+    let state = versioned.upgrade(rt.store())?;
+    state.check_unlocked()?;
+
+    let resolver = |addr: &Address| rt.resolve_address(addr);
+    runtime_context(rt, &resolver).require_owner(&state, "migrate")?;
+
+    state.save_to(rt)?;
+    Ok(())
+}
@@ -0,0 +1,129 @@
+//! Several token methods in one message, all or nothing.
+//!
+//! `multicall` runs each call in order as the message's caller, so e.g. an
+//! approval and the `transfer_from` that spends it, or a mint and a transfer
+//! of the minted tokens, land together. The calls share one copy of the state,
+//! so later calls see what earlier ones did, and it is saved once after the
+//! last of them. If any call fails, the multicall fails with that call's
+//! error and the message aborts, which discards every change the calls made.
+//!
+//! Only the methods `call` dispatches can be batched:
+//!
+//! | Method            | Returns              |
+//! |-------------------|----------------------|
+//! | Mint              | `bigint`             |
+//! | Transfer          | `TransferReturn`     |
+//! | TransferFrom      | `TransferFromReturn` |
+//! | TransferBatch     |                      |
+//! | Burn              |                      |
+//! | BurnFrom          |                      |
+//! | Approve           |                      |
+//! | IncreaseAllowance |                      |
+//! | DecreaseAllowance |                      |
+//! | RevokeAllowance   |                      |
+//! | SetOperator       |                      |
+
+use fvm_ipld_encoding::tuple::{Deserialize_tuple, Serialize_tuple};
+use fvm_ipld_encoding::{Cbor, RawBytes};
+use fvm_shared::bigint::bigint_ser::BigIntSer;
+use fvm_shared::MethodNum;
+use serde::de::DeserializeOwned;
+use serde::Serialize;
+
+use crate::allowance::{
+    approve_in, decrease_allowance_in, increase_allowance_in, revoke_allowance_in, set_operator_in,
+};
+use crate::error::ActorError;
+use crate::runtime::Runtime;
+use crate::{
+    burn_from_in, burn_in, check_non_negative, decode_params, method, mint_in, transfer_batch_in,
+    transfer_from_in, transfer_in, State, TokenAmounts,
+};
+
+/// One call in a multicall: a method number and its encoded params.
+#[derive(Serialize_tuple, Deserialize_tuple, Clone, Debug)]
+pub struct Call {
+    pub method: MethodNum,
+    pub params: RawBytes,
+}
+
+/// The input parameters for a multicall. Each call encodes as a
+/// `(method, params)` pair.
+#[derive(Serialize_tuple, Deserialize_tuple, Clone, Debug)]
+pub struct MulticallParams {
+    pub calls: Vec<Call>,
+}
+
+impl Cbor for MulticallParams {}
+
+fn decode_amount_params<T: DeserializeOwned + TokenAmounts>(
+    params: &RawBytes,
+) -> Result<T, ActorError> {
+    let params = decode_params(params.bytes())?;
+    check_non_negative(&params)?;
+    Ok(params)
+}
+
+fn encode_return<T: Serialize>(value: &T) -> Result<RawBytes, ActorError> {
+    RawBytes::serialize(value).map_err(|err| {
+        actor_error!(USR_SERIALIZATION, "failed to serialize return value: {:?}", err)
+    })
+}
+
+/// Run one call against `state`, returning what the method returns on its
+/// own, or empty bytes if it returns nothing.
+fn call<R: Runtime>(rt: &R, state: &mut State, entry: &Call) -> Result<RawBytes, ActorError> {
+    let params = &entry.params;
+    let none = RawBytes::default();
+    match entry.method {
+        method::MINT => {
+            let supply = mint_in(rt, state, decode_amount_params(params)?, None)?;
+            encode_return(&BigIntSer(&supply))
+        }
+        method::TRANSFER => encode_return(&transfer_in(rt, state, decode_amount_params(params)?)?),
+        method::TRANSFER_FROM => {
+            encode_return(&transfer_from_in(rt, state, decode_amount_params(params)?)?)
+        }
+        method::TRANSFER_BATCH => {
+            transfer_batch_in(rt, state, decode_amount_params(params)?).map(|_| none)
+        }
+        method::BURN => burn_in(rt, state, decode_amount_params(params)?).map(|_| none),
+        method::BURN_FROM => burn_from_in(rt, state, decode_amount_params(params)?).map(|_| none),
+        method::APPROVE => approve_in(rt, state, decode_amount_params(params)?).map(|_| none),
+        method::INCREASE_ALLOWANCE => {
+            increase_allowance_in(rt, state, decode_amount_params(params)?).map(|_| none)
+        }
+        method::DECREASE_ALLOWANCE => {
+            decrease_allowance_in(rt, state, decode_amount_params(params)?).map(|_| none)
+        }
+        method::REVOKE_ALLOWANCE => {
+            revoke_allowance_in(rt, state, decode_params(params.bytes())?).map(|_| none)
+        }
+        method::SET_OPERATOR => {
+            set_operator_in(rt, state, decode_params(params.bytes())?).map(|_| none)
+        }
+        other => Err(actor_error!(USR_ILLEGAL_ARGUMENT, "method {} can't be multicalled", other)),
+    }
+}
+
+/// Run every call in order, or none of them, returning each call's return
+/// value. Fails with the first failing call's error, unchanged, so its kind
+/// still decodes.
+pub fn multicall<R: Runtime>(
+    rt: &R,
+    params: MulticallParams,
+) -> Result<Vec<RawBytes>, ActorError> {
+    if params.calls.is_empty() {
+        return Err(actor_error!(USR_ILLEGAL_ARGUMENT, "a multicall needs at least one call"));
+    }
+    let mut state = State::load_from(rt)?;
+    state.check_unlocked()?;
+
+    let results = params
+        .calls
+        .iter()
+        .map(|entry| call(rt, &mut state, entry))
+        .collect::<Result<Vec<_>, _>>()?;
+    state.save_to(rt)?;
+    Ok(results)
+}
@@ -0,0 +1,937 @@
+//! Builders for method parameters.
+//!
+//! Each builder checks its input against the same rules the actor applies and
+//! returns the CBOR-encoded params, ready to put in a message. They run
+//! off-chain, so they report failures as errors instead of aborting.
+
+use anyhow::{anyhow, Result};
+use fvm_ipld_encoding::RawBytes;
+use fvm_shared::address::Address;
+use fvm_shared::clock::ChainEpoch;
+use fvm_shared::econ::TokenAmount;
+use fvm_shared::{ActorID, MethodNum};
+use serde::Serialize;
+
+use crate::access_control::{Role, RoleParams};
+use crate::allowance::{
+    AllowanceParams, AllowancesOfParams, ApproveAndCallParams, ApproveParams,
+    CompareAndSetAllowanceParams, DecreaseAllowanceParams, IncreaseAllowanceParams,
+    IsOperatorParams, RevokeAllowanceParams, SetOperatorParams, TransferAndApproveParams,
+};
+use crate::claim::{BalanceIncludingPendingParams, SetClaimTermsParams};
+use crate::error::ActorError;
+use crate::governance::{
+    validate_config, ConfigureGovernanceParams, ProposalIdParams, ProposeParams, Voter,
+};
+use crate::multicall::{Call, MulticallParams};
+use crate::permit::{NonceOfParams, PermitParams};
+use crate::wrap::WithdrawParams;
+use crate::{
+    require_positive, validate_hamt_bit_width, validate_memo, validate_metadata_uri,
+    validate_mint_schedule, validate_rate_limit, validate_split_weights, validate_transfer_fee,
+    BalanceAtSnapshotParams, BalanceOfIdParams, BalanceOfInUnitParams, BurnFromParams, BurnParams,
+    CanReceiveParams, ConstructorParams, ForceTransferParams, Frc46TransferParams,
+    FreezeAccountParams, IdempotencyKey, IdempotentTransferParams, LockParams, MintBatchParams,
+    MintWindowParams, PendingOperationParams, RateLimit, ReadFieldsParams, RecentTransfersParams,
+    RescueParams, SecondaryUnit, SetHookAllowedParams, SetMaxHoldersParams, SetMaxSupplyParams,
+    SetMetadataUriParams, SetMinTransferParams, SetMintScheduleParams, SetRateLimitParams,
+    SetTransferFeeParams, SplitBalanceParams, SplitTarget, TransferBatchParams, TransferFromParams,
+    TransferMemoParams, TransferOwnershipParams, TransferParams, TransferSplitParams,
+    TransferUntilParams, DEFAULT_DECIMALS, DEFAULT_HAMT_BIT_WIDTH,
+};
+
+fn encode<T: Serialize>(params: &T) -> Result<RawBytes> {
+    RawBytes::serialize(params).map_err(|err| anyhow!("failed to encode params: {:?}", err))
+}
+
+fn check(result: Result<(), ActorError>) -> Result<()> {
+    result.map_err(|err| anyhow!(err.msg))
+}
+
+/// Builds the constructor params. Everything but the name, symbol, supply cap
+/// and owner is optional, the HAMT bit width defaults to
+/// `DEFAULT_HAMT_BIT_WIDTH`, decimals default to `DEFAULT_DECIMALS`, and
+/// receiver hooks and wrapping are off unless requested.
+#[derive(Clone, Debug)]
+pub struct ConstructorParamsBuilder {
+    name: String,
+    symbol: String,
+    max_supply: TokenAmount,
+    owner: Address,
+    supply_oracle: Option<Address>,
+    hamt_bitwidth: u32,
+    metadata_uri: Option<String>,
+    require_receiver_hook: bool,
+    wrapped: bool,
+    decimals: u8,
+}
+
+impl ConstructorParamsBuilder {
+    pub fn new(name: &str, symbol: &str, max_supply: TokenAmount, owner: Address) -> Self {
+        ConstructorParamsBuilder {
+            name: name.to_string(),
+            symbol: symbol.to_string(),
+            max_supply,
+            owner,
+            supply_oracle: None,
+            hamt_bitwidth: DEFAULT_HAMT_BIT_WIDTH,
+            metadata_uri: None,
+            require_receiver_hook: true,
+            wrapped: false,
+            decimals: DEFAULT_DECIMALS,
+        }
+    }
+
+    pub fn supply_oracle(mut self, oracle: Address) -> Self {
+        self.supply_oracle = Some(oracle);
+        self
+    }
+
+    pub fn hamt_bitwidth(mut self, bit_width: u32) -> Self {
+        self.hamt_bitwidth = bit_width;
+        self
+    }
+
+    pub fn metadata_uri(mut self, uri: &str) -> Self {
+        self.metadata_uri = Some(uri.to_string());
+        self
+    }
+
+    /// Credit contract recipients without calling their receiver hook. Hooks
+    /// are called unless this is set.
+    pub fn without_receiver_hooks(mut self) -> Self {
+        self.require_receiver_hook = false;
+        self
+    }
+
+    /// Make this a wrapped token, minted by depositing FIL.
+    pub fn wrapped(mut self) -> Self {
+        self.wrapped = true;
+        self
+    }
+
+    pub fn decimals(mut self, decimals: u8) -> Self {
+        self.decimals = decimals;
+        self
+    }
+
+    /// Check the params and return them.
+    pub fn build(self) -> Result<ConstructorParams> {
+        check(validate_hamt_bit_width(self.hamt_bitwidth))?;
+        if let Some(uri) = &self.metadata_uri {
+            check(validate_metadata_uri(uri))?;
+        }
+        Ok(ConstructorParams {
+            name: self.name,
+            symbol: self.symbol,
+            max_supply: self.max_supply,
+            owner: self.owner,
+            supply_oracle: self.supply_oracle,
+            hamt_bitwidth: self.hamt_bitwidth,
+            metadata_uri: self.metadata_uri,
+            require_receiver_hook: self.require_receiver_hook,
+            wrapped: self.wrapped,
+            decimals: self.decimals,
+        })
+    }
+
+    /// Check the params and encode them.
+    pub fn encode(self) -> Result<RawBytes> {
+        encode(&self.build()?)
+    }
+}
+
+/// Encodes the params of every other method.
+pub struct ParamsBuilder;
+
+impl ParamsBuilder {
+    pub fn constructor(
+        name: &str,
+        symbol: &str,
+        max_supply: TokenAmount,
+        owner: Address,
+    ) -> ConstructorParamsBuilder {
+        ConstructorParamsBuilder::new(name, symbol, max_supply, owner)
+    }
+
+    pub fn mint(recipient: Address, amount: TokenAmount) -> Result<RawBytes> {
+        check(require_positive(&amount))?;
+        encode(&TransferParams { recipient, amount })
+    }
+
+    /// The params for `Transfer`. `operator_data` is handed to the
+    /// recipient's receiver hook.
+    pub fn transfer(to: Address, amount: TokenAmount, operator_data: RawBytes) -> Result<RawBytes> {
+        check(require_positive(&amount))?;
+        encode(&Frc46TransferParams { to, amount, operator_data })
+    }
+
+    /// The params for `TransferWithReceipt`, which are those of `Transfer`.
+    pub fn transfer_with_receipt(
+        to: Address,
+        amount: TokenAmount,
+        operator_data: RawBytes,
+    ) -> Result<RawBytes> {
+        Self::transfer(to, amount, operator_data)
+    }
+
+    pub fn transfer_with_memo(
+        recipient: Address,
+        amount: TokenAmount,
+        memo: Vec<u8>,
+    ) -> Result<RawBytes> {
+        check(require_positive(&amount))?;
+        check(validate_memo(&memo))?;
+        encode(&TransferMemoParams { recipient, amount, memo })
+    }
+
+    /// The params for `TransferUntil`, which fails if it runs after
+    /// `deadline`.
+    pub fn transfer_until(
+        recipient: Address,
+        amount: TokenAmount,
+        deadline: ChainEpoch,
+    ) -> Result<RawBytes> {
+        check(require_positive(&amount))?;
+        encode(&TransferUntilParams { recipient, amount, deadline })
+    }
+
+    /// The params for `MintIdempotent` and `TransferIdempotent`, which reject
+    /// a repeat of `idempotency_key`.
+    pub fn idempotent_transfer(
+        recipient: Address,
+        amount: TokenAmount,
+        idempotency_key: Option<IdempotencyKey>,
+    ) -> Result<RawBytes> {
+        check(require_positive(&amount))?;
+        encode(&IdempotentTransferParams { recipient, amount, idempotency_key })
+    }
+
+    pub fn mint_batch(mints: Vec<(Address, TokenAmount)>) -> Result<RawBytes> {
+        let mut entries = Vec::with_capacity(mints.len());
+        for (recipient, amount) in mints {
+            check(require_positive(&amount))?;
+            entries.push(TransferParams { recipient, amount });
+        }
+        encode(&MintBatchParams { mints: entries })
+    }
+
+    pub fn force_transfer(from: Address, to: Address, amount: TokenAmount) -> Result<RawBytes> {
+        check(require_positive(&amount))?;
+        encode(&ForceTransferParams { from, to, amount })
+    }
+
+    pub fn transfer_from(
+        from: Address,
+        to: Address,
+        amount: TokenAmount,
+        operator_data: RawBytes,
+    ) -> Result<RawBytes> {
+        check(require_positive(&amount))?;
+        encode(&TransferFromParams { from, to, amount, operator_data })
+    }
+
+    /// The params for `Approve`. A zero amount revokes the allowance, and
+    /// `infinite_allowance()` grants one that is never drawn down.
+    pub fn approve(spender: Address, amount: TokenAmount) -> Result<RawBytes> {
+        if amount < TokenAmount::from(0) {
+            return Err(anyhow!("allowance {} must not be negative", amount));
+        }
+        encode(&ApproveParams { spender, amount })
+    }
+
+    /// The params for `Permit`. `signature` is the owner's, type byte first,
+    /// over the CBOR of the matching `PermitMessage`.
+    pub fn permit(
+        owner: Address,
+        spender: Address,
+        amount: TokenAmount,
+        deadline: ChainEpoch,
+        signature: Vec<u8>,
+    ) -> Result<RawBytes> {
+        if amount < TokenAmount::from(0) {
+            return Err(anyhow!("allowance {} must not be negative", amount));
+        }
+        encode(&PermitParams { owner, spender, amount, deadline, signature })
+    }
+
+    /// The params for `ApproveAndCall`, which calls `call_method` with
+    /// `call_params` on the spender once it is approved.
+    pub fn approve_and_call(
+        spender: Address,
+        amount: TokenAmount,
+        call_method: MethodNum,
+        call_params: RawBytes,
+    ) -> Result<RawBytes> {
+        if amount < TokenAmount::from(0) {
+            return Err(anyhow!("allowance {} must not be negative", amount));
+        }
+        encode(&ApproveAndCallParams { spender, amount, call_method, call_params })
+    }
+
+    pub fn compare_and_set_allowance(
+        spender: Address,
+        expected: TokenAmount,
+        new: TokenAmount,
+    ) -> Result<RawBytes> {
+        if expected < TokenAmount::from(0) || new < TokenAmount::from(0) {
+            return Err(anyhow!("allowances must not be negative"));
+        }
+        encode(&CompareAndSetAllowanceParams { spender, expected, new })
+    }
+
+    pub fn allowances_of(
+        owner: Address,
+        start_after: Option<ActorID>,
+        limit: u32,
+    ) -> Result<RawBytes> {
+        if limit == 0 {
+            return Err(anyhow!("page limit must be positive"));
+        }
+        encode(&AllowancesOfParams { owner, start_after, limit })
+    }
+
+    /// The params for `TransferAndApprove`, which transfers to `recipient`
+    /// and sets the caller's allowance for `grant_spender`.
+    pub fn transfer_and_approve(
+        recipient: Address,
+        amount: TokenAmount,
+        grant_spender: Address,
+        grant_amount: TokenAmount,
+    ) -> Result<RawBytes> {
+        check(require_positive(&amount))?;
+        if grant_amount < TokenAmount::from(0) {
+            return Err(anyhow!("allowance {} must not be negative", grant_amount));
+        }
+        encode(&TransferAndApproveParams { recipient, amount, grant_spender, grant_amount })
+    }
+
+    pub fn nonce_of(owner: Address) -> Result<RawBytes> {
+        encode(&NonceOfParams { owner })
+    }
+
+    pub fn increase_allowance(spender: Address, increase: TokenAmount) -> Result<RawBytes> {
+        if increase < TokenAmount::from(0) {
+            return Err(anyhow!("allowance increase {} must not be negative", increase));
+        }
+        encode(&IncreaseAllowanceParams { spender, increase })
+    }
+
+    pub fn decrease_allowance(spender: Address, decrease: TokenAmount) -> Result<RawBytes> {
+        if decrease < TokenAmount::from(0) {
+            return Err(anyhow!("allowance decrease {} must not be negative", decrease));
+        }
+        encode(&DecreaseAllowanceParams { spender, decrease })
+    }
+
+    pub fn revoke_allowance(spender: Address) -> Result<RawBytes> {
+        encode(&RevokeAllowanceParams { spender })
+    }
+
+    pub fn allowance(owner: Address, spender: Address) -> Result<RawBytes> {
+        encode(&AllowanceParams { owner, spender })
+    }
+
+    /// The params for `SetOperator`, approving `operator` or revoking it.
+    pub fn set_operator(operator: Address, approved: bool) -> Result<RawBytes> {
+        encode(&SetOperatorParams { operator, approved })
+    }
+
+    pub fn is_operator(owner: Address, operator: Address) -> Result<RawBytes> {
+        encode(&IsOperatorParams { owner, operator })
+    }
+
+    pub fn grant_role(role: Role, account: Address) -> Result<RawBytes> {
+        encode(&RoleParams { role: role as u8, account })
+    }
+
+    pub fn revoke_role(role: Role, account: Address) -> Result<RawBytes> {
+        encode(&RoleParams { role: role as u8, account })
+    }
+
+    pub fn has_role(role: Role, account: Address) -> Result<RawBytes> {
+        encode(&RoleParams { role: role as u8, account })
+    }
+
+    pub fn freeze_account(account: Address) -> Result<RawBytes> {
+        encode(&FreezeAccountParams { account })
+    }
+
+    pub fn unfreeze_account(account: Address) -> Result<RawBytes> {
+        encode(&FreezeAccountParams { account })
+    }
+
+    pub fn is_frozen(account: Address) -> Result<RawBytes> {
+        encode(&FreezeAccountParams { account })
+    }
+
+    /// The params for `SetMaxHolders`. None clears the cap.
+    pub fn set_max_holders(max_holders: Option<u64>) -> Result<RawBytes> {
+        encode(&SetMaxHoldersParams { max_holders })
+    }
+
+    /// The params for `SetClaimTerms`. A zero amount turns claiming off.
+    pub fn set_claim_terms(
+        pool: Address,
+        amount: TokenAmount,
+        deadline: Option<ChainEpoch>,
+    ) -> Result<RawBytes> {
+        if amount < TokenAmount::from(0) {
+            return Err(anyhow!("claim amount {} must not be negative", amount));
+        }
+        encode(&SetClaimTermsParams { pool, amount, deadline })
+    }
+
+    pub fn withdraw(amount: TokenAmount) -> Result<RawBytes> {
+        check(require_positive(&amount))?;
+        encode(&WithdrawParams { amount })
+    }
+
+    pub fn burn(amount: TokenAmount) -> Result<RawBytes> {
+        check(require_positive(&amount))?;
+        encode(&BurnParams { amount })
+    }
+
+    pub fn burn_from(owner: Address, amount: TokenAmount) -> Result<RawBytes> {
+        check(require_positive(&amount))?;
+        encode(&BurnFromParams { owner, amount })
+    }
+
+    pub fn rescue(to: Address) -> Result<RawBytes> {
+        encode(&RescueParams { to })
+    }
+
+    pub fn set_max_supply(new_max: TokenAmount) -> Result<RawBytes> {
+        if new_max < TokenAmount::from(0) {
+            return Err(anyhow!("max supply {} must not be negative", new_max));
+        }
+        encode(&SetMaxSupplyParams { new_max })
+    }
+
+    /// The params for `SetMintSchedule`, from `(start_epoch, end_epoch,
+    /// max_in_window)` entries. An empty schedule lifts every restriction.
+    pub fn set_mint_schedule(
+        windows: Vec<(ChainEpoch, ChainEpoch, TokenAmount)>,
+    ) -> Result<RawBytes> {
+        let windows = windows
+            .into_iter()
+            .map(|(start_epoch, end_epoch, max_in_window)| MintWindowParams {
+                start_epoch,
+                end_epoch,
+                max_in_window,
+            })
+            .collect();
+        let params = SetMintScheduleParams { windows };
+        check(validate_mint_schedule(&params))?;
+        encode(&params)
+    }
+
+    pub fn split_balance(
+        source: Address,
+        targets: Vec<(Address, TokenAmount)>,
+    ) -> Result<RawBytes> {
+        let mut entries = Vec::with_capacity(targets.len());
+        for (recipient, amount) in targets {
+            check(require_positive(&amount))?;
+            entries.push(SplitTarget { recipient, amount });
+        }
+        encode(&SplitBalanceParams { source, targets: entries })
+    }
+
+    /// The params for `TransferSplit`, which splits `amount` across the
+    /// recipients in proportion to their weights.
+    pub fn transfer_split(
+        recipients: Vec<(Address, u32)>,
+        amount: TokenAmount,
+    ) -> Result<RawBytes> {
+        check(require_positive(&amount))?;
+        check(validate_split_weights(&recipients).map(|_| ()))?;
+        encode(&TransferSplitParams { recipients, amount })
+    }
+
+    /// The params for `TransferBatch`, which makes each `(recipient, amount)`
+    /// transfer from the caller.
+    pub fn transfer_batch(transfers: Vec<(Address, TokenAmount)>) -> Result<RawBytes> {
+        if transfers.is_empty() {
+            return Err(anyhow!("a batch needs at least one transfer"));
+        }
+        let mut entries = Vec::with_capacity(transfers.len());
+        for (recipient, amount) in transfers {
+            check(require_positive(&amount))?;
+            entries.push(TransferParams { recipient, amount });
+        }
+        encode(&TransferBatchParams { transfers: entries })
+    }
+
+    pub fn set_unit_rate(
+        unit: &str,
+        rate_numerator: TokenAmount,
+        rate_denominator: TokenAmount,
+    ) -> Result<RawBytes> {
+        if rate_denominator <= TokenAmount::from(0) {
+            return Err(anyhow!("rate denominator must be positive"));
+        }
+        encode(&SecondaryUnit { unit: unit.to_string(), rate_numerator, rate_denominator })
+    }
+
+    /// The params for `BalanceOf`: the bare address.
+    pub fn balance_of(account: Address) -> Result<RawBytes> {
+        encode(&account)
+    }
+
+    pub fn can_receive(account: Address, amount: TokenAmount) -> Result<RawBytes> {
+        check(require_positive(&amount))?;
+        encode(&CanReceiveParams { account, amount })
+    }
+
+    pub fn balance_including_pending(account: Address) -> Result<RawBytes> {
+        encode(&BalanceIncludingPendingParams { account })
+    }
+
+    /// The params for `BalanceOfId`. The ID must be the account's canonical
+    /// ID; the actor doesn't resolve it.
+    pub fn balance_of_id(id: ActorID) -> Result<RawBytes> {
+        encode(&BalanceOfIdParams { id })
+    }
+
+    pub fn balance_of_in_unit(account: Address, unit: &str) -> Result<RawBytes> {
+        encode(&BalanceOfInUnitParams { account, unit: unit.to_string() })
+    }
+
+    pub fn recent_transfers(limit: u32) -> Result<RawBytes> {
+        encode(&RecentTransfersParams { limit })
+    }
+
+    pub fn balance_at_snapshot(account: Address, snapshot_index: u32) -> Result<RawBytes> {
+        encode(&BalanceAtSnapshotParams { account, snapshot_index })
+    }
+
+    /// The params for `SetMetadataUri`. None clears the URI.
+    pub fn set_metadata_uri(uri: Option<&str>) -> Result<RawBytes> {
+        if let Some(uri) = uri {
+            check(validate_metadata_uri(uri))?;
+        }
+        encode(&SetMetadataUriParams { uri: uri.map(str::to_string) })
+    }
+
+    /// The params for `SetRateLimit`. None clears the limit.
+    pub fn set_rate_limit(limit: Option<RateLimit>) -> Result<RawBytes> {
+        if let Some(limit) = &limit {
+            check(validate_rate_limit(limit))?;
+        }
+        encode(&SetRateLimitParams { limit })
+    }
+
+    /// The params for `SetMinTransfer`. Zero turns the minimum off.
+    pub fn set_min_transfer(min_transfer: TokenAmount) -> Result<RawBytes> {
+        if min_transfer < TokenAmount::from(0) {
+            return Err(anyhow!("minimum transfer {} must not be negative", min_transfer));
+        }
+        encode(&SetMinTransferParams { min_transfer })
+    }
+
+    /// The params for `Lock`. The unlock epoch is only checked on chain,
+    /// against the epoch the message lands in.
+    pub fn lock(
+        account: Address,
+        amount: TokenAmount,
+        unlock_epoch: ChainEpoch,
+    ) -> Result<RawBytes> {
+        check(require_positive(&amount))?;
+        encode(&LockParams { account, amount, unlock_epoch })
+    }
+
+    /// The params for `SetHookAllowed`.
+    pub fn set_hook_allowed(actor: Address, allowed: bool) -> Result<RawBytes> {
+        encode(&SetHookAllowedParams { actor, allowed })
+    }
+
+    /// The params for `SetTransferFee`. A zero fee needs no collector.
+    pub fn set_transfer_fee(fee_bps: u16, fee_collector: Option<Address>) -> Result<RawBytes> {
+        let params = SetTransferFeeParams { fee_bps, fee_collector };
+        check(validate_transfer_fee(&params))?;
+        encode(&params)
+    }
+
+    pub fn transfer_ownership(new_owner: Address) -> Result<RawBytes> {
+        encode(&TransferOwnershipParams { new_owner })
+    }
+
+    pub fn read_fields(names: &[&str]) -> Result<RawBytes> {
+        encode(&ReadFieldsParams { names: names.iter().map(|n| n.to_string()).collect() })
+    }
+
+    pub fn pending_operation(id: u64) -> Result<RawBytes> {
+        encode(&PendingOperationParams { id })
+    }
+
+    pub fn configure_governance(voters: Vec<Voter>, threshold: u64) -> Result<RawBytes> {
+        let params = ConfigureGovernanceParams { voters, threshold };
+        check(validate_config(&params))?;
+        encode(&params)
+    }
+
+    pub fn propose(method: MethodNum, params: RawBytes) -> Result<RawBytes> {
+        encode(&ProposeParams { method, params })
+    }
+
+    /// The params for `Vote` and `Execute`.
+    pub fn proposal_id(id: u64) -> Result<RawBytes> {
+        encode(&ProposalIdParams { id })
+    }
+
+    /// The params for `Multicall`, from `(method, params)` pairs built with
+    /// the other functions here.
+    pub fn multicall(calls: Vec<(MethodNum, RawBytes)>) -> Result<RawBytes> {
+        if calls.is_empty() {
+            return Err(anyhow!("a multicall needs at least one call"));
+        }
+        let calls = calls.into_iter().map(|(method, params)| Call { method, params }).collect();
+        encode(&MulticallParams { calls })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{method, MAX_MEMO_LEN};
+    use fvm_ipld_encoding::Cbor;
+    use fvm_shared::bigint::bigint_ser::BigIntDe;
+
+    /// Decode `bytes` as `T`, checking it re-encodes to the same bytes.
+    fn round_trip<T: Cbor>(bytes: RawBytes) -> T {
+        let value = T::unmarshal_cbor(bytes.bytes()).unwrap();
+        assert_eq!(value.marshal_cbor().unwrap(), bytes.bytes());
+        value
+    }
+
+    fn amount(n: u64) -> TokenAmount {
+        TokenAmount::from(n)
+    }
+
+    #[test]
+    fn constructor_params_round_trip() {
+        let bytes = ParamsBuilder::constructor("Token", "TOK", amount(1000), Address::new_id(100))
+            .supply_oracle(Address::new_id(200))
+            .hamt_bitwidth(5)
+            .metadata_uri("ipfs://token")
+            .encode()
+            .unwrap();
+
+        let params: ConstructorParams = round_trip(bytes);
+        assert_eq!(params.name, "Token");
+        assert_eq!(params.symbol, "TOK");
+        assert_eq!(params.max_supply, amount(1000));
+        assert_eq!(params.owner, Address::new_id(100));
+        assert_eq!(params.supply_oracle, Some(Address::new_id(200)));
+        assert_eq!(params.hamt_bitwidth, 5);
+        assert_eq!(params.metadata_uri.as_deref(), Some("ipfs://token"));
+        assert!(params.require_receiver_hook);
+        assert!(!params.wrapped);
+        assert_eq!(params.decimals, DEFAULT_DECIMALS);
+
+        let bytes = ParamsBuilder::constructor("Token", "TOK", amount(1), Address::new_id(100))
+            .wrapped()
+            .decimals(6)
+            .without_receiver_hooks()
+            .encode()
+            .unwrap();
+        let params: ConstructorParams = round_trip(bytes);
+        assert!(params.wrapped);
+        assert_eq!(params.decimals, 6);
+        assert!(!params.require_receiver_hook);
+    }
+
+    #[test]
+    fn constructor_params_are_validated() {
+        let builder = ParamsBuilder::constructor("Token", "TOK", amount(1), Address::new_id(100));
+        assert_eq!(builder.clone().build().unwrap().hamt_bitwidth, DEFAULT_HAMT_BIT_WIDTH);
+        assert!(builder.clone().hamt_bitwidth(2).build().is_err());
+        assert!(builder.metadata_uri("").build().is_err());
+    }
+
+    #[test]
+    fn transfer_params_round_trip() {
+        let alice = Address::new_id(101);
+        let bob = Address::new_id(102);
+
+        let params: TransferParams = round_trip(ParamsBuilder::mint(alice, amount(5)).unwrap());
+        assert_eq!((params.recipient, params.amount), (alice, amount(5)));
+        let bytes = ParamsBuilder::transfer(alice, amount(5), RawBytes::new(b"op".to_vec()));
+        let params: Frc46TransferParams = round_trip(bytes.unwrap());
+        assert_eq!((params.to, params.amount), (alice, amount(5)));
+        assert_eq!(params.operator_data.bytes(), b"op");
+        let bytes = ParamsBuilder::transfer_with_receipt(alice, amount(5), RawBytes::default());
+        let params: Frc46TransferParams = round_trip(bytes.unwrap());
+        assert_eq!((params.to, params.amount), (alice, amount(5)));
+
+        let bytes = ParamsBuilder::transfer_with_memo(alice, amount(5), b"ref".to_vec()).unwrap();
+        let params: TransferMemoParams = round_trip(bytes);
+        assert_eq!(params.memo, b"ref".to_vec());
+
+        let bytes = ParamsBuilder::transfer_until(alice, amount(5), 60).unwrap();
+        let params: TransferUntilParams = round_trip(bytes);
+        assert_eq!((params.amount, params.deadline), (amount(5), 60));
+
+        let bytes = ParamsBuilder::idempotent_transfer(alice, amount(5), Some([7; 32])).unwrap();
+        let params: IdempotentTransferParams = round_trip(bytes);
+        assert_eq!(params.idempotency_key, Some([7; 32]));
+
+        let bytes = ParamsBuilder::mint_batch(vec![(alice, amount(1)), (bob, amount(2))]).unwrap();
+        let params: MintBatchParams = round_trip(bytes);
+        assert_eq!(params.mints.len(), 2);
+        assert_eq!((params.mints[1].recipient, &params.mints[1].amount), (bob, &amount(2)));
+
+        let bytes = ParamsBuilder::transfer_batch(vec![(alice, amount(1)), (bob, amount(2))]);
+        let bytes = bytes.unwrap();
+        let pairs: Vec<(Address, BigIntDe)> = bytes.deserialize().unwrap();
+        assert_eq!(pairs.len(), 2);
+        assert_eq!((pairs[1].0, &pairs[1].1 .0), (bob, &amount(2)));
+        let params: TransferBatchParams = round_trip(bytes);
+        assert_eq!(params.transfers.len(), 2);
+        assert_eq!(params.transfers[1].amount, amount(2));
+
+        let bytes = ParamsBuilder::force_transfer(alice, bob, amount(3)).unwrap();
+        let params: ForceTransferParams = round_trip(bytes);
+        assert_eq!((params.from, params.to, params.amount), (alice, bob, amount(3)));
+
+        let bytes = ParamsBuilder::transfer_from(alice, bob, amount(3), RawBytes::default());
+        let params: TransferFromParams = round_trip(bytes.unwrap());
+        assert_eq!((params.from, params.to, params.amount), (alice, bob, amount(3)));
+        assert!(ParamsBuilder::transfer_from(alice, bob, amount(0), RawBytes::default()).is_err());
+
+        let params: ApproveParams = round_trip(ParamsBuilder::approve(bob, amount(30)).unwrap());
+        assert_eq!((params.spender, params.amount), (bob, amount(30)));
+        assert!(ParamsBuilder::approve(bob, -amount(1)).is_err());
+        let bytes = ParamsBuilder::permit(alice, bob, amount(30), 10, vec![1, 2]).unwrap();
+        let params: PermitParams = round_trip(bytes);
+        assert_eq!((params.owner, params.amount, params.deadline), (alice, amount(30), 10));
+        assert_eq!(params.signature, vec![1, 2]);
+        assert!(ParamsBuilder::permit(alice, bob, -amount(1), 10, Vec::new()).is_err());
+        let inner = RawBytes::new(vec![7]);
+        let bytes = ParamsBuilder::approve_and_call(bob, amount(30), 42, inner.clone()).unwrap();
+        let params: ApproveAndCallParams = round_trip(bytes);
+        assert_eq!((params.spender, params.amount), (bob, amount(30)));
+        assert_eq!((params.call_method, params.call_params), (42, inner));
+        assert!(ParamsBuilder::approve_and_call(bob, -amount(1), 42, RawBytes::default()).is_err());
+        let params: NonceOfParams = round_trip(ParamsBuilder::nonce_of(alice).unwrap());
+        assert_eq!(params.owner, alice);
+        let bytes = ParamsBuilder::transfer_and_approve(bob, amount(3), alice, amount(2)).unwrap();
+        let params: TransferAndApproveParams = round_trip(bytes);
+        assert_eq!((params.recipient, params.amount), (bob, amount(3)));
+        assert_eq!((params.grant_spender, params.grant_amount), (alice, amount(2)));
+        assert!(ParamsBuilder::transfer_and_approve(bob, amount(0), alice, amount(2)).is_err());
+        let bytes = ParamsBuilder::compare_and_set_allowance(bob, amount(5), amount(8)).unwrap();
+        let params: CompareAndSetAllowanceParams = round_trip(bytes);
+        assert_eq!((params.spender, params.expected, params.new), (bob, amount(5), amount(8)));
+        assert!(ParamsBuilder::compare_and_set_allowance(bob, -amount(1), amount(8)).is_err());
+
+        let bytes = ParamsBuilder::increase_allowance(bob, amount(3)).unwrap();
+        let params: IncreaseAllowanceParams = round_trip(bytes);
+        assert_eq!((params.spender, params.increase), (bob, amount(3)));
+        assert!(ParamsBuilder::increase_allowance(bob, -amount(1)).is_err());
+        let bytes = ParamsBuilder::decrease_allowance(bob, amount(2)).unwrap();
+        let params: DecreaseAllowanceParams = round_trip(bytes);
+        assert_eq!((params.spender, params.decrease), (bob, amount(2)));
+        assert!(ParamsBuilder::decrease_allowance(bob, -amount(1)).is_err());
+        let params: RevokeAllowanceParams =
+            round_trip(ParamsBuilder::revoke_allowance(bob).unwrap());
+        assert_eq!(params.spender, bob);
+
+        let params: SetOperatorParams = round_trip(ParamsBuilder::set_operator(bob, true).unwrap());
+        assert_eq!((params.operator, params.approved), (bob, true));
+
+        let params: RoleParams = round_trip(ParamsBuilder::grant_role(Role::Minter, bob).unwrap());
+        assert_eq!((Role::from_u8(params.role), params.account), (Some(Role::Minter), bob));
+        let params: RoleParams = round_trip(ParamsBuilder::revoke_role(Role::Pauser, bob).unwrap());
+        assert_eq!(Role::from_u8(params.role), Some(Role::Pauser));
+        let params: RoleParams = round_trip(ParamsBuilder::has_role(Role::Burner, bob).unwrap());
+        assert_eq!(Role::from_u8(params.role), Some(Role::Burner));
+
+        let params: FreezeAccountParams = round_trip(ParamsBuilder::freeze_account(bob).unwrap());
+        assert_eq!(params.account, bob);
+        let params: FreezeAccountParams = round_trip(ParamsBuilder::unfreeze_account(bob).unwrap());
+        assert_eq!(params.account, bob);
+        let params: FreezeAccountParams = round_trip(ParamsBuilder::is_frozen(bob).unwrap());
+        assert_eq!(params.account, bob);
+
+        let params: SetMaxHoldersParams =
+            round_trip(ParamsBuilder::set_max_holders(Some(3)).unwrap());
+        assert_eq!(params.max_holders, Some(3));
+
+        let bytes = ParamsBuilder::set_claim_terms(alice, amount(10), Some(50)).unwrap();
+        let params: SetClaimTermsParams = round_trip(bytes);
+        assert_eq!((params.pool, params.amount, params.deadline), (alice, amount(10), Some(50)));
+        assert!(ParamsBuilder::set_claim_terms(alice, -amount(1), None).is_err());
+
+        let params: WithdrawParams = round_trip(ParamsBuilder::withdraw(amount(7)).unwrap());
+        assert_eq!(params.amount, amount(7));
+        assert!(ParamsBuilder::withdraw(amount(0)).is_err());
+
+        let params: BurnParams = round_trip(ParamsBuilder::burn(amount(4)).unwrap());
+        assert_eq!(params.amount, amount(4));
+        assert!(ParamsBuilder::burn(amount(0)).is_err());
+        let params: BurnFromParams =
+            round_trip(ParamsBuilder::burn_from(alice, amount(5)).unwrap());
+        assert_eq!((params.owner, params.amount), (alice, amount(5)));
+        assert!(ParamsBuilder::burn_from(alice, -amount(1)).is_err());
+
+        let params: RescueParams = round_trip(ParamsBuilder::rescue(bob).unwrap());
+        assert_eq!(params.to, bob);
+
+        let bytes = ParamsBuilder::split_balance(alice, vec![(bob, amount(4))]).unwrap();
+        let params: SplitBalanceParams = round_trip(bytes);
+        assert_eq!(params.source, alice);
+        assert_eq!(params.targets[0].amount, amount(4));
+    }
+
+    #[test]
+    fn transfer_params_are_validated() {
+        let alice = Address::new_id(101);
+        assert!(ParamsBuilder::mint(alice, amount(0)).is_err());
+        assert!(ParamsBuilder::transfer(alice, amount(0), RawBytes::default()).is_err());
+        assert!(
+            ParamsBuilder::transfer_with_memo(alice, amount(1), vec![0; MAX_MEMO_LEN + 1]).is_err()
+        );
+        assert!(ParamsBuilder::mint_batch(vec![(alice, amount(1)), (alice, amount(0))]).is_err());
+        assert!(ParamsBuilder::force_transfer(alice, alice, amount(0)).is_err());
+        assert!(ParamsBuilder::split_balance(alice, vec![(alice, amount(0))]).is_err());
+        assert!(ParamsBuilder::transfer_split(vec![], amount(1)).is_err());
+        assert!(ParamsBuilder::transfer_split(vec![(alice, 0)], amount(1)).is_err());
+        assert!(ParamsBuilder::transfer_batch(vec![]).is_err());
+        assert!(ParamsBuilder::transfer_batch(vec![(alice, amount(0))]).is_err());
+    }
+
+    #[test]
+    fn admin_params_round_trip() {
+        let params: SetMaxSupplyParams =
+            round_trip(ParamsBuilder::set_max_supply(amount(10)).unwrap());
+        assert_eq!(params.new_max, amount(10));
+        assert!(ParamsBuilder::set_max_supply(-amount(1)).is_err());
+
+        let bytes = ParamsBuilder::set_mint_schedule(vec![(10, 20, amount(5))]).unwrap();
+        let params: SetMintScheduleParams = round_trip(bytes);
+        let window = &params.windows[0];
+        assert_eq!((window.start_epoch, window.end_epoch), (10, 20));
+        assert_eq!(window.max_in_window, amount(5));
+        assert!(ParamsBuilder::set_mint_schedule(vec![(20, 20, amount(5))]).is_err());
+        assert!(ParamsBuilder::set_mint_schedule(vec![(10, 20, amount(0))]).is_err());
+
+        let bytes = ParamsBuilder::set_unit_rate("USD", amount(3), amount(2)).unwrap();
+        let unit: SecondaryUnit = round_trip(bytes);
+        assert_eq!(unit.unit, "USD");
+        assert_eq!((unit.rate_numerator, unit.rate_denominator), (amount(3), amount(2)));
+        assert!(ParamsBuilder::set_unit_rate("USD", amount(3), amount(0)).is_err());
+
+        let bytes = ParamsBuilder::set_metadata_uri(Some("ipfs://token")).unwrap();
+        let params: SetMetadataUriParams = round_trip(bytes);
+        assert_eq!(params.uri.as_deref(), Some("ipfs://token"));
+        let params: SetMetadataUriParams =
+            round_trip(ParamsBuilder::set_metadata_uri(None).unwrap());
+        assert_eq!(params.uri, None);
+        assert!(ParamsBuilder::set_metadata_uri(Some("")).is_err());
+
+        let limit = RateLimit { max_amount: amount(100), window_epochs: 10 };
+        let bytes = ParamsBuilder::set_rate_limit(Some(limit.clone())).unwrap();
+        let params: SetRateLimitParams = round_trip(bytes);
+        assert_eq!(params.limit, Some(limit));
+        let bytes = ParamsBuilder::set_transfer_fee(250, Some(Address::new_id(103))).unwrap();
+        let params: SetTransferFeeParams = round_trip(bytes);
+        assert_eq!((params.fee_bps, params.fee_collector), (250, Some(Address::new_id(103))));
+        assert!(ParamsBuilder::set_transfer_fee(10_001, Some(Address::new_id(103))).is_err());
+        assert!(ParamsBuilder::set_transfer_fee(250, None).is_err());
+
+        let bytes = ParamsBuilder::set_min_transfer(amount(10)).unwrap();
+        let params: SetMinTransferParams = round_trip(bytes);
+        assert_eq!(params.min_transfer, amount(10));
+        assert!(ParamsBuilder::set_min_transfer(-amount(1)).is_err());
+
+        let bytes = ParamsBuilder::lock(Address::new_id(101), amount(40), 500).unwrap();
+        let params: LockParams = round_trip(bytes);
+        assert_eq!((params.amount, params.unlock_epoch), (amount(40), 500));
+        assert!(ParamsBuilder::lock(Address::new_id(101), amount(0), 500).is_err());
+
+        let bytes = ParamsBuilder::set_hook_allowed(Address::new_id(104), true).unwrap();
+        let params: SetHookAllowedParams = round_trip(bytes);
+        assert_eq!((params.actor, params.allowed), (Address::new_id(104), true));
+
+        let zero_window = RateLimit { max_amount: amount(100), window_epochs: 0 };
+        assert!(ParamsBuilder::set_rate_limit(Some(zero_window)).is_err());
+    }
+
+    #[test]
+    fn query_params_round_trip() {
+        let alice = Address::new_id(101);
+
+        let bytes = ParamsBuilder::balance_of(alice).unwrap();
+        assert_eq!(bytes.deserialize::<Address>().unwrap(), alice);
+        let params: BalanceOfIdParams = round_trip(ParamsBuilder::balance_of_id(101).unwrap());
+        assert_eq!(params.id, 101);
+        let bytes = ParamsBuilder::balance_including_pending(alice).unwrap();
+        let params: BalanceIncludingPendingParams = round_trip(bytes);
+        assert_eq!(params.account, alice);
+        let bytes = ParamsBuilder::can_receive(alice, amount(5)).unwrap();
+        let params: CanReceiveParams = round_trip(bytes);
+        assert_eq!((params.account, params.amount), (alice, amount(5)));
+        let bytes = ParamsBuilder::allowance(alice, Address::new_id(102)).unwrap();
+        let params: AllowanceParams = round_trip(bytes);
+        assert_eq!((params.owner, params.spender), (alice, Address::new_id(102)));
+        let bytes = ParamsBuilder::is_operator(alice, Address::new_id(102)).unwrap();
+        let params: IsOperatorParams = round_trip(bytes);
+        assert_eq!((params.owner, params.operator), (alice, Address::new_id(102)));
+
+        let bytes = ParamsBuilder::balance_of_in_unit(alice, "USD").unwrap();
+        let params: BalanceOfInUnitParams = round_trip(bytes);
+        assert_eq!((params.account, params.unit.as_str()), (alice, "USD"));
+
+        let bytes = ParamsBuilder::allowances_of(alice, Some(102), 10).unwrap();
+        let params: AllowancesOfParams = round_trip(bytes);
+        assert_eq!((params.owner, params.start_after, params.limit), (alice, Some(102), 10));
+        assert!(ParamsBuilder::allowances_of(alice, None, 0).is_err());
+
+        let params: RecentTransfersParams = round_trip(ParamsBuilder::recent_transfers(5).unwrap());
+        assert_eq!(params.limit, 5);
+
+        let bytes = ParamsBuilder::balance_at_snapshot(alice, 7).unwrap();
+        let params: BalanceAtSnapshotParams = round_trip(bytes);
+        assert_eq!((params.account, params.snapshot_index), (alice, 7));
+
+        let bytes = ParamsBuilder::read_fields(&["name", "symbol"]).unwrap();
+        let params: ReadFieldsParams = round_trip(bytes);
+        assert_eq!(params.names, vec!["name".to_string(), "symbol".to_string()]);
+
+        let bytes = ParamsBuilder::transfer_ownership(alice).unwrap();
+        let params: TransferOwnershipParams = round_trip(bytes);
+        assert_eq!(params.new_owner, alice);
+
+        let params: PendingOperationParams =
+            round_trip(ParamsBuilder::pending_operation(3).unwrap());
+        assert_eq!(params.id, 3);
+    }
+
+    #[test]
+    fn governance_params_round_trip() {
+        let voters = vec![Voter { id: 101, weight: 1 }, Voter { id: 102, weight: 2 }];
+        let bytes = ParamsBuilder::configure_governance(voters.clone(), 3).unwrap();
+        let params: ConfigureGovernanceParams = round_trip(bytes);
+        assert_eq!((params.voters, params.threshold), (voters.clone(), 3));
+        assert!(ParamsBuilder::configure_governance(voters, 4).is_err());
+        assert!(ParamsBuilder::configure_governance(Vec::new(), 1).is_err());
+
+        let inner = RawBytes::new(vec![1, 2, 3]);
+        let params: ProposeParams = round_trip(ParamsBuilder::propose(42, inner.clone()).unwrap());
+        assert_eq!((params.method, params.params), (42, inner));
+
+        let params: ProposalIdParams = round_trip(ParamsBuilder::proposal_id(9).unwrap());
+        assert_eq!(params.id, 9);
+    }
+
+    #[test]
+    fn multicall_params_round_trip() {
+        let inner = ParamsBuilder::burn(amount(1)).unwrap();
+        let bytes = ParamsBuilder::multicall(vec![(method::BURN, inner.clone())]).unwrap();
+        let params: MulticallParams = round_trip(bytes);
+        assert_eq!((params.calls[0].method, &params.calls[0].params), (method::BURN, &inner));
+        assert!(ParamsBuilder::multicall(Vec::new()).is_err());
+    }
+}
@@ -0,0 +1,192 @@
+//! Permits: approvals signed off chain.
+//!
+//! An owner signs a `PermitMessage` naming a spender and an amount, and
+//! anyone may submit it with `permit` to set that allowance, so the owner
+//! needn't send a message or hold FIL for gas. Each owner has a nonce that a
+//! permit uses up, so every signature works once.
+
+use fvm_ipld_encoding::tuple::{Deserialize_tuple, Serialize_tuple};
+use fvm_ipld_encoding::{to_vec, Cbor};
+use fvm_ipld_hamt::Hamt;
+use fvm_shared::address::Address;
+use fvm_shared::bigint::bigint_ser;
+use fvm_shared::clock::ChainEpoch;
+use fvm_shared::crypto::signature::Signature;
+use fvm_shared::econ::TokenAmount;
+use fvm_shared::ActorID;
+
+use crate::allowance::set_allowance;
+use crate::error::ActorError;
+use crate::runtime::Runtime;
+use crate::{check_amount_cap, load_hamt, runtime_context, token, State, TokenAmounts};
+
+/// The permit nonce of each owner that has used one, keyed by actor ID.
+pub type Nonces<BS> = Hamt<BS, u64, ActorID>;
+
+/// The nonce `owner`'s next permit must be signed with: the number of
+/// permits it has used, starting from zero.
+pub fn get_nonce<BS: fvm_ipld_blockstore::Blockstore>(
+    store: &BS,
+    state: &State,
+    owner: ActorID,
+) -> Result<u64, ActorError> {
+    let root = match &state.nonces {
+        Some(root) => root,
+        None => return Ok(0),
+    };
+    let nonces: Nonces<&BS> = load_hamt(store, root, state.hamt_bit_width, "nonces")?;
+    let entry = nonces
+        .get(&owner)
+        .map_err(|err| actor_error!(USR_ILLEGAL_STATE, "failed to get nonce: {:?}", err))?;
+    Ok(entry.copied().unwrap_or(0))
+}
+
+fn set_nonce<BS: fvm_ipld_blockstore::Blockstore>(
+    store: &BS,
+    state: &mut State,
+    owner: ActorID,
+    nonce: u64,
+) -> Result<(), ActorError> {
+    let mut nonces: Nonces<&BS> = match &state.nonces {
+        Some(root) => load_hamt(store, root, state.hamt_bit_width, "nonces")?,
+        None => Hamt::new_with_bit_width(store, state.hamt_bit_width),
+    };
+    nonces
+        .set(owner, nonce)
+        .map_err(|err| actor_error!(USR_ILLEGAL_STATE, "failed to set nonce: {:?}", err))?;
+    let root = nonces
+        .flush()
+        .map_err(|err| actor_error!(USR_ILLEGAL_STATE, "failed to flush nonces: {:?}", err))?;
+    state.nonces = Some(root);
+    Ok(())
+}
+
+/// The input parameters for a permit: an approval signed off chain by the
+/// owner, which anyone may submit. `signature` is a Filecoin signature, its
+/// type byte (1 for secp256k1, 2 for BLS) followed by the signature itself,
+/// over the CBOR encoding of the matching `PermitMessage`.
+#[derive(Serialize_tuple, Deserialize_tuple, Clone, Debug)]
+pub struct PermitParams {
+    pub owner: Address,
+    pub spender: Address,
+    #[serde(with = "bigint_ser")]
+    pub amount: TokenAmount,
+    /// The last epoch the permit may be used in.
+    pub deadline: ChainEpoch,
+    pub signature: Vec<u8>,
+}
+
+impl Cbor for PermitParams {}
+
+impl TokenAmounts for PermitParams {
+    fn token_amounts(&self) -> Vec<&TokenAmount> {
+        vec![&self.amount]
+    }
+}
+
+/// What the owner signs to permit an approval. `token` is this actor's ID
+/// address, so the signature can't be used on any other token, and `nonce`
+/// is the owner's current nonce, so it can only be used once. `amount` is
+/// the permitted amount as a 32-byte big-endian EVM `uint256`, see
+/// [`token::to_evm_u256`], so EVM-side signers encode it the same way. The
+/// other fields are the permit's params, exactly as submitted.
+#[derive(Serialize_tuple, Deserialize_tuple, Clone, Debug)]
+pub struct PermitMessage {
+    pub token: Address,
+    pub owner: Address,
+    pub spender: Address,
+    pub amount: Vec<u8>,
+    pub deadline: ChainEpoch,
+    pub nonce: u64,
+}
+
+impl Cbor for PermitMessage {}
+
+/// Encode `amount` with `token::to_evm_u256`, for a permit message.
+fn evm_word(amount: &TokenAmount) -> Result<Vec<u8>, ActorError> {
+    match token::to_evm_u256(amount) {
+        Ok(word) => Ok(word.to_vec()),
+        Err(err) => Err(actor_error!(kind: InvalidAmount, USR_ILLEGAL_ARGUMENT, "{}", err)),
+    }
+}
+
+fn decode_signature(bytes: &[u8]) -> Result<Signature, ActorError> {
+    match bytes.split_first() {
+        Some((1, sig)) => Ok(Signature::new_secp256k1(sig.to_vec())),
+        Some((2, sig)) => Ok(Signature::new_bls(sig.to_vec())),
+        _ => Err(actor_error!(USR_ILLEGAL_ARGUMENT, "permit signature has no known type")),
+    }
+}
+
+/// Set the allowance `owner` granted `spender` to `amount`, as `approve`
+/// would for the owner, on the strength of the owner's signature rather than
+/// its message. The permit must be used by its deadline epoch, and uses up
+/// the owner's nonce, so a permit that has been used, or signed for a stale
+/// nonce, is rejected.
+pub fn permit<R: Runtime>(rt: &R, params: PermitParams) -> Result<(), ActorError> {
+    let mut state = State::load_from(rt)?;
+    state.check_unlocked()?;
+
+    let epoch = rt.curr_epoch();
+    if epoch > params.deadline {
+        return Err(actor_error!(
+            USR_ILLEGAL_ARGUMENT,
+            "permit expired at epoch {}, and it is now epoch {}",
+            params.deadline,
+            epoch
+        ));
+    }
+    let resolver = |addr: &Address| rt.resolve_address(addr);
+    let ctx = runtime_context(rt, &resolver);
+    let owner = ctx.resolve(&params.owner)?;
+    let spender = ctx.resolve(&params.spender)?;
+    if spender == owner {
+        return Err(actor_error!(USR_ILLEGAL_ARGUMENT, "an account cannot approve itself"));
+    }
+    check_amount_cap(&params.amount, "allowance")?;
+
+    let nonce = get_nonce(rt.store(), &state, owner)?;
+    let message = PermitMessage {
+        token: Address::new_id(rt.receiver()),
+        owner: params.owner,
+        spender: params.spender,
+        amount: evm_word(&params.amount)?,
+        deadline: params.deadline,
+        nonce,
+    };
+    let message = to_vec(&message).map_err(|err| {
+        actor_error!(USR_SERIALIZATION, "failed to serialize permit message: {:?}", err)
+    })?;
+    let signature = decode_signature(&params.signature)?;
+    if !rt.verify_signature(&signature, &params.owner, &message)? {
+        return Err(actor_error!(
+            USR_FORBIDDEN,
+            "permit is not signed by {} for nonce {}",
+            params.owner,
+            nonce
+        ));
+    }
+
+    set_nonce(rt.store(), &mut state, owner, nonce + 1)?;
+    set_allowance(rt.store(), &mut state, owner, spender, params.amount)?;
+    state.save_to(rt)?;
+    Ok(())
+}
+
+/// The input parameters for reading an owner's permit nonce.
+#[derive(Serialize_tuple, Deserialize_tuple, Clone, Debug)]
+pub struct NonceOfParams {
+    pub owner: Address,
+}
+
+impl Cbor for NonceOfParams {}
+
+/// Return the nonce `owner`'s next permit must be signed for. Owners that
+/// have never used a permit, and addresses that don't resolve, are at zero.
+pub fn nonce_of<R: Runtime>(rt: &R, params: NonceOfParams) -> Result<u64, ActorError> {
+    let state = State::load_from(rt)?;
+    match rt.resolve_address(&params.owner) {
+        Some(owner) => get_nonce(rt.store(), &state, owner),
+        None => Ok(0),
+    }
+}
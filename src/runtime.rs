@@ -0,0 +1,407 @@
+use cid::Cid;
+use fvm_ipld_encoding::RawBytes;
+use fvm_sdk as sdk;
+use fvm_shared::address::Address;
+use fvm_shared::clock::ChainEpoch;
+use fvm_shared::crypto::signature::Signature;
+use fvm_shared::econ::TokenAmount;
+use fvm_shared::receipt::Receipt;
+use fvm_shared::{ActorID, MethodNum};
+
+use crate::blockstore::CachingBlockstore;
+use crate::error::ActorError;
+
+/// The environment the actor's methods run in: message details, the state
+/// root, outbound sends and a blockstore.
+///
+/// [`FvmRuntime`] implements it over the SDK syscalls. Unit tests use
+/// [`MockRuntime`], which keeps everything in memory.
+pub trait Runtime {
+    type Blockstore: fvm_ipld_blockstore::Blockstore;
+
+    /// The blockstore state is read from and written to.
+    fn store(&self) -> &Self::Blockstore;
+
+    /// The immediate caller of the current method.
+    fn caller(&self) -> ActorID;
+
+    /// The ID of this actor.
+    fn receiver(&self) -> ActorID;
+
+    /// The account that sent the top-level message.
+    fn origin(&self) -> ActorID;
+
+    fn curr_epoch(&self) -> ChainEpoch;
+
+    /// The FIL attached to the current message.
+    fn value_received(&self) -> TokenAmount;
+
+    /// This actor's FIL balance, including the value received.
+    fn current_balance(&self) -> TokenAmount;
+
+    fn resolve_address(&self, addr: &Address) -> Option<ActorID>;
+
+    fn get_actor_code_cid(&self, addr: &Address) -> Option<Cid>;
+
+    /// The current state root.
+    fn root(&self) -> Result<Cid, ActorError>;
+
+    /// Replace the state root. The blocks it references must already be in
+    /// the blockstore.
+    fn set_root(&self, root: &Cid) -> Result<(), ActorError>;
+
+    fn send(
+        &self,
+        to: &Address,
+        method: MethodNum,
+        params: RawBytes,
+        value: TokenAmount,
+    ) -> Result<Receipt, ActorError>;
+
+    /// Returns true if `signature` is `signer`'s signature over `plaintext`.
+    fn verify_signature(
+        &self,
+        signature: &Signature,
+        signer: &Address,
+        plaintext: &[u8],
+    ) -> Result<bool, ActorError>;
+}
+
+/// A runtime backed by the SDK syscalls.
+///
+/// Blocks go through a [`CachingBlockstore`], which is flushed whenever the
+/// state root is set, so callers never need to flush it themselves.
+#[derive(Default)]
+pub struct FvmRuntime {
+    store: CachingBlockstore,
+}
+
+impl Runtime for FvmRuntime {
+    type Blockstore = CachingBlockstore;
+
+    fn store(&self) -> &CachingBlockstore {
+        &self.store
+    }
+
+    fn caller(&self) -> ActorID {
+        sdk::message::caller()
+    }
+
+    fn receiver(&self) -> ActorID {
+        sdk::message::receiver()
+    }
+
+    fn origin(&self) -> ActorID {
+        sdk::message::origin()
+    }
+
+    fn curr_epoch(&self) -> ChainEpoch {
+        sdk::network::curr_epoch()
+    }
+
+    fn value_received(&self) -> TokenAmount {
+        sdk::message::value_received()
+    }
+
+    fn current_balance(&self) -> TokenAmount {
+        sdk::sself::current_balance()
+    }
+
+    fn resolve_address(&self, addr: &Address) -> Option<ActorID> {
+        sdk::actor::resolve_address(addr)
+    }
+
+    fn get_actor_code_cid(&self, addr: &Address) -> Option<Cid> {
+        sdk::actor::get_actor_code_cid(addr)
+    }
+
+    fn root(&self) -> Result<Cid, ActorError> {
+        sdk::sself::root().map_err(|err| {
+            actor_error!(kind: MissingStateRoot, USR_ILLEGAL_STATE, "failed to get root: {:?}", err)
+        })
+    }
+
+    fn set_root(&self, root: &Cid) -> Result<(), ActorError> {
+        // Write the buffered blocks through before the new root references them.
+        self.store.flush().map_err(|err| {
+            actor_error!(USR_ILLEGAL_STATE, "failed to flush blockstore: {:?}", err)
+        })?;
+        sdk::sself::set_root(root)
+            .map_err(|err| actor_error!(USR_ILLEGAL_STATE, "failed to set root cid: {:?}", err))
+    }
+
+    fn send(
+        &self,
+        to: &Address,
+        method: MethodNum,
+        params: RawBytes,
+        value: TokenAmount,
+    ) -> Result<Receipt, ActorError> {
+        sdk::send::send(to, method, params, value)
+            .map_err(|err| actor_error!(USR_ILLEGAL_STATE, "failed to send to {}: {:?}", to, err))
+    }
+
+    fn verify_signature(
+        &self,
+        signature: &Signature,
+        signer: &Address,
+        plaintext: &[u8],
+    ) -> Result<bool, ActorError> {
+        sdk::crypto::verify_signature(signature, signer, plaintext).map_err(|err| {
+            actor_error!(USR_ILLEGAL_ARGUMENT, "failed to verify signature: {:?}", err)
+        })
+    }
+}
+
+#[cfg(test)]
+pub use self::mock::MockRuntime;
+
+#[cfg(test)]
+mod mock {
+    use std::cell::{Cell, RefCell};
+    use std::collections::{HashMap, VecDeque};
+
+    use super::*;
+    use crate::blockstore::MemoryBlockstore;
+    use cid::multihash::{Code, MultihashDigest};
+    use fvm_shared::address::Protocol;
+    use fvm_shared::error::ExitCode;
+
+    /// A message sent through [`MockRuntime::send`].
+    #[derive(Clone, Debug)]
+    pub struct SentMessage {
+        pub to: Address,
+        pub method: MethodNum,
+        pub params: RawBytes,
+        pub value: TokenAmount,
+    }
+
+    /// Answers a send in place of the receipt queue. It runs with the
+    /// recipient as the caller, so it can call back into the actor.
+    pub type SendHandler = Box<dyn Fn(&MockRuntime, &SentMessage) -> Receipt>;
+
+    /// An in-memory runtime for unit tests.
+    ///
+    /// ID addresses resolve to themselves, and other addresses resolve once
+    /// they're registered with [`MockRuntime::add_address`]. Sends are
+    /// recorded, and answered by the handler set with
+    /// [`MockRuntime::on_send`] if there is one, otherwise with the queued
+    /// receipts in order, or with an empty success once the queue runs out.
+    /// A successful send to an unregistered key address creates an account
+    /// for it, like the real runtime, and a successful send's value comes out
+    /// of `balance`. Only signatures made with [`MockRuntime::sign`] verify.
+    pub struct MockRuntime {
+        pub store: MemoryBlockstore,
+        pub caller: Cell<ActorID>,
+        pub receiver: ActorID,
+        pub origin: Cell<ActorID>,
+        pub epoch: Cell<ChainEpoch>,
+        pub value_received: RefCell<TokenAmount>,
+        pub balance: RefCell<TokenAmount>,
+        pub code_cids: RefCell<HashMap<ActorID, Cid>>,
+        pub addresses: RefCell<HashMap<Address, ActorID>>,
+        /// The ID the next created account is assigned.
+        pub next_id: Cell<ActorID>,
+        pub root: RefCell<Option<Cid>>,
+        /// How many times the state root has been set.
+        pub root_writes: Cell<usize>,
+        pub sent: RefCell<Vec<SentMessage>>,
+        pub receipts: RefCell<VecDeque<Receipt>>,
+        pub send_handler: RefCell<Option<SendHandler>>,
+    }
+
+    impl MockRuntime {
+        pub fn new(receiver: ActorID) -> Self {
+            MockRuntime {
+                store: MemoryBlockstore::default(),
+                caller: Cell::new(0),
+                receiver,
+                origin: Cell::new(0),
+                epoch: Cell::new(0),
+                value_received: RefCell::new(TokenAmount::from(0)),
+                balance: RefCell::new(TokenAmount::from(0)),
+                code_cids: RefCell::new(HashMap::new()),
+                addresses: RefCell::new(HashMap::new()),
+                next_id: Cell::new(2000),
+                root: RefCell::new(None),
+                root_writes: Cell::new(0),
+                sent: RefCell::new(Vec::new()),
+                receipts: RefCell::new(VecDeque::new()),
+                send_handler: RefCell::new(None),
+            }
+        }
+
+        /// Make `id` the caller, and the origin too, as in a top-level message.
+        pub fn set_caller(&self, id: ActorID) {
+            self.caller.set(id);
+            self.origin.set(id);
+        }
+
+        /// Attach `value` to the current message, crediting it to the actor's
+        /// balance as the real runtime does before the method runs.
+        pub fn set_value_received(&self, value: TokenAmount) {
+            *self.balance.borrow_mut() += &value;
+            *self.value_received.borrow_mut() = value;
+        }
+
+        /// Make `addr` resolve to `id`.
+        pub fn add_address(&self, addr: Address, id: ActorID) {
+            self.addresses.borrow_mut().insert(addr, id);
+        }
+
+        /// Give an actor a code CID, which makes it a non-account actor unless
+        /// the origin has the same code.
+        pub fn set_code_cid(&self, id: ActorID, code: Cid) {
+            self.code_cids.borrow_mut().insert(id, code);
+        }
+
+        /// Queue a receipt to answer the next unanswered send with.
+        pub fn expect_send_exit_code(&self, exit_code: ExitCode) {
+            self.receipts.borrow_mut().push_back(Receipt {
+                exit_code,
+                return_data: RawBytes::default(),
+                gas_used: 0,
+            });
+        }
+
+        /// Answer every later send with `handler`.
+        pub fn on_send(&self, handler: impl Fn(&MockRuntime, &SentMessage) -> Receipt + 'static) {
+            *self.send_handler.borrow_mut() = Some(Box::new(handler));
+        }
+
+        /// Run `method` as the top-level method of a message. If it fails,
+        /// the state root goes back to what it was, as an abort reverts
+        /// everything the message saved on chain.
+        pub fn apply<T>(
+            &self,
+            method: impl FnOnce(&MockRuntime) -> Result<T, ActorError>,
+        ) -> Result<T, ActorError> {
+            let root = *self.root.borrow();
+            let res = method(self);
+            if res.is_err() {
+                *self.root.borrow_mut() = root;
+            }
+            res
+        }
+
+        /// Sign `plaintext` as `signer`, returning the signature that
+        /// `verify_signature` accepts for them: a secp256k1 signature whose
+        /// bytes are the blake2b-256 hash of the signer and the plaintext.
+        pub fn sign(signer: &Address, plaintext: &[u8]) -> Signature {
+            let mut signed = signer.to_bytes();
+            signed.extend_from_slice(plaintext);
+            Signature::new_secp256k1(Code::Blake2b256.digest(&signed).digest().to_vec())
+        }
+    }
+
+    impl Runtime for MockRuntime {
+        type Blockstore = MemoryBlockstore;
+
+        fn store(&self) -> &MemoryBlockstore {
+            &self.store
+        }
+
+        fn caller(&self) -> ActorID {
+            self.caller.get()
+        }
+
+        fn receiver(&self) -> ActorID {
+            self.receiver
+        }
+
+        fn origin(&self) -> ActorID {
+            self.origin.get()
+        }
+
+        fn curr_epoch(&self) -> ChainEpoch {
+            self.epoch.get()
+        }
+
+        fn value_received(&self) -> TokenAmount {
+            self.value_received.borrow().clone()
+        }
+
+        fn current_balance(&self) -> TokenAmount {
+            self.balance.borrow().clone()
+        }
+
+        fn resolve_address(&self, addr: &Address) -> Option<ActorID> {
+            match addr.id() {
+                Ok(id) => Some(id),
+                Err(_) => self.addresses.borrow().get(addr).cloned(),
+            }
+        }
+
+        fn get_actor_code_cid(&self, addr: &Address) -> Option<Cid> {
+            let id = addr.id().ok()?;
+            self.code_cids.borrow().get(&id).cloned()
+        }
+
+        fn root(&self) -> Result<Cid, ActorError> {
+            self.root
+                .borrow()
+                .ok_or_else(|| {
+                    actor_error!(
+                        kind: MissingStateRoot,
+                        USR_ILLEGAL_STATE,
+                        "failed to get root: no state"
+                    )
+                })
+        }
+
+        fn set_root(&self, root: &Cid) -> Result<(), ActorError> {
+            *self.root.borrow_mut() = Some(*root);
+            self.root_writes.set(self.root_writes.get() + 1);
+            Ok(())
+        }
+
+        fn send(
+            &self,
+            to: &Address,
+            method: MethodNum,
+            params: RawBytes,
+            value: TokenAmount,
+        ) -> Result<Receipt, ActorError> {
+            let message = SentMessage { to: *to, method, params, value };
+            self.sent.borrow_mut().push(message.clone());
+
+            let receipt = match self.send_handler.borrow().as_ref() {
+                Some(handler) => {
+                    let (caller, origin) = (self.caller.get(), self.origin.get());
+                    if let Some(id) = self.resolve_address(to) {
+                        self.caller.set(id);
+                    }
+                    let receipt = handler(self, &message);
+                    self.caller.set(caller);
+                    self.origin.set(origin);
+                    receipt
+                }
+                None => self.receipts.borrow_mut().pop_front().unwrap_or(Receipt {
+                    exit_code: ExitCode::OK,
+                    return_data: RawBytes::default(),
+                    gas_used: 0,
+                }),
+            };
+            if receipt.exit_code.is_success() {
+                *self.balance.borrow_mut() -= &message.value;
+            }
+
+            let is_key = matches!(to.protocol(), Protocol::Secp256k1 | Protocol::BLS);
+            if receipt.exit_code.is_success() && is_key && self.resolve_address(to).is_none() {
+                let id = self.next_id.get();
+                self.next_id.set(id + 1);
+                self.add_address(*to, id);
+            }
+            Ok(receipt)
+        }
+
+        fn verify_signature(
+            &self,
+            signature: &Signature,
+            signer: &Address,
+            plaintext: &[u8],
+        ) -> Result<bool, ActorError> {
+            Ok(*signature == MockRuntime::sign(signer, plaintext))
+        }
+    }
+}
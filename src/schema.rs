@@ -0,0 +1,583 @@
+//! A machine-readable description of the actor's methods and the CBOR layout
+//! of their params and return values, returned by the `Describe` method.
+//!
+//! Every params and return struct encodes as a CBOR array of its fields in
+//! declaration order, so a type is described by its field names and wire
+//! types in that order. The one exception, `TransferBatchParams`, encodes as
+//! its list of transfers alone, so that method lists the list's wire type.
+//! Wire types are written as:
+//!
+//! - `u8`, `u16`, `u32`, `u64`, `i64`, `bool`, `string`, `bytes`, `address`, `cid`
+//! - `bigint`, for token amounts in the FVM's signed big-integer byte format
+//! - `optional<T>` for T or null, `list<T>` for an array of T,
+//!   `tuple<A, B>` for a two-element array, and `map<string, T>` for a map
+//! - the name of another described type, for a nested struct
+//!
+//! Every method's return value reaches the caller wrapped in a
+//! `MethodReturn`, whose `data` holds the encoding of the type listed here.
+
+use fvm_ipld_encoding::tuple::{Deserialize_tuple, Serialize_tuple};
+use fvm_ipld_encoding::Cbor;
+use fvm_shared::MethodNum;
+
+use crate::access_control::RoleParams;
+use crate::allowance::{
+    AllowanceParams, AllowancesOfParams, ApproveAndCallParams, ApproveParams,
+    CompareAndSetAllowanceParams, DecreaseAllowanceParams, IncreaseAllowanceParams,
+    IsOperatorParams, RevokeAllowanceParams, SetOperatorParams, TransferAndApproveParams,
+};
+use crate::claim::{BalanceIncludingPendingParams, SetClaimTermsParams};
+use crate::governance::{ConfigureGovernanceParams, ProposalIdParams, ProposeParams, Voter};
+use crate::multicall::{Call, MulticallParams};
+use crate::permit::{NonceOfParams, PermitMessage, PermitParams};
+use crate::wrap::WithdrawParams;
+use crate::{
+    method, ActorInfo, BalanceAtSnapshotParams, BalanceOfIdParams, BalanceOfInUnitParams,
+    BurnFromParams, BurnParams, CanReceiveParams, ConstructorParams, ConstructorReturn,
+    Eligibility, ForceTransferParams, Frc46TransferParams, FreezeAccountParams, HashedReceipt,
+    IdempotentTransferParams, InvariantReport, LockParams, MethodReturn, MintBatchParams,
+    MintWindowParams, PendingOperation, PendingOperationParams, RateLimit, ReadFieldsParams,
+    RecentTransfersParams, RecomputeTotalSupplyParams, RecomputeTotalSupplyReturn, RescueParams,
+    SecondaryUnit, SetHookAllowedParams, SetMaxHoldersParams, SetMaxSupplyParams,
+    SetMetadataUriParams, SetMinTransferParams, SetMintScheduleParams, SetRateLimitParams,
+    SetTransferFeeParams, SimulationResult, SplitBalanceParams, SplitTarget, StateSummary,
+    TransferFromParams, TransferFromReturn, TransferMemoParams, TransferOwnershipParams,
+    TransferParams, TransferReceipt, TransferRecord, TransferReturn, TransferSplitParams,
+    TransferUntilParams,
+};
+
+/// One field of a described type.
+#[derive(Serialize_tuple, Deserialize_tuple, Clone, Debug, PartialEq)]
+pub struct FieldSchema {
+    pub name: String,
+    pub ty: String,
+}
+
+/// A struct's fields, in the order they are encoded.
+#[derive(Serialize_tuple, Deserialize_tuple, Clone, Debug, PartialEq)]
+pub struct TypeSchema {
+    pub name: String,
+    pub fields: Vec<FieldSchema>,
+}
+
+/// A method's number and the wire types of its params and return value. None
+/// means the method takes no params, or returns nothing.
+#[derive(Serialize_tuple, Deserialize_tuple, Clone, Debug, PartialEq)]
+pub struct MethodSchema {
+    pub name: String,
+    pub number: MethodNum,
+    pub params: Option<String>,
+    pub ret: Option<String>,
+}
+
+/// Every exported method, and every struct their params and return values
+/// are built from.
+#[derive(Serialize_tuple, Deserialize_tuple, Clone, Debug, PartialEq)]
+pub struct ActorSchema {
+    pub methods: Vec<MethodSchema>,
+    pub types: Vec<TypeSchema>,
+}
+
+impl Cbor for ActorSchema {}
+
+impl ActorSchema {
+    pub fn method(&self, name: &str) -> Option<&MethodSchema> {
+        self.methods.iter().find(|m| m.name == name)
+    }
+
+    pub fn type_named(&self, name: &str) -> Option<&TypeSchema> {
+        self.types.iter().find(|t| t.name == name)
+    }
+}
+
+/// Describe a struct's fields in encoding order. The destructuring pattern
+/// stops compiling if a field is missing from the list or misnamed, so the
+/// description can't silently drift from the struct; keeping the listed order
+/// the same as the declared order is up to the author.
+macro_rules! describe_type {
+    ($ty:ident { $($field:ident: $wire:expr),* $(,)? }) => {{
+        #[allow(dead_code)]
+        fn check_fields(value: &$ty) {
+            let $ty { $($field: _),* } = value;
+        }
+        TypeSchema {
+            name: stringify!($ty).to_string(),
+            fields: vec![$(FieldSchema {
+                name: stringify!($field).to_string(),
+                ty: $wire.to_string(),
+            }),*],
+        }
+    }};
+}
+
+fn describe_method(
+    name: &str,
+    number: MethodNum,
+    params: Option<&str>,
+    ret: Option<&str>,
+) -> MethodSchema {
+    MethodSchema {
+        name: name.to_string(),
+        number,
+        params: params.map(str::to_string),
+        ret: ret.map(str::to_string),
+    }
+}
+
+fn methods() -> Vec<MethodSchema> {
+    let m = describe_method;
+    vec![
+        m("Constructor", method::CONSTRUCTOR, Some("ConstructorParams"), Some("ConstructorReturn")),
+        m("Mint", method::MINT, Some("TransferParams"), Some("bigint")),
+        m("MintBatch", method::MINT_BATCH, Some("MintBatchParams"), None),
+        m("MintIdempotent", method::MINT_IDEMPOTENT, Some("IdempotentTransferParams"), None),
+        m("Deposit", method::DEPOSIT, None, None),
+        m("Withdraw", method::WITHDRAW, Some("WithdrawParams"), None),
+        m("Burn", method::BURN, Some("BurnParams"), None),
+        m("BurnFrom", method::BURN_FROM, Some("BurnFromParams"), None),
+        m("SetClaimTerms", method::SET_CLAIM_TERMS, Some("SetClaimTermsParams"), None),
+        m("Claim", method::CLAIM, None, None),
+        m("ReclaimUnclaimed", method::RECLAIM_UNCLAIMED, None, Some("bigint")),
+        m("SetMaxSupply", method::SET_MAX_SUPPLY, Some("SetMaxSupplyParams"), None),
+        m("SetMintSchedule", method::SET_MINT_SCHEDULE, Some("SetMintScheduleParams"), None),
+        m("Transfer", method::TRANSFER, Some("Frc46TransferParams"), Some("TransferReturn")),
+        m("SafeTransfer", method::SAFE_TRANSFER, Some("TransferParams"), Some("bool")),
+        m(
+            "TransferFrom",
+            method::TRANSFER_FROM,
+            Some("TransferFromParams"),
+            Some("TransferFromReturn"),
+        ),
+        m("Approve", method::APPROVE, Some("ApproveParams"), None),
+        m("Allowance", method::ALLOWANCE, Some("AllowanceParams"), Some("bigint")),
+        m(
+            "IncreaseAllowance",
+            method::INCREASE_ALLOWANCE,
+            Some("IncreaseAllowanceParams"),
+            Some("bigint"),
+        ),
+        m(
+            "DecreaseAllowance",
+            method::DECREASE_ALLOWANCE,
+            Some("DecreaseAllowanceParams"),
+            Some("bigint"),
+        ),
+        m("RevokeAllowance", method::REVOKE_ALLOWANCE, Some("RevokeAllowanceParams"), None),
+        m("Granularity", method::GRANULARITY, None, Some("u64")),
+        m("SetOperator", method::SET_OPERATOR, Some("SetOperatorParams"), None),
+        m("IsOperator", method::IS_OPERATOR, Some("IsOperatorParams"), Some("bool")),
+        m(
+            "TransferWithReceipt",
+            method::TRANSFER_WITH_RECEIPT,
+            Some("Frc46TransferParams"),
+            Some("HashedReceipt"),
+        ),
+        m(
+            "TransferWithMemo",
+            method::TRANSFER_WITH_MEMO,
+            Some("TransferMemoParams"),
+            Some("HashedReceipt"),
+        ),
+        m(
+            "TransferUntil",
+            method::TRANSFER_UNTIL,
+            Some("TransferUntilParams"),
+            Some("HashedReceipt"),
+        ),
+        m(
+            "TransferIdempotent",
+            method::TRANSFER_IDEMPOTENT,
+            Some("IdempotentTransferParams"),
+            Some("HashedReceipt"),
+        ),
+        m(
+            "SimulateTransfer",
+            method::SIMULATE_TRANSFER,
+            Some("TransferParams"),
+            Some("SimulationResult"),
+        ),
+        m("CanReceive", method::CAN_RECEIVE, Some("CanReceiveParams"), Some("Eligibility")),
+        m(
+            "RecentTransfers",
+            method::RECENT_TRANSFERS,
+            Some("RecentTransfersParams"),
+            Some("list<TransferRecord>"),
+        ),
+        m("TransferSplit", method::TRANSFER_SPLIT, Some("TransferSplitParams"), None),
+        m("TransferBatch", method::TRANSFER_BATCH, Some("list<TransferParams>"), None),
+        m("Multicall", method::MULTICALL, Some("MulticallParams"), Some("list<bytes>")),
+        m("Permit", method::PERMIT, Some("PermitParams"), None),
+        m(
+            "TransferAndApprove",
+            method::TRANSFER_AND_APPROVE,
+            Some("TransferAndApproveParams"),
+            Some("TransferReturn"),
+        ),
+        m(
+            "AllowancesOf",
+            method::ALLOWANCES_OF,
+            Some("AllowancesOfParams"),
+            Some("list<tuple<u64, bigint>>"),
+        ),
+        m("ApproveAndCall", method::APPROVE_AND_CALL, Some("ApproveAndCallParams"), Some("bytes")),
+        m("NonceOf", method::NONCE_OF, Some("NonceOfParams"), Some("u64")),
+        m(
+            "CompareAndSetAllowance",
+            method::COMPARE_AND_SET_ALLOWANCE,
+            Some("CompareAndSetAllowanceParams"),
+            None,
+        ),
+        m("ForceTransfer", method::FORCE_TRANSFER, Some("ForceTransferParams"), None),
+        m("Rescue", method::RESCUE, Some("RescueParams"), None),
+        m("SplitBalance", method::SPLIT_BALANCE, Some("SplitBalanceParams"), None),
+        m("SetUnitRate", method::SET_UNIT_RATE, Some("SecondaryUnit"), None),
+        m(
+            "BalanceOfInUnit",
+            method::BALANCE_OF_IN_UNIT,
+            Some("BalanceOfInUnitParams"),
+            Some("bigint"),
+        ),
+        m("BalanceOf", method::BALANCE_OF, Some("address"), Some("bigint")),
+        m("BalanceOfId", method::BALANCE_OF_ID, Some("BalanceOfIdParams"), Some("bigint")),
+        m(
+            "BalanceIncludingPending",
+            method::BALANCE_INCLUDING_PENDING,
+            Some("BalanceIncludingPendingParams"),
+            Some("bigint"),
+        ),
+        m("Snapshot", method::SNAPSHOT, None, None),
+        m(
+            "BalanceAtSnapshot",
+            method::BALANCE_AT_SNAPSHOT,
+            Some("BalanceAtSnapshotParams"),
+            Some("bigint"),
+        ),
+        m("SetMetadataUri", method::SET_METADATA_URI, Some("SetMetadataUriParams"), None),
+        m("MetadataUri", method::METADATA_URI, None, Some("optional<string>")),
+        m("Owner", method::OWNER, None, Some("address")),
+        m("Name", method::NAME, None, Some("string")),
+        m("Symbol", method::SYMBOL, None, Some("string")),
+        m("Decimals", method::DECIMALS, None, Some("u8")),
+        m("TotalSupply", method::TOTAL_SUPPLY, None, Some("bigint")),
+        m("MaxSupply", method::MAX_SUPPLY, None, Some("bigint")),
+        m("ActorInfo", method::ACTOR_INFO, None, Some("ActorInfo")),
+        m("StateSummary", method::STATE_SUMMARY, None, Some("StateSummary")),
+        m("TransferOwnership", method::TRANSFER_OWNERSHIP, Some("TransferOwnershipParams"), None),
+        m("AcceptOwnership", method::ACCEPT_OWNERSHIP, None, None),
+        m("CancelOwnershipTransfer", method::CANCEL_OWNERSHIP_TRANSFER, None, None),
+        m("RenounceOwnership", method::RENOUNCE_OWNERSHIP, None, None),
+        m("GrantRole", method::GRANT_ROLE, Some("RoleParams"), None),
+        m("RevokeRole", method::REVOKE_ROLE, Some("RoleParams"), None),
+        m("HasRole", method::HAS_ROLE, Some("RoleParams"), Some("bool")),
+        m("Pause", method::PAUSE, None, None),
+        m("Unpause", method::UNPAUSE, None, None),
+        m("FreezeAccount", method::FREEZE_ACCOUNT, Some("FreezeAccountParams"), None),
+        m("UnfreezeAccount", method::UNFREEZE_ACCOUNT, Some("FreezeAccountParams"), None),
+        m("IsFrozen", method::IS_FROZEN, Some("FreezeAccountParams"), Some("bool")),
+        m("SetRateLimit", method::SET_RATE_LIMIT, Some("SetRateLimitParams"), None),
+        m("SetMinTransfer", method::SET_MIN_TRANSFER, Some("SetMinTransferParams"), None),
+        m("SetMaxHolders", method::SET_MAX_HOLDERS, Some("SetMaxHoldersParams"), None),
+        m("SetHookAllowed", method::SET_HOOK_ALLOWED, Some("SetHookAllowedParams"), None),
+        m("Lock", method::LOCK, Some("LockParams"), None),
+        m("SetTransferFee", method::SET_TRANSFER_FEE, Some("SetTransferFeeParams"), None),
+        m("ReadFields", method::READ_FIELDS, Some("ReadFieldsParams"), Some("map<string, bytes>")),
+        m("HolderCount", method::HOLDER_COUNT, None, Some("u64")),
+        m("VerifyInvariants", method::VERIFY_INVARIANTS, None, Some("InvariantReport")),
+        m(
+            "RecomputeTotalSupply",
+            method::RECOMPUTE_TOTAL_SUPPLY,
+            Some("RecomputeTotalSupplyParams"),
+            Some("RecomputeTotalSupplyReturn"),
+        ),
+        m(
+            "ConfigureGovernance",
+            method::CONFIGURE_GOVERNANCE,
+            Some("ConfigureGovernanceParams"),
+            None,
+        ),
+        m("Propose", method::PROPOSE, Some("ProposeParams"), Some("u64")),
+        m("Vote", method::VOTE, Some("ProposalIdParams"), None),
+        // Returns whatever the executed method returned.
+        m("Execute", method::EXECUTE, Some("ProposalIdParams"), Some("bytes")),
+        m(
+            "PendingOperation",
+            method::PENDING_OPERATION,
+            Some("PendingOperationParams"),
+            Some("PendingOperation"),
+        ),
+        m("Migrate", method::MIGRATE, None, None),
+        m("Describe", method::DESCRIBE, None, Some("ActorSchema")),
+    ]
+}
+
+fn types() -> Vec<TypeSchema> {
+    vec![
+        describe_type!(MethodReturn { code: "u32", data: "optional<bytes>" }),
+        describe_type!(ConstructorParams {
+            name: "string",
+            symbol: "string",
+            max_supply: "bigint",
+            owner: "address",
+            supply_oracle: "optional<address>",
+            hamt_bitwidth: "u32",
+            metadata_uri: "optional<string>",
+            require_receiver_hook: "bool",
+            wrapped: "bool",
+            decimals: "u8",
+        }),
+        describe_type!(ConstructorReturn {
+            state_root: "cid",
+            owner_id: "u64",
+            balances_root: "cid",
+        }),
+        describe_type!(TransferParams { recipient: "address", amount: "bigint" }),
+        describe_type!(Frc46TransferParams {
+            to: "address",
+            amount: "bigint",
+            operator_data: "bytes",
+        }),
+        describe_type!(TransferReturn {
+            from_balance: "bigint",
+            to_balance: "bigint",
+            recipient_data: "bytes",
+        }),
+        describe_type!(MintBatchParams { mints: "list<TransferParams>" }),
+        describe_type!(SetMaxSupplyParams { new_max: "bigint" }),
+        describe_type!(WithdrawParams { amount: "bigint" }),
+        describe_type!(BurnParams { amount: "bigint" }),
+        describe_type!(BurnFromParams { owner: "address", amount: "bigint" }),
+        describe_type!(SetClaimTermsParams {
+            pool: "address",
+            amount: "bigint",
+            deadline: "optional<i64>"
+        }),
+        describe_type!(MintWindowParams {
+            start_epoch: "i64",
+            end_epoch: "i64",
+            max_in_window: "bigint",
+        }),
+        describe_type!(SetMintScheduleParams { windows: "list<MintWindowParams>" }),
+        describe_type!(TransferMemoParams {
+            recipient: "address",
+            amount: "bigint",
+            memo: "bytes"
+        }),
+        describe_type!(TransferUntilParams {
+            recipient: "address",
+            amount: "bigint",
+            deadline: "i64",
+        }),
+        // The idempotency key is 32 bytes.
+        describe_type!(IdempotentTransferParams {
+            recipient: "address",
+            amount: "bigint",
+            idempotency_key: "optional<bytes>",
+        }),
+        describe_type!(TransferReceipt {
+            from: "u64",
+            to: "u64",
+            amount: "bigint",
+            epoch: "i64",
+            state_root: "cid",
+        }),
+        describe_type!(HashedReceipt { receipt: "TransferReceipt", hash: "bytes" }),
+        describe_type!(SimulationResult {
+            would_succeed: "bool",
+            reason: "optional<string>",
+            from_balance_after: "bigint",
+            to_balance_after: "bigint",
+        }),
+        describe_type!(CanReceiveParams { account: "address", amount: "bigint" }),
+        describe_type!(BalanceIncludingPendingParams { account: "address" }),
+        describe_type!(Eligibility { eligible: "bool", reason: "optional<string>" }),
+        describe_type!(RecentTransfersParams { limit: "u32" }),
+        describe_type!(TransferRecord { from: "u64", to: "u64", amount: "bigint", epoch: "i64" }),
+        // Each recipient is an `(address, weight)` pair.
+        describe_type!(TransferSplitParams {
+            recipients: "list<tuple<address, u32>>",
+            amount: "bigint"
+        }),
+        describe_type!(Call { method: "u64", params: "bytes" }),
+        describe_type!(MulticallParams { calls: "list<Call>" }),
+        describe_type!(ForceTransferParams { from: "address", to: "address", amount: "bigint" }),
+        describe_type!(RescueParams { to: "address" }),
+        describe_type!(SplitTarget { recipient: "address", amount: "bigint" }),
+        describe_type!(SplitBalanceParams { source: "address", targets: "list<SplitTarget>" }),
+        describe_type!(SecondaryUnit {
+            unit: "string",
+            rate_numerator: "bigint",
+            rate_denominator: "bigint",
+        }),
+        describe_type!(BalanceOfIdParams { id: "u64" }),
+        describe_type!(BalanceOfInUnitParams { account: "address", unit: "string" }),
+        describe_type!(BalanceAtSnapshotParams { account: "address", snapshot_index: "u32" }),
+        describe_type!(SetMetadataUriParams { uri: "optional<string>" }),
+        describe_type!(TransferOwnershipParams { new_owner: "address" }),
+        describe_type!(RateLimit { max_amount: "bigint", window_epochs: "i64" }),
+        describe_type!(SetRateLimitParams { limit: "optional<RateLimit>" }),
+        describe_type!(SetMinTransferParams { min_transfer: "bigint" }),
+        describe_type!(SetMaxHoldersParams { max_holders: "optional<u64>" }),
+        describe_type!(SetHookAllowedParams { actor: "address", allowed: "bool" }),
+        describe_type!(LockParams { account: "address", amount: "bigint", unlock_epoch: "i64" }),
+        describe_type!(TransferFromParams {
+            from: "address",
+            to: "address",
+            amount: "bigint",
+            operator_data: "bytes",
+        }),
+        describe_type!(TransferFromReturn {
+            from_balance: "bigint",
+            to_balance: "bigint",
+            allowance: "bigint",
+            recipient_data: "bytes",
+        }),
+        describe_type!(ApproveParams { spender: "address", amount: "bigint" }),
+        describe_type!(AllowanceParams { owner: "address", spender: "address" }),
+        describe_type!(IncreaseAllowanceParams { spender: "address", increase: "bigint" }),
+        describe_type!(DecreaseAllowanceParams { spender: "address", decrease: "bigint" }),
+        describe_type!(RevokeAllowanceParams { spender: "address" }),
+        describe_type!(PermitParams {
+            owner: "address",
+            spender: "address",
+            amount: "bigint",
+            deadline: "i64",
+            signature: "bytes",
+        }),
+        describe_type!(TransferAndApproveParams {
+            recipient: "address",
+            amount: "bigint",
+            grant_spender: "address",
+            grant_amount: "bigint",
+        }),
+        describe_type!(AllowancesOfParams {
+            owner: "address",
+            start_after: "optional<u64>",
+            limit: "u32",
+        }),
+        describe_type!(CompareAndSetAllowanceParams {
+            spender: "address",
+            expected: "bigint",
+            new: "bigint",
+        }),
+        describe_type!(ApproveAndCallParams {
+            spender: "address",
+            amount: "bigint",
+            call_method: "u64",
+            call_params: "bytes",
+        }),
+        describe_type!(NonceOfParams { owner: "address" }),
+        describe_type!(PermitMessage {
+            token: "address",
+            owner: "address",
+            spender: "address",
+            amount: "bytes",
+            deadline: "i64",
+            nonce: "u64",
+        }),
+        describe_type!(SetOperatorParams { operator: "address", approved: "bool" }),
+        describe_type!(IsOperatorParams { owner: "address", operator: "address" }),
+        describe_type!(RoleParams { role: "u8", account: "address" }),
+        describe_type!(FreezeAccountParams { account: "address" }),
+        describe_type!(SetTransferFeeParams { fee_bps: "u16", fee_collector: "optional<address>" }),
+        describe_type!(ReadFieldsParams { names: "list<string>" }),
+        describe_type!(ActorInfo { id: "u64", code_cid: "cid" }),
+        describe_type!(StateSummary {
+            name: "string",
+            symbol: "string",
+            owner: "address",
+            max_supply: "bigint",
+            total_supply: "bigint",
+            holder_count: "u64",
+            metadata_uri: "optional<string>",
+            require_receiver_hook: "bool",
+            fee_bps: "u16",
+            min_transfer: "bigint",
+        }),
+        describe_type!(InvariantReport {
+            balances_sum: "bigint",
+            total_supply: "bigint",
+            consistent: "bool",
+        }),
+        describe_type!(RecomputeTotalSupplyParams { start_after: "optional<u64>", limit: "u32" }),
+        describe_type!(RecomputeTotalSupplyReturn { next: "optional<u64>" }),
+        describe_type!(Voter { id: "u64", weight: "u64" }),
+        describe_type!(ConfigureGovernanceParams { voters: "list<Voter>", threshold: "u64" }),
+        describe_type!(ProposeParams { method: "u64", params: "bytes" }),
+        describe_type!(ProposalIdParams { id: "u64" }),
+        describe_type!(PendingOperationParams { id: "u64" }),
+        // `kind` and `status` are enums, encoded as their variant names.
+        describe_type!(PendingOperation {
+            kind: "string",
+            status: "string",
+            initiator: "u64",
+            deadline: "optional<i64>",
+            approved_weight: "u64",
+            required_weight: "u64",
+        }),
+        describe_type!(ActorSchema { methods: "list<MethodSchema>", types: "list<TypeSchema>" }),
+        describe_type!(MethodSchema {
+            name: "string",
+            number: "u64",
+            params: "optional<string>",
+            ret: "optional<string>",
+        }),
+        describe_type!(TypeSchema { name: "string", fields: "list<FieldSchema>" }),
+        describe_type!(FieldSchema { name: "string", ty: "string" }),
+    ]
+}
+
+/// Describe every exported method and the types they exchange.
+pub fn describe() -> ActorSchema {
+    ActorSchema { methods: methods(), types: types() }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use fvm_ipld_encoding::RawBytes;
+
+    #[test]
+    fn description_lists_transfer_params_in_encoding_order() {
+        let bytes = RawBytes::serialize(describe()).unwrap();
+        let schema: ActorSchema = bytes.deserialize().unwrap();
+
+        let transfer = schema.method("Transfer").unwrap();
+        assert_eq!(transfer.number, method::TRANSFER);
+        assert_eq!(transfer.params.as_deref(), Some("Frc46TransferParams"));
+        assert_eq!(transfer.ret.as_deref(), Some("TransferReturn"));
+
+        let params = schema.type_named("Frc46TransferParams").unwrap();
+        let fields: Vec<(&str, &str)> =
+            params.fields.iter().map(|f| (f.name.as_str(), f.ty.as_str())).collect();
+        assert_eq!(
+            fields,
+            vec![("to", "address"), ("amount", "bigint"), ("operator_data", "bytes")]
+        );
+    }
+
+    #[test]
+    fn description_is_self_contained() {
+        let schema = describe();
+
+        // Every method number is distinct.
+        let mut numbers: Vec<MethodNum> = schema.methods.iter().map(|m| m.number).collect();
+        numbers.sort_unstable();
+        numbers.dedup();
+        assert_eq!(numbers.len(), schema.methods.len());
+
+        // Every struct a method or field refers to is described.
+        let is_struct = |ty: &str| ty.starts_with(|c: char| c.is_ascii_uppercase());
+        let inner = |ty: &str| -> String {
+            let ty = ty.trim_start_matches("optional<").trim_start_matches("list<");
+            ty.trim_end_matches('>').to_string()
+        };
+        let method_types = schema.methods.iter().flat_map(|m| m.params.iter().chain(m.ret.iter()));
+        let field_types = schema.types.iter().flat_map(|t| t.fields.iter().map(|f| &f.ty));
+        for ty in method_types.chain(field_types) {
+            let ty = inner(ty);
+            if is_struct(&ty) {
+                assert!(schema.type_named(&ty).is_some(), "{} is not described", ty);
+            }
+        }
+    }
+}
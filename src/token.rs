@@ -0,0 +1,165 @@
+//! Helpers for working with token amounts.
+//!
+//! Amounts are always stored and transferred as integer base units. These
+//! helpers convert to and from the human-readable decimal form, where
+//! `decimals` is the number of base-unit digits after the decimal point. For
+//! bridges, they also convert to and from the 32-byte big-endian form of an EVM
+//! `uint256`.
+//!
+//! The actor itself only ever deals in base units, and every getter returns
+//! them, so these are for clients: wallets and scripts read `decimals` once and
+//! convert on their side.
+
+use anyhow::{anyhow, Result};
+use fvm_shared::bigint::Sign;
+use fvm_shared::econ::TokenAmount;
+
+/// Format a base-unit amount as a decimal string, e.g. `1500` with 3 decimals
+/// is `"1.5"`. Trailing fractional zeros are dropped, and whole amounts have no
+/// fractional part at all.
+pub fn format_amount(amount: &TokenAmount, decimals: u8) -> String {
+    let sign = if amount < &TokenAmount::from(0) { "-" } else { "" };
+    let digits = amount.magnitude().to_string();
+    let decimals = decimals as usize;
+
+    // Left-pad so there is at least one integer digit before the point.
+    let digits = if digits.len() <= decimals {
+        format!("{:0>width$}", digits, width = decimals + 1)
+    } else {
+        digits
+    };
+    let (int, frac) = digits.split_at(digits.len() - decimals);
+    let frac = frac.trim_end_matches('0');
+
+    if frac.is_empty() {
+        format!("{}{}", sign, int)
+    } else {
+        format!("{}{}.{}", sign, int, frac)
+    }
+}
+
+/// Parse a decimal string into base units, e.g. `"1.5"` with 3 decimals is
+/// `1500`. Fails if the string isn't a plain decimal number, or if it has more
+/// significant fractional digits than `decimals` allows.
+pub fn parse_amount(s: &str, decimals: u8) -> Result<TokenAmount> {
+    let (negative, body) = match s.strip_prefix('-') {
+        Some(rest) => (true, rest),
+        None => (false, s),
+    };
+    let (int, frac) = body.split_once('.').unwrap_or((body, ""));
+
+    let is_digits = |part: &str| part.chars().all(|c| c.is_ascii_digit());
+    if (int.is_empty() && frac.is_empty()) || !is_digits(int) || !is_digits(frac) {
+        return Err(anyhow!("invalid amount '{}'", s));
+    }
+
+    let frac = frac.trim_end_matches('0');
+    if frac.len() > decimals as usize {
+        return Err(anyhow!(
+            "amount '{}' has more than {} fractional digits",
+            s,
+            decimals
+        ));
+    }
+
+    let digits = format!("{}{:0<width$}", int, frac, width = decimals as usize);
+    let value = if digits.is_empty() {
+        TokenAmount::from(0)
+    } else {
+        TokenAmount::parse_bytes(digits.as_bytes(), 10)
+            .ok_or_else(|| anyhow!("invalid amount '{}'", s))?
+    };
+
+    Ok(if negative { -value } else { value })
+}
+
+/// Encode an amount as a 32-byte big-endian EVM `uint256`. Fails for negative
+/// amounts and for amounts of `2^256` or more, which a `uint256` can't hold.
+pub fn to_evm_u256(amount: &TokenAmount) -> Result<[u8; 32]> {
+    let (sign, bytes) = amount.to_bytes_be();
+    if sign == Sign::Minus {
+        return Err(anyhow!("amount {} is negative", amount));
+    }
+    if bytes.len() > 32 {
+        return Err(anyhow!("amount {} does not fit in 256 bits", amount));
+    }
+    let mut word = [0u8; 32];
+    word[32 - bytes.len()..].copy_from_slice(&bytes);
+    Ok(word)
+}
+
+/// Decode a 32-byte big-endian EVM `uint256` into an amount.
+pub fn from_evm_u256(bytes: &[u8; 32]) -> TokenAmount {
+    TokenAmount::from_bytes_be(Sign::Plus, bytes)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn amounts_round_trip_at_any_decimals() {
+        let amounts = [0i64, 1, 9, 10, 1500, 1_000_000, 123_456_789, -1, -1500];
+        for decimals in [0u8, 1, 3, 6, 18] {
+            for amount in amounts.iter().map(|n| TokenAmount::from(*n)) {
+                let formatted = format_amount(&amount, decimals);
+                assert_eq!(parse_amount(&formatted, decimals).unwrap(), amount, "{}", formatted);
+            }
+        }
+    }
+
+    #[test]
+    fn format_drops_trailing_zeros_and_whole_fractions() {
+        let format = |n: i64, decimals: u8| format_amount(&TokenAmount::from(n), decimals);
+        assert_eq!(format(1500, 3), "1.5");
+        assert_eq!(format(1050, 3), "1.05");
+        assert_eq!(format(3000, 3), "3");
+        assert_eq!(format(0, 3), "0");
+        assert_eq!(format(5, 3), "0.005");
+        assert_eq!(format(1500, 0), "1500");
+        assert_eq!(format(-1500, 3), "-1.5");
+        assert_eq!(format(-5, 3), "-0.005");
+    }
+
+    #[test]
+    fn parse_accepts_plain_decimals_only() {
+        let parse = |s: &str, decimals: u8| parse_amount(s, decimals).ok();
+        let amount = |n: i64| Some(TokenAmount::from(n));
+        assert_eq!(parse("1.5", 3), amount(1500));
+        assert_eq!(parse("1.500", 3), amount(1500));
+        assert_eq!(parse("1.5000", 3), amount(1500));
+        assert_eq!(parse("3", 3), amount(3000));
+        assert_eq!(parse("1.", 3), amount(1000));
+        assert_eq!(parse(".5", 3), amount(500));
+        assert_eq!(parse("-1.5", 3), amount(-1500));
+        assert_eq!(parse("7.0", 0), amount(7));
+
+        // Too many significant fractional digits.
+        assert_eq!(parse("1.0005", 3), None);
+        assert_eq!(parse("1.5", 0), None);
+        for invalid in ["", ".", "-", "--1", "1.2.3", "1e3", " 1", "+1", "1,5", "0x10"] {
+            assert_eq!(parse(invalid, 3), None, "{:?}", invalid);
+        }
+    }
+
+    #[test]
+    fn evm_u256_round_trips_boundary_values() {
+        let max = (TokenAmount::from(1) << 256usize) - 1;
+        for amount in [TokenAmount::from(0), TokenAmount::from(1), TokenAmount::from(256), max] {
+            let word = to_evm_u256(&amount).unwrap();
+            assert_eq!(from_evm_u256(&word), amount);
+        }
+
+        assert_eq!(to_evm_u256(&TokenAmount::from(0)).unwrap(), [0; 32]);
+        let mut one = [0; 32];
+        one[31] = 1;
+        assert_eq!(to_evm_u256(&TokenAmount::from(1)).unwrap(), one);
+        assert_eq!(from_evm_u256(&[0xff; 32]), (TokenAmount::from(1) << 256usize) - 1);
+    }
+
+    #[test]
+    fn evm_u256_rejects_amounts_out_of_range() {
+        assert!(to_evm_u256(&(TokenAmount::from(1) << 256usize)).is_err());
+        assert!(to_evm_u256(&TokenAmount::from(-1)).is_err());
+    }
+}
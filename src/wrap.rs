@@ -0,0 +1,119 @@
+//! Wrapped FIL.
+//!
+//! A token constructed with `wrapped` set is backed one to one by the FIL the
+//! actor holds. `deposit` mints the caller a token for each attoFIL attached
+//! to the message, and `withdraw` burns tokens to send the FIL back. The
+//! owner can't mint wrapped tokens any other way.
+
+use fvm_ipld_encoding::tuple::{Deserialize_tuple, Serialize_tuple};
+use fvm_ipld_encoding::{Cbor, RawBytes};
+use fvm_shared::address::Address;
+use fvm_shared::bigint::bigint_ser;
+use fvm_shared::econ::TokenAmount;
+use fvm_shared::METHOD_SEND;
+
+use crate::error::ActorError;
+use crate::runtime::Runtime;
+use crate::{
+    burn_tokens, check_locks, mint_tokens, require_positive, runtime_context, State, TokenAmounts,
+    TransferParams,
+};
+
+/// Fail unless the token is a wrapped token.
+fn require_wrapped(state: &State, method: &str) -> Result<(), ActorError> {
+    if !state.wrapped {
+        return Err(actor_error!(USR_FORBIDDEN, "{} is only available on wrapped tokens", method));
+    }
+    Ok(())
+}
+
+/// Fail if the token is a wrapped token, whose tokens are only ever minted
+/// for FIL deposited with `deposit`.
+pub(crate) fn require_unwrapped(state: &State, method: &str) -> Result<(), ActorError> {
+    if state.wrapped {
+        return Err(actor_error!(
+            kind: Forbidden,
+            USR_FORBIDDEN,
+            "{} is not available on wrapped tokens",
+            method
+        ));
+    }
+    Ok(())
+}
+
+/// Mint the caller tokens equal to the FIL attached to the message. Only
+/// wrapped tokens accept deposits, and `max_supply` doesn't limit them: every
+/// token is backed by the FIL deposited for it.
+pub fn deposit<R: Runtime>(rt: &R) -> Result<(), ActorError> {
+    let mut state = State::load_from(rt)?;
+    state.check_unlocked()?;
+    require_wrapped(&state, "deposit")?;
+
+    let resolver = |addr: &Address| rt.resolve_address(addr);
+    let ctx = runtime_context(rt, &resolver);
+    let recipient = Address::new_id(ctx.caller);
+    let params = TransferParams { recipient, amount: rt.value_received() };
+    mint_tokens(&ctx, &mut state, &params)?;
+    state.save_to(rt)?;
+    Ok(())
+}
+
+/// The input parameters for withdrawing FIL from a wrapped token.
+#[derive(Serialize_tuple, Deserialize_tuple, Clone, Debug)]
+pub struct WithdrawParams {
+    #[serde(with = "bigint_ser")]
+    pub amount: TokenAmount,
+}
+
+impl Cbor for WithdrawParams {}
+
+impl TokenAmounts for WithdrawParams {
+    fn token_amounts(&self) -> Vec<&TokenAmount> {
+        vec![&self.amount]
+    }
+}
+
+/// Burn `amount` of the caller's tokens and send it the same amount of FIL.
+/// Only wrapped tokens can be withdrawn from, and locked tokens can't be.
+///
+/// The tokens are burned before the FIL is sent, and the send holds the
+/// reentrancy lock, so the recipient can't spend them again while it runs.
+/// If the send fails the burn is undone.
+pub fn withdraw<R: Runtime>(rt: &R, params: WithdrawParams) -> Result<(), ActorError> {
+    let mut state = State::load_from(rt)?;
+    state.check_unlocked()?;
+    require_wrapped(&state, "withdraw")?;
+    require_positive(&params.amount)?;
+
+    let available = rt.current_balance();
+    if available < params.amount {
+        return Err(actor_error!(
+            USR_INSUFFICIENT_FUNDS,
+            "withdrawal of {} exceeds the actor's FIL balance of {}",
+            params.amount,
+            available
+        ));
+    }
+
+    let caller = rt.caller();
+    let before = state.clone();
+    check_locks(rt.store(), &mut state, caller, &params.amount, rt.curr_epoch())?;
+    burn_tokens(rt.store(), &mut state, caller, &params.amount)?;
+
+    let to = Address::new_id(caller);
+    let sent =
+        state.locked_send_value(rt, &to, METHOD_SEND, RawBytes::default(), params.amount.clone());
+    let err = match sent {
+        Ok(receipt) if receipt.exit_code.is_success() => return Ok(()),
+        Ok(receipt) => actor_error!(
+            USR_ILLEGAL_STATE,
+            "failed to send {} to {} with exit code {:?}",
+            params.amount,
+            to,
+            receipt.exit_code
+        ),
+        Err(err) => err,
+    };
+    before.save_to(rt)?;
+    Err(err)
+}
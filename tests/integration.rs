@@ -0,0 +1,322 @@
+//! End-to-end tests that run the compiled actor inside a real FVM.
+//!
+//! The build script compiles the actor to WASM as part of every `cargo build`
+//! and `cargo test`, writing it to `target/debug/wbuild/`. These tests load that
+//! binary from disk, so just run `cargo test` from the crate root; if the file
+//! is missing, run `cargo build` once and try again.
+
+use std::collections::BTreeMap;
+
+use fil_hello_world_actor::constants::INIT_ACTOR_ADDR;
+use fil_hello_world_actor::params::ParamsBuilder;
+use fil_hello_world_actor::{
+    method, ActorInfo, ConstructorReturn, Frc46TransferParams, MethodReturn, ReadFieldsParams,
+    TransferParams, TransferReturn,
+};
+use fvm::executor::{ApplyKind, ApplyRet, Executor};
+use fvm_integration_tests::dummy::DummyExterns;
+use fvm_integration_tests::tester::{Account, Tester};
+use fvm_ipld_blockstore::MemoryBlockstore;
+use fvm_ipld_encoding::RawBytes;
+use fvm_shared::address::Address;
+use fvm_shared::bigint::bigint_ser::{BigIntDe, BigIntSer};
+use fvm_shared::bigint::BigInt;
+use fvm_shared::econ::TokenAmount;
+use fvm_shared::error::ExitCode;
+use fvm_shared::message::Message;
+use fvm_shared::state::StateTreeVersion;
+use fvm_shared::version::NetworkVersion;
+use fvm_shared::{ActorID, MethodNum};
+
+const WASM_COMPILED_PATH: &str =
+    "target/debug/wbuild/fil_hello_world_actor/fil_hello_world_actor.compact.wasm";
+
+/// The ID the actor under test is installed at.
+const ACTOR_ID: ActorID = 10000;
+
+/// The ID a mock supply oracle is installed at, if there is one.
+const ORACLE_ID: ActorID = 10001;
+
+/// A supply oracle that answers every call with `cap`, written directly in
+/// WAT. Its `invoke` stores the CBOR-encoded cap as a block through the
+/// `ipld::block_create` syscall, which writes the new block's ID to memory
+/// offset 0, and returns that ID as the call's return block.
+fn oracle_wasm(cap: u64) -> Vec<u8> {
+    let data = RawBytes::serialize(BigIntSer(&TokenAmount::from(cap))).unwrap();
+    let escaped: String = data.bytes().iter().map(|byte| format!("\\{:02x}", byte)).collect();
+    let wat = format!(
+        r#"(module
+            (import "ipld" "block_create"
+                (func $block_create (param i32 i64 i32 i32) (result i32)))
+            (memory (export "memory") 1)
+            (data (i32.const 16) "{escaped}")
+            (func (export "invoke") (param i32) (result i32)
+                (drop (call $block_create
+                    (i32.const 0) (i64.const 0x71) (i32.const 16) (i32.const {len})))
+                (i32.load (i32.const 0))))"#,
+        escaped = escaped,
+        len = data.bytes().len(),
+    );
+    wat::parse_str(wat).unwrap()
+}
+
+/// An FVM with the actor installed and two funded accounts. Account 0 owns
+/// the token.
+struct TestEnv {
+    tester: Tester<MemoryBlockstore, DummyExterns>,
+    accounts: [Account; 2],
+    sequences: [u64; 2],
+    oracle: Option<Address>,
+}
+
+impl TestEnv {
+    fn new() -> Self {
+        let mut env = TestEnv::installed();
+        env.construct();
+        env
+    }
+
+    /// An FVM with the actor constructed to take its supply cap from a mock
+    /// oracle that always answers `cap`.
+    fn with_oracle(cap: u64) -> Self {
+        let mut env = TestEnv::installed_with(Some(cap));
+        env.construct();
+        env
+    }
+
+    /// An FVM with the actor installed but not yet constructed.
+    fn installed() -> Self {
+        TestEnv::installed_with(None)
+    }
+
+    fn installed_with(oracle_cap: Option<u64>) -> Self {
+        let mut tester = Tester::new(
+            NetworkVersion::V15,
+            StateTreeVersion::V4,
+            MemoryBlockstore::default(),
+        )
+        .unwrap();
+        let accounts: [Account; 2] = tester.create_accounts().unwrap();
+
+        let wasm_bin = std::fs::read(WASM_COMPILED_PATH)
+            .expect("actor WASM not found; run `cargo build` first");
+
+        // Placeholder state; the constructor replaces it.
+        let state_cid = tester.set_state(&()).unwrap();
+        tester
+            .set_actor_from_bin(&wasm_bin, state_cid, Address::new_id(ACTOR_ID), BigInt::from(0))
+            .unwrap();
+        let oracle = oracle_cap.map(|cap| {
+            let oracle = Address::new_id(ORACLE_ID);
+            let wasm_bin = oracle_wasm(cap);
+            tester.set_actor_from_bin(&wasm_bin, state_cid, oracle, BigInt::from(0)).unwrap();
+            oracle
+        });
+        tester.instantiate_machine(DummyExterns).unwrap();
+
+        TestEnv { tester, accounts, sequences: [0; 2], oracle }
+    }
+
+    fn address(&self, account: usize) -> Address {
+        self.accounts[account].1
+    }
+
+    fn id(&self, account: usize) -> ActorID {
+        self.accounts[account].0
+    }
+
+    fn execute(&mut self, message: Message, kind: ApplyKind) -> ApplyRet {
+        self.tester
+            .executor
+            .as_mut()
+            .unwrap()
+            .execute_message(message, kind, 100)
+            .unwrap()
+    }
+
+    /// Run the constructor as the init actor would, returning what it created.
+    fn construct(&mut self) -> ConstructorReturn {
+        let max_supply = TokenAmount::from(1_000_000);
+        let mut params =
+            ParamsBuilder::constructor("Test Token", "TEST", max_supply, self.address(0))
+                .hamt_bitwidth(5);
+        if let Some(oracle) = self.oracle {
+            params = params.supply_oracle(oracle);
+        }
+        let params = params.encode().unwrap();
+        let message = Message {
+            from: Address::new_id(INIT_ACTOR_ADDR),
+            to: Address::new_id(ACTOR_ID),
+            gas_limit: 1_000_000_000,
+            method_num: method::CONSTRUCTOR,
+            params,
+            ..Message::default()
+        };
+        let ret = self.execute(message, ApplyKind::Implicit);
+        assert_eq!(ret.msg_receipt.exit_code, ExitCode::OK, "{:?}", ret.failure_info);
+        return_data(&ret).deserialize().unwrap()
+    }
+
+    /// Send a message to the actor from one of the test accounts.
+    fn call(&mut self, from: usize, method_num: MethodNum, params: RawBytes) -> ApplyRet {
+        let message = Message {
+            from: self.address(from),
+            to: Address::new_id(ACTOR_ID),
+            gas_limit: 1_000_000_000,
+            method_num,
+            params,
+            sequence: self.sequences[from],
+            ..Message::default()
+        };
+        self.sequences[from] += 1;
+        self.execute(message, ApplyKind::Explicit)
+    }
+
+    fn mint(&mut self, recipient: usize, amount: u64) -> ApplyRet {
+        let params = TransferParams {
+            recipient: self.address(recipient),
+            amount: TokenAmount::from(amount),
+        };
+        self.call(0, method::MINT, RawBytes::serialize(params).unwrap())
+    }
+
+    fn transfer(&mut self, from: usize, to: usize, amount: u64) -> ApplyRet {
+        let params = Frc46TransferParams {
+            to: self.address(to),
+            amount: TokenAmount::from(amount),
+            operator_data: RawBytes::default(),
+        };
+        self.call(from, method::TRANSFER, RawBytes::serialize(params).unwrap())
+    }
+
+    fn total_supply(&mut self) -> TokenAmount {
+        let params = ReadFieldsParams { names: vec!["total_supply".to_string()] };
+        let ret = self.call(0, method::READ_FIELDS, RawBytes::serialize(params).unwrap());
+        assert_eq!(ret.msg_receipt.exit_code, ExitCode::OK, "{:?}", ret.failure_info);
+
+        let fields: BTreeMap<String, RawBytes> = return_data(&ret).deserialize().unwrap();
+        fields["total_supply"].deserialize::<BigIntDe>().unwrap().0
+    }
+}
+
+/// Unwrap the method's own return value from the envelope non-FRC-0046 methods return.
+fn return_data(ret: &ApplyRet) -> RawBytes {
+    let envelope: MethodReturn = ret.msg_receipt.return_data.deserialize().unwrap();
+    envelope.data.unwrap()
+}
+
+#[test]
+fn constructor_returns_the_owner_id_and_roots() {
+    let mut env = TestEnv::installed();
+
+    let created = env.construct();
+    assert_eq!(created.owner_id, env.id(0));
+    assert_ne!(created.state_root, created.balances_root);
+
+    // The owner the constructor reports is the one that can mint.
+    let ret = env.mint(1, 100);
+    assert_eq!(ret.msg_receipt.exit_code, ExitCode::OK, "{:?}", ret.failure_info);
+}
+
+#[test]
+fn mint_increases_total_supply() {
+    let mut env = TestEnv::new();
+
+    let ret = env.mint(0, 100);
+    assert_eq!(ret.msg_receipt.exit_code, ExitCode::OK, "{:?}", ret.failure_info);
+
+    assert_eq!(env.total_supply(), TokenAmount::from(100));
+}
+
+#[test]
+fn mints_stop_at_the_oracle_supply_cap() {
+    let mut env = TestEnv::with_oracle(150);
+
+    let ret = env.mint(0, 100);
+    assert_eq!(ret.msg_receipt.exit_code, ExitCode::OK, "{:?}", ret.failure_info);
+
+    // The max supply would allow this, but the oracle's cap doesn't.
+    let ret = env.mint(1, 51);
+    assert_eq!(ret.msg_receipt.exit_code, ExitCode::USR_ILLEGAL_ARGUMENT);
+    assert_eq!(env.total_supply(), TokenAmount::from(100));
+
+    let ret = env.mint(1, 50);
+    assert_eq!(ret.msg_receipt.exit_code, ExitCode::OK, "{:?}", ret.failure_info);
+    assert_eq!(env.total_supply(), TokenAmount::from(150));
+}
+
+#[test]
+fn transfer_moves_tokens_to_recipient() {
+    let mut env = TestEnv::new();
+    env.mint(0, 100);
+
+    let ret = env.transfer(0, 1, 40);
+    assert_eq!(ret.msg_receipt.exit_code, ExitCode::OK, "{:?}", ret.failure_info);
+
+    // Transfer is an FRC-0046 method, so its return value isn't enveloped.
+    let ret: TransferReturn = ret.msg_receipt.return_data.deserialize().unwrap();
+    assert_eq!(ret.from_balance, TokenAmount::from(60));
+    assert_eq!(ret.to_balance, TokenAmount::from(40));
+
+    // The recipient can now spend what it received, but no more.
+    let ret = env.transfer(1, 0, 40);
+    assert_eq!(ret.msg_receipt.exit_code, ExitCode::OK, "{:?}", ret.failure_info);
+    let ret = env.transfer(1, 0, 1);
+    assert_eq!(ret.msg_receipt.exit_code, ExitCode::USR_INSUFFICIENT_FUNDS);
+}
+
+#[test]
+fn transfer_with_insufficient_balance_fails() {
+    let mut env = TestEnv::new();
+    env.mint(0, 10);
+
+    let ret = env.transfer(0, 1, 11);
+    assert_eq!(ret.msg_receipt.exit_code, ExitCode::USR_INSUFFICIENT_FUNDS);
+
+    // Nothing moved, so the full balance is still spendable.
+    let ret = env.transfer(0, 1, 10);
+    assert_eq!(ret.msg_receipt.exit_code, ExitCode::OK, "{:?}", ret.failure_info);
+}
+
+#[test]
+fn negative_amounts_are_rejected_before_any_state_change() {
+    let mut env = TestEnv::new();
+    env.mint(0, 100);
+
+    // ParamsBuilder refuses negative amounts, so encode the params directly.
+    let params = TransferParams { recipient: env.address(0), amount: TokenAmount::from(-50) };
+    let ret = env.call(0, method::MINT, RawBytes::serialize(&params).unwrap());
+    assert_eq!(ret.msg_receipt.exit_code, ExitCode::USR_ILLEGAL_ARGUMENT);
+    let params = Frc46TransferParams {
+        to: env.address(1),
+        amount: params.amount,
+        operator_data: RawBytes::default(),
+    };
+    let ret = env.call(0, method::TRANSFER, RawBytes::serialize(&params).unwrap());
+    assert_eq!(ret.msg_receipt.exit_code, ExitCode::USR_ILLEGAL_ARGUMENT);
+
+    assert_eq!(env.total_supply(), TokenAmount::from(100));
+    let ret = env.transfer(0, 1, 100);
+    assert_eq!(ret.msg_receipt.exit_code, ExitCode::OK, "{:?}", ret.failure_info);
+}
+
+#[test]
+fn calls_without_params_fail_with_an_argument_error() {
+    let mut env = TestEnv::new();
+
+    let ret = env.call(0, method::TRANSFER, RawBytes::default());
+    assert_eq!(ret.msg_receipt.exit_code, ExitCode::USR_ILLEGAL_ARGUMENT);
+    let ret = env.call(0, method::TRANSFER, RawBytes::new(vec![0x61, 0x61]));
+    assert_eq!(ret.msg_receipt.exit_code, ExitCode::USR_SERIALIZATION);
+}
+
+#[test]
+fn actor_info_reports_the_receiver() {
+    let mut env = TestEnv::new();
+
+    let ret = env.call(1, method::ACTOR_INFO, RawBytes::default());
+    assert_eq!(ret.msg_receipt.exit_code, ExitCode::OK, "{:?}", ret.failure_info);
+
+    let info: ActorInfo = return_data(&ret).deserialize().unwrap();
+    assert_eq!(info.id, ACTOR_ID);
+}